@@ -3,8 +3,10 @@ use nes::{ChannelPlayback, SaveWram, UserInput};
 use serde::{Deserialize, Serialize};
 use ui::{
     audio::{Audio, SamplesSender},
-    gamepad::{GamepadChannel, GamepadEvent, GilrsInput},
-    input::{InputMap, InputType},
+    gamepad::{GamepadChannel, GamepadEvent, GamepadPlayers, GilrsInput},
+    input::{
+        InputMacro, InputMap, InputType, PadInput, PaddleSettings, PowerPadSettings, TurboSettings,
+    },
     wram::{CartridgeId, WramStorage},
 };
 
@@ -51,6 +53,12 @@ impl Into<nes::Region> for Region {
     }
 }
 
+// Level from 0.0 to 1.0 the audio input must reach to be heard as the Famicom microphone
+const DEFAULT_MICROPHONE_THRESHOLD: f32 = 0.1;
+
+// Keys typed to cover the Power Pad, matching the default of PowerPadSettings
+const DEFAULT_POWER_PAD_KEYS: &str = "rtyufghjvbnm";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default = "Default::default")]
 struct UiState {
@@ -82,6 +90,21 @@ struct UiState {
     variable_viewer: VariableViewerState,
     movie_settings: MovieSettingsState,
     game_genie: bool,
+    fds_auto_swap: bool,
+    fds_fast_load: bool,
+    port_one: nes::PortDeviceKind,
+    port_two: nes::PortDeviceKind,
+    expansion: nes::PortDeviceKind,
+    paddle_sensitivity: f32,
+    paddle_min: u16,
+    paddle_max: u16,
+    power_pad_side: nes::PowerPadSide,
+    power_pad_keys: String,
+    listen_microphone: bool,
+    microphone_threshold: f32,
+    turbo_rate: u32,
+    turbo_keys: String,
+    macros: String,
 }
 
 impl Default for UiState {
@@ -115,10 +138,118 @@ impl Default for UiState {
             variable_viewer: VariableViewerState::default(),
             movie_settings: MovieSettingsState::default(),
             game_genie: false,
+            fds_auto_swap: false,
+            fds_fast_load: false,
+            port_one: nes::PortDeviceKind::Controller,
+            port_two: nes::PortDeviceKind::Controller,
+            expansion: nes::PortDeviceKind::Unplugged,
+            paddle_sensitivity: PaddleSettings::default().sensitivity,
+            paddle_min: PaddleSettings::default().min,
+            paddle_max: PaddleSettings::default().max,
+            power_pad_side: nes::PowerPadSide::B,
+            power_pad_keys: DEFAULT_POWER_PAD_KEYS.to_string(),
+            listen_microphone: false,
+            microphone_threshold: DEFAULT_MICROPHONE_THRESHOLD,
+            turbo_rate: TurboSettings::default().rate,
+            turbo_keys: String::new(),
+            macros: String::new(),
+        }
+    }
+}
+
+impl UiState {
+    fn port_device(&self, port: nes::Port) -> nes::PortDeviceKind {
+        match port {
+            nes::Port::One => self.port_one,
+            nes::Port::Two => self.port_two,
+            nes::Port::Expansion => self.expansion,
+        }
+    }
+
+    fn paddle_settings(&self) -> PaddleSettings {
+        PaddleSettings {
+            sensitivity: self.paddle_sensitivity,
+            min: self.paddle_min,
+            max: self.paddle_max,
+        }
+    }
+
+    fn fds_config(&self) -> nes::FdsConfig {
+        nes::FdsConfig {
+            auto_swap: self.fds_auto_swap,
+            fast_load: self.fds_fast_load,
+        }
+    }
+
+    fn power_pad_settings(&self) -> PowerPadSettings {
+        let defaults = PowerPadSettings::default();
+        PowerPadSettings {
+            side: self.power_pad_side,
+            keys: PowerPadSettings::parse_keys(&self.power_pad_keys).unwrap_or(defaults.keys),
+        }
+    }
+
+    // Turbo stays unbound until both keys are entered
+    fn turbo_settings(&self) -> TurboSettings {
+        let defaults = TurboSettings::default();
+        let [a, b] = TurboSettings::parse_keys(&self.turbo_keys)
+            .map(|keys| keys.map(Some))
+            .unwrap_or([defaults.a, defaults.b]);
+        TurboSettings {
+            rate: self.turbo_rate.max(1),
+            a,
+            b,
         }
     }
+
+    // Macros are entered one per line, lines that don't parse are skipped
+    fn macros(&self) -> Vec<InputMacro> {
+        self.macros
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(InputMacro::parse)
+            .collect()
+    }
+
+    fn port_device_mut(&mut self, port: nes::Port) -> &mut nes::PortDeviceKind {
+        match port {
+            nes::Port::One => &mut self.port_one,
+            nes::Port::Two => &mut self.port_two,
+            nes::Port::Expansion => &mut self.expansion,
+        }
+    }
+}
+
+// Devices offered for each port, the Four Score is always plugged into both controller ports
+fn port_devices(port: nes::Port) -> &'static [(nes::PortDeviceKind, &'static str)] {
+    use nes::PortDeviceKind as Device;
+    match port {
+        nes::Port::One => &[
+            (Device::Controller, "Controller"),
+            (Device::FourScore, "Four Score"),
+        ],
+        nes::Port::Two => &[
+            (Device::Controller, "Controller"),
+            (Device::Zapper, "Zapper"),
+            (Device::FourScore, "Four Score"),
+            (Device::Vaus, "Arkanoid Vaus"),
+            (Device::PowerPad, "Power Pad"),
+            (Device::SnesMouse, "SNES Mouse"),
+            (Device::SuborMouse, "Subor Mouse"),
+        ],
+        nes::Port::Expansion => &[
+            (Device::Unplugged, "None"),
+            (Device::Hori, "Hori 4 Players Adapter"),
+            (Device::Vaus, "Arkanoid Vaus"),
+            (Device::FamilyKeyboard, "Family BASIC Keyboard"),
+            (Device::FamilyTrainer, "Family Trainer"),
+        ],
+    }
 }
 
+// Sample rate of blank tapes inserted into the Data Recorder
+const BLANK_TAPE_RATE: u32 = 44100;
+
 pub struct DebuggerApp<A> {
     app_events: AppEvents,
     input: SharedInput,
@@ -127,6 +258,11 @@ pub struct DebuggerApp<A> {
     nes_screen: NesScreen,
     recents: Recents,
     last_input: InputState,
+    last_zapper: nes::Zapper,
+    pads: [InputMap; 3],
+    gamepad_players: GamepadPlayers,
+    family_keys: InputMap,
+    keyboard_capture: bool,
     pause: bool,
     debug: DebugUiState,
     state: UiState,
@@ -148,6 +284,9 @@ pub struct DebuggerApp<A> {
     variable_viewer: VariableViewer,
     movie_settings: MovieSettings,
     recording_wav: bool,
+    recording_movie: Option<ui::movie::MovieMode>,
+    #[cfg(not(target_arch = "wasm32"))]
+    microphone: Option<ui::audio::CpalMicrophone>,
 }
 
 impl<A: Audio> DebuggerApp<A> {
@@ -226,6 +365,11 @@ impl<A: Audio> DebuggerApp<A> {
             audio,
             nes_screen,
             last_input,
+            last_zapper: nes::Zapper::default(),
+            pads: std::array::from_fn(|_| InputMap::new()),
+            gamepad_players: GamepadPlayers::new(),
+            family_keys: InputMap::new(),
+            keyboard_capture: false,
             pause: false,
             state,
             debug,
@@ -247,6 +391,9 @@ impl<A: Audio> DebuggerApp<A> {
             variable_viewer: VariableViewer::new(),
             movie_settings: MovieSettings::new(),
             recording_wav: false,
+            recording_movie: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            microphone: None,
         };
 
         app.hydrate(initial_file);
@@ -263,6 +410,20 @@ impl<A: Audio> DebuggerApp<A> {
 
         self.recents = Recents::new(&self.state.recent_files.as_slice(), 10);
         self.nes_screen.filter(self.state.filter);
+        for port in [nes::Port::One, nes::Port::Two, nes::Port::Expansion] {
+            self.emu_control
+                .set_port_device(port, self.state.port_device(port));
+        }
+        self.emu_control.set_fds_config(self.state.fds_config());
+        self.input.set_paddle_settings(self.state.paddle_settings());
+        self.input
+            .set_power_pad_settings(self.state.power_pad_settings());
+        self.input
+            .set_microphone_threshold(self.state.microphone_threshold);
+        self.input.set_turbo_settings(self.state.turbo_settings());
+        self.input.set_macros(self.state.macros());
+        self.listen_microphone(self.state.listen_microphone);
+        self.keyboard_capture = self.family_keyboard();
 
         if let Some(file) = initial_file {
             self.load_rom(file, self.state.bios.clone());
@@ -275,6 +436,39 @@ impl<A: Audio> DebuggerApp<A> {
         self.update_debug_req();
     }
 
+    fn set_port_device(&mut self, port: nes::Port, device: nes::PortDeviceKind) {
+        let four_score = nes::PortDeviceKind::FourScore;
+        let other = match port {
+            nes::Port::One => Some(nes::Port::Two),
+            nes::Port::Two => Some(nes::Port::One),
+            nes::Port::Expansion => None,
+        };
+
+        // Plugging or unplugging a Four Score changes both controller ports
+        if let Some(other) = other {
+            let other_device = if device == four_score {
+                Some(four_score)
+            } else if self.state.port_device(other) == four_score {
+                Some(nes::PortDeviceKind::Controller)
+            } else {
+                None
+            };
+
+            if let Some(other_device) = other_device {
+                *self.state.port_device_mut(other) = other_device;
+                self.emu_control.set_port_device(other, other_device);
+            }
+        }
+
+        *self.state.port_device_mut(port) = device;
+        self.emu_control.set_port_device(port, device);
+        self.keyboard_capture = self.family_keyboard();
+    }
+
+    fn family_keyboard(&self) -> bool {
+        self.state.expansion == nes::PortDeviceKind::FamilyKeyboard
+    }
+
     fn select_rom(&self) {
         let control = self.emu_control.clone();
         let region = self.state.region;
@@ -306,6 +500,32 @@ impl<A: Audio> DebuggerApp<A> {
         pick_movie(proxy);
     }
 
+    fn select_tape(&self) {
+        let proxy = self.app_events.create_proxy();
+        pick_tape(proxy);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn listen_microphone(&mut self, listen: bool) {
+        if !listen {
+            self.microphone = None;
+            self.input.microphone_level(0.0);
+        } else if self.microphone.is_none() {
+            match ui::audio::CpalMicrophone::new() {
+                Ok(microphone) => self.microphone = Some(microphone),
+                Err(err) => {
+                    tracing::error!("unable to open microphone: {err}");
+                    self.state.listen_microphone = false;
+                }
+            }
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn listen_microphone(&mut self, _listen: bool) {
+        self.state.listen_microphone = false;
+    }
+
     fn set_volume(&mut self, value: f32) {
         self.audio.volume(value);
     }
@@ -330,7 +550,14 @@ impl<A: Audio> DebuggerApp<A> {
             self.nes_screen.set_message(Message::StepForward);
         }
 
-        self.emu_control.player_one(input_state.controller);
+        self.emu_control.pad(0, input_state.pad.clone());
+        for (idx, pad) in self.pads.iter().enumerate() {
+            self.emu_control.pad(idx + 1, pad.pad());
+        }
+        self.emu_control.vaus(input_state.vaus);
+        self.emu_control.power_pad(input_state.power_pad);
+        self.emu_control.microphone(input_state.microphone);
+        self.emu_control.mouse(input_state.mouse);
 
         if let Some(slot) = input_state.save_state {
             self.emu_control.save_state(slot);
@@ -394,6 +621,8 @@ impl<A: Audio> DebuggerApp<A> {
                 self.recents.add(path);
                 self.state.recent_files = self.recents.iter().map(|p| p.to_path_buf()).collect();
                 self.pause = false;
+                // Loading a cartridge ends any movie recording
+                self.recording_movie = None;
                 self.handle_pause();
                 self.nes_screen.focus(ctx);
             }
@@ -404,25 +633,42 @@ impl<A: Audio> DebuggerApp<A> {
                 self.pause = true;
                 self.handle_pause();
             }
-            AppEvent::Gamepad(gamepad) => match gamepad {
-                GamepadEvent::Button { state, button, .. } => {
-                    let Some(mut input) = self.input.input_map.try_lock().ok() else {
-                        return;
-                    };
-                    if state.is_pressed() {
-                        input.press(button);
-                    } else {
-                        input.release(button);
+            AppEvent::Gamepad(gamepad) => {
+                let gamepad_id = gamepad.gamepad_id();
+                if let GamepadEvent::Disconnected { .. } = gamepad {
+                    if let Some(player) = self.gamepad_players.disconnect(gamepad_id) {
+                        if player > 0 {
+                            self.pads[player - 1] = InputMap::new();
+                        }
                     }
+                    return;
                 }
-                GamepadEvent::Axis { axis, value, .. } => {
-                    let Some(mut input) = self.input.input_map.try_lock().ok() else {
-                        return;
-                    };
-                    input.axis(axis, value);
+
+                let apply = |input: &mut InputMap| match gamepad {
+                    GamepadEvent::Button { state, button, .. } => {
+                        if state.is_pressed() {
+                            input.press(button);
+                        } else {
+                            input.release(button);
+                        }
+                    }
+                    GamepadEvent::Axis { axis, value, .. } => {
+                        input.axis(axis, value);
+                    }
+                    _ => (),
+                };
+
+                // Keyboard and the first gamepad control player one, later gamepads players 2-4
+                match self.gamepad_players.player(gamepad_id) {
+                    Some(0) => {
+                        if let Ok(mut input) = self.input.input_map.try_lock() {
+                            apply(&mut input);
+                        }
+                    }
+                    Some(player) => apply(&mut self.pads[player - 1]),
+                    None => (),
                 }
-                _ => (),
-            },
+            }
             AppEvent::CartridgeInfo(cartridge_kind) => match cartridge_kind {
                 CartridgeKind::Cartridge => {
                     self.fds_disk_sides = 0;
@@ -484,6 +730,30 @@ impl<A: Audio> DebuggerApp<A> {
                     tracing::error!("unable to create wav file");
                 }
             }
+            AppEvent::PickMovieSave(path_buf, format) => {
+                if let Ok(file) = File::create(path_buf) {
+                    self.recording_movie = Some(ui::movie::MovieMode::ReadWrite);
+                    self.emu_control.record_movie(file, format);
+                } else {
+                    tracing::error!("unable to create movie file");
+                }
+            }
+            AppEvent::TapeLoaded(bytes) => {
+                match ui::wav_reader::WavReader::new(std::io::Cursor::new(bytes)) {
+                    Ok(wav) => self.emu_control.insert_tape(nes::Tape {
+                        sample_rate: wav.sample_rate,
+                        samples: wav.samples,
+                    }),
+                    Err(e) => tracing::error!("Unable to load tape: {:?}", e),
+                }
+            }
+            AppEvent::PickTapeSave(path_buf) => {
+                if let Ok(file) = File::create(path_buf) {
+                    self.emu_control.save_tape(file);
+                } else {
+                    tracing::error!("unable to create tape file");
+                }
+            }
         }
     }
 
@@ -576,6 +846,12 @@ impl<A: Audio> eframe::App for DebuggerApp<A> {
 
                     ui.checkbox(&mut self.state.game_genie, "Game Genie");
 
+                    let fds_auto_swap = ui.checkbox(&mut self.state.fds_auto_swap, "FDS Auto Swap");
+                    let fds_fast_load = ui.checkbox(&mut self.state.fds_fast_load, "FDS Fast Load");
+                    if fds_auto_swap.changed() || fds_fast_load.changed() {
+                        self.emu_control.set_fds_config(self.state.fds_config());
+                    }
+
                     if ui.button("Load Movie").clicked() {
                         self.select_movie();
                     }
@@ -636,6 +912,209 @@ impl<A: Audio> eframe::App for DebuggerApp<A> {
                             }
                         });
                     }
+
+                    if self.family_keyboard() {
+                        ui.menu_button("Data Recorder", |ui| {
+                            if ui.button("Load Tape").clicked() {
+                                self.select_tape();
+                            }
+
+                            if ui.button("New Tape").clicked() {
+                                self.emu_control
+                                    .insert_tape(nes::Tape::blank(BLANK_TAPE_RATE));
+                            }
+
+                            ui.separator();
+
+                            let controls = [
+                                (nes::TapeControl::Play, "Play"),
+                                (nes::TapeControl::Record, "Record"),
+                                (nes::TapeControl::Stop, "Stop"),
+                                (nes::TapeControl::Rewind, "Rewind"),
+                            ];
+
+                            for (control, label) in controls {
+                                if ui.button(label).clicked() {
+                                    self.emu_control.tape_control(control);
+                                }
+                            }
+
+                            if cfg!(not(target_arch = "wasm32")) {
+                                ui.separator();
+
+                                if ui.button("Save Tape").clicked() {
+                                    pick_tape_save(self.app_events.create_proxy());
+                                }
+                            }
+                        });
+                    }
+
+                    ui.menu_button("Ports", |ui| {
+                        let ports = [
+                            (nes::Port::One, "Port 1"),
+                            (nes::Port::Two, "Port 2"),
+                            (nes::Port::Expansion, "Expansion Port"),
+                        ];
+
+                        for (port, label) in ports {
+                            ui.menu_button(label, |ui| {
+                                for &(device, label) in port_devices(port) {
+                                    if ui
+                                        .radio(self.state.port_device(port) == device, label)
+                                        .clicked()
+                                    {
+                                        self.set_port_device(port, device);
+                                    }
+                                }
+                            });
+                        }
+
+                        if self.family_keyboard() {
+                            if ui
+                                .checkbox(&mut self.keyboard_capture, "Capture Keyboard")
+                                .changed()
+                            {
+                                self.family_keys = InputMap::new();
+                            }
+                        }
+
+                        ui.menu_button("Paddle Settings", |ui| {
+                            let sensitivity =
+                                egui::Slider::new(&mut self.state.paddle_sensitivity, 0.25..=4.0)
+                                    .text("Sensitivity");
+                            let mut changed = ui.add(sensitivity).changed();
+                            let min = egui::Slider::new(&mut self.state.paddle_min, 0..=511)
+                                .text("Minimum");
+                            changed |= ui.add(min).changed();
+                            let max = egui::Slider::new(&mut self.state.paddle_max, 0..=511)
+                                .text("Maximum");
+                            changed |= ui.add(max).changed();
+
+                            if ui.button("Reset").clicked() {
+                                let defaults = PaddleSettings::default();
+                                self.state.paddle_sensitivity = defaults.sensitivity;
+                                self.state.paddle_min = defaults.min;
+                                self.state.paddle_max = defaults.max;
+                                changed = true;
+                            }
+
+                            if changed {
+                                self.input.set_paddle_settings(self.state.paddle_settings());
+                            }
+                        });
+
+                        ui.menu_button("Power Pad Settings", |ui| {
+                            let mut changed = false;
+                            ui.horizontal(|ui| {
+                                ui.label("Side");
+                                changed |= ui
+                                    .radio_value(
+                                        &mut self.state.power_pad_side,
+                                        nes::PowerPadSide::A,
+                                        "A",
+                                    )
+                                    .changed();
+                                changed |= ui
+                                    .radio_value(
+                                        &mut self.state.power_pad_side,
+                                        nes::PowerPadSide::B,
+                                        "B",
+                                    )
+                                    .changed();
+                            });
+
+                            ui.label("Keys, 3 rows of 4 from the top left");
+                            changed |= ui
+                                .text_edit_singleline(&mut self.state.power_pad_keys)
+                                .changed();
+                            if PowerPadSettings::parse_keys(&self.state.power_pad_keys).is_none() {
+                                ui.colored_label(ui.visuals().error_fg_color, "Invalid keys");
+                            }
+
+                            if ui.button("Reset").clicked() {
+                                self.state.power_pad_side = nes::PowerPadSide::B;
+                                self.state.power_pad_keys = DEFAULT_POWER_PAD_KEYS.to_string();
+                                changed = true;
+                            }
+
+                            if changed {
+                                self.input
+                                    .set_power_pad_settings(self.state.power_pad_settings());
+                            }
+                        });
+
+                        ui.menu_button("Turbo and Macros", |ui| {
+                            let rate = egui::Slider::new(&mut self.state.turbo_rate, 1..=30)
+                                .text("Turbo Rate");
+                            let mut changed = ui.add(rate).changed();
+
+                            ui.label("Turbo A and B keys");
+                            changed |= ui
+                                .text_edit_singleline(&mut self.state.turbo_keys)
+                                .changed();
+                            if !self.state.turbo_keys.is_empty()
+                                && TurboSettings::parse_keys(&self.state.turbo_keys).is_none()
+                            {
+                                ui.colored_label(ui.visuals().error_fg_color, "Invalid keys");
+                            }
+
+                            ui.label("Macros, one name=key:sequence per line");
+                            ui.label("such as fireball=q:down*2,down+right*2,right+b");
+                            changed |= ui.text_edit_multiline(&mut self.state.macros).changed();
+                            let invalid = self
+                                .state
+                                .macros
+                                .lines()
+                                .filter(|line| !line.trim().is_empty())
+                                .filter(|line| InputMacro::parse(line).is_none())
+                                .count();
+                            if invalid > 0 {
+                                let message = format!("{invalid} invalid macro(s)");
+                                ui.colored_label(ui.visuals().error_fg_color, message);
+                            }
+
+                            if ui.button("Reset").clicked() {
+                                self.state.turbo_rate = TurboSettings::default().rate;
+                                self.state.turbo_keys = String::new();
+                                changed = true;
+                            }
+
+                            if changed {
+                                self.input.set_turbo_settings(self.state.turbo_settings());
+                                self.input.set_macros(self.state.macros());
+                            }
+                        });
+
+                        ui.menu_button("Microphone Settings", |ui| {
+                            if cfg!(not(target_arch = "wasm32")) {
+                                if ui
+                                    .checkbox(
+                                        &mut self.state.listen_microphone,
+                                        "Listen to Audio Input",
+                                    )
+                                    .changed()
+                                {
+                                    self.listen_microphone(self.state.listen_microphone);
+                                }
+                            }
+
+                            let threshold =
+                                egui::Slider::new(&mut self.state.microphone_threshold, 0.0..=1.0)
+                                    .text("Threshold");
+                            let mut changed = ui.add(threshold).changed();
+                            ui.label("C or the north face button hold the microphone open");
+
+                            if ui.button("Reset").clicked() {
+                                self.state.microphone_threshold = DEFAULT_MICROPHONE_THRESHOLD;
+                                changed = true;
+                            }
+
+                            if changed {
+                                self.input
+                                    .set_microphone_threshold(self.state.microphone_threshold);
+                            }
+                        });
+                    });
                 });
                 ui.menu_button("Windows", |ui| {
                     ui.checkbox(&mut self.state.show_screen, "Screen");
@@ -750,8 +1229,49 @@ impl<A: Audio> eframe::App for DebuggerApp<A> {
             self.nes_screen.show(&ctx);
         }
 
-        self.movie_settings
-            .show(&mut self.state.movie_settings, ctx);
+        let zapper = self.nes_screen.zapper();
+        if let Ok(mut input) = self.input.input_map.try_lock() {
+            if let Some((x, _)) = zapper.aim {
+                input.mouse_position(x as f32 / 256.0);
+            }
+            if zapper.trigger {
+                input.press(egui::PointerButton::Primary);
+            } else {
+                input.release(egui::PointerButton::Primary);
+            }
+        }
+
+        if self.state.port_two == nes::PortDeviceKind::Zapper {
+            if zapper.aim.is_some() {
+                ctx.set_cursor_icon(egui::CursorIcon::Crosshair);
+            }
+            if zapper != self.last_zapper {
+                self.last_zapper = zapper;
+                self.emu_control.zapper(zapper);
+            }
+        }
+
+        let recording = self.recording_movie;
+        match self
+            .movie_settings
+            .show(&mut self.state.movie_settings, recording, ctx)
+        {
+            Some(MovieRecordAction::Start) => {
+                pick_movie_save(
+                    self.app_events.create_proxy(),
+                    self.state.movie_settings.record_format.into(),
+                );
+            }
+            Some(MovieRecordAction::Stop) => {
+                self.emu_control.stop_record_movie();
+                self.recording_movie = None;
+            }
+            Some(MovieRecordAction::SetMode(mode)) => {
+                self.emu_control.movie_mode(mode);
+                self.recording_movie = Some(mode);
+            }
+            None => (),
+        }
         self.help.show(&ctx);
 
         if self.state.show_code {
@@ -840,6 +1360,15 @@ impl<A: Audio> eframe::App for DebuggerApp<A> {
 
         if self.state.show_input_viewer {
             egui::Window::new("Input Viewer").show(ctx, |ui| {
+                let counter = self.debug.frame_counter();
+                ui.horizontal(|ui| {
+                    ui.label(format!("Frame: {}", counter.frame));
+                    ui.label(format!("Lag: {}", counter.lag_count));
+                    if counter.lag_frame {
+                        ui.colored_label(egui::Color32::RED, "LAG");
+                    }
+                });
+
                 let mut buttons = svg::NesButtons::empty();
                 let controller = self.debug.inputs()[0];
 
@@ -897,10 +1426,51 @@ impl<A: Audio> eframe::App for DebuggerApp<A> {
             }
         });
 
-        if ctx.memory(|m| m.has_focus(self.nes_screen.id())) {
-            if let Some(state) = self.input.update(input_iter) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(microphone) = self.microphone.as_ref() {
+            self.input.microphone_level(microphone.take_level());
+        }
+
+        if !ctx.memory(|m| m.has_focus(self.nes_screen.id())) {
+            return;
+        }
+
+        for ev in raw_input.events.iter() {
+            match *ev {
+                Event::MouseMoved(delta) => self.input.mouse_motion(delta.x, delta.y),
+                Event::PointerButton {
+                    button: egui::PointerButton::Secondary,
+                    pressed,
+                    ..
+                } => self
+                    .input
+                    .pointer_button(egui::PointerButton::Secondary, pressed),
+                _ => (),
+            }
+        }
+
+        // While the keyboard is captured every key goes to the Family BASIC keyboard, the
+        // emulator hotkeys are only reachable through the menus
+        if self.keyboard_capture {
+            for input in input_iter {
+                if input.pressed {
+                    self.family_keys.press(input.key);
+                } else {
+                    self.family_keys.release(input.key);
+                }
+            }
+
+            let mut keyboard = self.family_keys.family_keyboard();
+            keyboard.set(nes::FamilyKey::LeftShift, raw_input.modifiers.shift);
+            keyboard.set(nes::FamilyKey::Ctr, raw_input.modifiers.ctrl);
+            keyboard.set(nes::FamilyKey::Grph, raw_input.modifiers.alt);
+            self.emu_control.keyboard(keyboard);
+
+            if let Some(state) = self.input.update(std::iter::empty::<Input<egui::Key>>()) {
                 self.handle_input(state);
             }
+        } else if let Some(state) = self.input.update(input_iter) {
+            self.handle_input(state);
         }
     }
 }
@@ -916,6 +1486,9 @@ pub enum AppEvent {
     SaveWram(CartridgeId, SaveWram),
     MovieLoaded(String, Vec<u8>),
     PickWav(PathBuf),
+    PickMovieSave(PathBuf, ui::movie::MovieFormat),
+    TapeLoaded(Vec<u8>),
+    PickTapeSave(PathBuf),
 }
 
 impl From<GamepadEvent> for AppEvent {
@@ -986,10 +1559,8 @@ impl EmulatorControl {
         (EmulatorControl { tx, wram }, EmulatorCommands { rx, proxy })
     }
 
-    pub fn player_one(&self, controller: nes::Controller) {
-        let _ = self
-            .tx
-            .send(EmulatorInput::Nes(UserInput::PlayerOne(controller)));
+    pub fn pad(&self, player: usize, pad: PadInput) {
+        let _ = self.tx.send(EmulatorInput::Pad(player, pad));
     }
 
     pub fn load_rom(
@@ -1053,6 +1624,53 @@ impl EmulatorControl {
         let _ = self.tx.send(EmulatorInput::SetFdsDisk(side));
     }
 
+    /// Takes effect when the next cartridge is loaded
+    pub fn set_fds_config(&self, config: nes::FdsConfig) {
+        let _ = self.tx.send(EmulatorInput::SetFdsConfig(config));
+    }
+
+    pub fn set_port_device(&self, port: nes::Port, device: nes::PortDeviceKind) {
+        let _ = self.tx.send(EmulatorInput::SetPortDevice(port, device));
+    }
+
+    pub fn vaus(&self, vaus: nes::Vaus) {
+        let _ = self.tx.send(EmulatorInput::Nes(UserInput::Vaus(vaus)));
+    }
+
+    pub fn power_pad(&self, power_pad: nes::PowerPad) {
+        let _ = self
+            .tx
+            .send(EmulatorInput::Nes(UserInput::PowerPad(power_pad)));
+    }
+
+    pub fn mouse(&self, mouse: nes::Mouse) {
+        let _ = self.tx.send(EmulatorInput::Nes(UserInput::Mouse(mouse)));
+    }
+
+    pub fn microphone(&self, microphone: bool) {
+        let _ = self
+            .tx
+            .send(EmulatorInput::Nes(UserInput::Microphone(microphone)));
+    }
+
+    pub fn zapper(&self, zapper: nes::Zapper) {
+        let _ = self.tx.send(EmulatorInput::Nes(UserInput::Zapper(zapper)));
+    }
+
+    pub fn keyboard(&self, keyboard: nes::FamilyKeyboard) {
+        let _ = self
+            .tx
+            .send(EmulatorInput::Nes(UserInput::Keyboard(keyboard)));
+    }
+
+    pub fn insert_tape(&self, tape: nes::Tape) {
+        let _ = self.tx.send(EmulatorInput::InsertTape(tape));
+    }
+
+    pub fn tape_control(&self, control: nes::TapeControl) {
+        let _ = self.tx.send(EmulatorInput::TapeControl(control));
+    }
+
     fn save_wram(&self) {
         let _ = self.tx.send(EmulatorInput::SaveWram);
     }
@@ -1072,6 +1690,22 @@ impl EmulatorControl {
     fn stop_record_wav(&self) {
         let _ = self.tx.send(EmulatorInput::StopRecordWav);
     }
+
+    fn record_movie(&self, file: File, format: ui::movie::MovieFormat) {
+        let _ = self.tx.send(EmulatorInput::RecordMovie(file, format));
+    }
+
+    fn stop_record_movie(&self) {
+        let _ = self.tx.send(EmulatorInput::StopRecordMovie);
+    }
+
+    fn movie_mode(&self, mode: ui::movie::MovieMode) {
+        let _ = self.tx.send(EmulatorInput::MovieMode(mode));
+    }
+
+    fn save_tape(&self, file: File) {
+        let _ = self.tx.send(EmulatorInput::SaveTape(file));
+    }
 }
 
 pub struct EmulatorCommands {
@@ -1109,6 +1743,7 @@ pub struct Input<I: Into<InputType>> {
 
 pub struct InputState {
     pub controller: nes::Controller,
+    pub pad: PadInput,
     pub rewind: bool,
     pub power: bool,
     pub reset: bool,
@@ -1117,6 +1752,10 @@ pub struct InputState {
     pub step_backward: bool,
     pub save_state: Option<u8>,
     pub restore_state: Option<u8>,
+    pub vaus: nes::Vaus,
+    pub power_pad: nes::PowerPad,
+    pub microphone: bool,
+    pub mouse: nes::Mouse,
     fast_forward: bool,
 }
 
@@ -1148,6 +1787,7 @@ impl SharedInput {
 
         let state = InputState {
             controller: input_map.controller(),
+            pad: input_map.pad(),
             rewind: input_map.rewind(),
             power: input_map.power(),
             reset: input_map.reset(),
@@ -1156,17 +1796,68 @@ impl SharedInput {
             step_backward: input_map.step_backward(),
             save_state: input_map.save_state(),
             restore_state: input_map.restore_state(),
+            vaus: input_map.vaus(),
+            power_pad: input_map.power_pad(),
+            microphone: input_map.microphone(),
+            mouse: input_map.mouse(),
             fast_forward: input_map.fast_forward(),
         };
 
         Some(state)
     }
 
+    pub fn set_paddle_settings(&self, settings: PaddleSettings) {
+        self.input_map.lock().unwrap().set_paddle_settings(settings);
+    }
+
+    pub fn set_power_pad_settings(&self, settings: PowerPadSettings) {
+        self.input_map
+            .lock()
+            .unwrap()
+            .set_power_pad_settings(settings);
+    }
+
+    pub fn set_turbo_settings(&self, settings: TurboSettings) {
+        self.input_map.lock().unwrap().set_turbo_settings(settings);
+    }
+
+    pub fn set_macros(&self, macros: Vec<InputMacro>) {
+        self.input_map.lock().unwrap().set_macros(macros);
+    }
+
+    pub fn set_microphone_threshold(&self, threshold: f32) {
+        self.input_map
+            .lock()
+            .unwrap()
+            .set_microphone_threshold(threshold);
+    }
+
+    pub fn pointer_button(&self, button: egui::PointerButton, pressed: bool) {
+        let mut input_map = self.input_map.lock().unwrap();
+        if pressed {
+            input_map.press(button);
+        } else {
+            input_map.release(button);
+        }
+    }
+
+    pub fn mouse_motion(&self, dx: f32, dy: f32) {
+        self.input_map
+            .lock()
+            .unwrap()
+            .mouse_motion(dx as f64, dy as f64);
+    }
+
+    pub fn microphone_level(&self, level: f32) {
+        self.input_map.lock().unwrap().microphone_level(level);
+    }
+
     pub fn state(&self) -> InputState {
         let input_map = self.input_map.lock().unwrap();
 
         InputState {
             controller: input_map.controller(),
+            pad: input_map.pad(),
             rewind: input_map.rewind(),
             power: input_map.power(),
             reset: input_map.reset(),
@@ -1175,6 +1866,10 @@ impl SharedInput {
             step_backward: input_map.step_backward(),
             save_state: input_map.save_state(),
             restore_state: input_map.restore_state(),
+            vaus: input_map.vaus(),
+            power_pad: input_map.power_pad(),
+            microphone: input_map.microphone(),
+            mouse: input_map.mouse(),
             fast_forward: input_map.fast_forward(),
         }
     }
@@ -1365,6 +2060,78 @@ fn pick_wav(proxy: AppEventsProxy) {
     // unsupported
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn pick_movie_save(proxy: AppEventsProxy, format: ui::movie::MovieFormat) {
+    std::thread::spawn(move || {
+        let extension = format.extension();
+        let Some(movie_file) = rfd::FileDialog::new()
+            .set_file_name(format!("recording.{extension}"))
+            .add_filter("All Supported Files", &[extension])
+            .save_file()
+        else {
+            return;
+        };
+
+        proxy.send(AppEvent::PickMovieSave(movie_file, format));
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+fn pick_movie_save(proxy: AppEventsProxy, format: ui::movie::MovieFormat) {
+    // unsupported
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn pick_tape(proxy: AppEventsProxy) {
+    std::thread::spawn(move || {
+        let tape_file = rfd::FileDialog::new()
+            .add_filter("All Supported Files", &["wav"])
+            .pick_file();
+
+        if let Some(bytes) = tape_file.and_then(|p| std::fs::read(&p).ok()) {
+            proxy.send(AppEvent::TapeLoaded(bytes));
+        }
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+fn pick_tape(proxy: AppEventsProxy) {
+    let picker = rfd::AsyncFileDialog::new().add_filter("All Supported Files", &["wav"]);
+
+    let pick = async move {
+        let tape_file = picker.pick_file().await;
+
+        let Some(tape_file) = tape_file else {
+            return;
+        };
+
+        let bytes = tape_file.read().await;
+        proxy.send(AppEvent::TapeLoaded(bytes));
+    };
+
+    wasm_bindgen_futures::spawn_local(pick);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn pick_tape_save(proxy: AppEventsProxy) {
+    std::thread::spawn(move || {
+        let Some(tape_file) = rfd::FileDialog::new()
+            .set_file_name("tape.wav")
+            .add_filter("All Supported Files", &["wav"])
+            .save_file()
+        else {
+            return;
+        };
+
+        proxy.send(AppEvent::PickTapeSave(tape_file));
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+fn pick_tape_save(proxy: AppEventsProxy) {
+    // unsupported
+}
+
 fn read_first_match_in_zip<R: std::io::Read + std::io::Seek>(
     extension: &str,
     read: R,
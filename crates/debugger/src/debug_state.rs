@@ -60,20 +60,30 @@ impl DerefMut for WatchItems {
     }
 }
 
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FrameCounter {
+    pub frame: u32,
+    pub lag_frame: bool,
+    pub lag_count: u32,
+}
+
 #[derive(Debug, Clone, Default)]
-pub struct Inputs([nes::Controller; 2]);
+pub struct Inputs {
+    controllers: [nes::Controller; 2],
+    pub counter: FrameCounter,
+}
 
 impl Deref for Inputs {
     type Target = [nes::Controller; 2];
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.controllers
     }
 }
 
 impl DerefMut for Inputs {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.controllers
     }
 }
 
@@ -256,7 +266,11 @@ impl DebugUiState {
     }
 
     pub fn inputs(&self) -> &[nes::Controller; 2] {
-        &self.inputs.0
+        &self.inputs.controllers
+    }
+
+    pub fn frame_counter(&self) -> FrameCounter {
+        self.inputs.counter
     }
 
     pub fn swap(&mut self) {
@@ -2,11 +2,17 @@ use std::{collections::VecDeque, time::Duration};
 
 use blip_buf::BlipBuf;
 use nes::{
-    Cartridge, DebugEvent, FdsInput, InputSource, Machine, MapperInput, Region, RunResult,
-    SaveWram, SimpleInput, UserInput,
+    Cartridge, DebugEvent, FdsInput, InputSource, Machine, MapperInput, Port, PortDeviceKind,
+    Region, RunResult, SaveWram, SimpleInput, UserInput,
     run_until::{self, RunUntil},
 };
-use ui::{audio::SamplesSender, movie::MovieFile, wram::CartridgeId};
+use ui::{
+    audio::SamplesSender,
+    input::{PadInput, PadResolver},
+    movie::{MovieCheckpoint, MovieFile, MovieFormat, MovieMode, MovieRecorder},
+    wav_writer::WavWriter,
+    wram::CartridgeId,
+};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum StepKind {
@@ -104,6 +110,8 @@ impl Playback {
 #[derive(Debug)]
 pub enum EmulatorInput {
     Nes(UserInput),
+    /// Controller for players 1-4 indexed from 0, with turbo and macros still to be applied
+    Pad(usize, PadInput),
     Rewind(bool),
     SaveState(u32),
     RestoreState(u32),
@@ -121,11 +129,19 @@ pub enum EmulatorInput {
     StepForward(StepKind),
     FastForward(bool),
     SetFdsDisk(Option<usize>),
+    SetFdsConfig(nes::FdsConfig),
+    SetPortDevice(Port, PortDeviceKind),
     SaveWram,
     PlayMovie(MovieFile),
+    RecordMovie(std::fs::File, MovieFormat),
+    StopRecordMovie,
+    MovieMode(MovieMode),
     ChannelPlayback(nes::ChannelPlayback),
     RecordWav(std::fs::File),
     StopRecordWav,
+    InsertTape(nes::Tape),
+    TapeControl(nes::TapeControl),
+    SaveTape(std::fs::File),
 }
 
 #[derive(Debug)]
@@ -148,7 +164,7 @@ pub struct DebugRequest {
 
 use crate::{
     app::{CartridgeKind, EmulatorCommands},
-    debug_state::DebugSwapState,
+    debug_state::{DebugSwapState, FrameCounter},
     gfx::GfxBackBuffer,
     widgets::Breakpoints,
 };
@@ -156,20 +172,27 @@ use crate::{
 pub struct Runner {
     machine: Option<Machine>,
     cart_id: Option<CartridgeId>,
+    rom_name: String,
+    disk_sides: usize,
     back_buffer: GfxBackBuffer,
     commands: EmulatorCommands,
     movie_input: Option<MovieFile>,
+    movie_recording: Option<MovieRecording>,
     samples_tx: SamplesSender,
     sample_rate: u32,
     blip: BlipBuf,
     blip_delta: i32,
-    save_states: Vec<Option<(usize, nes::SaveData)>>,
+    save_states: Vec<Option<(usize, nes::SaveData, Option<MovieCheckpoint>)>>,
     save_store: SaveStore,
     frame: usize,
     total_frames: u64,
     debug: DebugSwapState,
     debug_request: DebugRequest,
     input_source: SimpleInput,
+    pads: PadResolver,
+    port_devices: Vec<(Port, PortDeviceKind)>,
+    fds_config: nes::FdsConfig,
+    tape: Option<nes::Tape>,
 }
 
 impl Runner {
@@ -194,6 +217,8 @@ impl Runner {
         Self {
             machine: None,
             cart_id: None,
+            rom_name: String::new(),
+            disk_sides: 0,
             back_buffer,
             commands,
             samples_tx,
@@ -222,7 +247,12 @@ impl Runner {
                 inputs: false,
             },
             movie_input: None,
+            movie_recording: None,
             input_source: SimpleInput::new(),
+            pads: PadResolver::new(),
+            port_devices: Vec::new(),
+            fds_config: nes::FdsConfig::default(),
+            tape: None,
         }
     }
 
@@ -241,18 +271,34 @@ impl Runner {
                             self.input_source.handle_input(input)
                         }
                     }
+                    EmulatorInput::Pad(player, pad) => self.pads.set_pad(player, pad),
                     EmulatorInput::SaveState(slot) => {
                         if let Some(machine) = &self.machine {
                             let data = machine.save_state();
+                            let movie = self
+                                .movie_recording
+                                .as_ref()
+                                .map(|r| r.recorder.checkpoint());
 
-                            self.save_states[slot as usize] = Some((self.frame, data));
+                            self.save_states[slot as usize] = Some((self.frame, data, movie));
                         }
                     }
                     EmulatorInput::RestoreState(slot) => {
                         if let Some(machine) = &mut self.machine {
-                            if let Some((_frame, data)) = self.save_states[slot as usize].as_ref() {
+                            if let Some((_frame, data, movie)) =
+                                self.save_states[slot as usize].as_ref()
+                            {
                                 machine.restore_state(data);
                                 self.frame = machine.frame() as usize;
+
+                                if let Some(recording) = self.movie_recording.as_mut() {
+                                    match movie {
+                                        Some(movie) => recording.recorder.restore(movie),
+                                        None => tracing::warn!(
+                                            "save state has no movie input log to restore"
+                                        ),
+                                    }
+                                }
                             }
                         }
                     }
@@ -261,7 +307,9 @@ impl Runner {
                         if let Some(machine) = &mut self.machine {
                             if let Some((_frame, data)) = self.save_store.pop() {
                                 machine.restore_state(&data);
-                                self.frame = machine.frame() as usize;
+                                let frame = machine.frame() as usize;
+                                self.rewind_movie(frame);
+                                self.frame = frame;
                                 playback = Playback::StepBackward;
                                 self.step(playback, run_until::Frames(1));
                             }
@@ -300,6 +348,7 @@ impl Runner {
                         let mut bios = bios.map(std::io::Cursor::new);
                         match Cartridge::load(&mut rom, wram, bios.as_mut(), file_name) {
                             Ok(cart) => {
+                                let cart = cart.with_fds_config(self.fds_config);
                                 let cart = if game_genie {
                                     cart.with_game_genie()
                                 } else {
@@ -313,14 +362,26 @@ impl Runner {
                                     },
                                     nes::CartridgeInfo::Nsf => CartridgeKind::Cartridge,
                                 };
+                                self.finish_movie_recording();
                                 self.save_store.clear();
                                 self.frame = 0;
                                 self.cart_id = Some(cart_id);
+                                self.rom_name = file_name.clone();
+                                self.disk_sides = match cart_info {
+                                    CartridgeKind::Fds { total_sides, .. } => total_sides,
+                                    CartridgeKind::Cartridge => 0,
+                                };
                                 self.movie_input = None;
-                                let machine = Machine::new(region, cart);
+                                let mut machine = Machine::new(region, cart);
                                 machine.set_debug_interest(
                                     self.debug_request.interests.iter().copied(),
                                 );
+                                for &(port, device) in self.port_devices.iter() {
+                                    machine.set_port_device(port, device);
+                                }
+                                if let Some(tape) = self.tape.clone() {
+                                    machine.insert_tape(tape);
+                                }
                                 self.machine = Some(machine);
                                 self.blip
                                     .set_rates(region.cpu_clock(), self.sample_rate as f64);
@@ -348,6 +409,19 @@ impl Runner {
                                 disk,
                             ))));
                     }
+                    EmulatorInput::SetFdsConfig(config) => self.fds_config = config,
+                    EmulatorInput::SetPortDevice(port, device) => {
+                        self.port_devices.retain(|(p, _)| *p != port);
+                        self.port_devices.push((port, device));
+                        if let Some(machine) = self.machine.as_mut() {
+                            machine.set_port_device(port, device);
+                            if let Some(tape) =
+                                self.tape.clone().filter(|_| port == Port::Expansion)
+                            {
+                                machine.insert_tape(tape);
+                            }
+                        }
+                    }
                     EmulatorInput::SaveWram => {
                         if let Some((wram, cart_id)) = self
                             .machine
@@ -362,6 +436,31 @@ impl Runner {
                         movie.prepare_frame();
                         self.movie_input = Some(movie);
                     }
+                    EmulatorInput::RecordMovie(file, format) => {
+                        self.finish_movie_recording();
+                        if let Some((machine, cart_id)) = self.machine.as_ref().zip(self.cart_id) {
+                            let recorder = MovieRecorder::new(
+                                cart_id,
+                                self.rom_name.clone(),
+                                machine.region(),
+                            )
+                            .with_disk_sides(self.disk_sides);
+                            self.movie_recording = Some(MovieRecording {
+                                recorder,
+                                format,
+                                file,
+                            });
+                            // Movies start from power on
+                            self.movie_input = None;
+                            self.input_source.handle_input(UserInput::Power);
+                        }
+                    }
+                    EmulatorInput::StopRecordMovie => self.finish_movie_recording(),
+                    EmulatorInput::MovieMode(mode) => {
+                        if let Some(recording) = self.movie_recording.as_mut() {
+                            recording.recorder.set_mode(mode);
+                        }
+                    }
                     EmulatorInput::ChannelPlayback(playback) => {
                         if let Some(machine) = self.machine.as_mut() {
                             machine.set_channel_playback(playback);
@@ -377,6 +476,35 @@ impl Runner {
                             tracing::error!("recording wav: {err:?}");
                         }
                     }
+                    EmulatorInput::InsertTape(tape) => {
+                        if let Some(machine) = self.machine.as_mut() {
+                            machine.insert_tape(tape.clone());
+                        }
+                        self.tape = Some(tape);
+                    }
+                    EmulatorInput::TapeControl(control) => {
+                        if let Some(machine) = self.machine.as_mut() {
+                            machine.tape_control(control);
+                        }
+                    }
+                    EmulatorInput::SaveTape(file) => {
+                        // Keep the recorded tape so it survives loading another cartridge
+                        if let Some(tape) = self.machine.as_mut().and_then(|m| m.tape()) {
+                            self.tape = Some(tape.clone());
+                        }
+
+                        if let Some(tape) = self.tape.as_ref() {
+                            let result =
+                                WavWriter::new(file, tape.sample_rate).and_then(|mut w| {
+                                    w.write_samples(&tape.samples)?;
+                                    w.finalize()
+                                });
+
+                            if let Err(err) = result {
+                                tracing::error!("saving tape: {err:?}");
+                            }
+                        }
+                    }
                 }
             }
 
@@ -413,7 +541,9 @@ impl Runner {
                     self.machine.as_mut().zip(self.save_store.pop())
                 {
                     machine.restore_state(&data);
-                    self.frame = machine.frame() as usize;
+                    let frame = machine.frame() as usize;
+                    self.rewind_movie(frame);
+                    self.frame = frame;
                 }
 
                 // Rewinding requires frame sized steps so the full frame of audio can be
@@ -447,23 +577,21 @@ impl Runner {
                 }
             };
 
+            let recorder = self.movie_recording.as_mut().map(|r| &mut r.recorder);
             let run_result = if let Some(movie) = self.movie_input.as_mut() {
-                let res = machine.run_with_breakpoints(
-                    nes::FrameEnd::SetVblank,
-                    until,
-                    break_handler,
-                    movie,
-                );
+                let res = run_input(machine, until, break_handler, movie, recorder);
                 if movie.done() {
                     self.movie_input = None;
                 }
                 res
             } else {
-                machine.run_with_breakpoints(
-                    nes::FrameEnd::SetVblank,
+                self.pads.apply(machine.frame(), &mut self.input_source);
+                run_input(
+                    machine,
                     until,
                     break_handler,
                     &mut self.input_source,
+                    recorder,
                 )
             };
 
@@ -475,6 +603,9 @@ impl Runner {
                         if let Some(movie) = self.movie_input.as_mut() {
                             movie.prepare_frame();
                         }
+                        if let Some(recording) = self.movie_recording.as_mut() {
+                            recording.recorder.end_frame(machine.lag_frame());
+                        }
                         self.frame = frame;
 
                         if playback.save_state() {
@@ -501,6 +632,24 @@ impl Runner {
         }
     }
 
+    // Keeps the movie's input log in step with the machine as it rewinds to `frame`
+    fn rewind_movie(&mut self, frame: usize) {
+        if let Some(recording) = self.movie_recording.as_mut() {
+            recording.recorder.rewind(self.frame.saturating_sub(frame));
+        }
+    }
+
+    fn finish_movie_recording(&mut self) {
+        if let Some(mut recording) = self.movie_recording.take() {
+            if let Err(err) = recording
+                .recorder
+                .write(recording.format, &mut recording.file)
+            {
+                tracing::error!("saving movie: {err:?}");
+            }
+        }
+    }
+
     fn update_audio(&mut self, playback: Playback) {
         if !playback.update_audio() {
             return;
@@ -611,9 +760,15 @@ impl Runner {
                 } else {
                     self.input_source.peek()
                 };
+                let counter = FrameCounter {
+                    frame: machine.frame(),
+                    lag_frame: machine.lag_frame(),
+                    lag_count: machine.lag_count(),
+                };
                 self.debug.inputs.update(|data| {
-                    data[0] = input.0;
-                    data[1] = input.1;
+                    data[0] = input.controllers[0];
+                    data[1] = input.controllers[1];
+                    data.counter = counter;
                 });
             }
 
@@ -622,6 +777,32 @@ impl Runner {
     }
 }
 
+struct MovieRecording {
+    recorder: MovieRecorder,
+    format: MovieFormat,
+    file: std::fs::File,
+}
+
+// Runs the machine from `input`, passing it through the movie recorder while one is active
+fn run_input<U: RunUntil, H: FnMut(&nes::Debug) -> bool, I: InputSource>(
+    machine: &mut Machine,
+    until: U,
+    break_handler: H,
+    input: &mut I,
+    recorder: Option<&mut MovieRecorder>,
+) -> RunResult {
+    if let Some(recorder) = recorder {
+        machine.run_with_breakpoints(
+            nes::FrameEnd::SetVblank,
+            until,
+            break_handler,
+            &mut recorder.record(input),
+        )
+    } else {
+        machine.run_with_breakpoints(nes::FrameEnd::SetVblank, until, break_handler, input)
+    }
+}
+
 struct SaveStoreBuilder {
     divisor: usize,
     generations: Vec<SaveStoreGeneration>,
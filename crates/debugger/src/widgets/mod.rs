@@ -23,7 +23,7 @@ pub use event_viewer::{EventViewer, Interests};
 pub use help::Help;
 pub use memory::MemoryViewer;
 pub use messages::{EguiMessageLayer, MessageStore, Messages};
-pub use movie_settings::{MovieSettings, MovieSettingsState};
+pub use movie_settings::{MovieRecordAction, MovieSettings, MovieSettingsState};
 pub use nametables::NametableViewer;
 pub use palette_viewer::PaletteViewer;
 pub use popup_message::{Message, PopupMessage};
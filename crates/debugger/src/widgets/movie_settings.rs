@@ -3,7 +3,7 @@ use crate::egui;
 use eframe::egui::Widget;
 use egui::Context;
 use serde::{Deserialize, Serialize};
-use ui::movie::SubframeMode as UiSubframeMode;
+use ui::movie::{MovieFormat as UiMovieFormat, MovieMode, SubframeMode as UiSubframeMode};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SubframeMode {
@@ -28,12 +28,42 @@ impl From<SubframeMode> for UiSubframeMode {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MovieFormat {
+    Fm2,
+    Bk2,
+}
+
+impl Default for MovieFormat {
+    fn default() -> Self {
+        Self::Fm2
+    }
+}
+
+impl From<MovieFormat> for UiMovieFormat {
+    fn from(value: MovieFormat) -> Self {
+        match value {
+            MovieFormat::Fm2 => UiMovieFormat::Fm2,
+            MovieFormat::Bk2 => UiMovieFormat::Bk2,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MovieRecordAction {
+    Start,
+    Stop,
+    SetMode(MovieMode),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default = "Default::default")]
 pub struct MovieSettingsState {
     pub show_settings: bool,
     pub frame_offset: i32,
     pub restore_wram: bool,
     pub subframe: SubframeMode,
+    pub record_format: MovieFormat,
 }
 
 impl Default for MovieSettingsState {
@@ -43,6 +73,7 @@ impl Default for MovieSettingsState {
             frame_offset: 0,
             restore_wram: true,
             subframe: SubframeMode::default(),
+            record_format: MovieFormat::default(),
         }
     }
 }
@@ -54,11 +85,18 @@ impl MovieSettings {
         Self {}
     }
 
-    pub fn show(&self, state: &mut MovieSettingsState, ctx: &Context) -> Option<egui::Response> {
+    pub fn show(
+        &self,
+        state: &mut MovieSettingsState,
+        recording: Option<MovieMode>,
+        ctx: &Context,
+    ) -> Option<MovieRecordAction> {
         if !state.show_settings {
             return None;
         }
 
+        let mut action = None;
+
         egui::Window::new("Movie Settings")
             .auto_sized()
             .show(ctx, |ui| {
@@ -79,6 +117,35 @@ impl MovieSettings {
                     });
                 });
 
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Record Format:");
+                    ui.add_enabled_ui(recording.is_none(), |ui| {
+                        ui.radio_value(&mut state.record_format, MovieFormat::Fm2, "FM2");
+                        ui.radio_value(&mut state.record_format, MovieFormat::Bk2, "BK2");
+                    });
+                });
+
+                if let Some(mut mode) = recording {
+                    ui.horizontal(|ui| {
+                        ui.label("Mode:");
+                        ui.radio_value(&mut mode, MovieMode::ReadWrite, "Read-Write");
+                        ui.radio_value(&mut mode, MovieMode::ReadOnly, "Read-Only");
+                    });
+                    if Some(mode) != recording {
+                        action = Some(MovieRecordAction::SetMode(mode));
+                    }
+
+                    if ui.button("Stop Recording").clicked() {
+                        action = Some(MovieRecordAction::Stop);
+                    }
+                } else if ui.button("Record Movie").clicked() {
+                    action = Some(MovieRecordAction::Start);
+                }
+
+                ui.separator();
+
                 ui.horizontal(|ui| {
                     if ui.button("Defaults").clicked() {
                         *state = MovieSettingsState {
@@ -87,14 +154,12 @@ impl MovieSettings {
                         };
                     }
 
-                    let res = ui.button("Ok");
-                    if res.clicked() {
+                    if ui.button("Ok").clicked() {
                         state.show_settings = false;
                     }
-                    res
-                })
-                .inner
-            })
-            .and_then(|res| res.inner)
+                });
+            });
+
+        action
     }
 }
@@ -45,6 +45,8 @@ pub struct NesScreen {
     size: Arc<Size>,
     gfx: Arc<Mutex<Gfx>>,
     popup: PopupMessage,
+    rect: egui::Rect,
+    zapper: nes::Zapper,
 }
 
 impl NesScreen {
@@ -54,7 +56,13 @@ impl NesScreen {
         let size = Arc::new(Size::new(width, height));
         let popup = PopupMessage::new();
 
-        Self { gfx, size, popup }
+        Self {
+            gfx,
+            size,
+            popup,
+            rect: egui::Rect::NOTHING,
+            zapper: nes::Zapper::default(),
+        }
     }
 
     fn paint(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
@@ -69,6 +77,7 @@ impl NesScreen {
         };
 
         let (rect, _res) = ui.allocate_exact_size(size, egui::Sense::focusable_noninteractive());
+        self.rect = rect;
 
         let gfx = self.gfx.clone();
         let size = self.size.clone();
@@ -143,6 +152,11 @@ impl NesScreen {
                 self.focus(ctx);
             }
 
+            self.zapper = nes::Zapper {
+                aim: res.hover_pos().and_then(|pos| self.screen_position(pos)),
+                trigger: res.hovered() && ctx.input(|i| i.pointer.primary_down()),
+            };
+
             ui.memory_mut(|m| m.set_focus_lock_filter(SCREEN_INTERACT.into(), focus_filter));
             res
         });
@@ -150,6 +164,24 @@ impl NesScreen {
         res.inner
     }
 
+    // Maps a pointer position onto the 256x240 nes screen
+    fn screen_position(&self, pos: egui::Pos2) -> Option<(u16, u16)> {
+        let offset = pos - self.rect.left_top();
+        let x = offset.x / self.rect.width() * 256.0;
+        let y = offset.y / self.rect.height() * 240.0;
+
+        if (0.0..256.0).contains(&x) && (0.0..240.0).contains(&y) {
+            Some((x as u16, y as u16))
+        } else {
+            None
+        }
+    }
+
+    /// Light gun state from the pointer hovering over the screen
+    pub fn zapper(&self) -> nes::Zapper {
+        self.zapper
+    }
+
     pub fn set_message(&mut self, message: Message) {
         self.popup.set_message(message);
     }
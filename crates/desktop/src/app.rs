@@ -1,12 +1,12 @@
 use glium::glutin::config::ConfigTemplateBuilder;
 use glium::winit;
-use winit::keyboard::PhysicalKey;
+use winit::keyboard::{KeyCode, PhysicalKey};
 
-use nes::UserInput;
+use nes::{UserInput, Zapper};
 use ui::audio::Audio;
 use ui::filters::Filter;
-use ui::gamepad::{GamepadEvent, GilrsInput};
-use ui::input::InputMap;
+use ui::gamepad::{GamepadEvent, GamepadPlayers, GilrsInput, gilrs::GamepadId};
+use ui::input::{InputMap, PadInput};
 
 use super::gfx::{Gfx, GfxBackBuffer, GliumContext};
 
@@ -21,12 +21,15 @@ impl From<GamepadEvent> for UserEvent {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum EmulatorInput {
     Nes(UserInput),
+    /// Controller for players 1-4 indexed from 0, with turbo and macros still to be applied
+    Pad(usize, PadInput),
     SaveState(u8),
     RestoreState(u8),
     Rewind,
+    Tape(nes::TapeControl),
 }
 
 impl From<UserInput> for EmulatorInput {
@@ -42,9 +45,16 @@ pub struct App<F, A> {
     window: winit::window::Window,
     event_loop: Option<winit::event_loop::EventLoop<UserEvent>>,
     input: InputMap,
+    pads: [InputMap; 3],
+    gamepad_players: GamepadPlayers,
     input_tx: Option<std::sync::mpsc::Sender<EmulatorInput>>,
     back_buffer: GfxBackBuffer,
     pause: bool,
+    zapper: Zapper,
+    family_keys: InputMap,
+    family_keyboard: bool,
+    keyboard_capture: bool,
+    microphone: Option<ui::audio::CpalMicrophone>,
 }
 
 impl<F: Filter<GliumContext>, A: Audio> App<F, A> {
@@ -66,7 +76,12 @@ impl<F: Filter<GliumContext>, A: Audio> App<F, A> {
 
         let proxy = event_loop.create_proxy();
         let back_buffer = GfxBackBuffer::new(proxy);
-        let gfx = Gfx::new(display, back_buffer.clone(), filter);
+        let gfx = Gfx::new(
+            display,
+            back_buffer.clone(),
+            filter,
+            ui::overlay::OverlaySettings::default(),
+        );
 
         event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
         window.set_cursor_visible(false);
@@ -80,12 +95,62 @@ impl<F: Filter<GliumContext>, A: Audio> App<F, A> {
             back_buffer,
             event_loop: Some(event_loop),
             input: InputMap::new(),
+            pads: std::array::from_fn(|_| InputMap::new()),
+            gamepad_players: GamepadPlayers::new(),
             input_tx: None,
             gamepad: Some(gamepad),
             pause: false,
+            zapper: Zapper::default(),
+            family_keys: InputMap::new(),
+            family_keyboard: false,
+            keyboard_capture: false,
+            microphone: None,
         }
     }
 
+    /// Shows a crosshair cursor for aiming a light gun
+    pub fn show_crosshair(&self) {
+        self.window.set_cursor(winit::window::CursorIcon::Crosshair);
+        self.window.set_cursor_visible(true);
+    }
+
+    /// Route the host keyboard to the Family BASIC keyboard, Scroll Lock switches back to
+    /// the regular key bindings
+    pub fn capture_family_keyboard(&mut self) {
+        self.family_keyboard = true;
+        self.keyboard_capture = true;
+    }
+
+    pub fn listen_microphone(&mut self, threshold: f32) {
+        match ui::audio::CpalMicrophone::new() {
+            Ok(microphone) => {
+                self.input.set_microphone_threshold(threshold);
+                self.microphone = Some(microphone);
+            }
+            Err(err) => tracing::warn!("unable to open microphone: {err}"),
+        }
+    }
+
+    pub fn set_power_pad_settings(&mut self, settings: ui::input::PowerPadSettings) {
+        self.input.set_power_pad_settings(settings);
+    }
+
+    pub fn set_turbo_settings(&mut self, settings: ui::input::TurboSettings) {
+        self.input.set_turbo_settings(settings);
+    }
+
+    pub fn set_macros(&mut self, macros: Vec<ui::input::InputMacro>) {
+        self.input.set_macros(macros);
+    }
+
+    pub fn set_overlay_settings(&mut self, settings: ui::overlay::OverlaySettings) {
+        self.gfx.set_overlay_settings(settings);
+    }
+
+    pub fn set_paddle_settings(&mut self, settings: ui::input::PaddleSettings) {
+        self.input.set_paddle_settings(settings);
+    }
+
     pub fn back_buffer(&self) -> GfxBackBuffer {
         self.back_buffer.clone()
     }
@@ -99,8 +164,6 @@ impl<F: Filter<GliumContext>, A: Audio> App<F, A> {
 
     fn send_inputs(&self) {
         if let Some(tx) = self.input_tx.as_ref() {
-            let p1 = self.input.controller();
-
             if self.input.reset() {
                 let _ = tx.send(UserInput::Reset.into());
             }
@@ -121,7 +184,42 @@ impl<F: Filter<GliumContext>, A: Audio> App<F, A> {
                 let _ = tx.send(EmulatorInput::Rewind);
             }
 
-            let _ = tx.send(UserInput::PlayerOne(p1).into());
+            let _ = tx.send(EmulatorInput::Pad(0, self.input.pad()));
+            for (idx, pad) in self.pads.iter().enumerate() {
+                let _ = tx.send(EmulatorInput::Pad(idx + 1, pad.pad()));
+            }
+            let _ = tx.send(UserInput::Zapper(self.zapper).into());
+            let _ = tx.send(UserInput::Vaus(self.input.vaus()).into());
+            let _ = tx.send(UserInput::PowerPad(self.input.power_pad()).into());
+            let _ = tx.send(UserInput::Microphone(self.input.microphone()).into());
+            let _ = tx.send(UserInput::Mouse(self.input.mouse()).into());
+            if self.family_keyboard {
+                let keyboard = self.family_keys.family_keyboard();
+                let _ = tx.send(UserInput::Keyboard(keyboard).into());
+            }
+        }
+    }
+
+    // Keys outside of the Family BASIC keyboard operate the Data Recorder
+    fn tape_control(&self, key: KeyCode) {
+        let control = match key {
+            KeyCode::F9 => nes::TapeControl::Play,
+            KeyCode::F10 => nes::TapeControl::Record,
+            KeyCode::F11 => nes::TapeControl::Stop,
+            KeyCode::F12 => nes::TapeControl::Rewind,
+            _ => return,
+        };
+
+        if let Some(tx) = self.input_tx.as_ref() {
+            let _ = tx.send(EmulatorInput::Tape(control));
+        }
+    }
+
+    // Keyboard and the first gamepad control player one, later gamepads players 2-4
+    fn gamepad_input(&mut self, gamepad_id: GamepadId) -> Option<&mut InputMap> {
+        match self.gamepad_players.player(gamepad_id)? {
+            0 => Some(&mut self.input),
+            player => self.pads.get_mut(player - 1),
         }
     }
 
@@ -155,6 +253,20 @@ impl<F: Filter<GliumContext>, A: Audio> winit::application::ApplicationHandler<U
 {
     fn resumed(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {}
 
+    fn device_event(
+        &mut self,
+        _event_loop: &winit::event_loop::ActiveEventLoop,
+        _device_id: winit::event::DeviceId,
+        event: winit::event::DeviceEvent,
+    ) {
+        if let winit::event::DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            self.input.mouse_motion(dx, dy);
+            if let Some(tx) = self.input_tx.as_ref() {
+                let _ = tx.send(UserInput::Mouse(self.input.mouse()).into());
+            }
+        }
+    }
+
     fn window_event(
         &mut self,
         event_loop: &winit::event_loop::ActiveEventLoop,
@@ -175,7 +287,23 @@ impl<F: Filter<GliumContext>, A: Audio> winit::application::ApplicationHandler<U
                 is_synthetic: _,
             } => {
                 if let PhysicalKey::Code(key) = event.physical_key {
-                    if event.state.is_pressed() {
+                    if self.family_keyboard
+                        && key == KeyCode::ScrollLock
+                        && event.state.is_pressed()
+                        && !event.repeat
+                    {
+                        self.keyboard_capture = !self.keyboard_capture;
+                        self.family_keys = InputMap::new();
+                    } else if self.keyboard_capture {
+                        if event.state.is_pressed() {
+                            self.family_keys.press(key);
+                            if !event.repeat {
+                                self.tape_control(key);
+                            }
+                        } else {
+                            self.family_keys.release(key);
+                        }
+                    } else if event.state.is_pressed() {
                         self.input.press(key);
                     } else {
                         self.input.release(key);
@@ -185,12 +313,35 @@ impl<F: Filter<GliumContext>, A: Audio> winit::application::ApplicationHandler<U
                         self.pause = !self.pause;
                         if self.pause {
                             self.audio.pause();
+                            self.gfx.message("Paused");
                         } else {
                             self.audio.play();
+                            self.gfx.message("Resumed");
                         }
+                        self.window.request_redraw();
                     }
                 }
             }
+            winit::event::WindowEvent::CursorMoved { position, .. } => {
+                self.zapper.aim = self.gfx.screen_position(position.into());
+                if let Some((x, _)) = self.zapper.aim {
+                    self.input.mouse_position(x as f32 / 256.0);
+                }
+            }
+            winit::event::WindowEvent::CursorLeft { .. } => {
+                self.zapper.aim = None;
+            }
+            winit::event::WindowEvent::MouseInput { state, button, .. } => {
+                if state.is_pressed() {
+                    self.input.press(button);
+                } else {
+                    self.input.release(button);
+                }
+
+                if button == winit::event::MouseButton::Left {
+                    self.zapper.trigger = state.is_pressed();
+                }
+            }
             winit::event::WindowEvent::ScaleFactorChanged {
                 scale_factor: _,
                 inner_size_writer: _,
@@ -200,6 +351,10 @@ impl<F: Filter<GliumContext>, A: Audio> winit::application::ApplicationHandler<U
             winit::event::WindowEvent::RedrawRequested => {
                 if self.window.is_visible() != Some(false) {
                     self.gfx.render();
+                    // Keep presenting while paused so messages still expire
+                    if self.gfx.showing_messages() {
+                        self.window.request_redraw();
+                    }
                 }
             }
             _ => (),
@@ -212,21 +367,47 @@ impl<F: Filter<GliumContext>, A: Audio> winit::application::ApplicationHandler<U
             UserEvent::Frame => {
                 self.gfx.swap();
                 self.window.request_redraw();
+                if let Some(microphone) = self.microphone.as_ref() {
+                    self.input.microphone_level(microphone.take_level());
+                    self.send_inputs();
+                }
             }
             UserEvent::Gamepad(ev) => match ev {
-                GamepadEvent::Button { state, button, .. } => {
-                    if state.is_pressed() {
-                        self.input.press(button);
-                    } else {
-                        self.input.release(button);
+                GamepadEvent::Button {
+                    gamepad_id,
+                    state,
+                    button,
+                } => {
+                    if let Some(input) = self.gamepad_input(gamepad_id) {
+                        if state.is_pressed() {
+                            input.press(button);
+                        } else {
+                            input.release(button);
+                        }
                     }
                     self.send_inputs();
                 }
-                GamepadEvent::Axis { axis, value, .. } => {
-                    self.input.axis(axis, value);
+                GamepadEvent::Axis {
+                    gamepad_id,
+                    axis,
+                    value,
+                } => {
+                    if let Some(input) = self.gamepad_input(gamepad_id) {
+                        input.axis(axis, value);
+                    }
                     self.send_inputs();
                 }
-                _ => (),
+                GamepadEvent::Connected { gamepad_id } => {
+                    self.gamepad_players.player(gamepad_id);
+                }
+                GamepadEvent::Disconnected { gamepad_id } => {
+                    if let Some(player) = self.gamepad_players.disconnect(gamepad_id) {
+                        if player > 0 {
+                            self.pads[player - 1] = InputMap::new();
+                            self.send_inputs();
+                        }
+                    }
+                }
             },
         }
     }
@@ -6,8 +6,10 @@ use glium::{Display, Program, Surface, VertexBuffer, implement_vertex};
 
 use std::borrow::Cow;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use ui::filters::{Filter, FilterContext, FilterUniforms, NesNtscSetup, TextureFormat};
+use ui::overlay::{FpsCounter, FrameInfo, Overlay, OverlaySettings};
 
 use crate::app::UserEvent;
 use tracy_ext::TracyExt;
@@ -25,15 +27,24 @@ pub struct Gfx<T> {
     display: GliumContext,
     indicies: glium::index::NoIndices,
     program: Program,
+    overlay_program: Program,
     vertex_buffer: VertexBuffer<Vertex>,
     size: (f64, f64),
     frame: Vec<u16>,
     tracy: Tracy,
     back_buffer: GfxBackBuffer,
+    overlay: Overlay,
+    fps: FpsCounter,
+    start: Instant,
 }
 
 impl<T: Filter<GliumContext>> Gfx<T> {
-    pub fn new(display: Display<WindowSurface>, back_buffer: GfxBackBuffer, filter: T) -> Self {
+    pub fn new(
+        display: Display<WindowSurface>,
+        back_buffer: GfxBackBuffer,
+        filter: T,
+        overlay: OverlaySettings,
+    ) -> Self {
         let ver = display.get_opengl_version_string();
         let glsl = display.get_supported_glsl_version();
         let vendor = display.get_opengl_vendor_string();
@@ -68,20 +79,17 @@ impl<T: Filter<GliumContext>> Gfx<T> {
         let vertex_buffer = VertexBuffer::new(&display, &shape).unwrap();
         let indicies = glium::index::NoIndices(glium::index::PrimitiveType::TriangleFan);
 
-        let program = Program::from_source(
+        let program = compile_program(
             &display,
             &*filter.vertex_shader(),
             &*filter.fragment_shader(),
-            None,
         );
 
-        let program = match program {
-            Ok(p) => p,
-            Err(glium::CompilationError(msg, kind)) => {
-                panic!("Shader Compilation Errror '{kind:?}':\n{msg}")
-            }
-            Err(e) => panic!("{e:?}"),
-        };
+        let overlay_shaders = ui::filters::Preprocessor::new(ui::filters::OVERLAY_SHADER)
+            .process()
+            .unwrap();
+        let overlay_program =
+            compile_program(&display, &overlay_shaders.vertex, &overlay_shaders.fragment);
 
         let size = filter.dimensions();
         let size = (size.0 as f64, size.1 as f64);
@@ -93,11 +101,15 @@ impl<T: Filter<GliumContext>> Gfx<T> {
             display: GliumContext(display),
             indicies,
             program,
+            overlay_program,
             vertex_buffer,
             size,
             back_buffer,
             frame: vec![15; 240 * 256],
             tracy,
+            overlay: Overlay::new(overlay),
+            fps: FpsCounter::new(),
+            start: Instant::now(),
         }
     }
 
@@ -108,10 +120,54 @@ impl<T: Filter<GliumContext>> Gfx<T> {
     }
 
     pub fn swap(&mut self) {
-        self.back_buffer.swap(&mut self.frame);
+        let (info, messages) = self.back_buffer.swap(&mut self.frame);
+        for message in messages {
+            self.overlay.message(message);
+        }
+        self.overlay.frame(info);
+        self.fps.frame(self.start.elapsed().as_secs_f64());
+        self.overlay.set_fps(self.fps.fps());
+    }
+
+    pub fn set_overlay_settings(&mut self, settings: OverlaySettings) {
+        self.overlay.set_settings(settings);
+    }
+
+    /// Shows a message on screen for a few seconds
+    pub fn message<S: Into<String>>(&mut self, message: S) {
+        self.overlay.message(message);
+    }
+
+    /// Maps a window position onto the 256x240 nes screen, `None` if it falls outside of
+    /// the rendered image
+    pub fn screen_position(&self, position: (f64, f64)) -> Option<(u16, u16)> {
+        let (filter_width, filter_height) = self.filter.dimensions();
+        let filter_ratio = filter_width as f64 / filter_height as f64;
+        let (window_width, window_height) = self.size;
+
+        let (width, height) = if filter_ratio > window_width / window_height {
+            (window_width, window_width / filter_ratio)
+        } else {
+            (window_height * filter_ratio, window_height)
+        };
+
+        let x = (position.0 - (window_width - width) / 2.0) / width * 256.0;
+        let y = (position.1 - (window_height - height) / 2.0) / height * 240.0;
+
+        if (0.0..256.0).contains(&x) && (0.0..240.0).contains(&y) {
+            Some((x as u16, y as u16))
+        } else {
+            None
+        }
+    }
+
+    /// Whether messages are on screen, rendering must continue until they expire
+    pub fn showing_messages(&self) -> bool {
+        self.overlay.showing_messages()
     }
 
     pub fn render(&mut self) {
+        self.overlay.update(self.start.elapsed().as_secs_f64());
         let mut target = self.display.draw();
 
         let (filter_width, filter_height) = self.filter.dimensions();
@@ -167,21 +223,63 @@ impl<T: Filter<GliumContext>> Gfx<T> {
                 &params,
             )
             .unwrap();
+
+        if let Some(overlay_uniforms) = self.overlay.uniforms(&self.display) {
+            let params = glium::DrawParameters {
+                blend: glium::Blend::alpha_blending(),
+                ..params
+            };
+
+            target
+                .draw(
+                    &self.vertex_buffer,
+                    &self.indicies,
+                    &self.overlay_program,
+                    &overlay_uniforms,
+                    &params,
+                )
+                .unwrap();
+        }
+
         target.finish().unwrap();
-        self.tracy.frame(&self.frame);
+
+        let overlay = self.overlay.settings().capture.then_some(&self.overlay);
+        self.tracy.frame(&self.frame, overlay);
+    }
+}
+
+fn compile_program(display: &Display<WindowSurface>, vertex: &str, fragment: &str) -> Program {
+    match Program::from_source(display, vertex, fragment, None) {
+        Ok(p) => p,
+        Err(glium::CompilationError(msg, kind)) => {
+            panic!("Shader Compilation Errror '{kind:?}':\n{msg}")
+        }
+        Err(e) => panic!("{e:?}"),
     }
 }
 
 #[derive(Clone)]
 pub struct GfxBackBuffer {
     frame: Arc<Mutex<Vec<u16>>>,
+    overlay: Arc<Mutex<(FrameInfo, Vec<String>)>>,
     tx: EventLoopProxy<UserEvent>,
 }
 
 impl GfxBackBuffer {
     pub fn new(tx: EventLoopProxy<UserEvent>) -> Self {
         let frame = Arc::new(Mutex::new(vec![0; 256 * 240]));
-        Self { frame, tx }
+        let overlay = Arc::new(Mutex::new((FrameInfo::default(), Vec::new())));
+        Self { frame, overlay, tx }
+    }
+
+    /// Counters and controllers shown by the overlay with the next frame
+    pub fn set_info(&self, info: FrameInfo) {
+        self.overlay.lock().unwrap().0 = info;
+    }
+
+    /// Queues a message to be shown by the overlay with the next frame
+    pub fn message<S: Into<String>>(&self, message: S) {
+        self.overlay.lock().unwrap().1.push(message.into());
     }
 
     pub fn update<F: FnOnce(&mut [u16])>(&mut self, func: F) {
@@ -192,9 +290,14 @@ impl GfxBackBuffer {
         let _ = self.tx.send_event(UserEvent::Frame);
     }
 
-    pub fn swap(&self, other: &mut Vec<u16>) {
-        let mut frame = self.frame.lock().unwrap();
-        std::mem::swap(&mut *frame, other);
+    pub fn swap(&self, other: &mut Vec<u16>) -> (FrameInfo, Vec<String>) {
+        {
+            let mut frame = self.frame.lock().unwrap();
+            std::mem::swap(&mut *frame, other);
+        }
+
+        let mut overlay = self.overlay.lock().unwrap();
+        (overlay.0, std::mem::take(&mut overlay.1))
     }
 }
 
@@ -215,13 +318,22 @@ impl Tracy {
         }
     }
 
-    fn frame(&mut self, screen: &[u16]) {
+    fn frame(&mut self, screen: &[u16], overlay: Option<&Overlay>) {
         if let Some(client) = tracy_client::Client::running() {
             let pixel = |x: usize, y: usize| {
                 let s = screen[y * 256 + x] as usize;
-                let r = self.palette[s * 3 + 2] as u32;
-                let g = self.palette[s * 3 + 1] as u32;
-                let b = self.palette[s * 3 + 0] as u32;
+                let mut rgb = [
+                    self.palette[s * 3 + 0],
+                    self.palette[s * 3 + 1],
+                    self.palette[s * 3 + 2],
+                ];
+                if let Some(overlay) = overlay {
+                    rgb = overlay.blend(x, y, rgb);
+                }
+
+                let r = rgb[2] as u32;
+                let g = rgb[1] as u32;
+                let b = rgb[0] as u32;
 
                 [r, g, b]
             };
@@ -1,13 +1,19 @@
 use clap::{Parser, Subcommand, ValueEnum};
 use nes::{Cartridge, Machine, SimpleInput};
-use runner::Runner;
+use resampler::Resampler;
+use runner::{FrontendSettings, Peripherals, RunAheadSettings, Runner};
 use ui::audio::{Audio, AudioDevices, Null, PipewireAudio, SamplesSender};
 use ui::filters::NesNtscSetup;
 
-use std::{fs::File, path::PathBuf};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
 
 pub mod app;
 pub mod gfx;
+mod nsf_render;
+mod resampler;
 mod runner;
 
 use app::App;
@@ -18,24 +24,158 @@ fn main() {
     init_tracing();
 
     match args.mode {
-        Mode::Run { file } => run(file, args.region.into()),
+        Mode::Run {
+            file,
+            esp_dir,
+            esp_server,
+            fds_auto_swap,
+            fds_fast_load,
+            epsm,
+            port_two,
+            expansion,
+            paddle_sensitivity,
+            paddle_min,
+            paddle_max,
+            tape,
+            power_pad_side,
+            power_pad_keys,
+            microphone_threshold,
+            turbo_rate,
+            turbo_keys,
+            macros,
+            overlay,
+            hide_messages,
+            overlay_in_capture,
+            run_ahead,
+            run_ahead_second_instance,
+            netplay_peer,
+            netplay_bind,
+            netplay_player,
+            netplay_delay,
+        } => {
+            let esp = nes::EspConfig {
+                fs_root: esp_dir,
+                server: esp_server,
+            };
+            let fds = nes::FdsConfig {
+                auto_swap: fds_auto_swap,
+                fast_load: fds_fast_load,
+            };
+            let mut ports = port_two.ports();
+            ports.push((nes::Port::Expansion, expansion.into()));
+
+            let default_paddle = ui::input::PaddleSettings::default();
+            let paddle = ui::input::PaddleSettings {
+                sensitivity: paddle_sensitivity.unwrap_or(default_paddle.sensitivity),
+                min: paddle_min.unwrap_or(default_paddle.min),
+                max: paddle_max.unwrap_or(default_paddle.max),
+            };
+            let mut power_pad = ui::input::PowerPadSettings {
+                side: power_pad_side.into(),
+                ..Default::default()
+            };
+            if let Some(keys) = power_pad_keys {
+                match ui::input::PowerPadSettings::parse_keys(&keys) {
+                    Some(keys) => power_pad.keys = keys,
+                    None => tracing::warn!("Power Pad keys must be 12 letters, digits or symbols"),
+                }
+            }
+            let mut turbo = ui::input::TurboSettings::default();
+            if let Some(rate) = turbo_rate {
+                turbo.rate = rate.max(1);
+            }
+            if let Some(keys) = turbo_keys {
+                match ui::input::TurboSettings::parse_keys(&keys) {
+                    Some([a, b]) => {
+                        turbo.a = Some(a);
+                        turbo.b = Some(b);
+                    }
+                    None => tracing::warn!("Turbo keys must be 2 letters, digits or symbols"),
+                }
+            }
+            let macros = macros
+                .iter()
+                .filter_map(|m| {
+                    let parsed = ui::input::InputMacro::parse(m);
+                    if parsed.is_none() {
+                        tracing::warn!("Invalid macro: {m}");
+                    }
+                    parsed
+                })
+                .collect();
+            let overlay = ui::overlay::OverlaySettings {
+                inputs: overlay.contains(&OverlayElement::Inputs),
+                counters: overlay.contains(&OverlayElement::Counters),
+                fps: overlay.contains(&OverlayElement::Fps),
+                messages: !hide_messages,
+                capture: overlay_in_capture,
+            };
+            let netplay = netplay_peer.and_then(|peer| {
+                let settings = ui::netplay::NetplaySettings {
+                    local_player: netplay_player.clamp(1, 2) - 1,
+                    input_delay: netplay_delay,
+                    ..Default::default()
+                };
+                match ui::netplay::UdpTransport::bind(netplay_bind, peer) {
+                    Ok(transport) => Some(ui::netplay::Session::new(transport, settings)),
+                    Err(err) => {
+                        tracing::error!("Unable to start netplay: {err:?}");
+                        None
+                    }
+                }
+            });
+            let cart = load_cart(&file).with_esp(esp).with_fds_config(fds);
+            let second_instance = (run_ahead > 0 && run_ahead_second_instance)
+                .then(|| load_cart(&file).with_fds_config(fds));
+            let settings = FrontendSettings {
+                peripherals: Peripherals { epsm, ports, tape },
+                paddle,
+                power_pad,
+                microphone_threshold,
+                turbo,
+                macros,
+                overlay,
+                run_ahead: RunAheadSettings {
+                    frames: run_ahead,
+                    second_instance,
+                },
+                netplay,
+            };
+            run(cart, args.region.into(), settings)
+        }
         Mode::Bench { frames, file } => bench(file, args.region.into(), frames),
         Mode::Mdf {
             out_file,
             sample_rate,
         } => mdf(out_file, sample_rate),
+        Mode::Nsf {
+            file,
+            out_dir,
+            sample_rate,
+            stems,
+            max_length,
+        } => nsf_render::render(
+            file,
+            out_dir,
+            args.region.into(),
+            sample_rate,
+            stems,
+            max_length,
+        ),
     }
 }
 
-fn run(path: PathBuf, region: nes::Region) {
-    let mut file = File::open(&path).unwrap();
+fn load_cart(path: &Path) -> Cartridge {
+    let mut file = File::open(path).unwrap();
     let file_name = path
         .file_name()
         .and_then(|s| s.to_str())
         .map(|s| s.to_string())
         .unwrap_or_default();
-    let cart = Cartridge::load(&mut file, None, None, file_name).unwrap();
+    Cartridge::load(&mut file, None, None, &file_name).unwrap()
+}
 
+fn run(cart: Cartridge, region: nes::Region, mut settings: FrontendSettings) {
     let mut setup = NesNtscSetup::composite();
     setup.merge_fields = false;
     let filter = ui::filters::CrtFilter::new(&setup);
@@ -45,13 +185,36 @@ fn run(path: PathBuf, region: nes::Region) {
     let (audio, samples_tx) = init_audio();
     let sample_rate = audio.sample_rate();
     let mut app = App::new(filter, audio);
+    let has_device = |device| settings.peripherals.ports.iter().any(|&(_, d)| d == device);
+    if has_device(nes::PortDeviceKind::Zapper) {
+        app.show_crosshair();
+    }
+    if has_device(nes::PortDeviceKind::FamilyKeyboard) {
+        app.capture_family_keyboard();
+    }
+    app.set_paddle_settings(settings.paddle);
+    app.set_power_pad_settings(settings.power_pad);
+    app.set_turbo_settings(settings.turbo);
+    app.set_macros(std::mem::take(&mut settings.macros));
+    app.set_overlay_settings(settings.overlay);
+    if let Some(threshold) = settings.microphone_threshold {
+        app.listen_microphone(threshold);
+    }
     let input = app.nes_io();
     let back_buffer = app.back_buffer();
 
     std::thread::Builder::new()
         .name("machine".into())
         .spawn(move || {
-            let runner = Runner::new(cart, region, input, back_buffer, samples_tx, sample_rate);
+            let runner = Runner::new(
+                cart,
+                region,
+                input,
+                back_buffer,
+                samples_tx,
+                sample_rate,
+                settings,
+            );
 
             runner.run()
         })
@@ -90,17 +253,8 @@ fn mdf(out_file: PathBuf, sample_rate: Option<u32>) {
     let cart = Cartridge::load(&mut rom, None, None, "mdfourier.nes").unwrap();
     let mut machine = Machine::new(region, cart);
 
-    let mut blip = if let Some(sample_rate) = sample_rate {
-        let mut blip = blip_buf::BlipBuf::new(sample_rate);
-        blip.set_rates(region.cpu_clock(), sample_rate as f64);
-        Some(blip)
-    } else {
-        None
-    };
-    let mut blip_delta = 0;
-
+    let mut resampler = Resampler::new(region.cpu_clock(), sample_rate);
     let mut recording = false;
-    let mut sample_buf = vec![0; region.frame_ticks() as usize * 4];
 
     let start_frame = 65;
     let end_frame = start_frame + (111 * 60);
@@ -125,21 +279,7 @@ fn mdf(out_file: PathBuf, sample_rate: Option<u32>) {
 
         machine.run(&mut input_source);
 
-        let samples = if let Some(blip) = blip.as_mut() {
-            let samples = machine.take_samples();
-            let count = samples.len();
-            for (i, v) in samples.enumerate() {
-                blip.add_delta(i as u32, v as i32 - blip_delta);
-                blip_delta = v as i32;
-            }
-            blip.end_frame(count as u32);
-            let n = blip.read_samples(&mut sample_buf, false);
-            &sample_buf[0..n]
-        } else {
-            sample_buf.clear();
-            sample_buf.extend(machine.take_samples());
-            &sample_buf
-        };
+        let samples = resampler.resample(machine.take_samples());
 
         if recording {
             wav_writer.write_samples(samples).unwrap();
@@ -206,6 +346,96 @@ impl From<Region> for nes::Region {
     }
 }
 
+#[derive(Debug, Copy, Clone, ValueEnum, Default)]
+pub enum PortDevice {
+    #[default]
+    Controller,
+    /// Light gun aimed with the mouse, left click pulls the trigger
+    Zapper,
+    /// Four player adapter, takes over both controller ports
+    FourScore,
+    /// Arkanoid paddle moved by the right stick or mouse, fired with left click or A
+    Vaus,
+    /// Power Pad floor mat, stepped on with the block of keys set by --power-pad-keys
+    PowerPad,
+    /// Super NES mouse moved by the host mouse
+    SnesMouse,
+    /// Subor mouse moved by the host mouse
+    SuborMouse,
+}
+
+impl PortDevice {
+    fn ports(self) -> Vec<(nes::Port, nes::PortDeviceKind)> {
+        match self {
+            PortDevice::Controller => vec![(nes::Port::Two, nes::PortDeviceKind::Controller)],
+            PortDevice::Zapper => vec![(nes::Port::Two, nes::PortDeviceKind::Zapper)],
+            PortDevice::FourScore => vec![
+                (nes::Port::One, nes::PortDeviceKind::FourScore),
+                (nes::Port::Two, nes::PortDeviceKind::FourScore),
+            ],
+            PortDevice::Vaus => vec![(nes::Port::Two, nes::PortDeviceKind::Vaus)],
+            PortDevice::PowerPad => vec![(nes::Port::Two, nes::PortDeviceKind::PowerPad)],
+            PortDevice::SnesMouse => vec![(nes::Port::Two, nes::PortDeviceKind::SnesMouse)],
+            PortDevice::SuborMouse => vec![(nes::Port::Two, nes::PortDeviceKind::SuborMouse)],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum, Default)]
+pub enum ExpansionDevice {
+    #[default]
+    None,
+    /// Famicom four player adapter
+    Hori,
+    /// Famicom version of the Arkanoid paddle
+    Vaus,
+    /// Family BASIC keyboard and Data Recorder, Scroll Lock toggles typing into it and
+    /// F9-F12 play, record, stop and rewind the tape
+    FamilyKeyboard,
+    /// Famicom version of the Power Pad
+    FamilyTrainer,
+}
+
+impl From<ExpansionDevice> for nes::PortDeviceKind {
+    fn from(value: ExpansionDevice) -> Self {
+        match value {
+            ExpansionDevice::None => nes::PortDeviceKind::Unplugged,
+            ExpansionDevice::Hori => nes::PortDeviceKind::Hori,
+            ExpansionDevice::Vaus => nes::PortDeviceKind::Vaus,
+            ExpansionDevice::FamilyKeyboard => nes::PortDeviceKind::FamilyKeyboard,
+            ExpansionDevice::FamilyTrainer => nes::PortDeviceKind::FamilyTrainer,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum, Default)]
+pub enum PowerPadSide {
+    /// Eight unnumbered buttons
+    A,
+    /// Twelve buttons numbered 1-12
+    #[default]
+    B,
+}
+
+impl From<PowerPadSide> for nes::PowerPadSide {
+    fn from(value: PowerPadSide) -> Self {
+        match value {
+            PowerPadSide::A => nes::PowerPadSide::A,
+            PowerPadSide::B => nes::PowerPadSide::B,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OverlayElement {
+    /// Controllers of players one and two
+    Inputs,
+    /// Frame and lag counters
+    Counters,
+    /// Frames per second
+    Fps,
+}
+
 #[derive(Subcommand)]
 enum Mode {
     /// Run for specified number of frames with ui
@@ -220,6 +450,88 @@ enum Mode {
     Run {
         /// Provides a rom file to emulate
         file: PathBuf,
+        /// Directory used as the file system of carts with an ESP Wi-Fi chip
+        #[arg(long)]
+        esp_dir: Option<PathBuf>,
+        /// Local TCP/UDP endpoint that ESP server connections are routed to
+        #[arg(long)]
+        esp_server: Option<std::net::SocketAddr>,
+        /// Insert the disk side a Famicom Disk System game asks for without waiting for a swap
+        #[arg(long)]
+        fds_auto_swap: bool,
+        /// Skip the Famicom Disk System drive delays while loading
+        #[arg(long)]
+        fds_fast_load: bool,
+        /// Attach the Expansion Port Sound Module even if the rom header does not request it
+        #[arg(long)]
+        epsm: bool,
+        /// Device plugged into the second controller port
+        #[arg(long, value_enum, default_value_t)]
+        port_two: PortDevice,
+        /// Device plugged into the Famicom expansion port
+        #[arg(long, value_enum, default_value_t)]
+        expansion: ExpansionDevice,
+        /// Multiplier for how far the paddle moves with the mouse or right stick
+        #[arg(long)]
+        paddle_sensitivity: Option<f32>,
+        /// Paddle position reported at the far left, from 0 to 511
+        #[arg(long)]
+        paddle_min: Option<u16>,
+        /// Paddle position reported at the far right, from 0 to 511
+        #[arg(long)]
+        paddle_max: Option<u16>,
+        /// .wav file used as the Data Recorder tape, recordings are saved back to it
+        #[arg(long)]
+        tape: Option<PathBuf>,
+        /// Side of the Power Pad facing up
+        #[arg(long, value_enum, default_value_t)]
+        power_pad_side: PowerPadSide,
+        /// Keys covering the Power Pad, typed as 3 rows of 4 from the top left of the mat
+        #[arg(long)]
+        power_pad_keys: Option<String>,
+        /// Use the default audio input as the Famicom microphone, heard by the game once louder
+        /// than this level from 0.0 to 1.0. C also holds the microphone open
+        #[arg(long)]
+        microphone_threshold: Option<f32>,
+        /// Frames the turbo buttons spend pressed and then released
+        #[arg(long)]
+        turbo_rate: Option<u32>,
+        /// Keys for turbo A and turbo B, typed as 2 characters, turbo is unbound without them
+        #[arg(long)]
+        turbo_keys: Option<String>,
+        /// Macro played when its key is pressed, as name=key:sequence. The sequence lists
+        /// buttons joined by + for each frame, a *count to hold them longer and - to wait,
+        /// such as "fireball=q:down*2,down+right*2,right+b"
+        #[arg(long = "macro")]
+        macros: Vec<String>,
+        /// Information drawn over the screen, as a comma separated list
+        #[arg(long, value_enum, value_delimiter = ',')]
+        overlay: Vec<OverlayElement>,
+        /// Hide messages such as save states being saved and loaded
+        #[arg(long)]
+        hide_messages: bool,
+        /// Also draw the overlay into frames captured by the Tracy profiler
+        #[arg(long)]
+        overlay_in_capture: bool,
+        /// Frames to run ahead of the presented screen, hiding input latency of the game
+        #[arg(long, default_value_t = 0)]
+        run_ahead: u32,
+        /// Run ahead on a second copy of the console, avoiding audio glitches at the cost of
+        /// extra memory
+        #[arg(long)]
+        run_ahead_second_instance: bool,
+        /// Address of the other player, starts two player netplay over UDP
+        #[arg(long)]
+        netplay_peer: Option<std::net::SocketAddr>,
+        /// Local address netplay packets are received on
+        #[arg(long, default_value = "0.0.0.0:7845")]
+        netplay_bind: std::net::SocketAddr,
+        /// Player controlled from this machine during netplay, 1 or 2
+        #[arg(long, default_value_t = 1)]
+        netplay_player: usize,
+        /// Frames local input is delayed during netplay, must match the other player
+        #[arg(long, default_value_t = 2)]
+        netplay_delay: u32,
     },
     /// Create a MDFourier recording
     Mdf {
@@ -228,4 +540,20 @@ enum Mode {
         /// Target sample rate, defaults to raw NES output rate of 1.78mhz with no resampling
         sample_rate: Option<u32>,
     },
+    /// Render each track of a NSF or NSFe to a .wav file
+    Nsf {
+        /// Provides a NSF or NSFe file to render
+        file: PathBuf,
+        /// Directory to write the .wav files to
+        out_dir: PathBuf,
+        /// Target sample rate
+        #[arg(short, long, default_value_t = 48000)]
+        sample_rate: u32,
+        /// Also write a .wav file for each APU channel of every track
+        #[arg(long)]
+        stems: bool,
+        /// Length in seconds that tracks without a duration are cut off at
+        #[arg(long, default_value_t = 300)]
+        max_length: u32,
+    },
 }
@@ -0,0 +1,275 @@
+use nes::{CHANNEL_SAMPLE_WINDOW, Cartridge, ChannelSamples, Machine, Region, SimpleInput};
+use ui::wav_writer::WavWriter;
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::resampler::Resampler;
+
+// Tracks without a duration end once the output stops changing for this long, kept
+// shorter than the silence the NSF player waits for before moving to the next track
+const SILENCE_SECONDS: u32 = 2;
+const SILENCE_THRESHOLD: u16 = 16;
+
+// Pole of the high-pass applied to stems, a cutoff of about 10Hz at the channel sample rate
+const DC_BLOCK_POLE: f32 = 0.999;
+
+const STEM_NAMES: [&str; 6] = ["pulse_1", "pulse_2", "triangle", "noise", "dmc", "external"];
+
+pub fn render(
+    path: PathBuf,
+    out_dir: PathBuf,
+    region: Region,
+    sample_rate: u32,
+    stems: bool,
+    max_length: u32,
+) {
+    let rom = std::fs::read(&path).unwrap();
+    let file_name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_string())
+        .unwrap_or_default();
+    let base_name = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_string())
+        .unwrap_or_default();
+
+    let Some(nsf) = load_nsf(&rom, &file_name) else {
+        tracing::error!("Not a NSF or NSFe file: {}", path.display());
+        return;
+    };
+
+    let tracks = track_list(&nsf);
+
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    for (idx, &song) in tracks.iter().enumerate() {
+        let mut nsf = load_nsf(&rom, &file_name).unwrap();
+
+        let mut name = format!("{base_name} {:02}", idx + 1);
+        if let Some(title) = nsf.metadata.track_title(song) {
+            name = format!("{name} - {title}");
+        }
+        let name = sanitize_file_name(&name);
+
+        let length = track_length(&nsf, song);
+
+        // Start the player directly on the track being rendered
+        nsf.starting_song = song;
+        nsf.metadata.playlist = None;
+
+        let mut machine = Machine::new(region, Cartridge::Nsf(nsf));
+        let mut input = SimpleInput::new();
+
+        let mut track = TrackBuffer::new(region.cpu_clock(), sample_rate);
+        let mut stem_tracks = stems.then(|| {
+            let clock = region.cpu_clock() / CHANNEL_SAMPLE_WINDOW as f64;
+            STEM_NAMES.map(|_| (TrackBuffer::new(clock, sample_rate), DcBlocker::default()))
+        });
+
+        let max_samples = match length {
+            Some(ms) => sample_rate as u64 * ms as u64 / 1000,
+            None => sample_rate as u64 * max_length as u64,
+        } as usize;
+        let silence_samples = (sample_rate * SILENCE_SECONDS) as usize;
+
+        tracing::info!("Rendering track {}/{}: {name}", idx + 1, tracks.len());
+
+        while track.len() < max_samples {
+            machine.run(&mut input);
+            track.add_samples(machine.take_samples());
+
+            if let Some(stem_tracks) = stem_tracks.as_mut() {
+                let channels: Vec<_> = machine.take_channel_samples().collect();
+                for (idx, (stem, dc_blocker)) in stem_tracks.iter_mut().enumerate() {
+                    let samples = channels.iter().map(|c| stem_sample(c, idx, dc_blocker));
+                    stem.add_samples(samples);
+                }
+            }
+
+            if length.is_none() && track.silent_len() >= silence_samples {
+                break;
+            }
+        }
+
+        let len = if length.is_some() {
+            track.len().min(max_samples)
+        } else {
+            track.sound_len()
+        };
+
+        let out_file = out_dir.join(format!("{name}.wav"));
+        track.write(&out_file, sample_rate, len);
+
+        if let Some(stem_tracks) = stem_tracks {
+            for ((stem, _), stem_name) in stem_tracks.iter().zip(STEM_NAMES) {
+                let out_file = out_dir.join(format!("{name} - {stem_name}.wav"));
+                stem.write(&out_file, sample_rate, len);
+            }
+        }
+    }
+
+    tracing::info!("Finished: {}", out_dir.display());
+}
+
+fn load_nsf(rom: &[u8], file_name: &str) -> Option<nes::NsfFile> {
+    let mut rom = rom;
+    match Cartridge::load(&mut rom, None, None, file_name) {
+        Ok(Cartridge::Nsf(nsf)) => Some(nsf),
+        _ => None,
+    }
+}
+
+// Songs in playlist order, or every song in the file when there is no playlist
+fn track_list(nsf: &nes::NsfFile) -> Vec<u8> {
+    match nsf.metadata.playlist.as_ref() {
+        Some(playlist) if !playlist.is_empty() => playlist.clone(),
+        _ => (0..nsf.total_songs).collect(),
+    }
+}
+
+// Milliseconds of audio to render including the fade, or None to detect silence
+fn track_length(nsf: &nes::NsfFile, song: u8) -> Option<u32> {
+    nsf.metadata
+        .track_time(song)
+        .map(|time| time + nsf.metadata.track_fade(song))
+}
+
+// Stems are faded along with the mixed output. The APU channels range from 0.0 to 1.0, so
+// every stem is high-passed to centre it around zero without a fixed offset
+fn stem_sample(channels: &ChannelSamples, idx: usize, dc_blocker: &mut DcBlocker) -> i16 {
+    let value = match idx {
+        0 => channels.pulse_1,
+        1 => channels.pulse_2,
+        2 => channels.triangle,
+        3 => channels.noise,
+        4 => channels.dmc,
+        _ => channels.external_sample,
+    };
+    let value = dc_blocker.filter(value);
+
+    ((value * channels.volume).clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+#[derive(Default)]
+struct DcBlocker {
+    input: f32,
+    output: f32,
+}
+
+impl DcBlocker {
+    fn filter(&mut self, input: f32) -> f32 {
+        self.output = input - self.input + DC_BLOCK_POLE * self.output;
+        self.input = input;
+        self.output
+    }
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+// Holds a rendered track in memory so trailing silence can be trimmed before writing
+struct TrackBuffer {
+    resampler: Resampler,
+    samples: Vec<i16>,
+    level: i16,
+    sound_len: usize,
+}
+
+impl TrackBuffer {
+    fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        Self {
+            resampler: Resampler::new(clock_rate, Some(sample_rate)),
+            samples: Vec::new(),
+            level: 0,
+            sound_len: 0,
+        }
+    }
+
+    fn add_samples<I: ExactSizeIterator<Item = i16>>(&mut self, samples: I) {
+        for &sample in self.resampler.resample(samples) {
+            self.samples.push(sample);
+            if sample.abs_diff(self.level) > SILENCE_THRESHOLD {
+                self.level = sample;
+                self.sound_len = self.samples.len();
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    // Length up to the last change in output
+    fn sound_len(&self) -> usize {
+        self.sound_len
+    }
+
+    fn silent_len(&self) -> usize {
+        self.samples.len() - self.sound_len
+    }
+
+    fn write(&self, path: &Path, sample_rate: u32, len: usize) {
+        let out_wav = File::create(path).unwrap();
+        let mut wav_writer = WavWriter::new(out_wav, sample_rate).unwrap();
+        let len = len.min(self.samples.len());
+        wav_writer.write_samples(&self.samples[0..len]).unwrap();
+        wav_writer.finalize().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(ident: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(ident);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    fn times(ms: &[i32]) -> Vec<u8> {
+        ms.iter().flat_map(|t| t.to_le_bytes()).collect()
+    }
+
+    // Three song NSFe whose init and play routines are a single RTS at $8000
+    fn nsfe(metadata: &[Vec<u8>]) -> nes::NsfFile {
+        let info = [0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0, 0, 3, 0];
+        let mut file = b"NSFE".to_vec();
+        file.extend(chunk(b"INFO", &info));
+        file.extend(chunk(b"DATA", &[0x60]));
+        file.extend(metadata.concat());
+        file.extend(chunk(b"NEND", &[]));
+        load_nsf(&file, "test.nsfe").unwrap()
+    }
+
+    #[test]
+    fn tracks_follow_playlist() {
+        let nsf = nsfe(&[chunk(b"plst", &[2, 5, 0])]);
+        assert_eq!(track_list(&nsf), [2, 0]);
+
+        let nsf = nsfe(&[chunk(b"plst", &[])]);
+        assert_eq!(track_list(&nsf), [0, 1, 2]);
+    }
+
+    #[test]
+    fn track_length_includes_fade() {
+        let nsf = nsfe(&[
+            chunk(b"time", &times(&[1000, 2000, -1])),
+            chunk(b"fade", &times(&[500, -1])),
+        ]);
+        assert_eq!(track_length(&nsf, 0), Some(1500));
+        assert_eq!(track_length(&nsf, 1), Some(2000 + nes::NSF_DEFAULT_FADE_MS));
+        assert_eq!(track_length(&nsf, 2), None);
+    }
+}
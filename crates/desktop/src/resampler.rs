@@ -0,0 +1,44 @@
+use blip_buf::BlipBuf;
+
+/// Resamples emulator output to a target sample rate, samples are passed through
+/// unchanged when no rate is given
+pub struct Resampler {
+    blip: Option<BlipBuf>,
+    blip_delta: i32,
+    sample_buf: Vec<i16>,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: Option<u32>) -> Self {
+        let blip = sample_rate.map(|sample_rate| {
+            let mut blip = BlipBuf::new(sample_rate);
+            blip.set_rates(clock_rate, sample_rate as f64);
+            blip
+        });
+
+        Self {
+            blip,
+            blip_delta: 0,
+            sample_buf: Vec::new(),
+        }
+    }
+
+    pub fn resample<I: ExactSizeIterator<Item = i16>>(&mut self, samples: I) -> &[i16] {
+        let Some(blip) = self.blip.as_mut() else {
+            self.sample_buf.clear();
+            self.sample_buf.extend(samples);
+            return &self.sample_buf;
+        };
+
+        let count = samples.len();
+        for (i, v) in samples.enumerate() {
+            blip.add_delta(i as u32, v as i32 - self.blip_delta);
+            self.blip_delta = v as i32;
+        }
+        blip.end_frame(count as u32);
+
+        self.sample_buf.resize(blip.samples_avail() as usize, 0);
+        let n = blip.read_samples(&mut self.sample_buf, false);
+        &self.sample_buf[0..n]
+    }
+}
@@ -1,11 +1,22 @@
-use std::{collections::VecDeque, time::Duration};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use blip_buf::BlipBuf;
 use nes::{
-    Cartridge, FrameEnd, Machine, Region, SimpleInput, UserInput,
+    Cartridge, FrameEnd, InputSource, Machine, Port, PortDeviceKind, Region, SimpleInput, Tape,
+    TapeControl, UserInput,
     run_until::{self, RunUntil},
 };
 use ui::audio::SamplesSender;
+use ui::input::{InputMacro, PadResolver, PaddleSettings, PowerPadSettings, TurboSettings};
+use ui::netplay::{NetplayEvent, Session, UdpTransport};
+use ui::overlay::{FrameInfo, OverlaySettings};
+use ui::run_ahead::RunAhead;
+use ui::wav_reader::WavReader;
+use ui::wav_writer::WavWriter;
 
 use crate::{
     app::{EmulatorInput, NesInputs},
@@ -23,6 +34,57 @@ pub struct Runner {
     save_store: SaveStore,
     frame: Option<u32>,
     input: SimpleInput,
+    pads: PadResolver,
+    tape_path: Option<PathBuf>,
+    tape_recording: bool,
+    run_ahead: RunAhead,
+    netplay: Option<Session<UdpTransport>>,
+}
+
+/// Hardware attached to the console alongside the cartridge
+pub struct Peripherals {
+    /// Attach the Expansion Port Sound Module
+    pub epsm: bool,
+    pub ports: Vec<(Port, PortDeviceKind)>,
+    /// .wav file loaded into the Data Recorder, recordings are written back to it
+    pub tape: Option<PathBuf>,
+}
+
+impl Peripherals {
+    fn attach(&self, machine: &mut Machine) {
+        if self.epsm {
+            machine.set_epsm(true);
+        }
+        for &(port, device) in self.ports.iter() {
+            machine.set_port_device(port, device);
+        }
+        if let Some(path) = self.tape.as_ref() {
+            machine.insert_tape(load_tape(path));
+        }
+    }
+}
+
+/// Frames the presented screen is run ahead of the machine
+pub struct RunAheadSettings {
+    pub frames: u32,
+    /// Same cartridge loaded a second time to run ahead on, keeping the audio of the machine
+    /// free of glitches
+    pub second_instance: Option<Cartridge>,
+}
+
+/// Options chosen on the command line, the input and overlay settings are handed to the
+/// window and the rest are used by the machine thread
+pub struct FrontendSettings {
+    pub peripherals: Peripherals,
+    pub paddle: PaddleSettings,
+    pub power_pad: PowerPadSettings,
+    /// Loudness the microphone must pass to be heard, the microphone is left closed without one
+    pub microphone_threshold: Option<f32>,
+    pub turbo: TurboSettings,
+    pub macros: Vec<InputMacro>,
+    pub overlay: OverlaySettings,
+    pub run_ahead: RunAheadSettings,
+    pub netplay: Option<Session<UdpTransport>>,
 }
 
 impl Runner {
@@ -33,8 +95,24 @@ impl Runner {
         back_buffer: GfxBackBuffer,
         samples_tx: SamplesSender,
         sample_rate: u32,
+        settings: FrontendSettings,
     ) -> Self {
-        let machine = Machine::new(region, cart);
+        let FrontendSettings {
+            peripherals,
+            run_ahead,
+            netplay,
+            ..
+        } = settings;
+        let mut machine = Machine::new(region, cart);
+        peripherals.attach(&mut machine);
+        let run_ahead = match run_ahead.second_instance {
+            Some(cart) => {
+                let mut second = Machine::new(region, cart);
+                peripherals.attach(&mut second);
+                RunAhead::with_second_instance(run_ahead.frames, second)
+            }
+            None => RunAhead::new(run_ahead.frames),
+        };
         let mut blip = BlipBuf::new(sample_rate / 20);
         blip.set_rates(region.cpu_clock(), sample_rate as f64);
 
@@ -49,6 +127,11 @@ impl Runner {
             save_store: SaveStore::new(32000, 5),
             frame: None,
             input: SimpleInput::new(),
+            pads: PadResolver::new(),
+            tape_path: peripherals.tape,
+            tape_recording: false,
+            run_ahead,
+            netplay,
         }
     }
 
@@ -61,19 +144,33 @@ impl Runner {
             for input in inputs.try_inputs() {
                 match input {
                     EmulatorInput::Nes(input) => self.handle_input(input),
+                    EmulatorInput::Pad(player, pad) => self.pads.set_pad(player, pad),
                     EmulatorInput::SaveState(slot) => {
                         if let Some(frame) = self.frame {
                             let data = self.machine.save_state();
 
                             self.save_states[slot as usize] = Some((frame as usize, data));
+                            self.back_buffer.message(format!("State {slot} saved"));
                         }
                     }
+                    // Both machines would have to restore the same state or tape to stay in sync
+                    EmulatorInput::RestoreState(_)
+                    | EmulatorInput::Rewind
+                    | EmulatorInput::Tape(_)
+                        if self.netplay.is_some() =>
+                    {
+                        self.back_buffer.message("Unavailable during netplay");
+                    }
                     EmulatorInput::RestoreState(slot) => {
                         if let Some((frame, data)) = self.save_states[slot as usize].as_ref() {
                             self.frame = Some(*frame as u32);
                             self.machine.restore_state(data);
+                            self.back_buffer.message(format!("State {slot} loaded"));
+                        } else {
+                            self.back_buffer.message(format!("State {slot} is empty"));
                         }
                     }
+                    EmulatorInput::Tape(control) => self.tape_control(control),
                     EmulatorInput::Rewind => {
                         if let Some((frame, data)) = self.save_store.pop() {
                             self.frame = Some(frame as u32);
@@ -93,17 +190,61 @@ impl Runner {
         }
     }
 
+    fn tape_control(&mut self, control: TapeControl) {
+        self.machine.tape_control(control);
+
+        if control == TapeControl::Record {
+            self.tape_recording = true;
+        } else if self.tape_recording {
+            self.tape_recording = false;
+            self.save_tape();
+        }
+    }
+
+    fn save_tape(&mut self) {
+        let Some(path) = self.tape_path.as_ref() else {
+            return;
+        };
+        let Some(tape) = self.machine.tape() else {
+            return;
+        };
+
+        let result = std::fs::File::create(path).and_then(|file| {
+            let mut wav_writer = WavWriter::new(file, tape.sample_rate)?;
+            wav_writer.write_samples(&tape.samples)?;
+            wav_writer.finalize()
+        });
+
+        match result {
+            Ok(()) => tracing::info!("Saved tape: {}", path.display()),
+            Err(err) => tracing::error!("Unable to save tape: {err:?}"),
+        }
+    }
+
     fn handle_input(&mut self, input: UserInput) {
+        // Only the first controller is sent to the other player, anything else would only
+        // reach the local machine
+        if self.netplay.is_some()
+            && !matches!(input, UserInput::PlayerOne(_) | UserInput::Player(0, _))
+        {
+            return;
+        }
+
         self.input.handle_input(input);
     }
 
     fn step(&mut self, samples: u32) {
-        self.machine.run_with_breakpoints(
-            FrameEnd::SetVblank,
-            run_until::Frames(1).or(run_until::Samples(samples)),
-            (),
-            &mut self.input,
-        );
+        self.pads.apply(self.machine.frame(), &mut self.input);
+        if self.netplay.is_some() {
+            self.step_netplay();
+        } else {
+            self.machine.run_with_breakpoints(
+                FrameEnd::SetVblank,
+                run_until::Frames(1).or(run_until::Samples(samples)),
+                (),
+                &mut self.input,
+            );
+        }
 
         self.update_audio();
         let frame = self.machine.frame();
@@ -115,6 +256,24 @@ impl Runner {
         }
     }
 
+    // Netplay emulates whole frames, with the second controller coming from the other player
+    fn step_netplay(&mut self) {
+        let Some(session) = self.netplay.as_mut() else {
+            return;
+        };
+
+        let local = self.input.peek().controllers[0];
+        if let Err(err) = session.advance(&mut self.machine, &mut self.input, local) {
+            tracing::error!("Netplay error: {err:?}");
+        }
+
+        for event in session.events() {
+            if let NetplayEvent::Desync { frame, .. } = event {
+                self.back_buffer.message(format!("Desync at frame {frame}"));
+            }
+        }
+    }
+
     fn update_audio(&mut self) {
         let mut count = 0;
         for (i, v) in self.machine.get_samples().enumerate() {
@@ -127,8 +286,11 @@ impl Runner {
     }
 
     fn update_frame(&mut self) {
+        self.back_buffer
+            .set_info(FrameInfo::new(&self.machine, &self.input.peek()));
         self.back_buffer.update(|frame| {
-            frame.copy_from_slice(self.machine.get_screen());
+            self.run_ahead
+                .present(&mut self.machine, &mut self.input, frame);
         });
     }
 }
@@ -166,3 +328,21 @@ impl SaveStore {
         self.saves.push_back((frame, data));
     }
 }
+
+// Sample rate of tapes created when the Data Recorder starts without a .wav file
+const BLANK_TAPE_RATE: u32 = 44100;
+
+fn load_tape(path: &Path) -> Tape {
+    match std::fs::File::open(path).and_then(WavReader::new) {
+        Ok(wav) => Tape {
+            sample_rate: wav.sample_rate,
+            samples: wav.samples,
+        },
+        Err(err) => {
+            if path.exists() {
+                tracing::error!("Unable to load tape: {err:?}");
+            }
+            Tape::blank(BLANK_TAPE_RATE)
+        }
+    }
+}
@@ -6,11 +6,14 @@ use serde::{Deserialize, Serialize};
 use crate::bus::{Address, AddressBus, AndEqualsAndMask, DeviceKind};
 use crate::channel::{Channel, Dmc, Noise, Pulse, PulseChannel, Triangle};
 use crate::cpu::dma::DmcDmaKind;
-use crate::mapper::RcMapper;
+use crate::epsm::Epsm;
+use crate::mapper::{NsfOutput, RcMapper};
 use crate::region::Region;
 use crate::ring_buf::RingBuf;
 use crate::run_until::{self, RunUntil};
 
+use std::rc::Rc;
+
 pub const LENGTH_TABLE: [u8; 0x20] = [
     10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
     192, 24, 72, 26, 16, 28, 32, 30,
@@ -45,6 +48,8 @@ pub struct Apu<S: Sample = i16> {
     pub noise: Noise,
     #[cfg_attr(feature = "save-states", save(nested))]
     pub dmc: Dmc,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    pub epsm: Epsm,
     #[cfg_attr(feature = "save-states", save(skip))]
     mixer: S::Mixer,
     #[cfg_attr(feature = "save-states", save(skip))]
@@ -64,6 +69,8 @@ pub struct Apu<S: Sample = i16> {
     debug_channels: DebugChannelSamples,
     #[cfg_attr(feature = "save-states", save(skip))]
     playback: ChannelPlayback,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    nsf_output: Option<Rc<NsfOutput>>,
 }
 
 impl<S: Sample> Apu<S> {
@@ -76,6 +83,7 @@ impl<S: Sample> Apu<S> {
             triangle: Triangle::new(),
             noise: Noise::new(region),
             dmc: Dmc::new(region),
+            epsm: Epsm::new(region),
             mixer: S::Mixer::default(),
             samples: RingBuf::new(region.frame_ticks().ceil() as usize * 2),
             current_tick: 0,
@@ -91,11 +99,13 @@ impl<S: Sample> Apu<S> {
             #[cfg(feature = "debugger")]
             debug_channels: DebugChannelSamples::new(),
             playback: ChannelPlayback::default(),
+            nsf_output: None,
         }
     }
 
     pub fn power(&mut self) {
         self.dmc.power();
+        self.epsm.power();
         for a in 0..4 {
             self.pulse_one.write(a, 0);
             self.pulse_two.write(a, 0);
@@ -281,11 +291,18 @@ impl<S: Sample> Apu<S> {
         let triangle = self.triangle.tick(snapshot);
         let noise = self.noise.tick(snapshot);
         let dmc = self.dmc.tick(snapshot);
-        let ext = self.mapper.get_sample().unwrap_or(0);
+        let ext = self
+            .mapper
+            .get_sample()
+            .unwrap_or(0)
+            .saturating_add(self.epsm.tick());
 
         #[cfg(feature = "debugger")]
-        self.debug_channels
-            .push_channels(pulse_1, pulse_2, triangle, noise, dmc, ext);
+        {
+            self.debug_channels.volume = self.nsf_output.as_ref().map_or(1.0, |o| o.volume());
+            self.debug_channels
+                .push_channels(pulse_1, pulse_2, triangle, noise, dmc, ext);
+        }
 
         let pulse_1 = self.playback.pulse_1(pulse_1);
         let pulse_2 = self.playback.pulse_2(pulse_2);
@@ -295,6 +312,10 @@ impl<S: Sample> Apu<S> {
         let ext = self.playback.ext(ext);
 
         let sample = self.mixer.mix(pulse_1, pulse_2, triangle, noise, dmc, ext);
+        let sample = match self.nsf_output.as_ref() {
+            Some(output) => output.mix(sample),
+            None => sample,
+        };
         self.samples.push(sample);
         until.add_sample();
     }
@@ -326,6 +347,11 @@ impl<S: Sample> Apu<S> {
         self.oam_req.take()
     }
 
+    /// Hands the mixed output to the NSF player, which can fade it out
+    pub fn set_nsf_output(&mut self, output: Rc<NsfOutput>) {
+        self.nsf_output = Some(output);
+    }
+
     pub fn set_channel_playback(&mut self, playback: ChannelPlayback) {
         self.playback = playback;
     }
@@ -341,6 +367,7 @@ impl<S: Sample> Apu<S> {
         self.triangle.register(cpu);
         self.noise.register(cpu);
         self.dmc.register(cpu);
+        self.epsm.register(cpu);
     }
 
     fn sequence_steps(&self) -> &'static [u32] {
@@ -400,22 +427,26 @@ impl<S: Sample> Apu<S> {
     }
 }
 
+/// Number of cpu cycles averaged into each `ChannelSamples`
+pub const CHANNEL_SAMPLE_WINDOW: usize = 32;
+
 struct DebugChannelSamples {
     window_size: usize,
     sample_accum_count: usize,
     sample_accum: ChannelSamples,
     samples: RingBuf<ChannelSamples>,
+    volume: f32,
 }
 
 #[allow(unused)]
 impl DebugChannelSamples {
     fn new() -> Self {
-        const WINDOW_SIZE: usize = 32;
         DebugChannelSamples {
-            window_size: WINDOW_SIZE,
+            window_size: CHANNEL_SAMPLE_WINDOW,
             sample_accum_count: 0,
             sample_accum: ChannelSamples::zero(),
-            samples: RingBuf::new((33248 / WINDOW_SIZE) + 1),
+            samples: RingBuf::new((33248 / CHANNEL_SAMPLE_WINDOW) + 1),
+            volume: 1.0,
         }
     }
 
@@ -435,6 +466,8 @@ impl DebugChannelSamples {
             noise: noise as f32 / 15.0,
             dmc: dmc as f32 / 127.0,
             external: (external as f32).abs() / i16::MAX as f32,
+            external_sample: external as f32 / i16::MAX as f32,
+            volume: self.volume,
         };
 
         self.sample_accum += channels;
@@ -456,6 +489,10 @@ pub struct ChannelSamples {
     pub noise: f32,
     pub dmc: f32,
     pub external: f32,
+    /// Signed expansion audio output, `external` is its magnitude
+    pub external_sample: f32,
+    /// Volume the mixed output is played at, lowered while an NSF track fades out
+    pub volume: f32,
 }
 
 impl Default for ChannelSamples {
@@ -473,6 +510,8 @@ impl ChannelSamples {
             noise: 0.0,
             dmc: 0.0,
             external: 0.0,
+            external_sample: 0.0,
+            volume: 0.0,
         }
     }
 }
@@ -485,6 +524,8 @@ impl std::ops::AddAssign for ChannelSamples {
         self.noise += rhs.noise;
         self.dmc += rhs.dmc;
         self.external += rhs.external;
+        self.external_sample += rhs.external_sample;
+        self.volume += rhs.volume;
     }
 }
 
@@ -499,6 +540,8 @@ impl std::ops::Div<f32> for ChannelSamples {
             noise: self.noise / rhs,
             dmc: self.dmc / rhs,
             external: self.external / rhs,
+            external_sample: self.external_sample / rhs,
+            volume: self.volume / rhs,
         }
     }
 }
@@ -580,6 +623,11 @@ impl ChannelPlayback {
 
 pub trait Sample: Copy + Default {
     type Mixer: SampleMixer<Self>;
+
+    /// The sample as a fraction of full scale
+    fn to_f32(self) -> f32;
+
+    fn scale(self, volume: f32) -> Self;
 }
 
 pub trait SampleMixer<S>: Default {
@@ -588,6 +636,14 @@ pub trait SampleMixer<S>: Default {
 
 impl Sample for i16 {
     type Mixer = I16LutMixer;
+
+    fn to_f32(self) -> f32 {
+        self as f32 / i16::MAX as f32
+    }
+
+    fn scale(self, volume: f32) -> Self {
+        (self as f32 * volume) as i16
+    }
 }
 
 pub struct I16LutMixer {
@@ -632,6 +688,14 @@ impl SampleMixer<i16> for I16LutMixer {
 
 impl Sample for f32 {
     type Mixer = ();
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn scale(self, volume: f32) -> Self {
+        self * volume
+    }
 }
 
 impl SampleMixer<f32> for () {
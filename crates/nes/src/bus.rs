@@ -16,6 +16,7 @@ pub enum DeviceKind {
     Noise,
     Triangle,
     Dmc,
+    Epsm,
     #[allow(unused)]
     Debug,
 }
@@ -65,14 +66,14 @@ impl AddressBus {
         }
     }
 
-    pub(crate) fn register_read<T>(&mut self, device: DeviceKind, addr_val: T)
+    pub fn register_read<T>(&mut self, device: DeviceKind, addr_val: T)
     where
         T: Into<MappingFn>,
     {
         self.read_mapping.insert(addr_val, device);
     }
 
-    pub(crate) fn register_write<T>(&mut self, device: DeviceKind, addr_val: T)
+    pub fn register_write<T>(&mut self, device: DeviceKind, addr_val: T)
     where
         T: Into<MappingFn>,
     {
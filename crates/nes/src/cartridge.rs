@@ -1,16 +1,18 @@
 use crate::Region;
 use crate::debug::Debug;
-use crate::mapper::{self, Nametable, SaveWram};
+use crate::mapper::{self, EspConfig, FdsConfig, MapperRegistry, Nametable, NsfOutput, SaveWram};
 use crate::memory::RomBlock;
 
 use std::ffi::CStr;
-use std::{fmt, io, rc::Rc};
+use std::io::{self, Read};
+use std::{fmt, rc::Rc};
 
 #[derive(Debug)]
 pub enum CartridgeError {
     InvalidFileType,
     NotSupported,
     BiosRequired(&'static str),
+    InvalidChecksum,
     IoError(io::Error),
 }
 
@@ -22,6 +24,7 @@ impl fmt::Display for CartridgeError {
             CartridgeError::BiosRequired(bios_name) => {
                 write!(f, "This rom requires a bios file named '{bios_name}'")
             }
+            CartridgeError::InvalidChecksum => write!(f, "Disk block checksum mismatch"),
             CartridgeError::IoError(ref x) => write!(f, "Cartridge io error: {}", x),
         }
     }
@@ -46,6 +49,10 @@ enum RomType {
 pub enum CartMirroring {
     Horizontal,
     Vertical,
+    /// Every nametable mirrors the first page of CIRAM
+    SingleA,
+    /// Every nametable mirrors the second page of CIRAM
+    SingleB,
 }
 
 impl CartMirroring {
@@ -56,6 +63,8 @@ impl CartMirroring {
                 CartMirroring::Horizontal => Nametable::InternalB,
                 CartMirroring::Vertical if address & 0x400 != 0 => Nametable::InternalA,
                 CartMirroring::Vertical => Nametable::InternalB,
+                CartMirroring::SingleA => Nametable::InternalA,
+                CartMirroring::SingleB => Nametable::InternalB,
             }
         } else {
             Nametable::External
@@ -68,6 +77,8 @@ impl From<CartMirroring> for mapper::Mirroring {
         match value {
             CartMirroring::Horizontal => mapper::Mirroring::Horizontal,
             CartMirroring::Vertical => mapper::Mirroring::Vertical,
+            CartMirroring::SingleA => mapper::Mirroring::Single(Nametable::InternalA),
+            CartMirroring::SingleB => mapper::Mirroring::Single(Nametable::InternalB),
         }
     }
 }
@@ -83,11 +94,160 @@ pub struct INes {
     pub submapper: Option<u32>,
     pub wram: Option<SaveWram>,
     pub battery: bool,
+    pub esp: EspConfig,
+    /// UNIF board name, `None` for iNES roms
+    pub board: Option<String>,
+    /// NES 2.0 default expansion device, 0 when unspecified
+    pub expansion_device: u8,
 }
 
 pub struct Fds {
+    /// Disk sides as seen by the drive, with gaps, start marks and checksums
     pub disk_sides: Vec<Vec<u8>>,
     pub bios: Vec<u8>,
+    /// IPS patch of disk modifications made by a previous session, against the headerless
+    /// `.fds` image
+    pub wram: Option<SaveWram>,
+    pub config: FdsConfig,
+}
+
+impl Fds {
+    /// Convert the disk sides back to an fwNES `.fds` image, optionally with its 16 byte header
+    pub fn export(&self, header: bool) -> Vec<u8> {
+        export_disk_sides(&self.disk_sides, header)
+    }
+}
+
+pub(crate) fn export_disk_sides(disk_sides: &[Vec<u8>], header: bool) -> Vec<u8> {
+    let mut image = Vec::new();
+    if header {
+        image.extend_from_slice(b"FDS\x1a");
+        image.push(disk_sides.len() as u8);
+        image.resize(16, 0);
+    }
+
+    for side in disk_sides.iter() {
+        let start = image.len();
+        let mut idx = 0;
+        let mut file_len = 0;
+        loop {
+            while side.get(idx) == Some(&0) {
+                idx += 1;
+            }
+
+            if side.get(idx) != Some(&0x80) {
+                break;
+            }
+            idx += 1;
+
+            let Some(block_len) = disk_block_len(side.get(idx..).unwrap_or(&[]), file_len) else {
+                break;
+            };
+            let Some(block) = side.get(idx..idx + block_len) else {
+                break;
+            };
+            if block[0] == 3 {
+                file_len = u16::from_le_bytes([block[13], block[14]]) as usize;
+            }
+
+            image.extend_from_slice(block);
+            idx += block_len + 2;
+        }
+
+        image.resize(start + FDS_SIDE_LEN, 0);
+    }
+
+    image
+}
+
+// Expand the sides of a headerless fwNES image back into the drive layout
+pub(crate) fn import_disk_sides(image: &[u8]) -> Result<Vec<Vec<u8>>, CartridgeError> {
+    image
+        .chunks(FDS_SIDE_LEN)
+        .map(|side| parse_disk_side(side, false))
+        .collect()
+}
+
+const FDS_SIDE_LEN: usize = 65500;
+const QD_SIDE_LEN: usize = 0x10000;
+
+fn has_extension(file_name: &str, ext: &str) -> bool {
+    let name = file_name.as_bytes();
+    name.len() >= ext.len() && name[name.len() - ext.len()..].eq_ignore_ascii_case(ext.as_bytes())
+}
+
+// Length of the block at the start of `data`, file data blocks use the size from the
+// preceding file header block
+fn disk_block_len(data: &[u8], file_len: usize) -> Option<usize> {
+    match data.first()? {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_len),
+        _ => None,
+    }
+}
+
+// Checksum over the block contents followed by two zero bytes, the 0x80 start mark is
+// accounted for by the initial value
+fn disk_crc(block: &[u8]) -> u16 {
+    let mut crc: u16 = 0x8000;
+    for &byte in block.iter().chain([0, 0].iter()) {
+        for bit in 0..8 {
+            let carry = crc & 1 != 0;
+            crc = (crc >> 1) | ((byte as u16 >> bit) & 1) << 15;
+            if carry {
+                crc ^= 0x8408;
+            }
+        }
+    }
+    crc
+}
+
+// Expand a side into the layout the drive emulation streams, with lead-in and inter-block gaps
+fn parse_disk_side(data: &[u8], quick_disk: bool) -> Result<Vec<u8>, CartridgeError> {
+    let mut side = vec![0; 28300 / 8];
+    let mut idx = 0;
+    let mut file_len = 0;
+
+    while let Some(block_len) = disk_block_len(&data[idx..], file_len) {
+        let Some(block) = data.get(idx..idx + block_len) else {
+            break;
+        };
+        if block[0] == 3 {
+            file_len = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+        idx += block_len;
+
+        let crc = if quick_disk {
+            let stored = data
+                .get(idx..idx + 2)
+                .ok_or(CartridgeError::InvalidChecksum)?;
+            let stored = u16::from_le_bytes([stored[0], stored[1]]);
+            if stored != disk_crc(block) {
+                return Err(CartridgeError::InvalidChecksum);
+            }
+            idx += 2;
+            stored
+        } else {
+            disk_crc(block)
+        };
+
+        side.push(0x80);
+        side.extend_from_slice(block);
+        side.extend_from_slice(&crc.to_le_bytes());
+        side.extend((0..976 / 8).map(|_| 0));
+
+        if idx >= data.len() {
+            break;
+        }
+    }
+
+    if side.len() < FDS_SIDE_LEN {
+        side.resize(FDS_SIDE_LEN, 0);
+    }
+
+    Ok(side)
 }
 
 pub struct NsfFile {
@@ -104,6 +264,8 @@ pub struct NsfFile {
     pub pal_speed: u16,
     pub region: NsfRegion,
     pub chips: NsfSoundChips,
+    pub features: Nsf2Features,
+    pub metadata: NsfMetadata,
     pub data: RomBlock,
     pub init_banks: Option<[u8; 8]>,
 }
@@ -205,6 +367,105 @@ impl From<u8> for NsfSoundChips {
     }
 }
 
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Nsf2Features(u8);
+
+impl Nsf2Features {
+    /// Uses the $401B-$401D IRQ timer and a writable IRQ vector at $FFFE
+    pub fn irq(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+
+    /// INIT may never return, PLAY is then called from NMI
+    pub fn non_returning_init(&self) -> bool {
+        self.0 & 0x40 != 0
+    }
+
+    /// PLAY is never called, only valid with a non-returning INIT
+    pub fn suppressed_play(&self) -> bool {
+        self.0 & 0x20 != 0
+    }
+
+    /// Metadata chunks following the program data contain required information
+    pub fn metadata_required(&self) -> bool {
+        self.0 & 0x10 != 0
+    }
+}
+
+impl From<u8> for Nsf2Features {
+    fn from(value: u8) -> Self {
+        Self(value)
+    }
+}
+
+/// Fade length used when a track has a duration but no fade
+pub const NSF_DEFAULT_FADE_MS: u32 = 8000;
+
+/// Per-track information from the NSFe `plst`, `tlbl`, `time`, `fade` and `text` chunks
+#[derive(Debug, Clone, Default)]
+pub struct NsfMetadata {
+    pub playlist: Option<Vec<u8>>,
+    pub track_titles: Vec<Option<String>>,
+    /// Track durations in milliseconds
+    pub track_times: Vec<Option<u32>>,
+    /// Track fade out lengths in milliseconds
+    pub track_fades: Vec<Option<u32>>,
+    pub text: Option<String>,
+}
+
+impl NsfMetadata {
+    pub fn track_title(&self, track: u8) -> Option<&str> {
+        self.track_titles.get(track as usize)?.as_deref()
+    }
+
+    pub fn track_time(&self, track: u8) -> Option<u32> {
+        self.track_times.get(track as usize).copied().flatten()
+    }
+
+    pub fn track_fade(&self, track: u8) -> u32 {
+        self.track_fades
+            .get(track as usize)
+            .copied()
+            .flatten()
+            .unwrap_or(NSF_DEFAULT_FADE_MS)
+    }
+
+    // Playlist entries naming songs that are not in the file are dropped
+    fn validate_playlist(&mut self, total_songs: u8) {
+        if let Some(playlist) = self.playlist.as_mut() {
+            let len = playlist.len();
+            playlist.retain(|&song| song < total_songs);
+            if playlist.len() != len {
+                tracing::warn!("NSFe playlist has {} invalid tracks", len - playlist.len());
+            }
+        }
+    }
+
+    fn read_chunk(&mut self, ident: &[u8; 4], data: Vec<u8>) {
+        // Negative durations mean the track length is unspecified
+        let read_times = |data: &[u8]| {
+            data.chunks_exact(4)
+                .map(|t| i32::from_le_bytes([t[0], t[1], t[2], t[3]]))
+                .map(|t| u32::try_from(t).ok())
+                .collect()
+        };
+
+        match ident {
+            b"plst" => self.playlist = Some(data),
+            b"tlbl" => {
+                self.track_titles = data
+                    .split(|&c| c == 0)
+                    .map(|s| read_str(s.iter().copied()))
+                    .collect();
+            }
+            b"time" => self.track_times = read_times(&data),
+            b"fade" => self.track_fades = read_times(&data),
+            b"text" => self.text = read_str(data.into_iter()),
+            _ => (),
+        }
+    }
+}
+
 pub enum CartridgeInfo {
     Cartridge,
     Fds { total_sides: usize },
@@ -231,8 +492,11 @@ impl Cartridge {
 
         match Cartridge::get_rom_type(&ident, file_name) {
             Some(RomType::Ines) => Cartridge::load_ines(file, ident, wram),
-            Some(RomType::Fds) => Cartridge::load_fds(file, ident, bios),
-            Some(RomType::Unif) => Cartridge::load_unif(file),
+            Some(RomType::Fds) => {
+                let quick_disk = has_extension(file_name, ".qd");
+                Cartridge::load_fds(file, ident, quick_disk, wram, bios)
+            }
+            Some(RomType::Unif) => Cartridge::load_unif(file, wram),
             Some(RomType::Nsf) => Cartridge::load_nsf(file, ident),
             Some(RomType::Nsfe) => Cartridge::load_nsfe(file, ident),
             None => Err(CartridgeError::InvalidFileType),
@@ -329,6 +593,8 @@ impl Cartridge {
             prg_ram_bytes = volatile + non_volatile;
         }
 
+        let expansion_device = if nes_2 { header[15] & 0x7f } else { 0 };
+
         let mut prg_rom = vec![0; prg_rom_bytes];
         let mut chr_rom = vec![0; chr_rom_bytes];
 
@@ -346,6 +612,9 @@ impl Cartridge {
             submapper,
             wram,
             battery,
+            esp: EspConfig::default(),
+            board: None,
+            expansion_device,
         };
 
         let format = if nes_2 { "NES 2.0" } else { "iNES" };
@@ -382,6 +651,8 @@ impl Cartridge {
     fn load_fds<T: std::io::Read, B: std::io::Read>(
         file: &mut T,
         ident: [u8; 4],
+        quick_disk: bool,
+        wram: Option<SaveWram>,
         bios: Option<B>,
     ) -> Result<Cartridge, CartridgeError> {
         let Some(mut bios_rom) = bios else {
@@ -402,47 +673,131 @@ impl Cartridge {
         let mut bios = vec![0; 1024 * 8];
         bios_rom.read_exact(&mut bios)?;
 
-        let mut disk_sides = Vec::new();
-        while disk_sides.len() * 65500 < buffer.len() {
-            let mut side = vec![0; 28300 / 8];
-
-            let offset = disk_sides.len() * 65500;
-            let mut i = 0;
-            while i < 65500 {
-                let idx = i + offset;
-                let block_type = buffer[idx];
-                let block_len = match block_type {
-                    1 => 56,
-                    2 => 2,
-                    3 => 16,
-                    4 => 1 + buffer[idx - 3] as usize + buffer[idx - 2] as usize * 0x100,
-                    _ => break,
-                };
+        // QuickDisk images keep the block checksums and use 64k sides
+        let quick_disk =
+            quick_disk || (buffer.len() % QD_SIDE_LEN == 0 && buffer.len() % FDS_SIDE_LEN != 0);
+        let side_len = if quick_disk {
+            QD_SIDE_LEN
+        } else {
+            FDS_SIDE_LEN
+        };
+
+        let disk_sides = buffer
+            .chunks(side_len)
+            .map(|side| parse_disk_side(side, quick_disk))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        tracing::debug!("FDS Disk Sides: {}", disk_sides.len());
+
+        let fds = Fds {
+            disk_sides,
+            bios,
+            wram,
+            config: FdsConfig::default(),
+        };
+
+        Ok(Cartridge::Fds(fds))
+    }
 
-                side.push(0x80);
-                side.extend_from_slice(&buffer[idx..idx + block_len]);
-                side.push(0x4d);
-                side.push(0x62);
-                side.extend((0..976 / 8).map(|_| 0));
-                i += block_len;
+    fn load_unif<T: std::io::Read>(
+        file: &mut T,
+        mut wram: Option<SaveWram>,
+    ) -> Result<Cartridge, CartridgeError> {
+        // remainder of 32 byte header, revision number followed by padding
+        file.read_exact(&mut [0; 28])?;
+
+        let mut board = None;
+        let mut prg_chunks: [Option<Vec<u8>>; 16] = Default::default();
+        let mut chr_chunks: [Option<Vec<u8>>; 16] = Default::default();
+        let mut mirroring = CartMirroring::Horizontal;
+        let mut alternative_mirroring = false;
+        let mut battery = false;
+
+        loop {
+            let mut chunk_header = [0; 8];
+            match file.read_exact(&mut chunk_header) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
             }
 
-            if side.len() < 65500 {
-                side.resize(65500, 0);
+            let id = &chunk_header[0..4];
+            let len = u32::from_le_bytes([
+                chunk_header[4],
+                chunk_header[5],
+                chunk_header[6],
+                chunk_header[7],
+            ]);
+            // Read through `take` so a corrupt length can't allocate more than the file holds
+            let mut data = Vec::new();
+            file.by_ref().take(len as u64).read_to_end(&mut data)?;
+            if data.len() != len as usize {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+
+            let hex_idx = |b: u8| (b as char).to_digit(16).map(|n| n as usize);
+
+            match id {
+                b"MAPR" => {
+                    let name = CStr::from_bytes_until_nul(&data)
+                        .map(|s| s.to_string_lossy().into_owned())
+                        .unwrap_or_else(|_| String::from_utf8_lossy(&data).into_owned());
+                    board = Some(name);
+                }
+                b"MIRR" => match data.first() {
+                    Some(0) => mirroring = CartMirroring::Horizontal,
+                    Some(1) => mirroring = CartMirroring::Vertical,
+                    Some(2) => mirroring = CartMirroring::SingleA,
+                    Some(3) => mirroring = CartMirroring::SingleB,
+                    Some(4) => alternative_mirroring = true,
+                    // Mirroring is controlled by the mapper
+                    Some(5) => (),
+                    value => tracing::warn!("UNIF unknown mirroring: {value:?}"),
+                },
+                b"BATR" => battery = true,
+                [b'P', b'R', b'G', n] if hex_idx(*n).is_some() => {
+                    prg_chunks[hex_idx(*n).unwrap()] = Some(data);
+                }
+                [b'C', b'H', b'R', n] if hex_idx(*n).is_some() => {
+                    chr_chunks[hex_idx(*n).unwrap()] = Some(data);
+                }
+                _ => tracing::debug!("UNIF skipping chunk: {}", String::from_utf8_lossy(id)),
             }
-            disk_sides.push(side);
         }
 
-        tracing::debug!("FDS Disk Sides: {}", disk_sides.len());
+        let board = board.ok_or(CartridgeError::InvalidFileType)?;
+        let prg_rom: Vec<u8> = prg_chunks.into_iter().flatten().flatten().collect();
+        let chr_rom: Vec<u8> = chr_chunks.into_iter().flatten().flatten().collect();
 
-        let fds = Fds { disk_sides, bios };
+        let (mapper, submapper) = unif_mapper(&board).unwrap_or_else(|| {
+            tracing::warn!("UNIF board has no iNES equivalent: {board}");
+            (0, None)
+        });
+        tracing::debug!("UNIF board: {board}, mapper: {mapper}");
 
-        Ok(Cartridge::Fds(fds))
-    }
+        let chr_ram_bytes = if chr_rom.is_empty() { 0x2000 } else { 0 };
+        let prg_ram_bytes = if mapper == 5 { 64 * 1024 } else { 8 * 1024 };
+        if !battery {
+            wram = None;
+        }
 
-    fn load_unif<T: std::io::Read>(_file: &mut T) -> Result<Cartridge, CartridgeError> {
-        tracing::debug!("UNIF");
-        Err(CartridgeError::NotSupported)
+        let cartridge = INes {
+            chr_ram_bytes,
+            prg_ram_bytes,
+            prg_rom: RomBlock::new(prg_rom),
+            chr_rom: RomBlock::new(chr_rom),
+            mirroring,
+            alternative_mirroring,
+            mapper,
+            submapper,
+            wram,
+            battery,
+            esp: EspConfig::default(),
+            board: Some(board),
+            expansion_device: 0,
+        };
+
+        Ok(Cartridge::INes(cartridge))
     }
 
     fn load_nsf<T: std::io::Read>(
@@ -463,13 +818,13 @@ impl Cartridge {
         let load_addr = u16::from_le_bytes([header[8], header[9]]);
         let init_addr = u16::from_le_bytes([header[10], header[11]]);
         let play_addr = u16::from_le_bytes([header[12], header[13]]);
-        let song_name = CStr::from_bytes_until_nul(&header[14..14 + 32])
+        let mut song_name = CStr::from_bytes_until_nul(&header[14..14 + 32])
             .ok()
             .map(|s| s.to_string_lossy().into_owned());
-        let artist_name = CStr::from_bytes_until_nul(&header[46..46 + 32])
+        let mut artist_name = CStr::from_bytes_until_nul(&header[46..46 + 32])
             .ok()
             .map(|s| s.to_string_lossy().into_owned());
-        let copyright_name = CStr::from_bytes_until_nul(&header[78..78 + 32])
+        let mut copyright_name = CStr::from_bytes_until_nul(&header[78..78 + 32])
             .ok()
             .map(|s| s.to_string_lossy().into_owned());
         let ntsc_speed = u16::from_le_bytes([header[110], header[111]]);
@@ -484,6 +839,12 @@ impl Cartridge {
         let pal_speed = u16::from_le_bytes([header[120], header[121]]);
         let region: NsfRegion = header[122].into();
         let chips: NsfSoundChips = header[123].into();
+        let mut metadata = NsfMetadata::default();
+        let features: Nsf2Features = if version >= 2 {
+            header[124].into()
+        } else {
+            Nsf2Features::default()
+        };
         let length = u32::from_le_bytes([header[125], header[126], header[127], 0]);
 
         let padding = if init_banks.is_some() {
@@ -504,9 +865,42 @@ impl Cartridge {
 
         let data = RomBlock::new(data);
 
+        // NSF2 may append NSFe chunks after the program data
+        if version >= 2 && length != 0 {
+            loop {
+                let reader = match ChunkReader::new(file) {
+                    Ok(reader) => reader,
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                    Err(e) => return Err(e.into()),
+                };
+
+                match reader.ident() {
+                    b"auth" => {
+                        let mut auth = reader.read()?.into_iter();
+                        song_name = read_str(&mut auth).or(song_name);
+                        artist_name = read_str(&mut auth).or(artist_name);
+                        copyright_name = read_str(&mut auth).or(copyright_name);
+                    }
+                    b"NEND" => break,
+                    b"plst" | b"tlbl" | b"time" | b"fade" | b"text" => {
+                        let ident = *reader.ident();
+                        metadata.read_chunk(&ident, reader.read()?);
+                    }
+                    ident if features.metadata_required() && ident[0].is_ascii_uppercase() => {
+                        tracing::warn!(
+                            "Unsupported required NSF2 chunk: {}",
+                            String::from_utf8_lossy(ident)
+                        );
+                        reader.skip()?;
+                    }
+                    _ => reader.skip()?,
+                }
+            }
+        }
+
         tracing::debug!("NSF Version: {version} {chips}");
         if version > 2 {
-            tracing::warn!("Only NSF version 1 is supported");
+            tracing::warn!("NSF versions above 2 are not supported");
         }
         if let Some(song) = song_name.as_ref() {
             tracing::info!("Title: {song}");
@@ -518,6 +912,8 @@ impl Cartridge {
             tracing::info!("Copyright: {copyright}");
         }
 
+        metadata.validate_playlist(total_songs);
+
         let nsf = NsfFile {
             version,
             total_songs,
@@ -533,6 +929,8 @@ impl Cartridge {
             pal_speed,
             region,
             chips,
+            features,
+            metadata,
             data,
         };
 
@@ -543,49 +941,6 @@ impl Cartridge {
         file: &mut T,
         _ident: [u8; 4],
     ) -> Result<Cartridge, CartridgeError> {
-        struct ChunkReader<'a, T: std::io::Read> {
-            ident: [u8; 4],
-            length: usize,
-            file: &'a mut T,
-        }
-
-        impl<'a, T: std::io::Read> ChunkReader<'a, T> {
-            fn new(file: &'a mut T) -> std::io::Result<Self> {
-                let mut buf = [0; 4];
-                file.read_exact(&mut buf)?;
-                let length = u32::from_le_bytes(buf) as usize;
-                file.read_exact(&mut buf)?;
-                Ok(Self {
-                    ident: buf,
-                    length,
-                    file,
-                })
-            }
-
-            fn ident(&self) -> &[u8; 4] {
-                &self.ident
-            }
-
-            fn skip(self) -> std::io::Result<()> {
-                let mut buf = [0; 256];
-                let mut cursor = self.length;
-
-                while cursor > 0 {
-                    let len = cursor.min(256);
-                    cursor -= self.file.read(&mut buf[..len])?;
-                }
-
-                Ok(())
-            }
-
-            fn read(self) -> std::io::Result<Vec<u8>> {
-                let mut data = vec![0; self.length];
-                self.file.read_exact(&mut data)?;
-
-                Ok(data)
-            }
-        }
-
         let mut total_songs = 0;
         let mut starting_song = 0;
         let mut load_addr = 0;
@@ -599,6 +954,7 @@ impl Cartridge {
         let mut region = NsfRegion::Ntsc;
         let mut chips = NsfSoundChips(0);
         let mut init_banks = None;
+        let mut metadata = NsfMetadata::default();
         let mut data = Vec::new();
 
         let mut read_chunks = std::collections::HashSet::new();
@@ -645,22 +1001,6 @@ impl Cartridge {
                     let auth_data = reader.read()?;
                     let mut auth = auth_data.into_iter();
 
-                    fn read_str<I: Iterator<Item = u8>>(auth: I) -> Option<String> {
-                        let mut str = Vec::new();
-                        for c in auth {
-                            if c == 0 {
-                                break;
-                            }
-                            str.push(c);
-                        }
-                        let s = String::from_utf8_lossy(&str);
-                        if !s.is_empty() {
-                            Some(s.to_string())
-                        } else {
-                            None
-                        }
-                    }
-
                     song_name = read_str(&mut auth);
                     artist_name = read_str(&mut auth);
                     copyright_name = read_str(&mut auth);
@@ -669,6 +1009,10 @@ impl Cartridge {
                     reader.skip()?;
                     break;
                 }
+                b"plst" | b"tlbl" | b"time" | b"fade" | b"text" => {
+                    let ident = *reader.ident();
+                    metadata.read_chunk(&ident, reader.read()?);
+                }
                 _ => reader.skip()?,
             }
         }
@@ -705,6 +1049,8 @@ impl Cartridge {
             tracing::info!("Copyright: {copyright}");
         }
 
+        metadata.validate_playlist(total_songs);
+
         let nsf = NsfFile {
             version: 0,
             total_songs,
@@ -720,6 +1066,8 @@ impl Cartridge {
             pal_speed,
             region,
             chips,
+            features: Nsf2Features::default(),
+            metadata,
             data,
         };
 
@@ -732,12 +1080,11 @@ impl Cartridge {
             return Some(RomType::Ines);
         }
 
-        let fds_ext = (file_name.len() >= 4)
-            .then(|| &file_name.as_bytes()[file_name.len() - 4..])
-            .map(|ext| ext.eq_ignore_ascii_case(b".fds"))
-            .unwrap_or(false);
+        let fds_ext = has_extension(file_name, ".fds") || has_extension(file_name, ".qd");
         let fds_header = b"FDS\x1a";
-        if rom.starts_with(fds_header) || fds_ext {
+        // Headerless images start with the disk info block, "\x01*NINTENDO-HVC*"
+        let disk_info = b"\x01*NI";
+        if rom.starts_with(fds_header) || rom.starts_with(disk_info) || fds_ext {
             return Some(RomType::Fds);
         }
 
@@ -759,12 +1106,21 @@ impl Cartridge {
         None
     }
 
-    pub fn build_mapper(self, region: Region, debug: Rc<Debug>) -> mapper::RcMapper {
+    /// `nsf_output` is the mixed APU output the NSF player watches, unused by other cartridges
+    pub(crate) fn build_mapper(
+        self,
+        region: Region,
+        debug: Rc<Debug>,
+        registry: &MapperRegistry,
+        nsf_output: Rc<NsfOutput>,
+    ) -> mapper::RcMapper {
         match self {
-            Cartridge::INes(ines) => mapper::ines(ines, debug),
+            Cartridge::INes(ines) => registry.build(ines, debug),
             Cartridge::Fds(fds) => mapper::fds(fds),
-            Cartridge::Nsf(nsf) => mapper::nsf(region, nsf),
-            Cartridge::GameGenie(inner) => inner.build_mapper(region, debug).with_game_genie(),
+            Cartridge::Nsf(nsf) => mapper::nsf(region, nsf, nsf_output),
+            Cartridge::GameGenie(inner) => inner
+                .build_mapper(region, debug, registry, nsf_output)
+                .with_game_genie(),
         }
     }
 
@@ -779,7 +1135,130 @@ impl Cartridge {
         }
     }
 
+    /// Enable the FDS disk automation options, ignored by other cartridge types
+    pub fn with_fds_config(self, config: FdsConfig) -> Self {
+        match self {
+            Cartridge::Fds(mut fds) => {
+                fds.config = config;
+                Cartridge::Fds(fds)
+            }
+            Cartridge::GameGenie(inner) => inner.with_fds_config(config).with_game_genie(),
+            cart => cart,
+        }
+    }
+
+    pub fn expansion_device(&self) -> u8 {
+        match self {
+            Cartridge::INes(ines) => ines.expansion_device,
+            Cartridge::GameGenie(inner) => inner.expansion_device(),
+            _ => 0,
+        }
+    }
+
+    /// NSF2 files driving the IRQ timer, its $401B-$401D registers overlap the EPSM
+    pub(crate) fn nsf_irq(&self) -> bool {
+        match self {
+            Cartridge::Nsf(nsf) => nsf.features.irq(),
+            Cartridge::GameGenie(inner) => inner.nsf_irq(),
+            _ => false,
+        }
+    }
+
     pub fn with_game_genie(self) -> Self {
         Cartridge::GameGenie(Box::new(self))
     }
+
+    /// Provide the host directory and server used by carts with an ESP Wi-Fi chip
+    pub fn with_esp(self, config: EspConfig) -> Self {
+        match self {
+            Cartridge::INes(mut ines) => {
+                ines.esp = config;
+                Cartridge::INes(ines)
+            }
+            Cartridge::GameGenie(inner) => inner.with_esp(config).with_game_genie(),
+            cart => cart,
+        }
+    }
+}
+
+struct ChunkReader<'a, T: std::io::Read> {
+    ident: [u8; 4],
+    length: usize,
+    file: &'a mut T,
+}
+
+impl<'a, T: std::io::Read> ChunkReader<'a, T> {
+    fn new(file: &'a mut T) -> std::io::Result<Self> {
+        let mut buf = [0; 4];
+        file.read_exact(&mut buf)?;
+        let length = u32::from_le_bytes(buf) as usize;
+        file.read_exact(&mut buf)?;
+        Ok(Self {
+            ident: buf,
+            length,
+            file,
+        })
+    }
+
+    fn ident(&self) -> &[u8; 4] {
+        &self.ident
+    }
+
+    fn skip(self) -> std::io::Result<()> {
+        let skipped = io::copy(&mut self.file.take(self.length as u64), &mut io::sink())?;
+        if skipped != self.length as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(())
+    }
+
+    fn read(self) -> std::io::Result<Vec<u8>> {
+        // Read through `take` so a corrupt length can't allocate more than the file holds
+        let mut data = Vec::new();
+        self.file.take(self.length as u64).read_to_end(&mut data)?;
+        if data.len() != self.length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(data)
+    }
+}
+
+fn read_str<I: Iterator<Item = u8>>(auth: I) -> Option<String> {
+    let mut str = Vec::new();
+    for c in auth {
+        if c == 0 {
+            break;
+        }
+        str.push(c);
+    }
+    let s = String::from_utf8_lossy(&str);
+    if !s.is_empty() {
+        Some(s.to_string())
+    } else {
+        None
+    }
+}
+
+fn unif_mapper(board: &str) -> Option<(u32, Option<u32>)> {
+    let mapper = match mapper::unif_board_name(board) {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" => 0,
+        "SAROM" | "SBROM" | "SCROM" | "SEROM" | "SGROM" | "SKROM" | "SLROM" | "SL1ROM"
+        | "SNROM" | "SOROM" | "SUROM" | "SXROM" => 1,
+        "UNROM" | "UOROM" => 2,
+        "CNROM" => 3,
+        "TBROM" | "TEROM" | "TFROM" | "TGROM" | "TKROM" | "TLROM" | "TL1ROM" | "TNROM"
+        | "TR1ROM" | "TSROM" | "TVROM" => 4,
+        "HKROM" => return Some((4, Some(1))),
+        "EKROM" | "ELROM" | "ETROM" | "EWROM" => 5,
+        "AMROM" | "ANROM" | "AN1ROM" | "AOROM" => 7,
+        "PNROM" | "PEEOROM" => 9,
+        "FJROM" | "FKROM" => 10,
+        "BNROM" => 34,
+        "GNROM" | "MHROM" => 66,
+        _ => return None,
+    };
+
+    Some((mapper, None))
 }
@@ -0,0 +1,1019 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+#[cfg(feature = "save-states")]
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{Address, AddressBus, DeviceKind, RangeAndMask};
+use crate::region::Region;
+
+/// NES 2.0 default expansion device id of the Expansion Port Sound Module
+pub const EPSM_EXPANSION_DEVICE: u8 = 0x4b;
+
+const MASTER_CLOCK: u64 = 8_000_000;
+const FM_DIVIDER: u64 = 144;
+const SSG_DIVIDER: u64 = 32;
+
+/// Expansion Port Sound Module, a YM2608 (OPNA) attached to the expansion port
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct Epsm {
+    enabled: bool,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    cpu_clock: u64,
+    addr: [u8; 2],
+    nibble_latch: Option<u8>,
+    fm_counter: u64,
+    ssg_counter: u64,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    fm: Fm,
+    ssg: Ssg,
+    rhythm: Rhythm,
+    fm_out: i32,
+    rhythm_out: i32,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    tables: LookupTables,
+}
+
+impl Epsm {
+    pub fn new(region: Region) -> Self {
+        Self {
+            enabled: false,
+            cpu_clock: region.cpu_clock().round() as u64,
+            addr: [0; 2],
+            nibble_latch: None,
+            fm_counter: 0,
+            ssg_counter: 0,
+            fm: Fm::new(),
+            ssg: Ssg::new(),
+            rhythm: Rhythm::new(),
+            fm_out: 0,
+            rhythm_out: 0,
+            tables: LookupTables::new(),
+        }
+    }
+
+    pub fn register(&self, cpu: &mut AddressBus) {
+        cpu.register_write(DeviceKind::Epsm, RangeAndMask(0x401c, 0x4020, 0xffff));
+        cpu.register_write(DeviceKind::Epsm, Address(0x4016));
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.enabled = enabled;
+            self.power();
+        }
+    }
+
+    pub fn power(&mut self) {
+        self.addr = [0; 2];
+        self.nibble_latch = None;
+        self.fm = Fm::new();
+        self.ssg = Ssg::new();
+        self.rhythm = Rhythm::new();
+        self.fm_out = 0;
+        self.rhythm_out = 0;
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        match addr {
+            0x401c => self.addr[0] = value,
+            0x401d => self.write_reg(0, self.addr[0], value),
+            0x401e => self.addr[1] = value,
+            0x401f => self.write_reg(1, self.addr[1], value),
+            // Famicom interface, each byte is sent as two nibbles: [DDDD AA1.]
+            // where A selects the chip's A0/A1 lines and the latch is clocked by OUT1
+            0x4016 if value & 0x02 != 0 => {
+                let nibble = value >> 4;
+                let lines = (value >> 2) & 3;
+                if let Some(high) = self.nibble_latch.take() {
+                    let data = high << 4 | nibble;
+                    match lines {
+                        0 => self.addr[0] = data,
+                        1 => self.write_reg(0, self.addr[0], data),
+                        2 => self.addr[1] = data,
+                        _ => self.write_reg(1, self.addr[1], data),
+                    }
+                } else {
+                    self.nibble_latch = Some(nibble);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn write_reg(&mut self, port: usize, reg: u8, value: u8) {
+        match (port, reg) {
+            (0, 0x00..=0x0f) => self.ssg.write(reg, value),
+            (0, 0x10..=0x1f) => self.rhythm.write(reg, value),
+            (0, 0x20..=0x2f) => self.fm.write_global(reg, value),
+            (_, 0x30..=0xb6) => self.fm.write_channel(port, reg, value),
+            // ADPCM-B has no sample memory on the module
+            _ => (),
+        }
+    }
+
+    pub fn tick(&mut self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+
+        self.fm_counter += MASTER_CLOCK;
+        while self.fm_counter >= self.cpu_clock * FM_DIVIDER {
+            self.fm_counter -= self.cpu_clock * FM_DIVIDER;
+            self.fm_out = self.fm.tick(&self.tables);
+            self.rhythm_out = self.rhythm.tick();
+        }
+
+        self.ssg_counter += MASTER_CLOCK;
+        while self.ssg_counter >= self.cpu_clock * SSG_DIVIDER {
+            self.ssg_counter -= self.cpu_clock * SSG_DIVIDER;
+            self.ssg.tick();
+        }
+
+        let out = self.fm_out + self.rhythm_out + self.ssg.output();
+        out.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LookupTables {
+    log_sin_entries: Vec<u16>,
+    exp_entries: Vec<u16>,
+}
+
+impl LookupTables {
+    fn new() -> Self {
+        use std::f32::consts::PI;
+
+        let mut log_sin_entries = Vec::with_capacity(256);
+        let mut exp_entries = Vec::with_capacity(256);
+
+        for n in 0..256 {
+            let n = n as f32;
+            let log_sin = (-(((n + 0.5) * PI / 256.0 / 2.0).sin()).log2() * 256.0).round();
+            let exp = (((n / 256.0).exp2() - 1.0) * 1024.0).round();
+            log_sin_entries.push(log_sin as u16);
+            exp_entries.push(exp as u16);
+        }
+
+        Self {
+            log_sin_entries,
+            exp_entries,
+        }
+    }
+
+    // 10-bit phase to 12-bit attenuation with the sign in bit 15
+    fn log_sin(&self, phase: u16) -> u16 {
+        let sign = phase & 0x200 != 0;
+        let mirror = phase & 0x100 != 0;
+        let phase = phase & 0xff;
+        let phase = if mirror { phase ^ 0xff } else { phase };
+
+        let entry = self.log_sin_entries[phase as usize];
+        if sign { entry | 0x8000 } else { entry }
+    }
+
+    // Returns a 14-bit signed result
+    fn exp(&self, value: u16) -> i32 {
+        let sign = value & 0x8000 != 0;
+        let value = value & 0x7fff;
+        let shift = value >> 8;
+        if shift > 13 {
+            return 0;
+        }
+        let entry = self.exp_entries[(value as usize & 0xff) ^ 0xff] as i32;
+        let result = ((entry | 0x400) << 2) >> shift;
+        if sign { -result } else { result }
+    }
+}
+
+const DETUNE_TABLE: [[u8; 4]; 32] = [
+    [0, 0, 1, 2],
+    [0, 0, 1, 2],
+    [0, 0, 1, 2],
+    [0, 0, 1, 2],
+    [0, 1, 2, 2],
+    [0, 1, 2, 3],
+    [0, 1, 2, 3],
+    [0, 1, 2, 3],
+    [0, 1, 2, 4],
+    [0, 1, 3, 4],
+    [0, 1, 3, 4],
+    [0, 1, 3, 5],
+    [0, 2, 4, 5],
+    [0, 2, 4, 6],
+    [0, 2, 4, 6],
+    [0, 2, 5, 7],
+    [0, 2, 5, 8],
+    [0, 3, 6, 8],
+    [0, 3, 6, 9],
+    [0, 3, 7, 10],
+    [0, 4, 8, 11],
+    [0, 4, 8, 12],
+    [0, 4, 9, 13],
+    [0, 5, 10, 14],
+    [0, 5, 11, 16],
+    [0, 6, 12, 17],
+    [0, 6, 13, 19],
+    [0, 7, 14, 20],
+    [0, 8, 16, 22],
+    [0, 8, 16, 22],
+    [0, 8, 16, 22],
+    [0, 8, 16, 22],
+];
+
+const EG_INC_TABLE: [[u16; 8]; 4] = [
+    [0, 1, 0, 1, 0, 1, 0, 1],
+    [0, 1, 0, 1, 1, 1, 0, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 1],
+];
+
+// Number of fm samples between lfo steps
+const LFO_PERIODS: [u16; 8] = [108, 77, 71, 67, 62, 44, 8, 5];
+// Vibrato depth in tenths of a cent
+const PM_DEPTHS: [i64; 8] = [0, 34, 67, 100, 140, 200, 400, 800];
+const AM_SHIFTS: [u8; 4] = [8, 3, 1, 0];
+
+const MAX_ATTENUATION: u16 = 0x3ff;
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EnvelopePhase {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+struct Operator {
+    detune: u8,
+    multiple: u8,
+    total_level: u8,
+    key_scale: u8,
+    attack_rate: u8,
+    am_enable: bool,
+    decay_rate: u8,
+    sustain_rate: u8,
+    sustain_level: u8,
+    release_rate: u8,
+    phase: u32,
+    attenuation: u16,
+    envelope_phase: EnvelopePhase,
+    key_on: bool,
+}
+
+impl Operator {
+    fn new() -> Self {
+        Self {
+            detune: 0,
+            multiple: 0,
+            total_level: 0x7f,
+            key_scale: 0,
+            attack_rate: 0,
+            am_enable: false,
+            decay_rate: 0,
+            sustain_rate: 0,
+            sustain_level: 0,
+            release_rate: 0,
+            phase: 0,
+            attenuation: MAX_ATTENUATION,
+            envelope_phase: EnvelopePhase::Release,
+            key_on: false,
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        match reg & 0xf0 {
+            0x30 => {
+                self.detune = (value >> 4) & 7;
+                self.multiple = value & 0xf;
+            }
+            0x40 => self.total_level = value & 0x7f,
+            0x50 => {
+                self.key_scale = value >> 6;
+                self.attack_rate = value & 0x1f;
+            }
+            0x60 => {
+                self.am_enable = value & 0x80 != 0;
+                self.decay_rate = value & 0x1f;
+            }
+            0x70 => self.sustain_rate = value & 0x1f,
+            0x80 => {
+                self.sustain_level = value >> 4;
+                self.release_rate = value & 0xf;
+            }
+            _ => (),
+        }
+    }
+
+    fn set_key(&mut self, on: bool) {
+        if on && !self.key_on {
+            self.phase = 0;
+            self.envelope_phase = EnvelopePhase::Attack;
+        } else if !on && self.key_on {
+            self.envelope_phase = EnvelopePhase::Release;
+        }
+        self.key_on = on;
+    }
+
+    fn phase_increment(&self, fnum: u32, block: u32, key_code: u8) -> u32 {
+        let base = (fnum << block) >> 1;
+        let detune = DETUNE_TABLE[key_code as usize][self.detune as usize & 3] as u32;
+        let base = if self.detune & 4 != 0 {
+            base.wrapping_sub(detune) & 0x1ffff
+        } else {
+            base + detune
+        };
+
+        if self.multiple == 0 {
+            base >> 1
+        } else {
+            base * self.multiple as u32
+        }
+    }
+
+    fn clock_envelope(&mut self, eg_counter: u32, key_code: u8) {
+        if self.envelope_phase == EnvelopePhase::Decay
+            && self.attenuation >= (self.sustain_level as u16) << 5
+        {
+            self.envelope_phase = EnvelopePhase::Sustain;
+        }
+
+        let rate = match self.envelope_phase {
+            EnvelopePhase::Attack => self.attack_rate,
+            EnvelopePhase::Decay => self.decay_rate,
+            EnvelopePhase::Sustain => self.sustain_rate,
+            EnvelopePhase::Release => self.release_rate << 1 | 1,
+        };
+
+        if rate == 0 {
+            return;
+        }
+
+        let rate = (rate as u32 * 2 + (key_code as u32 >> (3 - self.key_scale))).min(63);
+        let shift = 11u32.saturating_sub(rate >> 2);
+        if eg_counter & ((1 << shift) - 1) != 0 {
+            return;
+        }
+
+        let step = (eg_counter >> shift) as usize & 7;
+        let mult = if rate >> 2 > 11 {
+            1 << ((rate >> 2) - 11)
+        } else {
+            1
+        };
+        let inc = EG_INC_TABLE[rate as usize & 3][step] * mult;
+
+        match self.envelope_phase {
+            EnvelopePhase::Attack => {
+                if rate >= 62 {
+                    self.attenuation = 0;
+                } else if inc > 0 {
+                    let att = self.attenuation as i32;
+                    let att = att + ((!att * inc as i32) >> 4);
+                    self.attenuation = att.max(0) as u16;
+                }
+                if self.attenuation == 0 {
+                    self.envelope_phase = EnvelopePhase::Decay;
+                }
+            }
+            _ => {
+                self.attenuation = (self.attenuation + inc).min(MAX_ATTENUATION);
+            }
+        }
+    }
+
+    fn output(&self, tables: &LookupTables, modulation: i32, am: u16) -> i32 {
+        let phase = ((self.phase >> 10) as i32 + modulation) as u16 & 0x3ff;
+        let am = if self.am_enable { am } else { 0 };
+        let attenuation =
+            (self.attenuation + ((self.total_level as u16) << 3) + am).min(MAX_ATTENUATION);
+        let sin = tables.log_sin(phase);
+        let level = (sin & 0x7fff) + (attenuation << 2);
+        tables.exp(level | (sin & 0x8000))
+    }
+}
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+struct FmChannel {
+    operators: [Operator; 4],
+    fnum: u16,
+    block: u8,
+    feedback: u8,
+    algorithm: u8,
+    left: bool,
+    right: bool,
+    ams: u8,
+    pms: u8,
+    feedback_out: [i32; 2],
+}
+
+impl FmChannel {
+    fn new() -> Self {
+        Self {
+            operators: [
+                Operator::new(),
+                Operator::new(),
+                Operator::new(),
+                Operator::new(),
+            ],
+            fnum: 0,
+            block: 0,
+            feedback: 0,
+            algorithm: 0,
+            left: true,
+            right: true,
+            ams: 0,
+            pms: 0,
+            feedback_out: [0; 2],
+        }
+    }
+
+    fn key_code(&self) -> u8 {
+        let f11 = self.fnum & 0x400 != 0;
+        let f10 = self.fnum & 0x200 != 0;
+        let f9 = self.fnum & 0x100 != 0;
+        let f8 = self.fnum & 0x080 != 0;
+        let n3 = (f11 && (f10 || f9 || f8)) || (!f11 && f10 && f9 && f8);
+        (self.block << 2) | (f11 as u8) << 1 | n3 as u8
+    }
+
+    fn tick(&mut self, tables: &LookupTables, eg_counter: Option<u32>, lfo: u8) -> i32 {
+        let key_code = self.key_code();
+        let fnum = if self.pms != 0 {
+            // triangle centered on zero in the range -32..32
+            let wave = if lfo < 64 {
+                lfo as i64
+            } else {
+                127 - lfo as i64
+            } - 32;
+            let fnum = self.fnum as i64;
+            let adj = fnum * PM_DEPTHS[self.pms as usize] * wave / (17312 * 32);
+            (fnum + adj).clamp(0, 0x7ff) as u32
+        } else {
+            self.fnum as u32
+        };
+
+        let am_wave = if lfo < 64 { lfo } else { 127 - lfo } as u16;
+        let am = (am_wave << 1) >> AM_SHIFTS[self.ams as usize];
+
+        for op in self.operators.iter_mut() {
+            let inc = op.phase_increment(fnum, self.block as u32, key_code);
+            op.phase = op.phase.wrapping_add(inc) & 0xfffff;
+            if let Some(eg_counter) = eg_counter {
+                op.clock_envelope(eg_counter, key_code);
+            }
+        }
+
+        let fb = if self.feedback == 0 {
+            0
+        } else {
+            (self.feedback_out[0] + self.feedback_out[1]) >> (10 - self.feedback)
+        };
+
+        let [op1, op2, op3, op4] = &self.operators;
+        let out1 = op1.output(tables, fb, am);
+        self.feedback_out = [self.feedback_out[1], out1];
+        let m1 = out1 >> 1;
+
+        let out = match self.algorithm {
+            0 => {
+                let out2 = op2.output(tables, m1, am);
+                let out3 = op3.output(tables, out2 >> 1, am);
+                op4.output(tables, out3 >> 1, am)
+            }
+            1 => {
+                let out2 = op2.output(tables, 0, am);
+                let out3 = op3.output(tables, m1 + (out2 >> 1), am);
+                op4.output(tables, out3 >> 1, am)
+            }
+            2 => {
+                let out2 = op2.output(tables, 0, am);
+                let out3 = op3.output(tables, out2 >> 1, am);
+                op4.output(tables, m1 + (out3 >> 1), am)
+            }
+            3 => {
+                let out2 = op2.output(tables, m1, am);
+                let out3 = op3.output(tables, 0, am);
+                op4.output(tables, (out2 >> 1) + (out3 >> 1), am)
+            }
+            4 => {
+                let out2 = op2.output(tables, m1, am);
+                let out3 = op3.output(tables, 0, am);
+                out2 + op4.output(tables, out3 >> 1, am)
+            }
+            5 => {
+                op2.output(tables, m1, am) + op3.output(tables, m1, am) + op4.output(tables, m1, am)
+            }
+            6 => op2.output(tables, m1, am) + op3.output(tables, 0, am) + op4.output(tables, 0, am),
+            _ => {
+                out1 + op2.output(tables, 0, am)
+                    + op3.output(tables, 0, am)
+                    + op4.output(tables, 0, am)
+            }
+        };
+
+        if self.left || self.right {
+            out.clamp(-8192, 8191)
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+#[derive(Debug, Clone)]
+struct Fm {
+    channels: [FmChannel; 6],
+    fnum_latch: [u8; 2],
+    six_channels: bool,
+    lfo_enable: bool,
+    lfo_rate: u8,
+    lfo_counter: u16,
+    lfo_step: u8,
+    eg_divider: u8,
+    eg_counter: u32,
+}
+
+impl Fm {
+    fn new() -> Self {
+        Self {
+            channels: [
+                FmChannel::new(),
+                FmChannel::new(),
+                FmChannel::new(),
+                FmChannel::new(),
+                FmChannel::new(),
+                FmChannel::new(),
+            ],
+            fnum_latch: [0; 2],
+            six_channels: false,
+            lfo_enable: false,
+            lfo_rate: 0,
+            lfo_counter: 0,
+            lfo_step: 0,
+            eg_divider: 0,
+            eg_counter: 0,
+        }
+    }
+
+    fn write_global(&mut self, reg: u8, value: u8) {
+        match reg {
+            0x22 => {
+                self.lfo_enable = value & 0x08 != 0;
+                self.lfo_rate = value & 0x07;
+                if !self.lfo_enable {
+                    self.lfo_step = 0;
+                }
+            }
+            0x28 => {
+                let channel = match value & 0x07 {
+                    c @ 0..=2 => c as usize,
+                    c @ 4..=6 => c as usize - 1,
+                    _ => return,
+                };
+                // slot bits are ordered S1, S2, S3, S4 which map to operators 1, 2, 3, 4
+                for (idx, op) in self.channels[channel].operators.iter_mut().enumerate() {
+                    op.set_key(value & (0x10 << idx) != 0);
+                }
+            }
+            0x29 => self.six_channels = value & 0x80 != 0,
+            _ => (),
+        }
+    }
+
+    fn write_channel(&mut self, port: usize, reg: u8, value: u8) {
+        let offset = (reg & 3) as usize;
+        if offset == 3 {
+            return;
+        }
+        let channel = &mut self.channels[port * 3 + offset];
+
+        match reg {
+            0x30..=0x9f => {
+                // registers are ordered S1, S3, S2, S4
+                let op = match (reg >> 2) & 3 {
+                    0 => 0,
+                    1 => 2,
+                    2 => 1,
+                    _ => 3,
+                };
+                channel.operators[op].write(reg, value);
+            }
+            0xa0..=0xa2 => {
+                let hi = self.fnum_latch[port];
+                channel.block = (hi >> 3) & 7;
+                channel.fnum = ((hi as u16 & 7) << 8) | value as u16;
+            }
+            0xa4..=0xa6 => self.fnum_latch[port] = value,
+            0xb0..=0xb2 => {
+                channel.feedback = (value >> 3) & 7;
+                channel.algorithm = value & 7;
+            }
+            0xb4..=0xb6 => {
+                channel.left = value & 0x80 != 0;
+                channel.right = value & 0x40 != 0;
+                channel.ams = (value >> 4) & 3;
+                channel.pms = value & 7;
+            }
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, tables: &LookupTables) -> i32 {
+        self.eg_divider += 1;
+        let eg_counter = if self.eg_divider == 3 {
+            self.eg_divider = 0;
+            self.eg_counter = self.eg_counter.wrapping_add(1) & 0xfff;
+            Some(self.eg_counter)
+        } else {
+            None
+        };
+
+        if self.lfo_enable {
+            self.lfo_counter += 1;
+            if self.lfo_counter >= LFO_PERIODS[self.lfo_rate as usize] {
+                self.lfo_counter = 0;
+                self.lfo_step = (self.lfo_step + 1) & 0x7f;
+            }
+        }
+
+        let count = if self.six_channels { 6 } else { 3 };
+        let mut out = 0;
+        for channel in self.channels.iter_mut().take(count) {
+            out += channel.tick(tables, eg_counter, self.lfo_step);
+        }
+
+        // Leave headroom for all six channels playing at full volume
+        out / 4
+    }
+}
+
+const SSG_VOLUME: [i32; 32] = [
+    0, 30, 43, 61, 86, 122, 172, 243, 344, 486, 687, 971, 1372, 1939, 2740, 3871, 5470, 7729,
+    10921, 15432, 21806, 30812, 43538, 61520, 86930, 122834, 173566, 245251, 346544, 489674,
+    691917, 977689,
+];
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+struct Ssg {
+    regs: [u8; 16],
+    tone_counters: [u16; 3],
+    tone_state: [bool; 3],
+    noise_counter: u16,
+    noise_seed: u32,
+    noise_prescale: bool,
+    envelope_counter: u32,
+    envelope_step: u8,
+    envelope_holding: bool,
+    envelope_attack: bool,
+}
+
+impl Ssg {
+    fn new() -> Self {
+        Self {
+            regs: [0; 16],
+            tone_counters: [0; 3],
+            tone_state: [false; 3],
+            noise_counter: 0,
+            noise_seed: 1,
+            noise_prescale: false,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_holding: false,
+            envelope_attack: false,
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        self.regs[reg as usize & 0xf] = value;
+        if reg == 0x0d {
+            self.envelope_step = 0;
+            self.envelope_counter = 0;
+            self.envelope_holding = false;
+            self.envelope_attack = value & 0x04 != 0;
+        }
+    }
+
+    fn tick(&mut self) {
+        for ch in 0..3 {
+            let period =
+                (self.regs[ch * 2] as u16 | (self.regs[ch * 2 + 1] as u16 & 0xf) << 8).max(1);
+            self.tone_counters[ch] += 1;
+            if self.tone_counters[ch] >= period {
+                self.tone_counters[ch] = 0;
+                self.tone_state[ch] = !self.tone_state[ch];
+            }
+        }
+
+        self.noise_prescale = !self.noise_prescale;
+        if self.noise_prescale {
+            let period = (self.regs[6] as u16 & 0x1f).max(1);
+            self.noise_counter += 1;
+            if self.noise_counter >= period {
+                self.noise_counter = 0;
+                let bit = (self.noise_seed ^ (self.noise_seed >> 3)) & 1;
+                self.noise_seed = (self.noise_seed >> 1) | (bit << 16);
+            }
+        }
+
+        let period = (self.regs[0xb] as u32 | (self.regs[0xc] as u32) << 8).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter >= period {
+            self.envelope_counter = 0;
+            self.step_envelope();
+        }
+    }
+
+    fn step_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+
+        self.envelope_step += 1;
+        if self.envelope_step < 32 {
+            return;
+        }
+
+        let shape = self.regs[0xd];
+        let cont = shape & 0x08 != 0;
+        let alt = shape & 0x02 != 0;
+        let hold = shape & 0x01 != 0;
+
+        if !cont {
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+            self.envelope_step = 31;
+        } else if hold {
+            self.envelope_holding = true;
+            if alt {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 31;
+        } else {
+            if alt {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+
+    fn envelope_volume(&self) -> usize {
+        if self.envelope_holding {
+            return if self.envelope_attack { 31 } else { 0 };
+        }
+
+        if self.envelope_attack {
+            self.envelope_step as usize
+        } else {
+            31 - self.envelope_step as usize
+        }
+    }
+
+    fn output(&self) -> i32 {
+        let mixer = self.regs[7];
+        let noise = self.noise_seed & 1 != 0;
+        let mut out = 0;
+        for ch in 0..3 {
+            let tone_off = mixer & (1 << ch) != 0;
+            let noise_off = mixer & (8 << ch) != 0;
+            let on = (tone_off || self.tone_state[ch]) && (noise_off || noise);
+            if !on {
+                continue;
+            }
+
+            let level = self.regs[8 + ch];
+            let volume = if level & 0x10 != 0 {
+                self.envelope_volume()
+            } else if level & 0xf == 0 {
+                0
+            } else {
+                ((level & 0xf) as usize) << 1 | 1
+            };
+
+            out += SSG_VOLUME[volume];
+        }
+
+        // Full scale of the three channels is scaled to roughly a quarter of the output range
+        out / 360
+    }
+}
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+struct Drum {
+    level: u8,
+    muted: bool,
+    enabled: bool,
+    time: u32,
+}
+
+// The YM2608 rhythm rom is not available, each instrument is approximated with
+// simple tone and noise bursts tuned to roughly match the sampled drums
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+struct Rhythm {
+    total_level: u8,
+    drums: [Drum; 6],
+    noise_seed: u32,
+}
+
+const FM_SAMPLE_RATE: f32 = MASTER_CLOCK as f32 / FM_DIVIDER as f32;
+
+impl Rhythm {
+    fn new() -> Self {
+        let drum = Drum {
+            level: 0,
+            muted: false,
+            enabled: false,
+            time: 0,
+        };
+        Self {
+            total_level: 0,
+            drums: [
+                drum.clone(),
+                drum.clone(),
+                drum.clone(),
+                drum.clone(),
+                drum.clone(),
+                drum,
+            ],
+            noise_seed: 1,
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        match reg {
+            0x10 => {
+                let dump = value & 0x80 != 0;
+                for (idx, drum) in self.drums.iter_mut().enumerate() {
+                    if value & (1 << idx) == 0 {
+                        continue;
+                    }
+                    if dump {
+                        drum.enabled = false;
+                    } else {
+                        drum.enabled = true;
+                        drum.time = 0;
+                    }
+                }
+            }
+            0x11 => self.total_level = value & 0x3f,
+            0x18..=0x1d => {
+                let drum = &mut self.drums[(reg - 0x18) as usize];
+                // A drum panned to neither side isn't heard
+                drum.muted = value & 0xc0 == 0;
+                drum.level = value & 0x1f;
+            }
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self) -> i32 {
+        let bit = (self.noise_seed ^ (self.noise_seed >> 2)) & 1;
+        self.noise_seed = (self.noise_seed >> 1) | (bit << 22);
+        let noise = if self.noise_seed & 1 != 0 { 1.0 } else { -1.0 };
+
+        let master = attenuation_gain(0x3f - self.total_level);
+        let mut out = 0.0;
+
+        for (idx, drum) in self.drums.iter_mut().enumerate() {
+            if !drum.enabled {
+                continue;
+            }
+
+            let t = drum.time as f32 / FM_SAMPLE_RATE;
+            drum.time += 1;
+
+            // (decay time, output) for bass drum, snare, top cymbal, hi-hat, tom, rim shot
+            let (decay, sample) = match idx {
+                0 => (0.25, sine(t, 150.0 - 100.0 * (t * 8.0).min(1.0))),
+                1 => (0.15, 0.6 * noise + 0.4 * sine(t, 180.0)),
+                2 => (0.5, 0.5 * noise),
+                3 => (0.06, 0.5 * noise),
+                4 => (0.3, sine(t, 120.0 - 30.0 * (t * 4.0).min(1.0))),
+                _ => (0.03, sine(t, 500.0)),
+            };
+
+            if t > decay * 6.0 {
+                drum.enabled = false;
+                continue;
+            }
+
+            if drum.muted {
+                continue;
+            }
+
+            let envelope = (-t / decay).exp();
+            out += sample * envelope * attenuation_gain(0x1f - drum.level);
+        }
+
+        (out * master * 6000.0) as i32
+    }
+}
+
+// Levels are attenuation in 0.75dB steps
+fn attenuation_gain(level: u8) -> f32 {
+    10f32.powf(-(level as f32 * 0.75) / 20.0)
+}
+
+fn sine(t: f32, freq: f32) -> f32 {
+    (t * freq * std::f32::consts::TAU).sin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn epsm() -> Epsm {
+        let mut epsm = Epsm::new(Region::Ntsc);
+        epsm.set_enabled(true);
+        epsm
+    }
+
+    fn write_reg(epsm: &mut Epsm, port: u16, reg: u8, value: u8) {
+        epsm.write(0x401c + port * 2, reg);
+        epsm.write(0x401d + port * 2, value);
+    }
+
+    #[test]
+    fn register_writes() {
+        let mut epsm = epsm();
+
+        write_reg(&mut epsm, 0, 0xa4, 0x22);
+        write_reg(&mut epsm, 0, 0xa0, 0x69);
+        assert_eq!(epsm.fm.channels[0].fnum, 0x269);
+        assert_eq!(epsm.fm.channels[0].block, 4);
+
+        write_reg(&mut epsm, 1, 0xb1, 0x3d);
+        assert_eq!(epsm.fm.channels[4].feedback, 7);
+        assert_eq!(epsm.fm.channels[4].algorithm, 5);
+        assert_eq!(epsm.fm.channels[1].algorithm, 0);
+
+        write_reg(&mut epsm, 0, 0x07, 0x38);
+        assert_eq!(epsm.ssg.regs[7], 0x38);
+    }
+
+    #[test]
+    fn disabled_ignores_writes() {
+        let mut epsm = Epsm::new(Region::Ntsc);
+        write_reg(&mut epsm, 0, 0xb0, 0x07);
+        assert_eq!(epsm.fm.channels[0].algorithm, 0);
+    }
+
+    #[test]
+    fn key_on_produces_samples() {
+        let mut epsm = epsm();
+
+        // Every operator a carrier at full volume with an instant attack
+        write_reg(&mut epsm, 0, 0xb0, 0x07);
+        for reg in [0x30, 0x34, 0x38, 0x3c] {
+            write_reg(&mut epsm, 0, reg, 0x01);
+            write_reg(&mut epsm, 0, reg + 0x10, 0x00);
+            write_reg(&mut epsm, 0, reg + 0x20, 0x1f);
+        }
+        write_reg(&mut epsm, 0, 0xa4, 0x22);
+        write_reg(&mut epsm, 0, 0xa0, 0x69);
+
+        assert!((0..10_000).all(|_| epsm.tick() == 0));
+
+        write_reg(&mut epsm, 0, 0x28, 0xf0);
+        assert!((0..10_000).any(|_| epsm.tick() != 0));
+    }
+
+    #[test]
+    fn rhythm_pan_mutes_drum() {
+        let mut rhythm = Rhythm::new();
+        rhythm.write(0x11, 0x3f);
+
+        rhythm.write(0x18, 0xdf);
+        rhythm.write(0x10, 0x01);
+        assert!((0..1000).any(|_| rhythm.tick() != 0));
+
+        rhythm.write(0x18, 0x1f);
+        rhythm.write(0x10, 0x01);
+        assert!((0..1000).all(|_| rhythm.tick() == 0));
+
+        // Total level 0 is the quietest setting
+        rhythm.write(0x11, 0x00);
+        rhythm.write(0x18, 0xdf);
+        rhythm.write(0x10, 0x01);
+        let quiet = (0..1000).map(|_| rhythm.tick().abs()).max();
+        rhythm.write(0x11, 0x3f);
+        rhythm.write(0x10, 0x01);
+        let loud = (0..1000).map(|_| rhythm.tick().abs()).max();
+        assert!(quiet < loud);
+    }
+}
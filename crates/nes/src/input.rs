@@ -1,11 +1,36 @@
 #[cfg(feature = "save-states")]
 use nes_traits::SaveState;
+#[cfg(feature = "save-states")]
+use serde::{Deserialize, Serialize};
 
 use crate::{
     MapperInput,
     bus::{Address, AddressBus, AndEqualsAndMask, DeviceKind},
+    ppu::Ppu,
 };
 
+mod data_recorder;
+mod joypad;
+mod keyboard;
+mod mouse;
+mod multitap;
+mod power_pad;
+mod vaus;
+mod zapper;
+
+pub use data_recorder::{Tape, TapeControl};
+pub use keyboard::{FamilyKey, FamilyKeyboard};
+pub use power_pad::{PowerPad, PowerPadSide};
+
+use data_recorder::DataRecorder;
+use joypad::Joypad;
+use keyboard::Keyboard;
+use mouse::{SnesMouse, SuborMouse};
+use multitap::{FourScore, HoriAdapter};
+use power_pad::{FloorMat, TrainerMat};
+use vaus::Paddle;
+use zapper::LightGun;
+
 pub trait InputDevice {
     fn to_byte(&self) -> u8;
 }
@@ -69,21 +94,227 @@ impl InputDevice for Controller {
     }
 }
 
+/// Light gun aim and trigger, `aim` is in screen pixels and `None` when pointed away
+/// from the screen
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Zapper {
+    pub aim: Option<(u16, u16)>,
+    pub trigger: bool,
+}
+
+/// Arkanoid paddle knob and button, `position` is the 9 bit value reported to the game
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Vaus {
+    pub position: u16,
+    pub button: bool,
+}
+
+/// Host mouse motion and buttons, `x` and `y` count the total distance moved in mouse units
+/// and wrap around. Mice report how far they have moved since they were last read
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Mouse {
+    pub x: i32,
+    pub y: i32,
+    pub left: bool,
+    pub right: bool,
+}
+
+/// The connectors peripherals can be plugged into
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Port {
+    /// Controller port read through $4016
+    One,
+    /// Controller port read through $4017
+    Two,
+    /// Famicom expansion port, can drive lines of both $4016 and $4017
+    Expansion,
+}
+
+/// The peripherals that can be plugged into a [`Port`]
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PortDeviceKind {
+    Unplugged,
+    /// Standard joypad, on the expansion port it acts as a second player one controller
+    Controller,
+    /// Light gun, senses the brightness of the rendered frame around where it is aimed
+    Zapper,
+    /// NES four player adapter, plugged into both controller ports to connect players 1-4
+    FourScore,
+    /// Famicom four player adapter for the expansion port, connects players 3 and 4
+    Hori,
+    /// Arkanoid paddle, the NES version plugs into port 2 and the Famicom version into
+    /// the expansion port
+    Vaus,
+    /// Family BASIC keyboard for the expansion port, with a Data Recorder attached
+    FamilyKeyboard,
+    /// NES Power Pad floor mat for a controller port
+    PowerPad,
+    /// Famicom Family Trainer floor mat for the expansion port
+    FamilyTrainer,
+    /// Super NES mouse on a controller port
+    SnesMouse,
+    /// Subor mouse, used by the Subor educational computers
+    SuborMouse,
+}
+
+impl PortDeviceKind {
+    fn build(self, port: Port) -> Box<dyn PortDeviceState> {
+        match self {
+            PortDeviceKind::Unplugged => Box::new(Unplugged),
+            PortDeviceKind::Controller => Box::new(Joypad::new(port)),
+            PortDeviceKind::Zapper => Box::new(LightGun::new(port)),
+            PortDeviceKind::FourScore => Box::new(FourScore::new(port)),
+            PortDeviceKind::Hori => Box::new(HoriAdapter::new()),
+            PortDeviceKind::Vaus => Box::new(Paddle::new(port)),
+            PortDeviceKind::FamilyKeyboard => Box::new(Keyboard::new()),
+            PortDeviceKind::PowerPad => Box::new(FloorMat::new(port)),
+            PortDeviceKind::FamilyTrainer => Box::new(TrainerMat::new()),
+            PortDeviceKind::SnesMouse => Box::new(SnesMouse::new(port)),
+            PortDeviceKind::SuborMouse => Box::new(SuborMouse::new(port)),
+        }
+    }
+}
+
+/// Snapshot of the user input for every device plugged into the console
+#[derive(Debug, Copy, Clone, Default)]
+pub struct InputState {
+    pub controllers: [Controller; 4],
+    pub zapper: Zapper,
+    pub vaus: Vaus,
+    pub keyboard: FamilyKeyboard,
+    pub power_pad: PowerPad,
+    pub mouse: Mouse,
+    /// Famicom player two microphone, held high while it picks up sound
+    pub microphone: bool,
+}
+
+pub(crate) trait PortDevice {
+    /// Write to $4016, `value` holds the OUT0-OUT2 lines
+    fn write(&mut self, _value: u8) {}
+
+    /// Called every other cycle while OUT0 is held high, `input` is polled from the input
+    /// source once per strobe
+    fn strobe(&mut self, _input: &InputState) {}
+
+    /// Called at the start of every frame with the latest input, for devices that are
+    /// read without being strobed
+    fn update(&mut self, _input: &InputState) {}
+
+    /// Called every cpu cycle, only for devices that return true from `ticks`
+    fn tick(&mut self) {}
+
+    fn ticks(&self) -> bool {
+        false
+    }
+
+    /// Read from $4016 or $4017, the D0-D4 lines are returned in the low 5 bits
+    fn read(&mut self, addr: u16, ppu: &Ppu) -> u8 {
+        self.peek(addr, ppu)
+    }
+
+    fn peek(&self, addr: u16, ppu: &Ppu) -> u8;
+
+    fn data_recorder(&mut self) -> Option<&mut DataRecorder> {
+        None
+    }
+}
+
+#[cfg(feature = "save-states")]
+mod traits {
+    use nes_traits::BinarySaveState;
+
+    pub(crate) trait PortDeviceState: super::PortDevice + BinarySaveState {}
+    impl<T: super::PortDevice + BinarySaveState> PortDeviceState for T {}
+}
+
+#[cfg(not(feature = "save-states"))]
+mod traits {
+    pub(crate) trait PortDeviceState: super::PortDevice {}
+    impl<T: super::PortDevice> PortDeviceState for T {}
+}
+
+use traits::PortDeviceState;
+
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+struct Unplugged;
+
+impl PortDevice for Unplugged {
+    fn peek(&self, _addr: u16, _ppu: &Ppu) -> u8 {
+        0
+    }
+}
+
+struct PortSlot {
+    port: Port,
+    kind: PortDeviceKind,
+    ticks: bool,
+    device: Box<dyn PortDeviceState>,
+}
+
+impl PortSlot {
+    fn new(port: Port, kind: PortDeviceKind) -> Self {
+        let device = kind.build(port);
+        Self {
+            port,
+            kind,
+            ticks: device.ticks(),
+            device,
+        }
+    }
+
+    fn plug(&mut self, kind: PortDeviceKind) {
+        *self = PortSlot::new(self.port, kind);
+    }
+}
+
+// Devices are restored from the kind stored alongside their state, so loading a save
+// state reconnects whatever was plugged in when it was made
+#[cfg(feature = "save-states")]
+impl SaveState for PortSlot {
+    type Data = (PortDeviceKind, Vec<u8>);
+
+    fn save_state(&self) -> Self::Data {
+        (self.kind, self.device.binary_save_state())
+    }
+
+    fn restore_state(&mut self, state: &Self::Data) {
+        let (kind, data) = state;
+        if *kind != self.kind {
+            self.plug(*kind);
+        }
+        self.device.binary_restore_state(data);
+    }
+}
+
 #[cfg_attr(feature = "save-states", derive(SaveState))]
-#[derive(Default)]
 pub struct Input {
     current_tick: u32,
     strobe: bool,
     did_stobe: bool,
-    read_counter: [u32; 2],
-    read_shifter: [u8; 2],
-    input_buffer: [u8; 2],
+    frame_read: bool,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    polled: InputState,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    port_one: PortSlot,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    port_two: PortSlot,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    expansion: PortSlot,
 }
 
 impl Input {
     pub fn new() -> Input {
         Input {
-            ..Default::default()
+            current_tick: 0,
+            strobe: false,
+            did_stobe: false,
+            frame_read: false,
+            polled: InputState::default(),
+            port_one: PortSlot::new(Port::One, PortDeviceKind::Controller),
+            port_two: PortSlot::new(Port::Two, PortDeviceKind::Controller),
+            expansion: PortSlot::new(Port::Expansion, PortDeviceKind::Unplugged),
         }
     }
 
@@ -93,50 +324,58 @@ impl Input {
         cpu.register_write(DeviceKind::Input, Address(0x4016));
     }
 
+    fn slot(&self, port: Port) -> &PortSlot {
+        match port {
+            Port::One => &self.port_one,
+            Port::Two => &self.port_two,
+            Port::Expansion => &self.expansion,
+        }
+    }
+
     #[cfg(feature = "debugger")]
-    pub fn peek(&self, addr: u16, open_bus: u8) -> u8 {
+    fn slots(&self) -> [&PortSlot; 3] {
+        [&self.port_one, &self.port_two, &self.expansion]
+    }
+
+    fn slots_mut(&mut self) -> [&mut PortSlot; 3] {
+        [&mut self.port_one, &mut self.port_two, &mut self.expansion]
+    }
+
+    pub fn device(&self, port: Port) -> PortDeviceKind {
+        self.slot(port).kind
+    }
+
+    pub fn set_device(&mut self, port: Port, kind: PortDeviceKind) {
+        let slot = match port {
+            Port::One => &mut self.port_one,
+            Port::Two => &mut self.port_two,
+            Port::Expansion => &mut self.expansion,
+        };
+        slot.plug(kind);
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn peek(&self, addr: u16, open_bus: u8, ppu: &Ppu) -> u8 {
         let value = match addr {
-            0x4016 => {
-                if self.read_counter[0] == 0 {
-                    0x01
-                } else {
-                    self.read_shifter[0] & 1
-                }
-            }
-            0x4017 => {
-                if self.read_counter[1] == 0 {
-                    0x01
-                } else {
-                    self.read_shifter[1] & 1
-                }
-            }
+            0x4016 | 0x4017 => self
+                .slots()
+                .into_iter()
+                .fold(0, |value, slot| value | slot.device.peek(addr, ppu)),
             _ => open_bus,
         };
 
         value | (open_bus & 0xe0)
     }
 
-    pub fn read(&mut self, addr: u16, open_bus: u8) -> u8 {
+    // Every device sees reads of both registers, the Famicom controller II microphone is on
+    // $4016 despite the controller being read through $4017
+    pub fn read(&mut self, addr: u16, open_bus: u8, ppu: &Ppu) -> u8 {
         let value = match addr {
-            0x4016 => {
-                if self.read_counter[0] == 0 {
-                    0x01
-                } else {
-                    let value = self.read_shifter[0] & 1;
-                    self.read_shifter[0] >>= 1;
-                    self.read_counter[0] -= 1;
-                    value
-                }
-            }
-            0x4017 => {
-                if self.read_counter[1] == 0 {
-                    0x01
-                } else {
-                    let value = self.read_shifter[1] & 1;
-                    self.read_shifter[1] >>= 1;
-                    self.read_counter[1] -= 1;
-                    value
-                }
+            0x4016 | 0x4017 => {
+                self.frame_read = true;
+                self.slots_mut()
+                    .into_iter()
+                    .fold(0, |value, slot| value | slot.device.read(addr, ppu))
             }
             _ => open_bus,
         };
@@ -152,29 +391,60 @@ impl Input {
                     self.did_stobe = false;
                 } else {
                     self.strobe = false;
-                    self.read_shifter = self.input_buffer;
+                }
+
+                for slot in self.slots_mut() {
+                    slot.device.write(value);
                 }
             }
             _ => unimplemented!(),
         }
     }
 
+    /// Whether the ports were read since the last call, frames that never read them are lag
+    /// frames
+    pub fn take_frame_read(&mut self) -> bool {
+        std::mem::take(&mut self.frame_read)
+    }
+
+    pub fn update<I: InputSource>(&mut self, input_source: &I) {
+        let input = input_source.peek();
+        for slot in self.slots_mut() {
+            slot.device.update(&input);
+        }
+    }
+
+    pub fn data_recorder(&mut self) -> Option<&mut DataRecorder> {
+        self.slots_mut()
+            .into_iter()
+            .find_map(|slot| slot.device.data_recorder())
+    }
+
     pub fn tick<I: InputSource>(&mut self, input_source: &mut I) {
         self.current_tick += 1;
+        for slot in self.slots_mut() {
+            if slot.ticks {
+                slot.device.tick();
+            }
+        }
+
         if self.strobe && self.current_tick & 1 == 0 {
             if !self.did_stobe {
-                let (p1, p2) = input_source.strobe();
-                self.input_buffer = [p1.to_byte(), p2.to_byte()];
+                self.polled = input_source.strobe();
                 self.did_stobe = true;
             }
-            self.read_counter = [8, 8];
+
+            let polled = self.polled;
+            for slot in self.slots_mut() {
+                slot.device.strobe(&polled);
+            }
         }
     }
 }
 
 pub trait InputSource {
-    fn strobe(&mut self) -> (Controller, Controller);
-    fn peek(&self) -> (Controller, Controller);
+    fn strobe(&mut self) -> InputState;
+    fn peek(&self) -> InputState;
     fn power(&mut self) -> bool;
     fn reset(&mut self) -> bool;
     fn mapper(&mut self) -> Option<MapperInput>;
@@ -184,14 +454,21 @@ pub struct SimpleInput {
     power: bool,
     reset: bool,
     mapper: Option<MapperInput>,
-    player_one: Controller,
-    player_two: Controller,
+    state: InputState,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum UserInput {
     PlayerOne(Controller),
     PlayerTwo(Controller),
+    /// Controller for players 1-4, indexed from 0
+    Player(usize, Controller),
+    Zapper(Zapper),
+    Vaus(Vaus),
+    Keyboard(FamilyKeyboard),
+    PowerPad(PowerPad),
+    Microphone(bool),
+    Mouse(Mouse),
     Mapper(MapperInput),
     Power,
     Reset,
@@ -203,15 +480,25 @@ impl SimpleInput {
             power: false,
             reset: false,
             mapper: None,
-            player_one: Controller::default(),
-            player_two: Controller::default(),
+            state: InputState::default(),
         }
     }
 
     pub fn handle_input(&mut self, input: crate::UserInput) {
         match input {
-            crate::UserInput::PlayerOne(controller) => self.player_one = controller,
-            crate::UserInput::PlayerTwo(controller) => self.player_two = controller,
+            crate::UserInput::PlayerOne(controller) => self.state.controllers[0] = controller,
+            crate::UserInput::PlayerTwo(controller) => self.state.controllers[1] = controller,
+            crate::UserInput::Player(player, controller) => {
+                if let Some(slot) = self.state.controllers.get_mut(player) {
+                    *slot = controller;
+                }
+            }
+            crate::UserInput::Zapper(zapper) => self.state.zapper = zapper,
+            crate::UserInput::Vaus(vaus) => self.state.vaus = vaus,
+            crate::UserInput::Keyboard(keyboard) => self.state.keyboard = keyboard,
+            crate::UserInput::PowerPad(power_pad) => self.state.power_pad = power_pad,
+            crate::UserInput::Microphone(microphone) => self.state.microphone = microphone,
+            crate::UserInput::Mouse(mouse) => self.state.mouse = mouse,
             crate::UserInput::Mapper(mapper_input) => self.mapper = Some(mapper_input),
             crate::UserInput::Power => self.power = true,
             crate::UserInput::Reset => self.reset = true,
@@ -220,12 +507,12 @@ impl SimpleInput {
 }
 
 impl InputSource for SimpleInput {
-    fn strobe(&mut self) -> (Controller, Controller) {
-        (self.player_one, self.player_two)
+    fn strobe(&mut self) -> InputState {
+        self.state
     }
 
-    fn peek(&self) -> (Controller, Controller) {
-        (self.player_one, self.player_two)
+    fn peek(&self) -> InputState {
+        self.state
     }
 
     fn power(&mut self) -> bool {
@@ -0,0 +1,164 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+#[cfg(feature = "save-states")]
+use serde::{Deserialize, Serialize};
+
+/// Tape audio used by the Family BASIC Data Recorder
+#[derive(Debug, Clone, Default)]
+pub struct Tape {
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+impl Tape {
+    pub fn blank(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            samples: Vec::new(),
+        }
+    }
+}
+
+/// The transport buttons of the Data Recorder
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TapeControl {
+    Play,
+    Record,
+    Stop,
+    Rewind,
+}
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TapeState {
+    Stopped,
+    Playing,
+    Recording,
+}
+
+// Cassette deck connected through the Family BASIC keyboard, plays the tape into $4016 D1
+// and records the level written to OUT2
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct DataRecorder {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    tape: Option<Tape>,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    cycles_per_sample: f64,
+    state: TapeState,
+    position: usize,
+    phase: f64,
+    output: bool,
+}
+
+impl DataRecorder {
+    pub fn new() -> Self {
+        Self {
+            tape: None,
+            cycles_per_sample: 0.0,
+            state: TapeState::Stopped,
+            position: 0,
+            phase: 0.0,
+            output: false,
+        }
+    }
+
+    pub fn insert(&mut self, tape: Tape, cpu_clock: f64) {
+        self.cycles_per_sample = cpu_clock / tape.sample_rate.max(1) as f64;
+        self.tape = Some(tape);
+        self.state = TapeState::Stopped;
+        self.position = 0;
+        self.phase = 0.0;
+    }
+
+    pub fn eject(&mut self) -> Option<Tape> {
+        self.state = TapeState::Stopped;
+        self.tape.take()
+    }
+
+    pub fn tape(&self) -> Option<&Tape> {
+        self.tape.as_ref()
+    }
+
+    pub fn recording(&self) -> bool {
+        self.state == TapeState::Recording
+    }
+
+    pub fn control(&mut self, control: TapeControl) {
+        if self.tape.is_none() {
+            return;
+        }
+
+        match control {
+            TapeControl::Play => self.state = TapeState::Playing,
+            TapeControl::Record => {
+                // Recording replaces everything after the current position
+                if let Some(tape) = self.tape.as_mut() {
+                    tape.samples.truncate(self.position);
+                }
+                self.state = TapeState::Recording;
+            }
+            TapeControl::Stop => self.state = TapeState::Stopped,
+            TapeControl::Rewind => {
+                self.state = TapeState::Stopped;
+                self.position = 0;
+            }
+        }
+        self.phase = 0.0;
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.output = value & 0x04 != 0;
+    }
+
+    pub fn tick(&mut self) {
+        if self.state == TapeState::Stopped {
+            return;
+        }
+
+        self.phase += 1.0;
+        if self.phase < self.cycles_per_sample {
+            return;
+        }
+        self.phase -= self.cycles_per_sample;
+
+        let Some(tape) = self.tape.as_mut() else {
+            return;
+        };
+
+        match self.state {
+            TapeState::Playing => {
+                self.position += 1;
+                if self.position >= tape.samples.len() {
+                    self.state = TapeState::Stopped;
+                }
+            }
+            TapeState::Recording => {
+                let sample = if self.output {
+                    i16::MAX / 2
+                } else {
+                    i16::MIN / 2
+                };
+                tape.samples.push(sample);
+                self.position += 1;
+            }
+            TapeState::Stopped => (),
+        }
+    }
+
+    // Level of the tape signal returned on $4016 D1
+    pub fn read(&self) -> u8 {
+        if self.state != TapeState::Playing {
+            return 0;
+        }
+
+        let sample = self
+            .tape
+            .as_ref()
+            .and_then(|t| t.samples.get(self.position))
+            .copied()
+            .unwrap_or(0);
+
+        if sample > 0 { 0x02 } else { 0x00 }
+    }
+}
@@ -0,0 +1,98 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+
+use super::{InputDevice, InputState, Port, PortDevice};
+use crate::ppu::Ppu;
+
+// Standard controller, reports its buttons serially after being latched by OUT0. The Famicom's
+// player two controller also has a microphone, its level is read directly from $4016 D2
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct Joypad {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    player: usize,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    addr: u16,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    line: u8,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    has_microphone: bool,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    microphone: bool,
+    buffer: u8,
+    shifter: u8,
+    counter: u32,
+}
+
+impl Joypad {
+    pub fn new(port: Port) -> Self {
+        // Famicom expansion controllers report on D1, most games treat them as player one
+        let (player, addr, line) = match port {
+            Port::One => (0, 0x4016, 0),
+            Port::Two => (1, 0x4017, 0),
+            Port::Expansion => (0, 0x4016, 1),
+        };
+
+        Self {
+            player,
+            addr,
+            line,
+            has_microphone: port == Port::Two,
+            microphone: false,
+            buffer: 0,
+            shifter: 0,
+            counter: 0,
+        }
+    }
+}
+
+impl PortDevice for Joypad {
+    fn write(&mut self, value: u8) {
+        if value & 0x01 == 0 {
+            self.shifter = self.buffer;
+        }
+    }
+
+    fn strobe(&mut self, input: &InputState) {
+        self.buffer = input.controllers[self.player].to_byte();
+        self.counter = 8;
+        self.microphone = self.has_microphone && input.microphone;
+    }
+
+    fn update(&mut self, input: &InputState) {
+        self.microphone = self.has_microphone && input.microphone;
+    }
+
+    fn read(&mut self, addr: u16, ppu: &Ppu) -> u8 {
+        if addr != self.addr {
+            return self.peek(addr, ppu);
+        }
+
+        let value = if self.counter == 0 {
+            0x01
+        } else {
+            let value = self.shifter & 1;
+            self.shifter >>= 1;
+            self.counter -= 1;
+            value
+        };
+
+        value << self.line
+    }
+
+    fn peek(&self, addr: u16, _ppu: &Ppu) -> u8 {
+        if addr == 0x4016 && self.microphone {
+            return 0x04;
+        }
+        if addr != self.addr {
+            return 0;
+        }
+
+        let value = if self.counter == 0 {
+            0x01
+        } else {
+            self.shifter & 1
+        };
+
+        value << self.line
+    }
+}
@@ -0,0 +1,185 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+
+use super::data_recorder::DataRecorder;
+use super::{InputState, PortDevice};
+use crate::ppu::Ppu;
+
+/// Keys of the Family BASIC keyboard, numbered by their position in the 9x8 key matrix
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum FamilyKey {
+    RightBracket,
+    LeftBracket,
+    Return,
+    F8,
+    Stop,
+    Yen,
+    RightShift,
+    Kana,
+    Semicolon,
+    Colon,
+    At,
+    F7,
+    Caret,
+    Minus,
+    Slash,
+    Underscore,
+    K,
+    L,
+    O,
+    F6,
+    Num0,
+    P,
+    Comma,
+    Period,
+    J,
+    U,
+    I,
+    F5,
+    Num8,
+    Num9,
+    N,
+    M,
+    H,
+    G,
+    Y,
+    F4,
+    Num6,
+    Num7,
+    V,
+    B,
+    D,
+    R,
+    T,
+    F3,
+    Num4,
+    Num5,
+    C,
+    F,
+    A,
+    S,
+    W,
+    F2,
+    Num3,
+    E,
+    Z,
+    X,
+    Ctr,
+    Q,
+    Escape,
+    F1,
+    Num2,
+    Num1,
+    Grph,
+    LeftShift,
+    Left,
+    Right,
+    Up,
+    ClrHome,
+    Insert,
+    Delete,
+    Space,
+    Down,
+}
+
+/// Pressed keys of the Family BASIC keyboard
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FamilyKeyboard {
+    rows: [u8; 9],
+}
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, key: FamilyKey, pressed: bool) {
+        let (row, bit) = (key as usize / 8, key as u8 % 8);
+        if pressed {
+            self.rows[row] |= 1 << bit;
+        } else {
+            self.rows[row] &= !(1 << bit);
+        }
+    }
+
+    pub fn is_pressed(&self, key: FamilyKey) -> bool {
+        self.rows[key as usize / 8] & (1 << (key as u8 % 8)) != 0
+    }
+
+    // Keys of one half of a matrix row, bit 0 is the first key
+    fn nibble(&self, row: usize, column: u8) -> u8 {
+        (self.rows[row] >> (column * 4)) & 0x0f
+    }
+}
+
+// HVC-007 keyboard on the expansion port. OUT0 resets the scan to the first row, OUT1 selects
+// the column, advancing to the next row as it falls, and OUT2 enables the matrix. The selected
+// keys are reported inverted on $4017 D1-D4 and the Data Recorder's tape on $4016 D1
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct Keyboard {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    keys: FamilyKeyboard,
+    row: u8,
+    column: u8,
+    enabled: bool,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    data_recorder: DataRecorder,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self {
+            keys: FamilyKeyboard::new(),
+            row: 0,
+            column: 0,
+            enabled: false,
+            data_recorder: DataRecorder::new(),
+        }
+    }
+}
+
+impl PortDevice for Keyboard {
+    fn write(&mut self, value: u8) {
+        let column = (value >> 1) & 1;
+        if value & 0x01 != 0 {
+            self.row = 0;
+        } else if self.column == 1 && column == 0 {
+            self.row = (self.row + 1).min(9);
+        }
+        self.column = column;
+        self.enabled = value & 0x04 != 0;
+
+        self.data_recorder.write(value);
+    }
+
+    fn strobe(&mut self, input: &InputState) {
+        self.keys = input.keyboard;
+    }
+
+    fn tick(&mut self) {
+        self.data_recorder.tick();
+    }
+
+    fn ticks(&self) -> bool {
+        true
+    }
+
+    fn peek(&self, addr: u16, _ppu: &Ppu) -> u8 {
+        if addr == 0x4016 {
+            return self.data_recorder.read();
+        }
+
+        if !self.enabled {
+            0
+        } else if self.row < 9 {
+            (!self.keys.nibble(self.row as usize, self.column) & 0x0f) << 1
+        } else {
+            0x1e
+        }
+    }
+
+    fn data_recorder(&mut self) -> Option<&mut DataRecorder> {
+        Some(&mut self.data_recorder)
+    }
+}
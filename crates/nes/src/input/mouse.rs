@@ -0,0 +1,260 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+
+use super::{InputState, Mouse, Port, PortDevice};
+use crate::ppu::Ppu;
+
+// Tracks how far the host mouse has moved since the last report, motion beyond what a report
+// can hold is carried over to the next one
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+struct Motion {
+    x: i32,
+    y: i32,
+}
+
+impl Motion {
+    fn new() -> Self {
+        Self { x: 0, y: 0 }
+    }
+
+    fn take(&mut self, mouse: &Mouse, limit: i32) -> (i32, i32) {
+        let dx = mouse.x.wrapping_sub(self.x).clamp(-limit, limit);
+        let dy = mouse.y.wrapping_sub(self.y).clamp(-limit, limit);
+        self.x = self.x.wrapping_add(dx);
+        self.y = self.y.wrapping_add(dy);
+        (dx, dy)
+    }
+
+    // Plugging in a mouse shouldn't report all of the motion made before it was connected
+    fn sync(&mut self, mouse: &Mouse) {
+        self.x = mouse.x;
+        self.y = mouse.y;
+    }
+}
+
+fn mouse_line(port: Port) -> (u16, u8) {
+    match port {
+        Port::One => (0x4016, 0),
+        Port::Two => (0x4017, 0),
+        Port::Expansion => (0x4017, 1),
+    }
+}
+
+// SNES mouse, latched by OUT0 and shifted out as a 32 bit report: 8 zero bits, the right and
+// left buttons, a 2 bit sensitivity and the 0001 signature, then the Y and X motion each as a
+// direction bit and a 7 bit magnitude. Clocking it while OUT0 is high cycles the sensitivity
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct SnesMouse {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    addr: u16,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    line: u8,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    mouse: Option<Mouse>,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    motion: Motion,
+    strobe: bool,
+    sensitivity: u8,
+    shifter: u32,
+}
+
+impl SnesMouse {
+    pub fn new(port: Port) -> Self {
+        let (addr, line) = mouse_line(port);
+
+        Self {
+            addr,
+            line,
+            mouse: None,
+            motion: Motion::new(),
+            strobe: false,
+            sensitivity: 0,
+            shifter: 0,
+        }
+    }
+
+    fn latch(&mut self) {
+        let mouse = self.mouse.unwrap_or_default();
+        let (dx, dy) = self.motion.take(&mouse, 127);
+        // The acceleration curves of the higher sensitivities are approximated by scaling
+        let scale = |d: i32| (d.unsigned_abs() * (self.sensitivity as u32 + 2) / 2).min(127);
+
+        let mut report = 0x0001_0000 | ((self.sensitivity as u32) << 20);
+        if mouse.right {
+            report |= 0x0080_0000;
+        }
+        if mouse.left {
+            report |= 0x0040_0000;
+        }
+        if dy < 0 {
+            report |= 0x0000_8000;
+        }
+        report |= scale(dy) << 8;
+        if dx < 0 {
+            report |= 0x0000_0080;
+        }
+        report |= scale(dx);
+
+        self.shifter = report;
+    }
+}
+
+impl PortDevice for SnesMouse {
+    fn write(&mut self, value: u8) {
+        let strobe = value & 0x01 != 0;
+        if self.strobe && !strobe {
+            self.latch();
+        }
+        self.strobe = strobe;
+    }
+
+    fn strobe(&mut self, input: &InputState) {
+        if self.mouse.is_none() {
+            self.motion.sync(&input.mouse);
+        }
+        self.mouse = Some(input.mouse);
+    }
+
+    fn read(&mut self, addr: u16, ppu: &Ppu) -> u8 {
+        let value = self.peek(addr, ppu);
+        if addr != self.addr {
+            return value;
+        }
+
+        if self.strobe {
+            self.sensitivity = (self.sensitivity + 1) % 3;
+        } else {
+            self.shifter = (self.shifter << 1) | 1;
+        }
+
+        value
+    }
+
+    fn peek(&self, addr: u16, _ppu: &Ppu) -> u8 {
+        if addr != self.addr {
+            return 0;
+        }
+
+        ((self.shifter >> 31) as u8) << self.line
+    }
+}
+
+// Subor mouse, each OUT0 latch loads the next byte of a 1 or 3 byte packet shifted out MSB
+// first. The first byte holds the left and right buttons then 2 bit X and Y directions, with
+// bit 0 set when it is followed by the X and Y motion as 6 bit sign and magnitude values
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct SuborMouse {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    addr: u16,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    line: u8,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    mouse: Option<Mouse>,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    motion: Motion,
+    strobe: bool,
+    packet: [u8; 3],
+    packet_len: usize,
+    packet_pos: usize,
+    shifter: u8,
+    counter: u32,
+}
+
+impl SuborMouse {
+    pub fn new(port: Port) -> Self {
+        let (addr, line) = mouse_line(port);
+
+        Self {
+            addr,
+            line,
+            mouse: None,
+            motion: Motion::new(),
+            strobe: false,
+            packet: [0; 3],
+            packet_len: 0,
+            packet_pos: 0,
+            shifter: 0,
+            counter: 0,
+        }
+    }
+
+    fn next_packet(&mut self) {
+        let mouse = self.mouse.unwrap_or_default();
+        let (dx, dy) = self.motion.take(&mouse, 31);
+
+        let direction = |d: i32| match d.signum() {
+            1 => 0x01,
+            -1 => 0x03,
+            _ => 0x00,
+        };
+        let motion = |d: i32| (if d < 0 { 0x20 } else { 0x00 }) | d.unsigned_abs() as u8;
+
+        let mut header = (direction(dx) << 4) | (direction(dy) << 2);
+        if mouse.left {
+            header |= 0x80;
+        }
+        if mouse.right {
+            header |= 0x40;
+        }
+
+        if dx.abs() <= 1 && dy.abs() <= 1 {
+            self.packet = [header, 0, 0];
+            self.packet_len = 1;
+        } else {
+            self.packet = [header | 0x01, motion(dx), motion(dy)];
+            self.packet_len = 3;
+        }
+        self.packet_pos = 0;
+    }
+
+    fn latch(&mut self) {
+        if self.packet_pos >= self.packet_len {
+            self.next_packet();
+        }
+
+        self.shifter = self.packet[self.packet_pos];
+        self.packet_pos += 1;
+        self.counter = 8;
+    }
+}
+
+impl PortDevice for SuborMouse {
+    fn write(&mut self, value: u8) {
+        let strobe = value & 0x01 != 0;
+        if self.strobe && !strobe {
+            self.latch();
+        }
+        self.strobe = strobe;
+    }
+
+    fn strobe(&mut self, input: &InputState) {
+        if self.mouse.is_none() {
+            self.motion.sync(&input.mouse);
+        }
+        self.mouse = Some(input.mouse);
+    }
+
+    fn read(&mut self, addr: u16, ppu: &Ppu) -> u8 {
+        let value = self.peek(addr, ppu);
+        if addr == self.addr && self.counter > 0 {
+            self.shifter <<= 1;
+            self.counter -= 1;
+        }
+
+        value
+    }
+
+    fn peek(&self, addr: u16, _ppu: &Ppu) -> u8 {
+        if addr != self.addr {
+            return 0;
+        }
+
+        let value = if self.counter == 0 {
+            0x01
+        } else {
+            self.shifter >> 7
+        };
+
+        value << self.line
+    }
+}
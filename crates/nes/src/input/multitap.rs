@@ -0,0 +1,161 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+
+use super::{InputDevice, InputState, Port, PortDevice};
+use crate::ppu::Ppu;
+
+// One data line of a multi-player adapter, shifts out each controller in turn followed by
+// a signature byte identifying the adapter. Everything is shifted out LSB first, so the
+// Four Score's $4016 signature of 0x08 is seen on the 20th read
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+struct TapLine {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    addr: u16,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    line: u8,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    players: &'static [usize],
+    #[cfg_attr(feature = "save-states", save(skip))]
+    signature: u8,
+    buffer: u32,
+    shifter: u32,
+    counter: u32,
+}
+
+impl TapLine {
+    fn new(addr: u16, line: u8, players: &'static [usize], signature: u8) -> Self {
+        Self {
+            addr,
+            line,
+            players,
+            signature,
+            buffer: 0,
+            shifter: 0,
+            counter: 0,
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        if value & 0x01 == 0 {
+            self.shifter = self.buffer;
+        }
+    }
+
+    fn strobe(&mut self, input: &InputState) {
+        let mut buffer = 0;
+        for (idx, &player) in self.players.iter().enumerate() {
+            buffer |= (input.controllers[player].to_byte() as u32) << (idx * 8);
+        }
+        buffer |= (self.signature as u32) << (self.players.len() * 8);
+
+        self.buffer = buffer;
+        self.counter = (self.players.len() as u32 + 1) * 8;
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr != self.addr {
+            return 0;
+        }
+
+        let value = if self.counter == 0 {
+            0x01
+        } else {
+            let value = self.shifter & 1;
+            self.shifter >>= 1;
+            self.counter -= 1;
+            value as u8
+        };
+
+        value << self.line
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        if addr != self.addr {
+            return 0;
+        }
+
+        let value = if self.counter == 0 {
+            0x01
+        } else {
+            (self.shifter & 1) as u8
+        };
+
+        value << self.line
+    }
+}
+
+// NES Four Score, one is plugged into each controller port. Each port reports two players
+// followed by a signature
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct FourScore {
+    #[cfg_attr(feature = "save-states", save(nested))]
+    line: TapLine,
+}
+
+impl FourScore {
+    pub fn new(port: Port) -> Self {
+        let line = match port {
+            Port::One | Port::Expansion => TapLine::new(0x4016, 0, &[0, 2], 0x08),
+            Port::Two => TapLine::new(0x4017, 0, &[1, 3], 0x04),
+        };
+
+        Self { line }
+    }
+}
+
+impl PortDevice for FourScore {
+    fn write(&mut self, value: u8) {
+        self.line.write(value);
+    }
+
+    fn strobe(&mut self, input: &InputState) {
+        self.line.strobe(input);
+    }
+
+    fn read(&mut self, addr: u16, _ppu: &Ppu) -> u8 {
+        self.line.read(addr)
+    }
+
+    fn peek(&self, addr: u16, _ppu: &Ppu) -> u8 {
+        self.line.peek(addr)
+    }
+}
+
+// Famicom Hori 4 Players Adapter on the expansion port, players three and four are reported
+// on D1 alongside the built in controllers, with the signatures swapped from the Four Score
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct HoriAdapter {
+    #[cfg_attr(feature = "save-states", save(nested))]
+    port_one: TapLine,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    port_two: TapLine,
+}
+
+impl HoriAdapter {
+    pub fn new() -> Self {
+        Self {
+            port_one: TapLine::new(0x4016, 1, &[2], 0x04),
+            port_two: TapLine::new(0x4017, 1, &[3], 0x08),
+        }
+    }
+}
+
+impl PortDevice for HoriAdapter {
+    fn write(&mut self, value: u8) {
+        self.port_one.write(value);
+        self.port_two.write(value);
+    }
+
+    fn strobe(&mut self, input: &InputState) {
+        self.port_one.strobe(input);
+        self.port_two.strobe(input);
+    }
+
+    fn read(&mut self, addr: u16, _ppu: &Ppu) -> u8 {
+        self.port_one.read(addr) | self.port_two.read(addr)
+    }
+
+    fn peek(&self, addr: u16, _ppu: &Ppu) -> u8 {
+        self.port_one.peek(addr) | self.port_two.peek(addr)
+    }
+}
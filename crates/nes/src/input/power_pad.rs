@@ -0,0 +1,181 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+#[cfg(feature = "save-states")]
+use serde::{Deserialize, Serialize};
+
+use super::{InputState, Port, PortDevice};
+use crate::ppu::Ppu;
+
+/// Pressed buttons of the Power Pad or Family Trainer mat, numbered 1-12 as printed on side B
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PowerPad {
+    buttons: u16,
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, button: u8, pressed: bool) {
+        if !(1..=12).contains(&button) {
+            return;
+        }
+
+        if pressed {
+            self.buttons |= 1 << (button - 1);
+        } else {
+            self.buttons &= !(1 << (button - 1));
+        }
+    }
+
+    pub fn is_pressed(&self, button: u8) -> bool {
+        (1..=12).contains(&button) && self.buttons & (1 << (button - 1)) != 0
+    }
+}
+
+/// Which side of the mat faces up
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum PowerPadSide {
+    /// Eight unnumbered buttons, the underside of side B with the corners missing
+    A,
+    /// Twelve buttons numbered 1-12
+    #[default]
+    B,
+}
+
+impl PowerPadSide {
+    /// The button under a position on the mat as seen by the player, `row` is 0-2 from the
+    /// top and `column` 0-3 from the left. Returns `None` for the empty corners of side A
+    pub fn button(self, row: usize, column: usize) -> Option<u8> {
+        if row > 2 || column > 3 {
+            return None;
+        }
+
+        match self {
+            PowerPadSide::B => Some((row * 4 + column + 1) as u8),
+            PowerPadSide::A if row != 1 && (column == 0 || column == 3) => None,
+            PowerPadSide::A => Some((row * 4 + (3 - column) + 1) as u8),
+        }
+    }
+}
+
+// NES Power Pad, latched by OUT0 like a joypad but shifted out on D3 and D4. D3 reports
+// buttons 2, 1, 5, 9, 6, 10, 11, 7 and D4 reports 4, 3, 12, 8, both followed by 1s
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct FloorMat {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    addr: u16,
+    buffer_low: u8,
+    buffer_high: u8,
+    low: u16,
+    high: u16,
+}
+
+impl FloorMat {
+    pub fn new(port: Port) -> Self {
+        let addr = match port {
+            Port::One | Port::Expansion => 0x4016,
+            Port::Two => 0x4017,
+        };
+
+        Self {
+            addr,
+            buffer_low: 0,
+            buffer_high: 0,
+            low: 0,
+            high: 0,
+        }
+    }
+}
+
+impl PortDevice for FloorMat {
+    fn write(&mut self, value: u8) {
+        if value & 0x01 == 0 {
+            self.low = self.buffer_low as u16 | 0xff00;
+            self.high = self.buffer_high as u16 | 0xfff0;
+        }
+    }
+
+    fn strobe(&mut self, input: &InputState) {
+        const LOW: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+        const HIGH: [u8; 4] = [4, 3, 12, 8];
+
+        let pad = &input.power_pad;
+        self.buffer_low = 0;
+        for (idx, &button) in LOW.iter().enumerate() {
+            self.buffer_low |= (pad.is_pressed(button) as u8) << idx;
+        }
+        self.buffer_high = 0;
+        for (idx, &button) in HIGH.iter().enumerate() {
+            self.buffer_high |= (pad.is_pressed(button) as u8) << idx;
+        }
+    }
+
+    fn read(&mut self, addr: u16, ppu: &Ppu) -> u8 {
+        let value = self.peek(addr, ppu);
+        if addr == self.addr {
+            self.low = (self.low >> 1) | 0x8000;
+            self.high = (self.high >> 1) | 0x8000;
+        }
+        value
+    }
+
+    fn peek(&self, addr: u16, _ppu: &Ppu) -> u8 {
+        if addr != self.addr {
+            return 0;
+        }
+
+        ((self.low & 1) as u8) << 3 | ((self.high & 1) as u8) << 4
+    }
+}
+
+// Family Trainer mat on the expansion port, OUT0-OUT2 each select a row of four buttons when
+// low and the selected columns are reported inverted on $4017 D1-D4
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct TrainerMat {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    pad: PowerPad,
+    ignored_rows: u8,
+}
+
+impl TrainerMat {
+    pub fn new() -> Self {
+        Self {
+            pad: PowerPad::new(),
+            ignored_rows: 0x07,
+        }
+    }
+}
+
+impl PortDevice for TrainerMat {
+    fn write(&mut self, value: u8) {
+        self.ignored_rows = value & 0x07;
+    }
+
+    fn update(&mut self, input: &InputState) {
+        self.pad = input.power_pad;
+    }
+
+    fn peek(&self, addr: u16, _ppu: &Ppu) -> u8 {
+        if addr != 0x4017 {
+            return 0;
+        }
+
+        // OUT2 selects buttons 1-4, OUT1 5-8 and OUT0 9-12
+        let mut columns = 0;
+        for row in 0..3 {
+            if self.ignored_rows & (0x04 >> row) != 0 {
+                continue;
+            }
+            for column in 0..4 {
+                if self.pad.is_pressed((row * 4 + column + 1) as u8) {
+                    columns |= 0x10 >> column;
+                }
+            }
+        }
+
+        !columns & 0x1e
+    }
+}
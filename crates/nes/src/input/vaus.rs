@@ -0,0 +1,76 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+
+use super::{InputState, Port, PortDevice};
+use crate::ppu::Ppu;
+
+// Taito Vaus paddle, the knob position is latched by OUT0 and shifted out inverted MSB
+// first. The NES version reports on $4017 D3/D4, the Famicom version on D1 of both registers
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct Paddle {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    button_addr: u16,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    button_line: u8,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    data_addr: u16,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    data_line: u8,
+    position: u16,
+    button: bool,
+    shifter: u16,
+}
+
+impl Paddle {
+    pub fn new(port: Port) -> Self {
+        let (button_addr, button_line, data_addr, data_line) = match port {
+            Port::One => (0x4016, 3, 0x4016, 4),
+            Port::Two => (0x4017, 3, 0x4017, 4),
+            Port::Expansion => (0x4016, 1, 0x4017, 1),
+        };
+
+        Self {
+            button_addr,
+            button_line,
+            data_addr,
+            data_line,
+            position: 0,
+            button: false,
+            shifter: 0,
+        }
+    }
+}
+
+impl PortDevice for Paddle {
+    fn write(&mut self, value: u8) {
+        if value & 0x01 == 0 {
+            // Align the 9 bit position to the top of the shifter
+            self.shifter = !self.position << 7;
+        }
+    }
+
+    fn strobe(&mut self, input: &InputState) {
+        self.position = input.vaus.position.min(0x1ff);
+        self.button = input.vaus.button;
+    }
+
+    fn read(&mut self, addr: u16, ppu: &Ppu) -> u8 {
+        let value = self.peek(addr, ppu);
+        if addr == self.data_addr {
+            self.shifter <<= 1;
+        }
+        value
+    }
+
+    fn peek(&self, addr: u16, _ppu: &Ppu) -> u8 {
+        let mut value = 0;
+        if addr == self.button_addr && self.button {
+            value |= 1 << self.button_line;
+        }
+        if addr == self.data_addr {
+            value |= ((self.shifter >> 15) as u8 & 1) << self.data_line;
+        }
+
+        value
+    }
+}
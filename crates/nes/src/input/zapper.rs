@@ -0,0 +1,98 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+
+use super::{InputState, Port, PortDevice};
+use crate::ppu::Ppu;
+
+// Pixels around the aim point seen by the photodiode
+const SENSE_RADIUS: i32 = 3;
+// How many scanlines a lit pixel keeps the sensor triggered after being drawn
+const SENSE_LINES: u32 = 20;
+// Minimum luminance (0-255) of a pixel to count as light
+const SENSE_BRIGHTNESS: u32 = 0x90;
+
+// Light gun, reports the trigger on D4 and a lack of light on D3
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct LightGun {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    addr: u16,
+    aim: Option<(u16, u16)>,
+    trigger: bool,
+}
+
+impl LightGun {
+    pub fn new(port: Port) -> Self {
+        // The Famicom light gun is connected through the expansion port and read from $4017
+        let addr = match port {
+            Port::One => 0x4016,
+            Port::Two | Port::Expansion => 0x4017,
+        };
+
+        Self {
+            addr,
+            aim: None,
+            trigger: false,
+        }
+    }
+
+    fn light_sensed(&self, ppu: &Ppu) -> bool {
+        let Some((aim_x, aim_y)) = self.aim else {
+            return false;
+        };
+
+        let (scanline, dot) = ppu.position();
+        let palette = ppu.region().default_palette();
+        let screen = ppu.screen();
+
+        let min_y = (aim_y as i32 - SENSE_RADIUS).max(0) as u32;
+        let max_y = (aim_y as i32 + SENSE_RADIUS).min(239) as u32;
+        let min_x = (aim_x as i32 - SENSE_RADIUS).max(0) as u32;
+        let max_x = (aim_x as i32 + SENSE_RADIUS).min(255) as u32;
+
+        for y in min_y..=max_y {
+            // Only rows drawn recently in the current frame are still glowing
+            if y > scanline || scanline - y > SENSE_LINES {
+                continue;
+            }
+
+            for x in min_x..=max_x {
+                if y == scanline && x > dot {
+                    break;
+                }
+
+                let pixel = screen[(y * 256 + x) as usize] as usize & 0x1ff;
+                let rgb = &palette[pixel * 3..pixel * 3 + 3];
+                let luma = (rgb[0] as u32 * 299 + rgb[1] as u32 * 587 + rgb[2] as u32 * 114) / 1000;
+
+                if luma >= SENSE_BRIGHTNESS {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl PortDevice for LightGun {
+    fn update(&mut self, input: &InputState) {
+        self.aim = input.zapper.aim.filter(|&(x, y)| x < 256 && y < 240);
+        self.trigger = input.zapper.trigger;
+    }
+
+    fn peek(&self, addr: u16, ppu: &Ppu) -> u8 {
+        if addr != self.addr {
+            return 0;
+        }
+
+        let mut value = 0;
+        if self.trigger {
+            value |= 0x10;
+        }
+        if !self.light_sensed(ppu) {
+            value |= 0x08;
+        }
+
+        value
+    }
+}
@@ -4,6 +4,7 @@ mod cartridge;
 mod channel;
 mod cpu;
 mod debug;
+mod epsm;
 mod input;
 mod machine;
 mod mapper;
@@ -14,17 +15,31 @@ mod region;
 mod ring_buf;
 pub mod run_until;
 
-pub use apu::{ChannelPlayback, ChannelSamples};
-pub use cartridge::{Cartridge, CartridgeInfo};
+pub use apu::{CHANNEL_SAMPLE_WINDOW, ChannelPlayback, ChannelSamples};
+pub use bus::{
+    Address, AddressBus, AndAndMask, AndEqualsAndMask, BusKind, DeviceKind, MappingFn, NotAndMask,
+    RangeAndMask,
+};
+pub use cartridge::{
+    CartMirroring, Cartridge, CartridgeInfo, INes, NSF_DEFAULT_FADE_MS, NsfFile, NsfMetadata,
+};
 pub use debug::{Debug, DebugEvent, MachineState};
 #[cfg(feature = "debugger")]
-pub use debug::{WatchFieldName, WatchItem};
-pub use input::{Controller, InputSource, SimpleInput, UserInput};
+pub use debug::{WatchFieldName, WatchGroup, WatchItem, WatchValue, WatchVisitor};
+pub use epsm::EPSM_EXPANSION_DEVICE;
+pub use input::{
+    Controller, FamilyKey, FamilyKeyboard, InputSource, InputState, Mouse, Port, PortDeviceKind,
+    PowerPad, PowerPadSide, SimpleInput, Tape, TapeControl, UserInput, Vaus, Zapper,
+};
 pub use machine::{Machine, RunResult};
-pub use mapper::{FdsInput, MapperInput, SaveWram};
+pub use mapper::{
+    EspConfig, FdsConfig, FdsInput, Mapper, MapperInput, MapperRegistry, MapperState, Mirroring,
+    Nametable, RcMapper, SaveWram, SimpleMirroring,
+};
+pub use memory::{FixedMemoryBlock, Memory, MemoryBlock, RomBlock};
 #[cfg(feature = "save-states")]
-use nes_traits::SaveState;
-pub use ppu::FrameEnd;
+pub use nes_traits::{BinarySaveState, SaveState};
+pub use ppu::{FrameEnd, PpuFetchKind};
 pub use region::Region;
 #[cfg(feature = "save-states")]
 use serde::{Deserialize, Serialize};
@@ -32,3 +47,15 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "save-states")]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SaveData(<Machine as SaveState>::Data);
+
+#[cfg(feature = "save-states")]
+impl SaveData {
+    /// FNV-1a hash of the serialized state, equal between machines that have run the same
+    /// cartridge with the same inputs
+    pub fn checksum(&self) -> u64 {
+        let bytes = postcard::to_allocvec(&self.0).unwrap();
+        bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+}
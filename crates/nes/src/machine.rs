@@ -5,11 +5,12 @@ use nes_traits::SaveState;
 
 use crate::apu::{Apu, ChannelPlayback};
 use crate::bus::{AddressBus, BusKind, DeviceKind, RangeAndMask};
-use crate::cartridge::Cartridge;
+use crate::cartridge::{Cartridge, CartridgeInfo};
 use crate::cpu::{Cpu, CpuPinIn, TickResult};
 use crate::debug::{Debug, DebugEvent};
-use crate::input::{Input, InputSource};
-use crate::mapper::{RcMapper, SaveWram};
+use crate::epsm::EPSM_EXPANSION_DEVICE;
+use crate::input::{Input, InputSource, Port, PortDeviceKind, Tape, TapeControl};
+use crate::mapper::{MapperRegistry, NsfOutput, RcMapper, SaveWram};
 use crate::memory::{FixedMemoryBlock, Memory};
 use crate::ppu::{FrameEnd, Ppu};
 use crate::region::Region;
@@ -61,17 +62,39 @@ pub struct Machine {
     #[cfg_attr(feature = "save-states", save(skip))]
     debug: Rc<Debug>,
     cpu_pin_in: CpuPinIn,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    nsf_irq: bool,
+    lag_frame: bool,
+    lag_count: u32,
 }
 
 impl Machine {
     pub fn new(region: Region, cartridge: Cartridge) -> Machine {
+        Self::with_mappers(region, cartridge, &MapperRegistry::default())
+    }
+
+    /// Create a machine that prefers mappers from `registry` over the built in mappers
+    pub fn with_mappers(
+        region: Region,
+        cartridge: Cartridge,
+        registry: &MapperRegistry,
+    ) -> Machine {
         let cpu = Cpu::new(region);
         let debug = Rc::new(Debug::new());
         let mut cpu_bus = AddressBus::new(0, 0xffff);
         let cpu_mem = FixedMemoryBlock::new();
         let input = Input::new();
-        let mapper = cartridge.build_mapper(region, debug.clone());
-        let apu = Apu::new(region, mapper.clone());
+        let epsm = cartridge.expansion_device() == EPSM_EXPANSION_DEVICE;
+        let nsf_irq = cartridge.nsf_irq();
+        // The NSF player fades and watches the mixed output of the APU
+        let nsf = matches!(cartridge.info(), CartridgeInfo::Nsf);
+        let nsf_output = Rc::new(NsfOutput::new());
+        let mapper = cartridge.build_mapper(region, debug.clone(), registry, nsf_output.clone());
+        let mut apu = Apu::new(region, mapper.clone());
+        apu.epsm.set_enabled(epsm);
+        if nsf {
+            apu.set_nsf_output(nsf_output);
+        }
         let ppu = Ppu::new(region, mapper.clone(), debug.clone());
 
         cpu_bus.register_read(DeviceKind::CpuRam, RangeAndMask(0x0000, 0x2000, 0x07ff));
@@ -96,6 +119,9 @@ impl Machine {
             mapper,
             debug,
             cpu_pin_in: CpuPinIn::default(),
+            nsf_irq,
+            lag_frame: false,
+            lag_count: 0,
         };
 
         machine.power();
@@ -126,10 +152,82 @@ impl Machine {
         self.region
     }
 
+    /// Attach or remove the Expansion Port Sound Module, by default it is only
+    /// attached when the rom header requests it. It can not be attached to an NSF2 using
+    /// the IRQ timer, the timer's registers overlap the module's at $401C-$401D
+    pub fn set_epsm(&mut self, enabled: bool) {
+        if enabled && self.nsf_irq {
+            tracing::warn!("epsm not attached, nsf irq timer shares its registers");
+            return;
+        }
+        self.apu.epsm.set_enabled(enabled);
+    }
+
+    pub fn epsm(&self) -> bool {
+        self.apu.epsm.enabled()
+    }
+
+    /// Connect a peripheral to one of the controller ports or the expansion port, both
+    /// controller ports default to a standard controller
+    pub fn set_port_device(&mut self, port: Port, device: PortDeviceKind) {
+        self.input.set_device(port, device);
+    }
+
+    pub fn port_device(&self, port: Port) -> PortDeviceKind {
+        self.input.device(port)
+    }
+
+    /// Load a tape into the Data Recorder, returns false if no device with a Data Recorder
+    /// is plugged in
+    pub fn insert_tape(&mut self, tape: Tape) -> bool {
+        let cpu_clock = self.region.cpu_clock();
+        if let Some(recorder) = self.input.data_recorder() {
+            recorder.insert(tape, cpu_clock);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn eject_tape(&mut self) -> Option<Tape> {
+        self.input.data_recorder().and_then(|r| r.eject())
+    }
+
+    /// The tape in the Data Recorder, including anything recorded onto it
+    pub fn tape(&mut self) -> Option<&Tape> {
+        self.input.data_recorder().and_then(|r| r.tape())
+    }
+
+    /// True while the Data Recorder is recording, samples recorded onto the tape are not
+    /// undone by restoring a save state
+    pub fn tape_recording(&mut self) -> bool {
+        self.input.data_recorder().is_some_and(|r| r.recording())
+    }
+
+    pub fn tape_control(&mut self, control: TapeControl) {
+        if let Some(recorder) = self.input.data_recorder() {
+            recorder.control(control);
+        }
+    }
+
     pub fn frame(&self) -> u32 {
         self.ppu.frame()
     }
 
+    /// True when the last completed frame never read the controller ports
+    pub fn lag_frame(&self) -> bool {
+        self.lag_frame
+    }
+
+    /// Number of lag frames since the machine was created or the count was last reset
+    pub fn lag_count(&self) -> u32 {
+        self.lag_count
+    }
+
+    pub fn reset_lag_count(&mut self) {
+        self.lag_count = 0;
+    }
+
     pub fn run_with_breakpoints<H: BreakpointHandler, U: RunUntil, I: InputSource>(
         &mut self,
         frame_end: FrameEnd,
@@ -172,6 +270,8 @@ impl Machine {
             self.mapper.input(input);
         }
 
+        self.input.update(input_source);
+
         while !until.done() {
             if self.tick % 3 == 0 {
                 // The order of operations here is very sensitive to changes:
@@ -228,7 +328,14 @@ impl Machine {
     }
 
     fn tick_ppu<U: RunUntil>(&mut self, frame_end: FrameEnd, until: &mut U) {
+        let frame = self.ppu.frame();
         self.ppu.tick(frame_end, until);
+        if self.ppu.frame() != frame {
+            self.lag_frame = !self.input.take_frame_read();
+            if self.lag_frame {
+                self.lag_count += 1;
+            }
+        }
         let ppu_state = self.ppu.debug_state();
         self.debug.trace_ppu(&self, ppu_state);
     }
@@ -288,7 +395,7 @@ impl Machine {
             Some((addr, DeviceKind::Debug)) => self.debug.read(addr),
             Some((addr, DeviceKind::Input)) if cpu_regs => {
                 read_input = true;
-                self.input.read(addr, open_bus)
+                self.input.read(addr, open_bus, &self.ppu)
             }
             _ => open_bus,
         };
@@ -297,7 +404,7 @@ impl Machine {
             let mirror_addr = 0x4000 | (addr & 0x1f);
 
             let bus_val = if !read_input {
-                self.input.read(mirror_addr, value)
+                self.input.read(mirror_addr, value, &self.ppu)
             } else {
                 value
             };
@@ -336,6 +443,7 @@ impl Machine {
                 (addr, DeviceKind::Noise) => self.apu.noise.write(addr, value),
                 (addr, DeviceKind::Triangle) => self.apu.triangle.write(addr, value),
                 (addr, DeviceKind::Dmc) => self.apu.dmc.write(addr, value),
+                (addr, DeviceKind::Epsm) => self.apu.epsm.write(addr, value),
                 (addr, DeviceKind::Debug) => self.debug.write(addr, value),
             }
         }
@@ -354,7 +462,7 @@ impl Machine {
             Some((addr, DeviceKind::Debug)) => self.debug.read(addr),
             Some((addr, DeviceKind::Input)) if cpu_regs => {
                 read_input = true;
-                self.input.peek(addr, open_bus)
+                self.input.peek(addr, open_bus, &self.ppu)
             }
             _ => open_bus,
         };
@@ -362,7 +470,7 @@ impl Machine {
         if cpu_regs {
             let addr = 0x4000 | (addr & 0x1f);
             if !read_input {
-                self.input.peek(addr, value);
+                self.input.peek(addr, value, &self.ppu);
             }
             self.apu.peek(addr, value)
         } else {
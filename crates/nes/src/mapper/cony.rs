@@ -0,0 +1,348 @@
+use std::rc::Rc;
+
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+
+use crate::Debug;
+use crate::bus::{AddressBus, AndAndMask, AndEqualsAndMask, BusKind, DeviceKind};
+use crate::cartridge::INes;
+use crate::mapper::Mapper;
+use crate::memory::{Memory, MemoryBlock};
+use crate::ppu::PpuFetchKind;
+
+use super::SimpleMirroring;
+
+// Cony/Yoko boards, mapper 83
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct Cony {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    cartridge: INes,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    debug: Rc<Debug>,
+    prg_ram: MemoryBlock,
+    chr_ram: Option<MemoryBlock>,
+    prg_regs: [u8; 3],
+    chr_regs: [u8; 8],
+    bank: u8,
+    mode: u8,
+    chr_2k_write: bool,
+    chr_1k_write: bool,
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq: bool,
+    scratch_ram: [u8; 4],
+    mirroring: SimpleMirroring,
+}
+
+impl Cony {
+    pub fn new(mut cartridge: INes, debug: Rc<Debug>) -> Self {
+        let prg_ram_size = (8 * 1024).max(cartridge.prg_ram_bytes);
+        let mut prg_ram = MemoryBlock::new(prg_ram_size / 1024);
+        if let Some(wram) = cartridge.wram.take() {
+            prg_ram.restore_wram(wram);
+        }
+
+        let chr_ram =
+            (cartridge.chr_ram_bytes > 0).then(|| MemoryBlock::new(cartridge.chr_ram_bytes / 1024));
+        let mirroring = SimpleMirroring::new(cartridge.mirroring);
+
+        Self {
+            cartridge,
+            debug,
+            prg_ram,
+            chr_ram,
+            prg_regs: [0; 3],
+            chr_regs: [0; 8],
+            bank: 0,
+            mode: 0,
+            chr_2k_write: false,
+            chr_1k_write: false,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq: false,
+            scratch_ram: [0; 4],
+            mirroring,
+        }
+    }
+
+    fn chr_2k(&self) -> bool {
+        // Early dumps lack a submapper, 2k banking is detected from the registers in use
+        match self.cartridge.submapper {
+            Some(1) => true,
+            Some(_) => false,
+            None => self.chr_2k_write && !self.chr_1k_write,
+        }
+    }
+
+    fn map_prg(&self, addr: u16) -> usize {
+        if self.mode & 0x40 != 0 {
+            if addr < 0xc000 {
+                (self.bank as usize & 0x3f) << 1 | (addr as usize >> 13 & 1)
+            } else {
+                ((self.bank as usize & 0x30) | 0xf) << 1 | (addr as usize >> 13 & 1)
+            }
+        } else {
+            match addr {
+                0x8000..=0x9fff => self.prg_regs[0] as usize,
+                0xa000..=0xbfff => self.prg_regs[1] as usize,
+                0xc000..=0xdfff => self.prg_regs[2] as usize,
+                _ => (self.cartridge.prg_rom.len() / (8 * 1024)).saturating_sub(1),
+            }
+        }
+    }
+
+    fn map_chr(&self, addr: u16) -> (usize, usize) {
+        if self.chr_2k() {
+            let reg = match addr >> 11 & 3 {
+                0 => self.chr_regs[0],
+                1 => self.chr_regs[1],
+                2 => self.chr_regs[6],
+                _ => self.chr_regs[7],
+            };
+            (reg as usize, 2 * 1024)
+        } else {
+            let reg = self.chr_regs[(addr >> 10 & 7) as usize] as usize;
+            (reg | (self.bank as usize & 0x30) << 4, 1024)
+        }
+    }
+
+    fn prg_ram_bank(&self) -> usize {
+        if self.cartridge.submapper == Some(2) {
+            (self.bank >> 6) as usize
+        } else {
+            0
+        }
+    }
+
+    fn read_cpu(&self, addr: u16) -> u8 {
+        match addr {
+            // Dip switch selects the title screen on multicarts
+            0x5000..=0x50ff => 0,
+            0x5100..=0x5fff => self.scratch_ram[(addr & 3) as usize],
+            0x6000..=0x7fff => self
+                .prg_ram
+                .read_mapped(self.prg_ram_bank(), 8 * 1024, addr),
+            _ => {
+                let bank = self.map_prg(addr);
+                self.cartridge.prg_rom.read_mapped(bank, 8 * 1024, addr)
+            }
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5100..=0x5fff => self.scratch_ram[(addr & 3) as usize] = value,
+            0x6000..=0x7fff => {
+                let bank = self.prg_ram_bank();
+                self.prg_ram.write_mapped(bank, 8 * 1024, addr, value);
+            }
+            0x8000 | 0xb000 | 0xb0ff | 0xb1ff => {
+                if addr == 0x8000 {
+                    self.chr_2k_write = true;
+                }
+                self.bank = value;
+                self.mode |= 0x40;
+            }
+            0x8100 => {
+                self.mode = value | (self.mode & 0x40);
+                match value & 3 {
+                    0 => self.mirroring.vertical(),
+                    1 => self.mirroring.horizontal(),
+                    2 => self.mirroring.internal_a(),
+                    _ => self.mirroring.internal_b(),
+                }
+            }
+            0x8200 => {
+                self.irq_counter = (self.irq_counter & 0xff00) | value as u16;
+                self.irq = false;
+            }
+            0x8201 => {
+                self.irq_enabled = self.mode & 0x80 != 0;
+                self.irq_counter = (self.irq_counter & 0x00ff) | (value as u16) << 8;
+            }
+            0x8300..=0x8302 => {
+                self.prg_regs[(addr & 3) as usize] = value;
+                self.mode &= !0x40;
+            }
+            0x8310..=0x8317 => {
+                let idx = (addr & 7) as usize;
+                if (2..=5).contains(&idx) {
+                    self.chr_1k_write = true;
+                }
+                self.chr_regs[idx] = value;
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Mapper for Cony {
+    fn register(&self, cpu: &mut AddressBus) {
+        cpu.register_read(DeviceKind::Mapper, AndEqualsAndMask(0xf000, 0x5000, 0xffff));
+        cpu.register_write(DeviceKind::Mapper, AndEqualsAndMask(0xf000, 0x5000, 0xffff));
+        cpu.register_read(DeviceKind::Mapper, AndEqualsAndMask(0xe000, 0x6000, 0x7fff));
+        cpu.register_write(DeviceKind::Mapper, AndEqualsAndMask(0xe000, 0x6000, 0x7fff));
+        cpu.register_read(DeviceKind::Mapper, AndAndMask(0x8000, 0xffff));
+        cpu.register_write(DeviceKind::Mapper, AndAndMask(0x8000, 0xffff));
+    }
+
+    fn peek(&self, bus: BusKind, addr: u16) -> u8 {
+        match bus {
+            BusKind::Cpu => self.read_cpu(addr),
+            BusKind::Ppu => {
+                let (bank, size) = self.map_chr(addr);
+                if let Some(ram) = self.chr_ram.as_ref() {
+                    ram.read_mapped(bank, size, addr)
+                } else {
+                    self.cartridge.chr_rom.read_mapped(bank, size, addr)
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, bus: BusKind, addr: u16, value: u8) {
+        match bus {
+            BusKind::Cpu => self.write_cpu(addr, value),
+            BusKind::Ppu => {
+                let (bank, size) = self.map_chr(addr);
+                if let Some(ram) = self.chr_ram.as_mut() {
+                    ram.write_mapped(bank, size, addr, value)
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.irq_enabled {
+            if self.irq_counter == 0 {
+                self.irq_enabled = false;
+                self.irq_counter = 0xffff;
+                self.irq = true;
+                self.debug.event(crate::DebugEvent::MapperIrq);
+            } else {
+                self.irq_counter -= 1;
+            }
+        }
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq
+    }
+
+    fn peek_ppu_fetch(&self, address: u16, _kind: PpuFetchKind) -> super::Nametable {
+        self.mirroring.ppu_fetch(address)
+    }
+
+    fn save_wram(&self) -> Option<super::SaveWram> {
+        if self.cartridge.battery {
+            self.prg_ram.save_wram()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cartridge::CartMirroring;
+    use crate::mapper::EspConfig;
+    use crate::memory::RomBlock;
+
+    fn cony() -> Cony {
+        // Each 8k prg bank and 1k chr bank is filled with its own bank number
+        let prg_rom: Vec<u8> = (0..32u8).flat_map(|bank| [bank; 8 * 1024]).collect();
+        let chr_rom: Vec<u8> = (0..=255u8).flat_map(|bank| [bank; 1024]).collect();
+        let cartridge = INes {
+            chr_ram_bytes: 0,
+            prg_ram_bytes: 8 * 1024,
+            prg_rom: RomBlock::new(prg_rom),
+            chr_rom: RomBlock::new(chr_rom),
+            mirroring: CartMirroring::Vertical,
+            alternative_mirroring: false,
+            mapper: 83,
+            submapper: None,
+            wram: None,
+            battery: false,
+            esp: EspConfig::default(),
+            board: None,
+            expansion_device: 0,
+        };
+
+        Cony::new(cartridge, Rc::new(Debug::new()))
+    }
+
+    fn prg_banks(mapper: &Cony) -> [u8; 4] {
+        [0x8000, 0xa000, 0xc000, 0xe000].map(|addr| mapper.read_cpu(addr))
+    }
+
+    #[test]
+    fn prg_banks_8k_and_16k() {
+        let mut mapper = cony();
+        assert_eq!(prg_banks(&mapper), [0, 0, 0, 31]);
+
+        mapper.write_cpu(0x8300, 1);
+        mapper.write_cpu(0x8301, 2);
+        mapper.write_cpu(0x8302, 3);
+        assert_eq!(prg_banks(&mapper), [1, 2, 3, 31]);
+
+        // Writing the outer bank switches to 16k mode with the last bank of the outer 256k fixed
+        mapper.write_cpu(0xb000, 0x05);
+        assert_eq!(prg_banks(&mapper), [10, 11, 30, 31]);
+
+        mapper.write_cpu(0x8300, 4);
+        assert_eq!(prg_banks(&mapper), [4, 2, 3, 31]);
+    }
+
+    #[test]
+    fn chr_banks_1k_and_2k() {
+        let mut mapper = cony();
+        for (reg, bank) in (0x8310..=0x8317).zip([10, 11, 12, 13, 14, 15, 16, 17]) {
+            mapper.write_cpu(reg, bank);
+        }
+        for (addr, bank) in (0..0x2000).step_by(0x400).zip(10..) {
+            assert_eq!(mapper.peek(BusKind::Ppu, addr), bank);
+        }
+
+        // Without a submapper, 2k banking is used when only the 2k registers are written
+        let mut mapper = cony();
+        mapper.write_cpu(0x8000, 0);
+        for (reg, bank) in [(0x8310, 1), (0x8311, 2), (0x8316, 3), (0x8317, 4)] {
+            mapper.write_cpu(reg, bank);
+        }
+        let banks =
+            [0x0000, 0x0400, 0x0800, 0x1000, 0x1800].map(|addr| mapper.peek(BusKind::Ppu, addr));
+        assert_eq!(banks, [2, 3, 4, 6, 8]);
+    }
+
+    #[test]
+    fn irq_counts_down_when_enabled() {
+        let mut mapper = cony();
+        mapper.write_cpu(0x8100, 0x80);
+        mapper.write_cpu(0x8200, 0x10);
+        mapper.write_cpu(0x8201, 0x00);
+        for _ in 0..0x10 {
+            mapper.tick();
+        }
+        assert!(!mapper.get_irq());
+        mapper.tick();
+        assert!(mapper.get_irq());
+
+        // One-shot, acknowledged by writing the low byte
+        mapper.write_cpu(0x8200, 0x10);
+        assert!(!mapper.get_irq());
+        for _ in 0..0x100 {
+            mapper.tick();
+        }
+        assert!(!mapper.get_irq());
+
+        // Counting is only enabled by the mode register when the high byte is written
+        mapper.write_cpu(0x8100, 0x00);
+        mapper.write_cpu(0x8201, 0x00);
+        for _ in 0..0x100 {
+            mapper.tick();
+        }
+        assert!(!mapper.get_irq());
+    }
+}
@@ -6,12 +6,35 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     bus::{AddressBus, AndAndMask, AndEqualsAndMask, BusKind, DeviceKind, RangeAndMask},
-    mapper::{FdsInput, Mapper, MapperInput},
+    cartridge::{export_disk_sides, import_disk_sides},
+    mapper::{FdsInput, Mapper, MapperInput, SaveWram},
     memory::{FixedMemoryBlock, Memory},
 };
 
 use super::SimpleMirroring;
 
+/// Optional behaviors that trade accuracy for convenience
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FdsConfig {
+    /// Insert the requested disk side whenever the BIOS checks for a disk that does not match
+    pub auto_swap: bool,
+    /// Skip the motor spin up and gap delays of the disk drive
+    pub fast_load: bool,
+}
+
+// BIOS routine that compares the inserted disk header against the ID at ($00)
+const CHECK_DISK_HEADER: u16 = 0xe445;
+const DISK_ID_LEN: usize = 10;
+// Offset of the manufacturer code within the disk info block
+const DISK_ID_OFFSET: usize = 15;
+
+const SEEK_TICKS: u64 = 50000;
+const FAST_SEEK_TICKS: u64 = 100;
+const BYTE_TICKS: u64 = 152;
+const FAST_GAP_TICKS: u64 = 8;
+const SWAP_TICKS: u64 = 2_000_000;
+const FAST_SWAP_TICKS: u64 = 200_000;
+
 #[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum DiskMode {
@@ -24,6 +47,14 @@ pub struct Fds {
     #[cfg_attr(feature = "save-states", save(skip))]
     disk_sides: Vec<Vec<u8>>,
     #[cfg_attr(feature = "save-states", save(skip))]
+    original_image: Vec<u8>,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    disk_ids: Vec<Option<[u8; DISK_ID_LEN]>>,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    disk_id_offsets: Vec<Option<usize>>,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    config: FdsConfig,
+    #[cfg_attr(feature = "save-states", save(skip))]
     bios: Vec<u8>,
     prg_ram: FixedMemoryBlock<32>,
     chr_ram: FixedMemoryBlock<8>,
@@ -54,14 +85,34 @@ pub struct Fds {
     disk_swap_counter: u64,
     sound: Sound,
     mirroring_mode: bool,
+    disk_id_request: [u8; DISK_ID_LEN],
+    disk_id_index: Option<usize>,
+    disk_id_check: bool,
+    disk_read_index: usize,
 }
 
 impl Fds {
     pub fn new(disk: crate::cartridge::Fds) -> Self {
         let prg_ram = FixedMemoryBlock::new();
         let chr_ram = FixedMemoryBlock::new();
+        let original_image = export_disk_sides(&disk.disk_sides, false);
+        let mut disk_sides = disk.disk_sides;
+        if let Some(wram) = disk.wram {
+            match apply_ips(&original_image, &wram.to_bytes()).map(|i| import_disk_sides(&i)) {
+                Some(Ok(sides)) => disk_sides = sides,
+                Some(Err(err)) => tracing::warn!("fds save could not be applied: {err:?}"),
+                None => tracing::warn!("fds save is not an ips patch for this disk"),
+            }
+        }
+        let disk_ids = disk_sides.iter().map(|side| disk_id(side)).collect();
+        let disk_id_offsets = disk_sides.iter().map(|side| disk_id_offset(side)).collect();
+
         Fds {
-            disk_sides: disk.disk_sides,
+            disk_sides,
+            original_image,
+            disk_ids,
+            disk_id_offsets,
+            config: disk.config,
             bios: disk.bios,
             prg_ram,
             chr_ram,
@@ -92,6 +143,10 @@ impl Fds {
             disk_swap_counter: 0,
             sound: Sound::new(),
             mirroring_mode: false,
+            disk_id_request: [0; DISK_ID_LEN],
+            disk_id_index: None,
+            disk_id_check: false,
+            disk_read_index: 0,
         }
     }
 
@@ -104,6 +159,10 @@ impl Fds {
     }
 
     fn read_cpu(&mut self, addr: u16) -> u8 {
+        if self.config.auto_swap {
+            self.watch_disk_id(addr);
+        }
+
         if addr >= 0x4040 && addr < 0x4098 {
             return self.sound.read(addr);
         }
@@ -265,7 +324,11 @@ impl Fds {
             }
 
             self.disk_side = Some(side);
-            self.disk_swap_counter = 2_000_000;
+            self.disk_swap_counter = if self.config.fast_load {
+                FAST_SWAP_TICKS
+            } else {
+                SWAP_TICKS
+            };
         } else {
             self.disk_side = None;
         }
@@ -273,6 +336,81 @@ impl Fds {
         self.disk_motor_enabled = false;
         self.disk_ready = false;
     }
+
+    // The BIOS compares the disk header one byte at a time against the ID the game
+    // requested, each ID byte read from the disk is followed by a read of the requested byte
+    fn watch_disk_id(&mut self, addr: u16) {
+        match addr {
+            CHECK_DISK_HEADER => {
+                self.disk_id_check = true;
+                self.disk_id_index = None;
+            }
+            0x4031 if self.disk_id_check => {
+                let offset = self
+                    .disk_side
+                    .and_then(|side| self.disk_id_offsets.get(side).copied().flatten());
+                self.disk_id_index = offset
+                    .and_then(|offset| self.disk_read_index.checked_sub(offset))
+                    .filter(|&idx| idx < DISK_ID_LEN);
+            }
+            0x6000..0xe000 => {
+                if let Some(idx) = self.disk_id_index.take() {
+                    self.compare_disk_id(idx, self.prg_ram.read(addr - 0x6000));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn compare_disk_id(&mut self, idx: usize, value: u8) {
+        self.disk_id_request[idx] = value;
+
+        let inserted = self
+            .disk_side
+            .and_then(|side| self.disk_ids.get(side).copied().flatten());
+        let matches = value == 0xff || inserted.is_some_and(|id| id[idx] == value);
+
+        if !matches {
+            self.disk_id_check = false;
+            self.insert_requested_disk(idx + 1);
+        } else if idx + 1 == DISK_ID_LEN {
+            self.disk_id_check = false;
+        }
+    }
+
+    fn insert_requested_disk(&mut self, len: usize) {
+        let request = &self.disk_id_request[..len];
+        let side = self.disk_ids.iter().position(|id| {
+            id.is_some_and(|id| {
+                request
+                    .iter()
+                    .zip(id)
+                    .all(|(&req, id)| req == 0xff || req == id)
+            })
+        });
+
+        if let Some(side) = side
+            && Some(side) != self.disk_side
+        {
+            tracing::debug!("fds auto swap to side: {side}");
+            self.change_disk(Some(side));
+        }
+    }
+}
+
+// Position of the disk ID within the disk info block of a side
+fn disk_id_offset(side: &[u8]) -> Option<usize> {
+    let start = side.iter().position(|&b| b != 0)?;
+    if side.get(start + 1) != Some(&1) {
+        return None;
+    }
+
+    Some(start + 1 + DISK_ID_OFFSET)
+}
+
+fn disk_id(side: &[u8]) -> Option<[u8; DISK_ID_LEN]> {
+    let offset = disk_id_offset(side)?;
+    side.get(offset..offset + DISK_ID_LEN)?.try_into().ok()
 }
 
 impl Mapper for Fds {
@@ -333,7 +471,11 @@ impl Mapper for Fds {
         if !self.disk_motor_enabled || self.disk_ejected() {
             self.disk_ready = false;
             self.disk_index = 0;
-            self.disk_transfer_counter = 50000;
+            self.disk_transfer_counter = if self.config.fast_load {
+                FAST_SEEK_TICKS
+            } else {
+                SEEK_TICKS
+            };
             self.disk_gap_ended = false;
             return;
         }
@@ -347,7 +489,7 @@ impl Mapper for Fds {
             self.disk_transfer_counter -= 1;
         } else {
             self.disk_ready = true;
-            self.disk_transfer_counter = 152;
+            self.disk_transfer_counter = BYTE_TICKS;
             let mut need_irq = self.disk_irq_enabled;
 
             match self.disk_transfer_mode {
@@ -363,6 +505,7 @@ impl Mapper for Fds {
 
                     if self.disk_gap_ended {
                         self.disk_read_data = disk_data;
+                        self.disk_read_index = self.disk_index;
                         self.disk_transfer_flag = true;
                         if need_irq {
                             self.disk_irq = true;
@@ -394,6 +537,14 @@ impl Mapper for Fds {
 
             self.disk_prev_crc_control = self.disk_crc_control;
 
+            // Nothing is transferred to the cpu while searching for the end of a gap
+            if self.config.fast_load
+                && self.disk_transfer_mode == DiskMode::Read
+                && !self.disk_gap_ended
+            {
+                self.disk_transfer_counter = FAST_GAP_TICKS;
+            }
+
             self.disk_index += 1;
             if self.disk_index >= self.disk_side_len() {
                 self.disk_motor_enabled = false;
@@ -417,6 +568,106 @@ impl Mapper for Fds {
             },
         }
     }
+
+    // Disk modifications are saved as an IPS patch against the headerless .fds image. The
+    // offsets only match the loaded file when it was a headerless .fds, a headered image is
+    // 16 bytes ahead and a .qd image has a different side layout
+    fn save_wram(&self) -> Option<SaveWram> {
+        let image = export_disk_sides(&self.disk_sides, false);
+        let patch = diff_ips(&self.original_image, &image)?;
+        Some(SaveWram::from_bytes(patch))
+    }
+}
+
+const IPS_HEADER: &[u8] = b"PATCH";
+const IPS_FOOTER: &[u8] = b"EOF";
+const IPS_MAX_RECORD: usize = 0xffff;
+// Unchanged bytes between two modified runs that are cheaper to repeat than
+// starting a new record
+const IPS_MERGE_GAP: usize = 5;
+
+fn diff_ips(original: &[u8], modified: &[u8]) -> Option<Vec<u8>> {
+    let mut patch = IPS_HEADER.to_vec();
+    let mut idx = 0;
+    while idx < modified.len() {
+        if original.get(idx) == Some(&modified[idx]) {
+            idx += 1;
+            continue;
+        }
+
+        // An offset that spells out the footer would end the patch early
+        let start = if idx == 0x454f46 { idx - 1 } else { idx };
+        let mut end = idx + 1;
+        let mut gap = 0;
+        while end + gap < modified.len()
+            && end + gap - start < IPS_MAX_RECORD
+            && gap <= IPS_MERGE_GAP
+        {
+            if original.get(end + gap) == Some(&modified[end + gap]) {
+                gap += 1;
+            } else {
+                end += gap + 1;
+                gap = 0;
+            }
+        }
+
+        if start > 0xffffff {
+            break;
+        }
+
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&modified[start..end]);
+        idx = end;
+    }
+
+    if patch.len() == IPS_HEADER.len() {
+        return None;
+    }
+
+    patch.extend_from_slice(IPS_FOOTER);
+    if modified.len() <= 0xffffff {
+        patch.extend_from_slice(&(modified.len() as u32).to_be_bytes()[1..]);
+    }
+    Some(patch)
+}
+
+// Returns `None` when the patch is malformed or was made for a differently sized image
+fn apply_ips(original: &[u8], patch: &[u8]) -> Option<Vec<u8>> {
+    let mut patch = patch.strip_prefix(IPS_HEADER)?;
+    let mut image = original.to_vec();
+
+    loop {
+        if let Some(trailer) = patch.strip_prefix(IPS_FOOTER) {
+            // Truncation extension, the size of the image the patch was made against
+            if let &[a, b, c] = trailer
+                && u32::from_be_bytes([0, a, b, c]) as usize != image.len()
+            {
+                return None;
+            }
+            return Some(image);
+        }
+
+        let &[o0, o1, o2, s0, s1, ref rest @ ..] = patch else {
+            return None;
+        };
+        let offset = u32::from_be_bytes([0, o0, o1, o2]) as usize;
+        let size = u16::from_be_bytes([s0, s1]) as usize;
+
+        if size == 0 {
+            // Run length encoded record
+            let &[r0, r1, value, ref rest @ ..] = rest else {
+                return None;
+            };
+            let size = u16::from_be_bytes([r0, r1]) as usize;
+            image.get_mut(offset..offset + size)?.fill(value);
+            patch = rest;
+        } else {
+            let data = rest.get(..size)?;
+            image.get_mut(offset..offset + size)?.copy_from_slice(data);
+            patch = &rest[size..];
+        }
+    }
 }
 
 #[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
@@ -725,3 +976,150 @@ impl Envelope {
         self.gain
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cartridge::import_disk_sides;
+
+    // Headerless image with a disk info and file amount block per side
+    fn disk_image(ids: &[[u8; DISK_ID_LEN]]) -> Vec<u8> {
+        let mut image = Vec::new();
+        for id in ids {
+            let start = image.len();
+            image.push(1);
+            image.extend_from_slice(b"*NINTENDO-HVC*");
+            image.extend_from_slice(id);
+            image.resize(start + 56, 0);
+            image.extend_from_slice(&[2, 0]);
+            image.resize(start + 65500, 0);
+        }
+        image
+    }
+
+    fn auto_swap_fds(ids: &[[u8; DISK_ID_LEN]]) -> Fds {
+        let disk = crate::cartridge::Fds {
+            disk_sides: import_disk_sides(&disk_image(ids)).unwrap(),
+            bios: vec![0; 0x2000],
+            wram: None,
+            config: FdsConfig {
+                auto_swap: true,
+                fast_load: true,
+            },
+        };
+        Fds::new(disk)
+    }
+
+    // Wait for the drive to transfer a byte, as the BIOS does by polling $4030
+    fn read_disk_byte(fds: &mut Fds) -> u8 {
+        for _ in 0..1_000_000 {
+            fds.tick();
+            if fds.read_cpu(0x4030) & 0x2 != 0 {
+                return fds.read_cpu(0x4031);
+            }
+        }
+        panic!("no byte transferred");
+    }
+
+    // Read the disk info block comparing each ID byte with the request at $6000
+    fn check_disk_header(fds: &mut Fds, request: [u8; DISK_ID_LEN]) {
+        for (idx, &value) in request.iter().enumerate() {
+            fds.write_cpu(0x6000 + idx as u16, value);
+        }
+
+        fds.write_cpu(0x4023, 0x01);
+        fds.write_cpu(0x4025, 0x45);
+        fds.read_cpu(CHECK_DISK_HEADER);
+
+        while read_disk_byte(fds) != 1 {}
+        for _ in 0..14 {
+            read_disk_byte(fds);
+        }
+        for idx in 0..DISK_ID_LEN {
+            let disk = read_disk_byte(fds);
+            let requested = fds.read_cpu(0x6000 + idx as u16);
+            if requested != 0xff && disk != requested {
+                break;
+            }
+        }
+
+        // Stop the motor and give the drive a tick to return to the start of the disk
+        fds.write_cpu(0x4025, 0x00);
+        fds.tick();
+    }
+
+    #[test]
+    fn auto_swap_inserts_requested_side() {
+        let side_a = [0x01, b'T', b'S', b'T', 0, 0, 0, 0, 0, 0];
+        let side_b = [0x01, b'T', b'S', b'T', 0, 0, 1, 0, 0, 0];
+        let mut fds = auto_swap_fds(&[side_a, side_b]);
+        fds.change_disk(Some(0));
+
+        check_disk_header(&mut fds, side_a);
+        assert_eq!(fds.disk_side, Some(0));
+
+        let mut request = side_b;
+        request[4] = 0xff;
+        check_disk_header(&mut fds, request);
+        assert_eq!(fds.disk_side, Some(1));
+
+        check_disk_header(&mut fds, side_a);
+        assert_eq!(fds.disk_side, Some(0));
+    }
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    fn round_trip(original: &[u8], modified: &[u8]) -> Vec<u8> {
+        let patch = diff_ips(original, modified).expect("images differ");
+        apply_ips(original, &patch).expect("patch applies")
+    }
+
+    #[test]
+    fn ips_round_trip() {
+        let original = image(2 * 65500);
+        let mut modified = original.clone();
+        for offset in [0, 1, 9, 16, 1000, 65499, 65500, 2 * 65500 - 1] {
+            modified[offset] ^= 0xff;
+        }
+
+        assert_eq!(diff_ips(&original, &original), None);
+        assert_eq!(round_trip(&original, &modified), modified);
+    }
+
+    #[test]
+    fn ips_long_runs() {
+        let original = image(4 * 65500);
+        let mut modified = original.clone();
+        for byte in &mut modified[10..10 + 0x20000] {
+            *byte = !*byte;
+        }
+
+        assert_eq!(round_trip(&original, &modified), modified);
+    }
+
+    #[test]
+    fn ips_offset_spelling_eof() {
+        let original = image(0x454f46 + 16);
+        let mut modified = original.clone();
+        modified[0x454f46] ^= 0xff;
+
+        let patch = diff_ips(&original, &modified).unwrap();
+        assert!(!patch[IPS_HEADER.len()..].starts_with(IPS_FOOTER));
+        assert_eq!(apply_ips(&original, &patch), Some(modified));
+    }
+
+    #[test]
+    fn ips_rejects_other_image_sizes() {
+        let original = image(2 * 65500);
+        let mut modified = original.clone();
+        modified[100] ^= 0xff;
+        let patch = diff_ips(&original, &modified).unwrap();
+
+        assert_eq!(apply_ips(&image(3 * 65500), &patch), None);
+        assert_eq!(apply_ips(&image(50), &patch), None);
+        assert_eq!(apply_ips(&original, b"NOT A PATCH"), None);
+    }
+}
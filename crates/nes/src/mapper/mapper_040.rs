@@ -0,0 +1,194 @@
+use std::rc::Rc;
+
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+
+use crate::Debug;
+use crate::bus::{AddressBus, AndAndMask, AndEqualsAndMask, BusKind, DeviceKind};
+use crate::cartridge::INes;
+use crate::mapper::Mapper;
+use crate::memory::{Memory, MemoryBlock};
+use crate::ppu::PpuFetchKind;
+
+// NTDEC 2722 Super Mario Bros. 2 (FDS) conversion
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct Mapper040 {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    cartridge: INes,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    debug: Rc<Debug>,
+    chr_ram: Option<MemoryBlock>,
+    prg_bank: u8,
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq: bool,
+}
+
+impl Mapper040 {
+    pub fn new(cartridge: INes, debug: Rc<Debug>) -> Self {
+        let chr_ram =
+            (cartridge.chr_ram_bytes > 0).then(|| MemoryBlock::new(cartridge.chr_ram_bytes / 1024));
+
+        Self {
+            cartridge,
+            debug,
+            chr_ram,
+            prg_bank: 0,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq: false,
+        }
+    }
+
+    fn read_cpu(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x6000..=0x7fff => 6,
+            0x8000..=0x9fff => 4,
+            0xa000..=0xbfff => 5,
+            0xc000..=0xdfff => self.prg_bank,
+            0xe000..=0xffff => 7,
+            _ => unreachable!(),
+        };
+
+        self.cartridge
+            .prg_rom
+            .read_mapped(bank as usize, 8 * 1024, addr)
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) {
+        match addr & 0xe000 {
+            0x8000 => {
+                self.irq_enabled = false;
+                self.irq_counter = 0;
+                self.irq = false;
+            }
+            0xa000 => self.irq_enabled = true,
+            0xe000 => self.prg_bank = value & 7,
+            _ => (),
+        }
+    }
+}
+
+impl Mapper for Mapper040 {
+    fn register(&self, cpu: &mut AddressBus) {
+        cpu.register_read(DeviceKind::Mapper, AndEqualsAndMask(0xe000, 0x6000, 0x7fff));
+        cpu.register_read(DeviceKind::Mapper, AndAndMask(0x8000, 0xffff));
+        cpu.register_write(DeviceKind::Mapper, AndAndMask(0x8000, 0xffff));
+    }
+
+    fn peek(&self, bus: BusKind, addr: u16) -> u8 {
+        match bus {
+            BusKind::Cpu => self.read_cpu(addr),
+            BusKind::Ppu => {
+                if let Some(ram) = self.chr_ram.as_ref() {
+                    ram.read_mapped(0, 8 * 1024, addr)
+                } else {
+                    self.cartridge.chr_rom.read_mapped(0, 8 * 1024, addr)
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, bus: BusKind, addr: u16, value: u8) {
+        match bus {
+            BusKind::Cpu => self.write_cpu(addr, value),
+            BusKind::Ppu => {
+                if let Some(ram) = self.chr_ram.as_mut() {
+                    ram.write_mapped(0, 8 * 1024, addr, value)
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.irq_enabled {
+            self.irq_counter += 1;
+            if self.irq_counter == 4096 {
+                self.irq_enabled = false;
+                self.irq = true;
+                self.debug.event(crate::DebugEvent::MapperIrq);
+            }
+        }
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq
+    }
+
+    fn peek_ppu_fetch(&self, address: u16, _kind: PpuFetchKind) -> super::Nametable {
+        self.cartridge.mirroring.ppu_fetch(address)
+    }
+
+    fn power(&mut self) {
+        self.prg_bank = 0;
+        self.irq_enabled = false;
+        self.irq_counter = 0;
+        self.irq = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cartridge::CartMirroring;
+    use crate::mapper::EspConfig;
+    use crate::memory::RomBlock;
+
+    fn mapper_040() -> Mapper040 {
+        // Each 8k bank is filled with its own bank number
+        let prg_rom: Vec<u8> = (0..8u8).flat_map(|bank| [bank; 8 * 1024]).collect();
+        let cartridge = INes {
+            chr_ram_bytes: 8 * 1024,
+            prg_ram_bytes: 0,
+            prg_rom: RomBlock::new(prg_rom),
+            chr_rom: RomBlock::new(Vec::new()),
+            mirroring: CartMirroring::Vertical,
+            alternative_mirroring: false,
+            mapper: 40,
+            submapper: None,
+            wram: None,
+            battery: false,
+            esp: EspConfig::default(),
+            board: None,
+            expansion_device: 0,
+        };
+
+        Mapper040::new(cartridge, Rc::new(Debug::new()))
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut mapper = mapper_040();
+        let banks = [0x6000, 0x8000, 0xa000, 0xc000, 0xe000].map(|addr| mapper.read_cpu(addr));
+        assert_eq!(banks, [6, 4, 5, 0, 7]);
+
+        mapper.write_cpu(0xe000, 0xfb);
+        assert_eq!(mapper.read_cpu(0xc000), 3);
+        assert_eq!(mapper.read_cpu(0xe000), 7);
+    }
+
+    #[test]
+    fn irq_after_4096_cycles() {
+        let mut mapper = mapper_040();
+        mapper.write_cpu(0xa000, 0);
+        for _ in 0..4095 {
+            mapper.tick();
+        }
+        assert!(!mapper.get_irq());
+        mapper.tick();
+        assert!(mapper.get_irq());
+
+        // The counter stops once it fires and only restarts when re-enabled
+        for _ in 0..8192 {
+            mapper.tick();
+        }
+        assert!(mapper.get_irq());
+        mapper.write_cpu(0x8000, 0);
+        assert!(!mapper.get_irq());
+        for _ in 0..4096 {
+            mapper.tick();
+        }
+        assert!(!mapper.get_irq());
+    }
+}
@@ -0,0 +1,221 @@
+use std::rc::Rc;
+
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+
+use crate::Debug;
+use crate::bus::{AddressBus, AndAndMask, AndEqualsAndMask, BusKind, DeviceKind};
+use crate::cartridge::INes;
+use crate::mapper::Mapper;
+use crate::memory::{Memory, MemoryBlock};
+use crate::ppu::PpuFetchKind;
+
+use super::SimpleMirroring;
+
+// FDS conversions such as Ai Senshi Nicol and Mario Baby
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct Mapper042 {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    cartridge: INes,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    debug: Rc<Debug>,
+    chr_ram: Option<MemoryBlock>,
+    prg_bank: u8,
+    chr_bank: u8,
+    irq_enabled: bool,
+    irq_counter: u16,
+    mirroring: SimpleMirroring,
+}
+
+impl Mapper042 {
+    pub fn new(cartridge: INes, debug: Rc<Debug>) -> Self {
+        let chr_ram =
+            (cartridge.chr_ram_bytes > 0).then(|| MemoryBlock::new(cartridge.chr_ram_bytes / 1024));
+        let mirroring = SimpleMirroring::new(cartridge.mirroring);
+
+        Self {
+            cartridge,
+            debug,
+            chr_ram,
+            prg_bank: 0,
+            chr_bank: 0,
+            irq_enabled: false,
+            irq_counter: 0,
+            mirroring,
+        }
+    }
+
+    fn read_cpu(&self, addr: u16) -> u8 {
+        if addr < 0x8000 {
+            self.cartridge
+                .prg_rom
+                .read_mapped(self.prg_bank as usize, 8 * 1024, addr)
+        } else {
+            let last_bank = (self.cartridge.prg_rom.len() / (32 * 1024)).saturating_sub(1);
+            self.cartridge
+                .prg_rom
+                .read_mapped(last_bank, 32 * 1024, addr)
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) {
+        match addr & 0xe003 {
+            0x8000 => self.chr_bank = value,
+            0xe000 => self.prg_bank = value & 0xf,
+            0xe001 => {
+                if value & 0x08 != 0 {
+                    self.mirroring.horizontal();
+                } else {
+                    self.mirroring.vertical();
+                }
+            }
+            0xe002 => {
+                self.irq_enabled = value & 0x02 != 0;
+                if !self.irq_enabled {
+                    self.irq_counter = 0;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_counter & 0x6000 == 0x6000
+    }
+}
+
+impl Mapper for Mapper042 {
+    fn register(&self, cpu: &mut AddressBus) {
+        cpu.register_read(DeviceKind::Mapper, AndEqualsAndMask(0xe000, 0x6000, 0x7fff));
+        cpu.register_read(DeviceKind::Mapper, AndAndMask(0x8000, 0xffff));
+        cpu.register_write(DeviceKind::Mapper, AndAndMask(0x8000, 0xffff));
+    }
+
+    fn peek(&self, bus: BusKind, addr: u16) -> u8 {
+        match bus {
+            BusKind::Cpu => self.read_cpu(addr),
+            BusKind::Ppu => {
+                if let Some(ram) = self.chr_ram.as_ref() {
+                    ram.read_mapped(0, 8 * 1024, addr)
+                } else {
+                    self.cartridge
+                        .chr_rom
+                        .read_mapped(self.chr_bank as usize, 8 * 1024, addr)
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, bus: BusKind, addr: u16, value: u8) {
+        match bus {
+            BusKind::Cpu => self.write_cpu(addr, value),
+            BusKind::Ppu => {
+                if let Some(ram) = self.chr_ram.as_mut() {
+                    ram.write_mapped(0, 8 * 1024, addr, value)
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.irq_enabled {
+            let was_irq = self.irq();
+            // 15-bit counter, irq is held while the top two bits are set
+            self.irq_counter = (self.irq_counter + 1) & 0x7fff;
+            if !was_irq && self.irq() {
+                self.debug.event(crate::DebugEvent::MapperIrq);
+            }
+        }
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq()
+    }
+
+    fn peek_ppu_fetch(&self, address: u16, _kind: PpuFetchKind) -> super::Nametable {
+        self.mirroring.ppu_fetch(address)
+    }
+
+    fn power(&mut self) {
+        self.prg_bank = 0;
+        self.chr_bank = 0;
+        self.irq_enabled = false;
+        self.irq_counter = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cartridge::CartMirroring;
+    use crate::mapper::EspConfig;
+    use crate::memory::RomBlock;
+
+    fn mapper_042() -> Mapper042 {
+        // Each 8k bank is filled with its own bank number
+        let prg_rom: Vec<u8> = (0..16u8).flat_map(|bank| [bank; 8 * 1024]).collect();
+        let chr_rom: Vec<u8> = (0..4u8).flat_map(|bank| [bank; 8 * 1024]).collect();
+        let cartridge = INes {
+            chr_ram_bytes: 0,
+            prg_ram_bytes: 0,
+            prg_rom: RomBlock::new(prg_rom),
+            chr_rom: RomBlock::new(chr_rom),
+            mirroring: CartMirroring::Vertical,
+            alternative_mirroring: false,
+            mapper: 42,
+            submapper: None,
+            wram: None,
+            battery: false,
+            esp: EspConfig::default(),
+            board: None,
+            expansion_device: 0,
+        };
+
+        Mapper042::new(cartridge, Rc::new(Debug::new()))
+    }
+
+    #[test]
+    fn prg_and_chr_banks() {
+        let mut mapper = mapper_042();
+        let banks = [0x6000, 0x8000, 0xa000, 0xc000, 0xe000].map(|addr| mapper.read_cpu(addr));
+        assert_eq!(banks, [0, 12, 13, 14, 15]);
+        assert_eq!(mapper.peek(BusKind::Ppu, 0x0000), 0);
+
+        mapper.write_cpu(0xe000, 0xf5);
+        assert_eq!(mapper.read_cpu(0x6000), 5);
+        assert_eq!(mapper.read_cpu(0x8000), 12);
+
+        mapper.write_cpu(0x8000, 2);
+        assert_eq!(mapper.peek(BusKind::Ppu, 0x1fff), 2);
+    }
+
+    #[test]
+    fn irq_held_while_counter_top_bits_set() {
+        let mut mapper = mapper_042();
+        mapper.write_cpu(0xe002, 0x02);
+        for _ in 0..0x5fff {
+            mapper.tick();
+        }
+        assert!(!mapper.get_irq());
+        mapper.tick();
+        assert!(mapper.get_irq());
+
+        // Released when the 15-bit counter wraps
+        for _ in 0..0x1fff {
+            mapper.tick();
+        }
+        assert!(mapper.get_irq());
+        mapper.tick();
+        assert!(!mapper.get_irq());
+
+        for _ in 0..0x6000 {
+            mapper.tick();
+        }
+        assert!(mapper.get_irq());
+        mapper.write_cpu(0xe002, 0);
+        assert!(!mapper.get_irq());
+        mapper.tick();
+        assert!(!mapper.get_irq());
+    }
+}
@@ -303,6 +303,8 @@ impl Mmc5 {
             }
             CartMirroring::Horizontal => [M::InternalA, M::InternalA, M::InternalB, M::InternalB],
             CartMirroring::Vertical => [M::InternalA, M::InternalB, M::InternalA, M::InternalB],
+            CartMirroring::SingleA => [M::InternalA; 4],
+            CartMirroring::SingleB => [M::InternalB; 4],
         };
 
         Self {
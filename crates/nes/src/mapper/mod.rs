@@ -4,23 +4,30 @@ mod bf909x;
 mod bxrom;
 mod cnrom;
 mod color_dreams;
+mod cony;
 mod fds;
 mod fme7;
 mod game_genie;
 mod gxrom;
 mod j87;
 mod mapper_031;
+mod mapper_040;
+mod mapper_042;
+mod mapper_050;
 mod mmc1;
 mod mmc2;
 mod mmc3;
 mod mmc5;
 mod namco163;
 mod namco175_340;
+mod nanjing;
 mod nina001;
 mod nina006;
 mod nrom;
 mod nsf;
 mod rainbow;
+mod rainbow_esp;
+mod registry;
 mod uxrom;
 mod vrc4;
 mod vrc6;
@@ -40,6 +47,11 @@ use crate::ppu::PpuFetchKind;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+pub use fds::FdsConfig;
+pub(crate) use nsf::NsfOutput;
+pub use rainbow_esp::EspConfig;
+pub use registry::MapperRegistry;
+pub(crate) use registry::unif_board_name;
 pub use traits::MapperState;
 
 #[derive(Debug, Clone)]
@@ -256,16 +268,22 @@ pub fn ines(cart: INes, debug: Rc<Debug>) -> RcMapper {
                 }
             }
         },
+        40 => mapper_040::Mapper040::new(cart, debug).rc(),
+        42 => mapper_042::Mapper042::new(cart, debug).rc(),
+        50 => mapper_050::Mapper050::new(cart, debug).rc(),
         66 => gxrom::Gxrom::new(cart).rc(),
         69 => fme7::Fme7::new(cart, debug).rc(),
         71 | 232 => bf909x::Bf909x::new(cart).rc(),
         79 | 146 => nina006::Nina006::new(cart).rc(),
+        83 => cony::Cony::new(cart, debug).rc(),
         85 => match cart.submapper {
             Some(1) => vrc7::Vrc7::new(cart, vrc7::Vrc7Variant::Vrc7b, debug).rc(),
             Some(2) => vrc7::Vrc7::new(cart, vrc7::Vrc7Variant::Vrc7a, debug).rc(),
             _ => vrc7::Vrc7::new(cart, vrc7::Vrc7Variant::Undefined, debug).rc(),
         },
         87 => j87::J87::new(cart).rc(),
+        163 => nanjing::Nanjing::new(cart, nanjing::NanjingVariant::Nanjing).rc(),
+        164 => nanjing::Nanjing::new(cart, nanjing::NanjingVariant::Yancheng).rc(),
         206 => {
             tracing::warn!("limited mapper support");
             mmc3::Mmc3::new(cart, mmc3::Mmc3Variant::Mmc3, debug).rc()
@@ -282,10 +300,7 @@ pub fn ines(cart: INes, debug: Rc<Debug>) -> RcMapper {
                 namco175_340::Namco175_340::new(cart, namco175_340::NamcoVariant::Unspecified).rc()
             }
         },
-        682 | 3871 => {
-            tracing::warn!("limited mapper support");
-            rainbow::Rainbow::new(cart, debug).rc()
-        }
+        682 | 3871 => rainbow::Rainbow::new(cart, debug).rc(),
         _ => {
             tracing::error!("mapper not implemented");
             nrom::Nrom::new(cart).rc()
@@ -297,8 +312,8 @@ pub fn fds(disk: Fds) -> RcMapper {
     fds::Fds::new(disk).rc()
 }
 
-pub fn nsf(region: Region, data: NsfFile) -> RcMapper {
-    nsf::Nsf::new(region, data).rc()
+pub fn nsf(region: Region, data: NsfFile, output: Rc<NsfOutput>) -> RcMapper {
+    nsf::Nsf::new(region, data, output).rc()
 }
 
 #[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
@@ -0,0 +1,253 @@
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+#[cfg(feature = "save-states")]
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{AddressBus, AndAndMask, AndEqualsAndMask, BusKind, DeviceKind};
+use crate::cartridge::INes;
+use crate::mapper::Mapper;
+use crate::memory::{Memory, MemoryBlock};
+use crate::ppu::PpuFetchKind;
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NanjingVariant {
+    Nanjing,
+    Yancheng,
+}
+
+// Nanjing (163) and Yancheng (164) boards
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct Nanjing {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    cartridge: INes,
+    variant: NanjingVariant,
+    prg_ram: MemoryBlock,
+    chr_ram: Option<MemoryBlock>,
+    prg_hi: u8,
+    prg_lo: u8,
+    feedback: u8,
+    protect: u8,
+    strobe: bool,
+    trigger: bool,
+    chr_latch: bool,
+    prg_override: bool,
+}
+
+impl Nanjing {
+    pub fn new(mut cartridge: INes, variant: NanjingVariant) -> Self {
+        let prg_ram_size = (8 * 1024).max(cartridge.prg_ram_bytes);
+        let mut prg_ram = MemoryBlock::new(prg_ram_size / 1024);
+        if let Some(wram) = cartridge.wram.take() {
+            prg_ram.restore_wram(wram);
+        }
+
+        let chr_ram = (cartridge.chr_ram_bytes > 0)
+            .then(|| MemoryBlock::new((8 * 1024).max(cartridge.chr_ram_bytes) / 1024));
+
+        Self {
+            cartridge,
+            variant,
+            prg_ram,
+            chr_ram,
+            prg_hi: 0,
+            prg_lo: 0xff,
+            feedback: 0,
+            protect: 0,
+            strobe: true,
+            trigger: false,
+            chr_latch: false,
+            prg_override: false,
+        }
+    }
+
+    fn prg_bank(&self) -> usize {
+        if self.prg_override {
+            3
+        } else {
+            ((self.prg_hi as usize) << 4) | (self.prg_lo as usize & 0xf)
+        }
+    }
+
+    fn chr_bank(&self, addr: u16) -> (usize, usize) {
+        // In auto-switch mode both pattern tables follow the nametable row being rendered
+        if self.variant == NanjingVariant::Nanjing && self.prg_lo & 0x80 != 0 {
+            (self.chr_latch as usize, 4 * 1024)
+        } else {
+            ((addr >> 12 & 1) as usize, 4 * 1024)
+        }
+    }
+
+    fn read_cpu(&self, addr: u16) -> u8 {
+        match addr {
+            0x5000..=0x5fff => match addr & 0x7700 {
+                0x5100 => self.feedback | self.prg_hi | self.prg_lo | (self.protect ^ 0xff),
+                0x5500 if self.trigger => self.feedback | self.prg_lo,
+                0x5500 => 0,
+                _ => 4,
+            },
+            0x6000..=0x7fff => self.prg_ram.read_mapped(0, 8 * 1024, addr),
+            _ => self
+                .cartridge
+                .prg_rom
+                .read_mapped(self.prg_bank(), 32 * 1024, addr),
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram.write_mapped(0, 8 * 1024, addr, value),
+            0x5101 if self.variant == NanjingVariant::Nanjing => {
+                if self.strobe && value == 0 {
+                    self.trigger = !self.trigger;
+                }
+                self.strobe = value != 0;
+            }
+            // Protected titles expect this write to swap in the fourth 32k bank
+            0x5100 if self.variant == NanjingVariant::Nanjing && value == 6 => {
+                self.prg_override = true;
+            }
+            0x5000..=0x5fff => match (addr & 0x7300, self.variant) {
+                (0x5000, _) => {
+                    self.prg_lo = value;
+                    self.prg_override = false;
+                }
+                (0x5100, NanjingVariant::Nanjing) => self.protect = value,
+                (0x5100, NanjingVariant::Yancheng) => {
+                    self.prg_hi = value;
+                    self.prg_override = false;
+                }
+                (0x5200, NanjingVariant::Nanjing) => {
+                    self.prg_hi = value;
+                    self.prg_override = false;
+                }
+                (0x5200, NanjingVariant::Yancheng) => self.protect = value,
+                (0x5300, _) => self.feedback = value,
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+impl Mapper for Nanjing {
+    fn register(&self, cpu: &mut AddressBus) {
+        cpu.register_read(DeviceKind::Mapper, AndEqualsAndMask(0xf000, 0x5000, 0xffff));
+        cpu.register_write(DeviceKind::Mapper, AndEqualsAndMask(0xf000, 0x5000, 0xffff));
+        cpu.register_read(DeviceKind::Mapper, AndEqualsAndMask(0xe000, 0x6000, 0x7fff));
+        cpu.register_write(DeviceKind::Mapper, AndEqualsAndMask(0xe000, 0x6000, 0x7fff));
+        cpu.register_read(DeviceKind::Mapper, AndAndMask(0x8000, 0xffff));
+    }
+
+    fn peek(&self, bus: BusKind, addr: u16) -> u8 {
+        match bus {
+            BusKind::Cpu => self.read_cpu(addr),
+            BusKind::Ppu => {
+                let (bank, size) = self.chr_bank(addr);
+                if let Some(ram) = self.chr_ram.as_ref() {
+                    ram.read_mapped(bank, size, addr)
+                } else {
+                    self.cartridge.chr_rom.read_mapped(bank, size, addr)
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, bus: BusKind, addr: u16, value: u8) {
+        match bus {
+            BusKind::Cpu => self.write_cpu(addr, value),
+            BusKind::Ppu => {
+                let (bank, size) = self.chr_bank(addr);
+                if let Some(ram) = self.chr_ram.as_mut() {
+                    ram.write_mapped(bank, size, addr, value)
+                }
+            }
+        }
+    }
+
+    fn ppu_fetch(&mut self, address: u16, kind: PpuFetchKind) -> super::Nametable {
+        // Latch the upper half of the nametable on tile fetches, attribute fetches are ignored
+        if kind == PpuFetchKind::Read && address & 0x2000 != 0 && address & 0x3c0 != 0x3c0 {
+            self.chr_latch = address & 0x200 != 0;
+        }
+
+        self.peek_ppu_fetch(address, kind)
+    }
+
+    fn peek_ppu_fetch(&self, address: u16, _kind: PpuFetchKind) -> super::Nametable {
+        self.cartridge.mirroring.ppu_fetch(address)
+    }
+
+    fn power(&mut self) {
+        self.prg_hi = 0;
+        self.prg_lo = 0xff;
+        self.feedback = 0;
+        self.protect = 0;
+        self.strobe = true;
+        self.trigger = false;
+        self.prg_override = false;
+    }
+
+    fn save_wram(&self) -> Option<super::SaveWram> {
+        if self.cartridge.battery {
+            self.prg_ram.save_wram()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cartridge::CartMirroring;
+    use crate::mapper::EspConfig;
+    use crate::memory::RomBlock;
+
+    fn nanjing() -> Nanjing {
+        // Each 32k bank is filled with its own bank number
+        let prg_rom: Vec<u8> = (0..4u8).flat_map(|bank| [bank; 32 * 1024]).collect();
+        let cartridge = INes {
+            chr_ram_bytes: 8 * 1024,
+            prg_ram_bytes: 8 * 1024,
+            prg_rom: RomBlock::new(prg_rom),
+            chr_rom: RomBlock::new(Vec::new()),
+            mirroring: CartMirroring::Vertical,
+            alternative_mirroring: false,
+            mapper: 163,
+            submapper: None,
+            wram: None,
+            battery: false,
+            esp: EspConfig::default(),
+            board: None,
+            expansion_device: 0,
+        };
+
+        let mut mapper = Nanjing::new(cartridge, NanjingVariant::Nanjing);
+        mapper.write_cpu(0x5000, 0);
+        mapper.write_cpu(0x5200, 0);
+        mapper
+    }
+
+    #[test]
+    fn prg_override_survives_register_writes() {
+        let mut mapper = nanjing();
+        assert_eq!(mapper.read_cpu(0x8000), 0);
+
+        mapper.write_cpu(0x5100, 6);
+        assert_eq!(mapper.read_cpu(0x8000), 3);
+
+        mapper.write_cpu(0x5101, 1);
+        mapper.write_cpu(0x5101, 0);
+        mapper.write_cpu(0x5300, 4);
+        assert_eq!(mapper.read_cpu(0x8000), 3);
+
+        mapper.write_cpu(0x5000, 1);
+        assert_eq!(mapper.read_cpu(0x8000), 1);
+
+        mapper.write_cpu(0x5100, 6);
+        mapper.write_cpu(0x5200, 0);
+        assert_eq!(mapper.read_cpu(0x8000), 1);
+    }
+}
@@ -8,23 +8,68 @@ use super::namco163::Sound as Namco163;
 use super::vrc6::Sound as Vrc6;
 use super::vrc7::Sound as Vrc7;
 use crate::Region;
+use crate::apu::Sample;
 use crate::bus::{AddressBus, AndAndMask, AndEqualsAndMask, BusKind, DeviceKind, RangeAndMask};
 use crate::cartridge::NsfFile;
 use crate::mapper::Mapper;
 use crate::memory::{FixedMemoryBlock, Memory, MemoryBlock, RomBlock};
 use crate::ppu::PpuFetchKind;
 
+use std::cell::Cell;
 use std::fmt::Write;
+use std::rc::Rc;
 
 static NSF_PLAYER_ROM: &[u8] = include_bytes!("nsf_player/nsf_player.bin");
 static NSF_PLAYER_CHR: &[u8] = include_bytes!("nsf_player/ascii-by-jroatch.chr");
 
+// Tracks without a duration advance after this long without any change in output
+const SILENCE_MS: u32 = 3000;
+const SILENCE_WINDOW_MS: u32 = 10;
+const SILENCE_THRESHOLD: f32 = 1.0 / 1024.0;
+
+/// Mixed output of the APU, shared with the NSF player so it can fade out tracks and end
+/// them after a silence
+#[derive(Debug)]
+pub struct NsfOutput {
+    level: Cell<f32>,
+    volume: Cell<f32>,
+}
+
+impl NsfOutput {
+    pub fn new() -> Self {
+        Self {
+            level: Cell::new(0.0),
+            volume: Cell::new(1.0),
+        }
+    }
+
+    pub(crate) fn volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    pub(crate) fn mix<S: Sample>(&self, sample: S) -> S {
+        self.level.set(sample.to_f32());
+        let volume = self.volume();
+        if volume < 1.0 {
+            sample.scale(volume)
+        } else {
+            sample
+        }
+    }
+}
+
 #[cfg_attr(feature = "save-states", derive(SaveState))]
 pub struct Nsf {
     #[cfg_attr(feature = "save-states", save(skip))]
     region: Region,
     #[cfg_attr(feature = "save-states", save(skip))]
     file: NsfFile,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    output: Rc<NsfOutput>,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    silence_window: u64,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    silence_length: u64,
     banks: Option<[u8; 8]>,
     prg_ram: FixedMemoryBlock<8>,
     ex_ram: FixedMemoryBlock<1>,
@@ -39,7 +84,21 @@ pub struct Nsf {
     play_timer_load: u32,
     play_timer: u32,
     play_pending: bool,
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq: bool,
+    irq_vector: u16,
     current_song: u8,
+    playlist_pos: u8,
+    track_ticks: u64,
+    track_end: Option<u64>,
+    fade_ticks: u64,
+    silence_ticks: u64,
+    window_ticks: u64,
+    window_min: f32,
+    window_max: f32,
+    track_ended: bool,
     mul_left: u8,
     mul_right: u8,
     #[cfg_attr(feature = "save-states", save(nested))]
@@ -54,7 +113,7 @@ pub struct Nsf {
 }
 
 impl Nsf {
-    pub fn new(region: Region, file: NsfFile) -> Nsf {
+    pub fn new(region: Region, file: NsfFile, output: Rc<NsfOutput>) -> Nsf {
         if file.chips.vt02() {
             tracing::warn!("VT02 audio not supported in NSF");
         }
@@ -91,7 +150,8 @@ impl Nsf {
         let play_timer_load = play_timer_load as u32;
         let play_timer = 0;
         let play_pending = true;
-        let current_song = file.starting_song;
+        let playlist_pos = Self::starting_position(&file);
+        let current_song = Self::playlist_song(&file, playlist_pos);
 
         let sunsoft5b = file.chips.sunsoft5b().then(|| Sunsoft5b::new());
         let namco163 = file.chips.namco163().then(|| {
@@ -116,6 +176,9 @@ impl Nsf {
         Nsf {
             region,
             file,
+            output,
+            silence_window: Self::ms_to_ticks(region, SILENCE_WINDOW_MS),
+            silence_length: Self::ms_to_ticks(region, SILENCE_MS),
             banks,
             prg_ram,
             ex_ram,
@@ -128,9 +191,23 @@ impl Nsf {
             play_timer_load,
             play_timer,
             play_pending,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq: false,
+            irq_vector: 0,
             mul_left: 0xff,
             mul_right: 0xff,
             current_song,
+            playlist_pos,
+            track_ticks: 0,
+            track_end: None,
+            fade_ticks: 0,
+            silence_ticks: 0,
+            window_ticks: 0,
+            window_min: 0.0,
+            window_max: 0.0,
+            track_ended: false,
             sunsoft5b,
             namco163,
             vrc6,
@@ -140,6 +217,49 @@ impl Nsf {
         }
     }
 
+    fn starting_position(file: &NsfFile) -> u8 {
+        match file.metadata.playlist.as_ref() {
+            Some(playlist) if !playlist.is_empty() => 0,
+            _ => file.starting_song,
+        }
+    }
+
+    fn playlist_song(file: &NsfFile, pos: u8) -> u8 {
+        match file.metadata.playlist.as_ref() {
+            Some(playlist) if !playlist.is_empty() => playlist[pos as usize],
+            _ => pos,
+        }
+    }
+
+    fn playlist_len(&self) -> u8 {
+        match self.file.metadata.playlist.as_ref() {
+            Some(playlist) if !playlist.is_empty() => playlist.len().min(255) as u8,
+            _ => self.file.total_songs,
+        }
+    }
+
+    fn set_position(&mut self, pos: u8) {
+        self.playlist_pos = pos;
+        self.current_song = Self::playlist_song(&self.file, pos);
+    }
+
+    fn next_position(&self) -> u8 {
+        let next = self.playlist_pos.saturating_add(1);
+        if next >= self.playlist_len() { 0 } else { next }
+    }
+
+    fn prev_position(&self) -> u8 {
+        if self.playlist_pos == 0 {
+            self.playlist_len().saturating_sub(1)
+        } else {
+            self.playlist_pos - 1
+        }
+    }
+
+    fn ms_to_ticks(region: Region, ms: u32) -> u64 {
+        (region.cpu_clock() * ms as f64 / 1000.0) as u64
+    }
+
     fn peek_cpu(&self, addr: u16) -> u8 {
         match addr {
             0x5205 if self.file.chips.mmc5() => {
@@ -162,36 +282,22 @@ impl Nsf {
                 Region::Pal => 1,
             },
             0x5303 => self.current_song,
-            0x5304 => {
-                let next = self.current_song.saturating_add(1);
-                if next >= self.file.total_songs {
-                    0
-                } else {
-                    next
-                }
-            }
-            0x5305 => {
-                if self.current_song == 0 {
-                    self.file.total_songs.saturating_sub(1)
-                } else {
-                    self.current_song.saturating_sub(1)
-                }
-            }
+            0x5304 => Self::playlist_song(&self.file, self.next_position()),
+            0x5305 => Self::playlist_song(&self.file, self.prev_position()),
+            0x5306 => self.track_ended as u8,
+            0x5307 if self.file.features.non_returning_init() => 0x80,
+            0x5307 => 0,
 
-            0x5310 => 0x20, // JSR
-            0x5311 => (self.file.init_addr & 0xff) as u8,
-            0x5312 => (self.file.init_addr >> 8) as u8,
-            0x5313 => 0x60, // RTS
-
-            0x5320 => 0x20, // JSR
-            0x5321 => (self.file.play_addr & 0xff) as u8,
-            0x5322 => (self.file.play_addr >> 8) as u8,
-            0x5323 => 0x60, // RTS
+            0x5310..=0x5313 => self.init_sub()[addr as usize - 0x5310],
+            0x5320..=0x5323 => self.play_sub()[addr as usize - 0x5320],
 
             0x5400..=0x57ff => self.sys_prg.read_mapped(0, 1024, addr),
             0x5800..=0x5bff => self.sys_ram.read_mapped(0, 1024, addr),
             0x5c00..=0x5fff if self.file.chips.mmc5() => self.ex_ram.read_mapped(0, 1024, addr),
             0x6000..=0x7fff => self.read_prg_ram(addr),
+            0xfffe | 0xffff if self.file.features.irq() => {
+                self.irq_vector.to_le_bytes()[addr as usize & 1]
+            }
             0xfffd | 0xfffb => 0x54,
             0xfffc => 0x03,
             0xfffa => 0x06,
@@ -225,26 +331,20 @@ impl Nsf {
                 }
             }
             0x5304 => {
-                let next = self.current_song.saturating_add(1);
-                let next = if next >= self.file.total_songs {
-                    0
-                } else {
-                    next
-                };
-                self.current_song = next;
+                self.set_position(self.next_position());
                 self.current_song
             }
             0x5305 => {
-                let prev = if self.current_song == 0 {
-                    self.file.total_songs.saturating_sub(1)
-                } else {
-                    self.current_song.saturating_sub(1)
-                };
-                self.current_song = prev;
+                self.set_position(self.prev_position());
                 self.current_song
             }
+            0x401d if self.file.features.irq() => {
+                self.irq = false;
+                self.irq_enabled as u8
+            }
             0xfffc => 0x03,
             0xfffd => 0x54,
+            0xfffe | 0xffff if self.file.features.irq() => self.peek_cpu(addr),
             0x8000.. => {
                 let value = self.read_prg(addr);
                 if let Some(mmc5) = self.mmc5.as_mut() {
@@ -285,6 +385,23 @@ impl Nsf {
                 self.ex_ram.write_mapped(0, 1024, addr, value)
             }
             0x6000..=0x7fff => self.write_prg_ram(addr, value),
+            0x401b if self.file.features.irq() => {
+                self.irq_reload = (self.irq_reload & 0xff00) | value as u16;
+            }
+            0x401c if self.file.features.irq() => {
+                self.irq_reload = (self.irq_reload & 0x00ff) | (value as u16) << 8;
+            }
+            0x401d if self.file.features.irq() => {
+                self.irq_enabled = value & 1 != 0;
+                self.irq_counter = self.irq_reload;
+                self.irq = false;
+            }
+            0xfffe if self.file.features.irq() => {
+                self.irq_vector = (self.irq_vector & 0xff00) | value as u16;
+            }
+            0xffff if self.file.features.irq() => {
+                self.irq_vector = (self.irq_vector & 0x00ff) | (value as u16) << 8;
+            }
             _ => (),
         }
 
@@ -394,6 +511,7 @@ impl Nsf {
             self.prg_ram.write(a, 0x00);
         }
 
+        self.reset_track();
         let _ = self.display_info();
 
         if let Some(fds_ram) = self.fds_ram.as_mut() {
@@ -415,21 +533,102 @@ impl Nsf {
         }
     }
 
+    fn reset_track(&mut self) {
+        let metadata = &self.file.metadata;
+        let time = metadata.track_time(self.current_song);
+        let fade = metadata.track_fade(self.current_song);
+
+        self.track_end = time.map(|ms| Self::ms_to_ticks(self.region, ms));
+        self.fade_ticks = Self::ms_to_ticks(self.region, fade);
+        self.track_ticks = 0;
+        self.output.volume.set(1.0);
+        self.silence_ticks = 0;
+        self.window_ticks = 0;
+        self.track_ended = false;
+    }
+
+    // Tracks are only advanced automatically when there is somewhere to advance to
+    fn end_track(&mut self) {
+        if self.playlist_len() > 1 {
+            self.track_ended = true;
+        }
+    }
+
+    // Tracks with a duration fade out once it has passed, other tracks end after a silence
+    fn update_volume(&mut self) {
+        let Some(track_end) = self.track_end else {
+            self.detect_silence(self.output.level.get());
+            return;
+        };
+
+        let volume = if self.track_ticks < track_end {
+            1.0
+        } else if self.track_ticks - track_end >= self.fade_ticks {
+            self.end_track();
+            0.0
+        } else {
+            1.0 - (self.track_ticks - track_end) as f32 / self.fade_ticks as f32
+        };
+        self.output.volume.set(volume);
+    }
+
+    fn detect_silence(&mut self, sample: f32) {
+        if self.window_ticks == 0 {
+            self.window_min = sample;
+            self.window_max = sample;
+        } else {
+            self.window_min = self.window_min.min(sample);
+            self.window_max = self.window_max.max(sample);
+        }
+
+        self.window_ticks += 1;
+        if self.window_ticks < self.silence_window {
+            return;
+        }
+
+        if self.window_max - self.window_min < SILENCE_THRESHOLD {
+            self.silence_ticks += self.window_ticks;
+        } else {
+            self.silence_ticks = 0;
+        }
+        self.window_ticks = 0;
+
+        if self.silence_ticks >= self.silence_length {
+            self.end_track();
+        }
+    }
+
+    fn init_sub(&self) -> [u8; 4] {
+        let [lo, hi] = self.file.init_addr.to_le_bytes();
+        [0x20, lo, hi, 0x60] // JSR, RTS
+    }
+
+    fn play_sub(&self) -> [u8; 4] {
+        if self.file.features.suppressed_play() {
+            [0xea, 0xea, 0xea, 0x60] // NOP, RTS
+        } else {
+            let [lo, hi] = self.file.play_addr.to_le_bytes();
+            [0x20, lo, hi, 0x60] // JSR, RTS
+        }
+    }
+
     fn display_info(&mut self) -> std::fmt::Result {
+        let total_tracks = self.playlist_len();
         let mut cursor = NametableCursor::new(&mut self.sys_nt_ram);
 
         writeln!(cursor)?;
         writeln!(cursor, "NSF Player")?;
         writeln!(cursor)?;
 
-        cursor.write_label("Title:", self.file.song_name.as_ref())?;
-        cursor.write_label("Artist:", self.file.artist_name.as_ref())?;
-        cursor.write_label("Copyright:", self.file.copyright_name.as_ref())?;
+        cursor.write_label("Title:", self.file.song_name.as_deref())?;
+        cursor.write_label("Artist:", self.file.artist_name.as_deref())?;
+        cursor.write_label("Copyright:", self.file.copyright_name.as_deref())?;
+        cursor.write_label("Track:", self.file.metadata.track_title(self.current_song))?;
 
-        if self.file.total_songs > 1 {
+        if total_tracks > 1 {
             cursor.move_to_line(-7);
-            let current_track = self.current_song.saturating_add(1);
-            writeln!(cursor, "Track {current_track} of {}", self.file.total_songs)?;
+            let current_track = self.playlist_pos.saturating_add(1);
+            writeln!(cursor, "Track {current_track} of {total_tracks}")?;
             writeln!(cursor)?;
             writeln!(cursor, "Use left/right to change track")?;
         }
@@ -485,6 +684,11 @@ impl Mapper for Nsf {
             cpu.register_read(DeviceKind::Mapper, RangeAndMask(0x4020, 0x4100, 0xffff));
             cpu.register_write(DeviceKind::Mapper, RangeAndMask(0x4020, 0x4100, 0xffff));
         }
+
+        if self.file.features.irq() {
+            cpu.register_read(DeviceKind::Mapper, RangeAndMask(0x401b, 0x401e, 0xffff));
+            cpu.register_write(DeviceKind::Mapper, RangeAndMask(0x401b, 0x401e, 0xffff));
+        }
     }
 
     fn peek(&self, bus: BusKind, addr: u16) -> u8 {
@@ -513,12 +717,23 @@ impl Mapper for Nsf {
     }
 
     fn tick(&mut self) {
+        self.track_ticks += 1;
+        self.update_volume();
         self.play_timer = self.play_timer.saturating_sub(1);
         if self.play_timer == 0 {
             self.play_pending = true;
             self.play_timer = self.play_timer_load;
         }
 
+        if self.irq_enabled {
+            if self.irq_counter == 0 {
+                self.irq = true;
+                self.irq_counter = self.irq_reload;
+            } else {
+                self.irq_counter -= 1;
+            }
+        }
+
         self.sunsoft5b.tick();
         self.namco163.tick();
         self.vrc6.tick();
@@ -545,10 +760,17 @@ impl Mapper for Nsf {
         }
     }
 
+    fn get_irq(&self) -> bool {
+        self.irq
+    }
+
     fn power(&mut self) {
         self.play_timer = self.play_timer_load;
         self.play_pending = false;
-        self.current_song = self.file.starting_song;
+        self.irq_enabled = false;
+        self.irq = false;
+        self.set_position(Self::starting_position(&self.file));
+        self.reset_track();
 
         for a in 0..0x400u16 {
             self.sys_nt_ram.write(a, 0x00);
@@ -592,17 +814,18 @@ impl<'a, M: Memory> NametableCursor<'a, M> {
         }
     }
 
-    fn write_label(&mut self, label: &str, value: Option<&String>) -> std::fmt::Result {
+    fn write_label(&mut self, label: &str, value: Option<&str>) -> std::fmt::Result {
         if let Some(value) = value.filter(|v| !v.is_empty()) {
-            if label.len() + value.len() < 30 {
+            if label.chars().count() + value.chars().count() < 30 {
                 writeln!(self, "{label} {value}")?;
             } else {
                 writeln!(self, "{label}")?;
-                let mut v = value.as_str().trim();
-                while v.len() > 0 {
-                    let len = v.len().min(31);
+                let mut v = value.trim();
+                while !v.is_empty() {
+                    // Split after 31 characters, not bytes, to stay on a char boundary
+                    let len = v.char_indices().nth(31).map_or(v.len(), |(i, _)| i);
                     writeln!(self, "{}", &v[0..len])?;
-                    v = &v[len..].trim_start();
+                    v = v[len..].trim_start();
                 }
             }
             writeln!(self)?;
@@ -794,3 +1017,146 @@ impl ExternalAudio for Fds {
         Some(self.output())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cartridge::{Cartridge, NSF_DEFAULT_FADE_MS};
+
+    fn chunk(ident: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(ident);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    // NSFe whose init and play routines are a single RTS at $8000
+    fn nsfe(total_songs: u8, metadata: &[Vec<u8>]) -> Nsf {
+        let info = [0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0, 0, total_songs, 0];
+        let mut file = b"NSFE".to_vec();
+        file.extend(chunk(b"INFO", &info));
+        file.extend(chunk(b"DATA", &[0x60]));
+        file.extend(metadata.concat());
+        file.extend(chunk(b"NEND", &[]));
+
+        let Ok(Cartridge::Nsf(file)) = Cartridge::load(&mut &file[..], None, None, "test.nsfe")
+        else {
+            panic!("invalid NSFe");
+        };
+        Nsf::new(Region::Ntsc, file, Rc::new(NsfOutput::new()))
+    }
+
+    fn times(ms: &[i32]) -> Vec<u8> {
+        ms.iter().flat_map(|t| t.to_le_bytes()).collect()
+    }
+
+    fn tick_ms(nsf: &mut Nsf, ms: u32) {
+        for _ in 0..Nsf::ms_to_ticks(Region::Ntsc, ms) {
+            nsf.tick();
+        }
+    }
+
+    #[test]
+    fn playlist_order() {
+        let mut nsf = nsfe(4, &[chunk(b"plst", &[3, 1, 7, 2])]);
+        assert_eq!(nsf.peek_cpu(0x5303), 3);
+        assert_eq!(nsf.peek_cpu(0x5305), 2);
+
+        // Songs missing from the file are dropped and the playlist wraps in both directions
+        let songs: Vec<u8> = (0..4).map(|_| nsf.read_cpu(0x5304)).collect();
+        assert_eq!(songs, [1, 2, 3, 1]);
+        let songs: Vec<u8> = (0..4).map(|_| nsf.read_cpu(0x5305)).collect();
+        assert_eq!(songs, [3, 2, 1, 3]);
+
+        let mut nsf = nsfe(4, &[]);
+        let songs: Vec<u8> = (0..4).map(|_| nsf.read_cpu(0x5304)).collect();
+        assert_eq!(songs, [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn track_fades_out_after_time() {
+        let mut nsf = nsfe(
+            2,
+            &[
+                chunk(b"time", &times(&[100])),
+                chunk(b"fade", &times(&[200])),
+            ],
+        );
+        nsf.write_cpu(0x5302, 0);
+
+        tick_ms(&mut nsf, 100);
+        assert_eq!(nsf.output.volume(), 1.0);
+        tick_ms(&mut nsf, 100);
+        assert!((nsf.output.volume() - 0.5).abs() < 0.01);
+        assert_eq!(nsf.peek_cpu(0x5306), 0);
+        tick_ms(&mut nsf, 101);
+        assert_eq!(nsf.output.volume(), 0.0);
+        assert_eq!(nsf.peek_cpu(0x5306), 1);
+
+        // Starting the next track restores the volume
+        nsf.read_cpu(0x5304);
+        nsf.write_cpu(0x5302, 0);
+        assert_eq!(nsf.output.volume(), 1.0);
+        assert_eq!(nsf.peek_cpu(0x5306), 0);
+    }
+
+    #[test]
+    fn track_without_fade_uses_default() {
+        let mut nsf = nsfe(2, &[chunk(b"time", &times(&[100, -1]))]);
+        nsf.write_cpu(0x5302, 0);
+
+        tick_ms(&mut nsf, 100 + NSF_DEFAULT_FADE_MS / 2);
+        assert!((nsf.output.volume() - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn silence_ends_track() {
+        let mut nsf = nsfe(2, &[]);
+        nsf.write_cpu(0x5302, 0);
+
+        // Output changing within every window is not silent, however quiet the track is
+        for step in 0..(SILENCE_MS / SILENCE_WINDOW_MS + 10) * 2 {
+            let level = if step % 2 == 0 { 0.0 } else { 0.01 };
+            nsf.output.level.set(level);
+            tick_ms(&mut nsf, SILENCE_WINDOW_MS / 2);
+        }
+        assert_eq!(nsf.peek_cpu(0x5306), 0);
+
+        nsf.output.level.set(0.25);
+        tick_ms(&mut nsf, SILENCE_MS - 100);
+        assert_eq!(nsf.peek_cpu(0x5306), 0);
+        // Silence is measured in whole windows after the last change
+        tick_ms(&mut nsf, 100 + 2 * SILENCE_WINDOW_MS);
+        assert_eq!(nsf.peek_cpu(0x5306), 1);
+        assert_eq!(nsf.output.volume(), 1.0);
+
+        // A single track has nothing to advance to
+        let mut nsf = nsfe(1, &[]);
+        nsf.write_cpu(0x5302, 0);
+        tick_ms(&mut nsf, SILENCE_MS + SILENCE_WINDOW_MS);
+        assert_eq!(nsf.peek_cpu(0x5306), 0);
+    }
+
+    fn line(nt: &MemoryBlock, line: u16) -> String {
+        (1..32)
+            .map(|column| nt.read(line * 32 + column) as char)
+            .filter(|&c| c != '\0')
+            .collect()
+    }
+
+    #[test]
+    fn label_wraps_non_ascii_value() {
+        let mut nt = MemoryBlock::new(1);
+        let title = "Ünïcödé ".repeat(5);
+
+        let mut cursor = NametableCursor::new(&mut nt);
+        cursor.write_label("Title:", Some(&title)).unwrap();
+
+        // Characters outside of ASCII aren't drawn
+        assert_eq!(line(&nt, 0), "Title:");
+        assert_eq!(line(&nt, 1), "ncd ncd ncd ncd");
+        assert_eq!(line(&nt, 2), "ncd");
+        assert_eq!(line(&nt, 3), "");
+    }
+}
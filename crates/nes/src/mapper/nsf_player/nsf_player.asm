@@ -17,6 +17,8 @@ NsfInitBanks = $5302
 NsfCurrentSong = $5303
 NsfNextSong = $5304
 NsfPrevSong = $5305
+NsfTrackEnded = $5306
+NsfFeatures = $5307
 NsfInitSub = $5310
 NsfPlaySub = $5320
 
@@ -25,6 +27,7 @@ NsfPlaySub = $5320
 .bss
 buttons: .res 1
 buttons_reset: .res 1
+init_running: .res 1
 
 .code
 InterruptVector:
@@ -32,7 +35,7 @@ InterruptVector:
 ResetVector:
     jmp InitSystem
 NmiVector:
-    jmp InitSystemNmi
+    jmp Nmi
 
 InitSystemNmi:
     lda #$00
@@ -43,6 +46,12 @@ InitSystem:
     bit PpuStatus
     bpl @PpuWait1
 InitSong:
+    ; Track changes may come from within INIT or NMI, start over with a fresh stack
+    ldx #$ff
+    txs
+    lda #$00
+    sta PpuCtrl
+    sta init_running
 @PpuWait2:
     bit PpuStatus
     bpl @PpuWait2
@@ -91,33 +100,45 @@ InitSong:
     dex
     bne @LoopX
     sta $4000
-    lda $0f
+    lda #$0f
     sta $4015
-    lda $40
+    lda #$40
     sta $4017
     sta NsfInitBanks
     lda NsfCurrentSong
     ldx NsfRegion
     ldy #$00
+    bit NsfFeatures
+    bmi @NonReturning
     jsr NsfInitSub
+    jmp PlayWait
+@NonReturning:
+    jsr NonReturningInit
 
 PlayWait:
     lda NsfPlayTimer
     beq PlayWait
     jsr NsfPlaySub
+    jsr UpdateTrack
+    jmp PlayWait
+
+UpdateTrack:
+    lda NsfTrackEnded
+    bne NextSong
     jsr ReadJoySafe
     lda buttons
     and #$ff
     beq ResetButtons
     lda buttons_reset
-    beq PlayWait
+    beq @Done
     lda buttons
     and #JoyPadLeft
     bne PrevSong
     lda buttons
     and #JoyPadRight
     bne NextSong
-    jmp PlayWait
+@Done:
+    rts
 
 PrevSong:
     lda #$00
@@ -134,7 +155,46 @@ NextSong:
 ResetButtons:
     lda #$01
     sta buttons_reset
-    jmp PlayWait
+    rts
+
+; NSF2 non-returning INIT runs with NMI enabled so PLAY can interrupt it, if INIT
+; does return NMI is disabled and the main loop calls PLAY as usual
+NonReturningInit:
+    pha
+    lda #$80
+    sta init_running
+    sta PpuCtrl
+    pla
+    jsr NsfInitSub
+    pha
+    lda #$00
+    sta PpuCtrl
+    sta init_running
+    pla
+    rts
+
+Nmi:
+    bit init_running
+    bmi NmiPlay
+    jmp InitSystemNmi
+
+NmiPlay:
+    pha
+    txa
+    pha
+    tya
+    pha
+    lda NsfPlayTimer
+    beq @Skip
+    jsr NsfPlaySub
+@Skip:
+    jsr UpdateTrack
+    pla
+    tay
+    pla
+    tax
+    pla
+    rti
 
 ReadJoy:
     lda #$01
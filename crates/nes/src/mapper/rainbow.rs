@@ -15,6 +15,7 @@ use crate::memory::{FixedMemoryBlock, Memory, MemoryBlock};
 use crate::ppu::PpuFetchKind;
 
 use super::Nametable;
+use super::rainbow_esp::Esp;
 
 #[derive(Debug, Copy, Clone)]
 enum MemSource {
@@ -23,6 +24,14 @@ enum MemSource {
     Fpga,
 }
 
+#[derive(Debug, Copy, Clone)]
+enum ChrSource {
+    Rom,
+    Ram,
+    Fpga,
+    Ciram,
+}
+
 #[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone)]
 struct SpriteExtRegs(#[cfg_attr(feature = "save-states", serde(with = "serde_arrays"))] [u8; 64]);
@@ -102,11 +111,8 @@ pub struct Rainbow {
     redir_irq: bool,
     redir_irq_lo: u8,
     redir_irq_hi: u8,
-    wifi_enable: bool,
-    wifi_irq_enable: bool,
-    wifi_tx_addr: u8,
-    wifi_rx_addr: u8,
-    wifi_data_pending: bool,
+    #[cfg_attr(feature = "save-states", save(nested))]
+    esp: Esp,
     window_tile_start: u8,
     window_tile_end: u8,
     window_line_start: u8,
@@ -114,7 +120,9 @@ pub struct Rainbow {
     window_tile_scroll: u8,
     window_line_scroll: u8,
     m2_parity: bool,
-    warn_oam: bool,
+    oam_copy_page: u8,
+    oam_copy_len: u8,
+    oam_copy_idx: u16,
 }
 
 impl Rainbow {
@@ -135,6 +143,7 @@ impl Rainbow {
             None
         };
         let nt_ram = FixedMemoryBlock::new();
+        let esp = Esp::new(std::mem::take(&mut cartridge.esp));
 
         let master_volume = (i16::MAX as f32 / 64.0) as i16;
 
@@ -184,11 +193,7 @@ impl Rainbow {
             redir_irq: false,
             redir_irq_lo: 0,
             redir_irq_hi: 0,
-            wifi_enable: false,
-            wifi_irq_enable: false,
-            wifi_tx_addr: 0,
-            wifi_rx_addr: 0,
-            wifi_data_pending: false,
+            esp,
             window_tile_start: 0,
             window_tile_end: 0,
             window_line_start: 0,
@@ -196,7 +201,9 @@ impl Rainbow {
             window_tile_scroll: 0,
             window_line_scroll: 0,
             m2_parity: false,
-            warn_oam: false,
+            oam_copy_page: 0,
+            oam_copy_len: 0,
+            oam_copy_idx: 0,
         }
     }
 
@@ -245,26 +252,8 @@ impl Rainbow {
 
                 val
             }
-            0x4190 => {
-                let mut val = 0;
-                if self.wifi_enable {
-                    val |= 1;
-                }
-                if self.wifi_irq_enable {
-                    val |= 2;
-                }
-
-                val
-            }
-            0x4191 => 0,
-            0x4192 => {
-                let mut val = 0;
-                if !self.wifi_data_pending {
-                    val |= 0x80;
-                }
-
-                val
-            }
+            0x4190..=0x4194 => self.esp.peek(addr),
+            0x4280..=0x4286 => self.peek_oam_routine(addr),
             0xfffa if self.redir_nmi => self.redir_nmi_lo,
             0xfffb if self.redir_nmi => self.redir_nmi_hi,
             0xfffe if self.redir_irq => self.redir_irq_lo,
@@ -303,13 +292,15 @@ impl Rainbow {
                 self.fpga_reader_addr &= 0x1fff;
                 return val;
             }
-            0x4280..=0x4286 => {
-                if !self.warn_oam {
-                    tracing::error!("rainbow oam routine unsupported: {:04x}", addr);
-                    self.warn_oam = true;
-                }
+            0x4280 if self.oam_copy_done() => {
+                self.oam_copy_idx = 0;
                 return 0x60; // RTS
             }
+            0x4285 => {
+                let val = self.peek_oam_routine(addr);
+                self.oam_copy_idx += 1;
+                return val;
+            }
             0xfffa | 0xfffb => self.ppu_state.leave_frame(),
             _ => (),
         }
@@ -385,14 +376,7 @@ impl Rainbow {
             0x4173 => self.window_line_end = value,
             0x4174 => self.window_tile_scroll = value & 0x1f,
             0x4175 => self.window_line_scroll = value,
-            0x4190 => {
-                self.wifi_enable = value & 1 != 0;
-                self.wifi_irq_enable = value & 2 != 0;
-            }
-            0x4191 => (),
-            0x4192 => self.wifi_data_pending = true,
-            0x4193 => self.wifi_rx_addr = value & 0x7,
-            0x4194 => self.wifi_tx_addr = value & 0x7,
+            0x4190..=0x4194 => self.esp.write(addr, value, &self.fpga_ram),
             0x41a0 => self.pulse_a.volume(value),
             0x41a1 => self.pulse_a.freq_low(value),
             0x41a2 => self.pulse_a.freq_high(value),
@@ -412,11 +396,50 @@ impl Rainbow {
             }
             0x4200..=0x423f => self.spr_ext_lo[(addr & 0x3f) as usize] = value,
             0x4240 => self.spr_ext_hi = value,
+            0x4280 => {
+                self.oam_copy_page = value & 0x1f;
+                self.oam_copy_idx = 0;
+            }
+            0x4281 => {
+                self.oam_copy_len = value;
+                self.oam_copy_idx = 0;
+            }
             0x4800.. => self.write_prg(addr, value),
             _ => tracing::debug!("unsupported rainbow write reg: {addr:04x}:{value:02x}"),
         }
     }
 
+    fn oam_copy_done(&self) -> bool {
+        let len = if self.oam_copy_len == 0 {
+            256
+        } else {
+            self.oam_copy_len as u16
+        };
+        self.oam_copy_idx >= len
+    }
+
+    fn oam_copy_byte(&self) -> u8 {
+        let addr = ((self.oam_copy_page as u16) << 8).wrapping_add(self.oam_copy_idx);
+        self.fpga_ram.read(addr & 0x1fff)
+    }
+
+    // JSR $4280 executes an unrolled copy from FPGA-RAM into $2004, one
+    // `LDA #imm / STA $2004 / BPL|BMI $4280` iteration per byte, then RTS
+    fn peek_oam_routine(&self, addr: u16) -> u8 {
+        match addr {
+            0x4280 if self.oam_copy_done() => 0x60,
+            0x4280 => 0xa9,
+            0x4281 => self.oam_copy_byte(),
+            0x4282 => 0x8d,
+            0x4283 => 0x04,
+            0x4284 => 0x20,
+            0x4285 if self.oam_copy_byte() & 0x80 == 0 => 0x10,
+            0x4285 => 0x30,
+            0x4286 => 0xf9,
+            _ => 0,
+        }
+    }
+
     fn read_ppu(&self, addr: u16) -> u8 {
         let (addr, nt_idx) = if let Some(win_addr) = self.window_address(addr) {
             (win_addr, 4)
@@ -566,66 +589,46 @@ impl Rainbow {
         (bank, size, mem_source)
     }
 
-    fn read_chr(&self, addr: u16) -> u8 {
-        if self.chr_mode & 0xc0 == 0x80 {
-            return self.fpga_ram.read(addr);
-        } else if self.chr_mode & 0xc0 == 0xc0 {
-            return self.nt_ram.read(addr);
+    fn chr_source(&self) -> ChrSource {
+        match self.chr_mode >> 6 {
+            0 => ChrSource::Rom,
+            1 => ChrSource::Ram,
+            2 => ChrSource::Fpga,
+            _ => ChrSource::Ciram,
         }
+    }
 
-        let ram = self.chr_mode & 0x40 != 0;
-
+    fn read_chr(&self, addr: u16) -> u8 {
         let (bank, size) = self.map_chr(addr);
-
-        if ram {
-            if let Some(chr_ram) = self.chr_ram.as_ref() {
-                chr_ram.read_mapped(bank, size, addr)
-            } else {
-                0
-            }
-        } else {
-            self.cartridge.chr_rom.read_mapped(bank, size, addr)
-        }
+        self.read_bank_chr(bank, addr, size)
     }
 
     fn read_bank_chr(&self, bank: usize, addr: u16, size: usize) -> u8 {
-        if self.chr_mode & 0xc0 == 0x80 {
-            return self.fpga_ram.read(addr);
-        } else if self.chr_mode & 0xc0 == 0xc0 {
-            return self.nt_ram.read(addr);
-        }
-
-        let ram = self.chr_mode & 0x40 != 0;
-
-        if ram {
-            if let Some(chr_ram) = self.chr_ram.as_ref() {
-                chr_ram.read_mapped(bank, size, addr)
-            } else {
-                0
+        match self.chr_source() {
+            ChrSource::Rom => self.cartridge.chr_rom.read_mapped(bank, size, addr),
+            ChrSource::Ram => {
+                if let Some(chr_ram) = self.chr_ram.as_ref() {
+                    chr_ram.read_mapped(bank, size, addr)
+                } else {
+                    0
+                }
             }
-        } else {
-            self.cartridge.chr_rom.read_mapped(bank, size, addr)
+            ChrSource::Fpga => self.fpga_ram.read_mapped(bank, size, addr),
+            ChrSource::Ciram => self.nt_ram.read_mapped(bank, size, addr),
         }
     }
 
     fn write_chr(&mut self, addr: u16, value: u8) {
-        if self.chr_mode & 0xc0 == 0x80 {
-            self.fpga_ram.write(addr, value);
-            return;
-        } else if self.chr_mode & 0xc0 == 0xc0 {
-            self.nt_ram.write(addr, value);
-            return;
-        }
-
-        let ram = self.chr_mode & 0x40 != 0;
-        if !ram {
-            return;
-        }
-
         let (bank, size) = self.map_chr(addr);
-
-        if let Some(chr_ram) = self.chr_ram.as_mut() {
-            chr_ram.write_mapped(bank, size, addr, value);
+        match self.chr_source() {
+            ChrSource::Rom => (),
+            ChrSource::Ram => {
+                if let Some(chr_ram) = self.chr_ram.as_mut() {
+                    chr_ram.write_mapped(bank, size, addr, value);
+                }
+            }
+            ChrSource::Fpga => self.fpga_ram.write_mapped(bank, size, addr, value),
+            ChrSource::Ciram => self.nt_ram.write_mapped(bank, size, addr, value),
         }
     }
 
@@ -780,6 +783,11 @@ impl Mapper for Rainbow {
                 self.debug.event(crate::DebugEvent::MapperIrq);
             }
         }
+        let was_esp_irq = self.esp.irq();
+        self.esp.tick(&mut self.fpga_ram);
+        if !was_esp_irq && self.esp.irq() {
+            self.debug.event(crate::DebugEvent::MapperIrq);
+        }
         self.ppu_state.tick();
         self.pulse_a.tick(FreqMode::X1);
         self.pulse_b.tick(FreqMode::X1);
@@ -790,6 +798,7 @@ impl Mapper for Rainbow {
     fn get_irq(&self) -> bool {
         (self.ppu_irq_enabled && self.ppu_state.irq_pending)
             || (self.cpu_irq_enable && self.cpu_irq_pending)
+            || self.esp.irq()
     }
 
     fn get_sample(&self) -> Option<i16> {
@@ -1036,12 +1045,11 @@ impl ShadowOam {
 
     fn eval(&mut self, scanline: u8) {
         let height = if self.tall_sprite() { 16 } else { 8 };
-        let end = scanline + height;
 
         let mut sprites_on_line = 0;
 
         for (idx, s) in self.oam.chunks(4).enumerate() {
-            if s[0] >= scanline && s[0] < end {
+            if scanline.wrapping_sub(s[0]) < height {
                 self.line_oam[sprites_on_line] = idx as u8;
                 sprites_on_line += 1;
                 if sprites_on_line == 8 {
@@ -0,0 +1,1055 @@
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError, channel};
+use std::time::Duration;
+
+#[cfg(feature = "save-states")]
+use nes_traits::SaveState;
+#[cfg(feature = "save-states")]
+use serde::{Deserialize, Serialize};
+
+use crate::memory::{FixedMemoryBlock, Memory};
+
+/// Host resources used to stand in for the Rainbow ESP32 module
+#[derive(Debug, Clone, Default)]
+pub struct EspConfig {
+    /// Directory backing the ESP file system, file commands fail when unset
+    pub fs_root: Option<PathBuf>,
+    /// Endpoint used for all server connections regardless of the host requested by the rom
+    pub server: Option<SocketAddr>,
+}
+
+const FIRMWARE_VERSION: &[u8] = b"mass-nes";
+const FILE_PATHS: [&str; 3] = ["save", "roms", "user"];
+const MAX_FILE_ID: u8 = 64;
+const MAX_QUEUED_MESSAGES: usize = 32;
+// Message length is stored in a single byte and includes the command id
+const MAX_MESSAGE_DATA: usize = 254;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+enum ToEsp {
+    EspGetStatus = 0,
+    DebugGetLevel,
+    DebugSetLevel,
+    DebugLog,
+    BufferClearRxTx,
+    BufferDropFromEsp,
+    EspGetFirmwareVersion,
+    EspFactoryReset,
+    EspRestart,
+    WifiGetStatus,
+    WifiGetSsid,
+    WifiGetIp,
+    ApGetSsid,
+    ApGetIp,
+    RndGetByte,
+    RndGetByteRange,
+    RndGetWord,
+    RndGetWordRange,
+    ServerGetStatus,
+    ServerPing,
+    ServerSetProtocol,
+    ServerGetSettings,
+    ServerSetSettings,
+    ServerGetSavedSettings,
+    ServerSetSavedSettings,
+    ServerRestoreSavedSettings,
+    ServerConnect,
+    ServerDisconnect,
+    ServerSendMsg,
+    NetworkScan,
+    NetworkGetScanResult,
+    NetworkGetDetails,
+    NetworkGetRegistered,
+    NetworkGetRegisteredDetails,
+    NetworkRegister,
+    NetworkUnregister,
+    NetworkSetActive,
+    FileOpen,
+    FileClose,
+    FileStatus,
+    FileExists,
+    FileDelete,
+    FileSetCur,
+    FileRead,
+    FileWrite,
+    FileAppend,
+    FileCount,
+    FileGetList,
+    FileGetFreeId,
+    FileGetFsInfo,
+    FileGetInfo,
+    FileDownload,
+    FileFormat,
+}
+
+impl ToEsp {
+    const ALL: [ToEsp; 53] = [
+        ToEsp::EspGetStatus,
+        ToEsp::DebugGetLevel,
+        ToEsp::DebugSetLevel,
+        ToEsp::DebugLog,
+        ToEsp::BufferClearRxTx,
+        ToEsp::BufferDropFromEsp,
+        ToEsp::EspGetFirmwareVersion,
+        ToEsp::EspFactoryReset,
+        ToEsp::EspRestart,
+        ToEsp::WifiGetStatus,
+        ToEsp::WifiGetSsid,
+        ToEsp::WifiGetIp,
+        ToEsp::ApGetSsid,
+        ToEsp::ApGetIp,
+        ToEsp::RndGetByte,
+        ToEsp::RndGetByteRange,
+        ToEsp::RndGetWord,
+        ToEsp::RndGetWordRange,
+        ToEsp::ServerGetStatus,
+        ToEsp::ServerPing,
+        ToEsp::ServerSetProtocol,
+        ToEsp::ServerGetSettings,
+        ToEsp::ServerSetSettings,
+        ToEsp::ServerGetSavedSettings,
+        ToEsp::ServerSetSavedSettings,
+        ToEsp::ServerRestoreSavedSettings,
+        ToEsp::ServerConnect,
+        ToEsp::ServerDisconnect,
+        ToEsp::ServerSendMsg,
+        ToEsp::NetworkScan,
+        ToEsp::NetworkGetScanResult,
+        ToEsp::NetworkGetDetails,
+        ToEsp::NetworkGetRegistered,
+        ToEsp::NetworkGetRegisteredDetails,
+        ToEsp::NetworkRegister,
+        ToEsp::NetworkUnregister,
+        ToEsp::NetworkSetActive,
+        ToEsp::FileOpen,
+        ToEsp::FileClose,
+        ToEsp::FileStatus,
+        ToEsp::FileExists,
+        ToEsp::FileDelete,
+        ToEsp::FileSetCur,
+        ToEsp::FileRead,
+        ToEsp::FileWrite,
+        ToEsp::FileAppend,
+        ToEsp::FileCount,
+        ToEsp::FileGetList,
+        ToEsp::FileGetFreeId,
+        ToEsp::FileGetFsInfo,
+        ToEsp::FileGetInfo,
+        ToEsp::FileDownload,
+        ToEsp::FileFormat,
+    ];
+
+    fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+enum FromEsp {
+    Ready = 0,
+    DebugLevel,
+    EspFirmwareVersion,
+    WifiStatus,
+    Ssid,
+    IpAddress,
+    RndByte,
+    RndWord,
+    ServerStatus,
+    ServerPing,
+    ServerSettings,
+    MessageFromServer,
+    NetworkScanResult,
+    NetworkScannedDetails,
+    NetworkRegisteredDetails,
+    NetworkRegistered,
+    FileStatus,
+    FileExists,
+    FileDelete,
+    FileList,
+    FileData,
+    FileCount,
+    FileId,
+    FileFsInfo,
+    FileInfo,
+    FileDownload,
+}
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ServerProtocol {
+    Tcp,
+    Udp,
+}
+
+impl From<u8> for ServerProtocol {
+    fn from(value: u8) -> Self {
+        // WebSocket and secured variants are carried over plain TCP to the stand-in server
+        match value {
+            4 => ServerProtocol::Udp,
+            _ => ServerProtocol::Tcp,
+        }
+    }
+}
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FileName {
+    Auto { path: u8, file: u8 },
+    Manual(Vec<u8>),
+}
+
+impl FileName {
+    /// Parses a file reference and returns it along with the remaining message bytes
+    fn parse(data: &[u8]) -> Option<(FileName, &[u8])> {
+        let (&config, data) = data.split_first()?;
+        if config & 1 == 0 {
+            let (&path, data) = data.split_first()?;
+            let (&file, data) = data.split_first()?;
+            if path as usize >= FILE_PATHS.len() || file >= MAX_FILE_ID {
+                return None;
+            }
+            Some((FileName::Auto { path, file }, data))
+        } else {
+            let (&len, data) = data.split_first()?;
+            let len = (len as usize).min(data.len());
+            let (name, data) = data.split_at(len);
+            Some((FileName::Manual(name.to_vec()), data))
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            FileName::Auto { path, file } => buf.extend_from_slice(&[0, *path, *file]),
+            FileName::Manual(name) => {
+                buf.extend_from_slice(&[1, name.len() as u8]);
+                buf.extend_from_slice(name);
+            }
+        }
+    }
+
+    fn resolve(&self, root: &Path) -> Option<PathBuf> {
+        match self {
+            FileName::Auto { path, file } => {
+                Some(root.join(FILE_PATHS[*path as usize]).join(file.to_string()))
+            }
+            FileName::Manual(name) => {
+                let name = std::str::from_utf8(name).ok()?;
+                let name = Path::new(name);
+                // Keep rom supplied names inside of the configured root
+                if name
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+                {
+                    Some(root.join(name))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+struct OpenFile {
+    name: FileName,
+    cursor: u64,
+}
+
+#[cfg_attr(feature = "save-states", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+struct ServerSettings {
+    port: u16,
+    host: Vec<u8>,
+}
+
+enum Socket {
+    /// TCP connections are made on a worker thread so a frame never waits on the network,
+    /// messages sent in the meantime are held until it connects
+    Connecting(Receiver<std::io::Result<TcpStream>>, Vec<Vec<u8>>),
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl Socket {
+    fn connect(protocol: ServerProtocol, addr: SocketAddr) -> std::io::Result<Self> {
+        let socket = match protocol {
+            ServerProtocol::Tcp => {
+                let (tx, rx) = channel();
+                std::thread::Builder::new()
+                    .name("rainbow-esp-connect".into())
+                    .spawn(move || {
+                        let stream =
+                            TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).and_then(|stream| {
+                                stream.set_nodelay(true)?;
+                                stream.set_nonblocking(true)?;
+                                Ok(stream)
+                            });
+                        let _ = tx.send(stream);
+                    })?;
+                Socket::Connecting(rx, Vec::new())
+            }
+            ServerProtocol::Udp => {
+                let bind: SocketAddr = if addr.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let socket = UdpSocket::bind(bind)?;
+                socket.connect(addr)?;
+                socket.set_nonblocking(true)?;
+                Socket::Udp(socket)
+            }
+        };
+
+        Ok(socket)
+    }
+
+    fn poll_connect(&mut self) -> std::io::Result<()> {
+        let Socket::Connecting(rx, pending) = self else {
+            return Ok(());
+        };
+
+        let mut stream = match rx.try_recv() {
+            Ok(stream) => stream?,
+            Err(TryRecvError::Empty) => return Ok(()),
+            Err(TryRecvError::Disconnected) => {
+                return Err(std::io::ErrorKind::NotConnected.into());
+            }
+        };
+
+        for data in pending.drain(..) {
+            stream.write_all(&data)?;
+        }
+        *self = Socket::Tcp(stream);
+
+        Ok(())
+    }
+
+    fn is_connected(&mut self) -> std::io::Result<bool> {
+        self.poll_connect()?;
+        Ok(!matches!(self, Socket::Connecting(..)))
+    }
+
+    fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.poll_connect()?;
+        match self {
+            Socket::Connecting(_, pending) => {
+                pending.push(data.to_vec());
+                Ok(())
+            }
+            Socket::Tcp(stream) => stream.write_all(data),
+            Socket::Udp(socket) => socket.send(data).map(|_| ()),
+        }
+    }
+
+    /// Returns `Ok(None)` when no data is waiting and `Ok(Some(0))` when the peer has hung up
+    fn recv(&mut self, buf: &mut [u8]) -> std::io::Result<Option<usize>> {
+        self.poll_connect()?;
+        let res = match self {
+            Socket::Connecting(..) => return Ok(None),
+            Socket::Tcp(stream) => stream.read(buf),
+            Socket::Udp(socket) => socket.recv(buf),
+        };
+
+        match res {
+            Ok(n) => Ok(Some(n)),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg_attr(feature = "save-states", derive(SaveState))]
+pub struct Esp {
+    #[cfg_attr(feature = "save-states", save(skip))]
+    config: EspConfig,
+    #[cfg_attr(feature = "save-states", save(skip))]
+    socket: Option<Socket>,
+    enabled: bool,
+    irq_enabled: bool,
+    rx_addr: u8,
+    tx_addr: u8,
+    data_ready: bool,
+    responses: VecDeque<Vec<u8>>,
+    rng: u32,
+    debug_level: u8,
+    protocol: ServerProtocol,
+    server_settings: Option<ServerSettings>,
+    open_file: Option<OpenFile>,
+    poll_timer: u16,
+}
+
+impl Esp {
+    pub fn new(config: EspConfig) -> Self {
+        Self {
+            config,
+            socket: None,
+            enabled: false,
+            irq_enabled: false,
+            rx_addr: 0,
+            tx_addr: 0,
+            data_ready: false,
+            responses: VecDeque::new(),
+            rng: 0x2545_f491,
+            debug_level: 0,
+            protocol: ServerProtocol::Tcp,
+            server_settings: None,
+            open_file: None,
+            poll_timer: 0,
+        }
+    }
+
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4190 => {
+                let mut val = 0;
+                if self.enabled {
+                    val |= 1;
+                }
+                if self.irq_enabled {
+                    val |= 2;
+                }
+
+                val
+            }
+            0x4191 if self.data_ready => 0x80,
+            // Messages are handled as soon as they are sent so the TX buffer is always free
+            0x4192 => 0x80,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8, fpga_ram: &FixedMemoryBlock<8>) {
+        match addr {
+            0x4190 => {
+                self.enabled = value & 1 != 0;
+                self.irq_enabled = value & 2 != 0;
+            }
+            0x4191 => self.data_ready = false,
+            0x4192 if self.enabled => {
+                let base = (self.tx_addr as usize) << 8;
+                let len = fpga_ram.read(base) as usize;
+                let message: Vec<u8> = (1..=len).map(|i| fpga_ram.read(base + i)).collect();
+                self.receive(&message);
+            }
+            0x4193 => self.rx_addr = value & 0x7,
+            0x4194 => self.tx_addr = value & 0x7,
+            _ => (),
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.enabled && self.irq_enabled && self.data_ready
+    }
+
+    pub fn tick(&mut self, fpga_ram: &mut FixedMemoryBlock<8>) {
+        if !self.enabled {
+            return;
+        }
+
+        self.poll_timer = self.poll_timer.wrapping_add(1);
+        if self.poll_timer & 0x3ff == 0 {
+            self.poll_server();
+        }
+
+        if !self.data_ready
+            && let Some(message) = self.responses.pop_front()
+        {
+            let base = (self.rx_addr as usize) << 8;
+            fpga_ram.write(base, message.len() as u8);
+            for (i, &v) in message.iter().enumerate() {
+                fpga_ram.write(base + 1 + i, v);
+            }
+            self.data_ready = true;
+        }
+    }
+
+    fn respond(&mut self, cmd: FromEsp, data: &[u8]) {
+        let mut message = Vec::with_capacity(data.len() + 1);
+        message.push(cmd as u8);
+        message.extend_from_slice(&data[..data.len().min(MAX_MESSAGE_DATA)]);
+        self.responses.push_back(message);
+    }
+
+    fn respond_str(&mut self, cmd: FromEsp, value: &[u8]) {
+        let mut data = Vec::with_capacity(value.len() + 1);
+        data.push(value.len() as u8);
+        data.extend_from_slice(value);
+        self.respond(cmd, &data);
+    }
+
+    fn receive(&mut self, message: &[u8]) {
+        let Some((&cmd, data)) = message.split_first() else {
+            return;
+        };
+
+        let Some(cmd) = ToEsp::from_u8(cmd) else {
+            tracing::warn!("unknown rainbow esp command: {cmd:02x}");
+            return;
+        };
+
+        match cmd {
+            ToEsp::EspGetStatus => self.respond(FromEsp::Ready, &[]),
+            ToEsp::DebugGetLevel => self.respond(FromEsp::DebugLevel, &[self.debug_level]),
+            ToEsp::DebugSetLevel => {
+                if let Some(&level) = data.first() {
+                    self.debug_level = level;
+                }
+            }
+            ToEsp::DebugLog => {
+                tracing::info!("rainbow esp log: {:02x?}", data);
+            }
+            ToEsp::BufferClearRxTx => {
+                self.responses.clear();
+                self.data_ready = false;
+            }
+            ToEsp::BufferDropFromEsp => self.responses.clear(),
+            ToEsp::EspGetFirmwareVersion => {
+                self.respond_str(FromEsp::EspFirmwareVersion, FIRMWARE_VERSION)
+            }
+            ToEsp::EspFactoryReset => {
+                self.server_settings = None;
+                self.protocol = ServerProtocol::Tcp;
+                self.debug_level = 0;
+            }
+            ToEsp::EspRestart => {
+                self.socket = None;
+                self.open_file = None;
+                self.responses.clear();
+                self.data_ready = false;
+            }
+            ToEsp::WifiGetStatus => {
+                // 3 = WL_CONNECTED, 0 = WL_IDLE_STATUS
+                let status = if self.config.server.is_some() { 3 } else { 0 };
+                self.respond(FromEsp::WifiStatus, &[status]);
+            }
+            ToEsp::WifiGetSsid | ToEsp::ApGetSsid => self.respond_str(FromEsp::Ssid, b"mass-nes"),
+            ToEsp::WifiGetIp | ToEsp::ApGetIp => self.respond_str(FromEsp::IpAddress, b"127.0.0.1"),
+            ToEsp::RndGetByte => {
+                let value = self.next_random() as u8;
+                self.respond(FromEsp::RndByte, &[value]);
+            }
+            ToEsp::RndGetByteRange => {
+                let (min, max) = match data {
+                    [min, max, ..] => (*min as u32, *max as u32),
+                    _ => (0, 0xff),
+                };
+                let value = self.random_range(min, max) as u8;
+                self.respond(FromEsp::RndByte, &[value]);
+            }
+            ToEsp::RndGetWord => {
+                let value = self.next_random() as u16;
+                self.respond(FromEsp::RndWord, &value.to_be_bytes());
+            }
+            ToEsp::RndGetWordRange => {
+                let (min, max) = match data {
+                    [min_hi, min_lo, max_hi, max_lo, ..] => (
+                        u16::from_be_bytes([*min_hi, *min_lo]) as u32,
+                        u16::from_be_bytes([*max_hi, *max_lo]) as u32,
+                    ),
+                    _ => (0, 0xffff),
+                };
+                let value = self.random_range(min, max) as u16;
+                self.respond(FromEsp::RndWord, &value.to_be_bytes());
+            }
+            ToEsp::ServerGetStatus => {
+                let connected = self.server_connected() as u8;
+                self.respond(FromEsp::ServerStatus, &[connected]);
+            }
+            ToEsp::ServerPing => {
+                if self.server_connected() {
+                    // min, max, average in 4ms units followed by lost packet count
+                    self.respond(FromEsp::ServerPing, &[1, 1, 1, 0]);
+                } else {
+                    self.respond(FromEsp::ServerPing, &[]);
+                }
+            }
+            ToEsp::ServerSetProtocol => {
+                if let Some(&protocol) = data.first() {
+                    self.protocol = protocol.into();
+                }
+            }
+            ToEsp::ServerGetSettings => self.respond_server_settings(false),
+            ToEsp::ServerGetSavedSettings => self.respond_server_settings(true),
+            ToEsp::ServerSetSettings | ToEsp::ServerSetSavedSettings => {
+                if let [port_hi, port_lo, host @ ..] = data {
+                    let port = u16::from_be_bytes([*port_hi, *port_lo]);
+                    tracing::debug!(
+                        "rainbow esp server settings: {}:{port}",
+                        String::from_utf8_lossy(host)
+                    );
+                    self.server_settings = Some(ServerSettings {
+                        port,
+                        host: host.to_vec(),
+                    });
+                }
+            }
+            ToEsp::ServerRestoreSavedSettings => self.server_settings = None,
+            ToEsp::ServerConnect => self.connect(),
+            ToEsp::ServerDisconnect => self.socket = None,
+            ToEsp::ServerSendMsg => {
+                if let Some(socket) = self.socket.as_mut()
+                    && let Err(err) = socket.send(data)
+                {
+                    tracing::warn!("rainbow esp send failed: {err}");
+                    self.socket = None;
+                }
+            }
+            ToEsp::NetworkScan => self.respond(FromEsp::NetworkScanResult, &[0]),
+            ToEsp::NetworkGetScanResult | ToEsp::NetworkGetDetails => {
+                self.respond(FromEsp::NetworkScannedDetails, &[])
+            }
+            ToEsp::NetworkGetRegistered => self.respond(FromEsp::NetworkRegistered, &[0; 3]),
+            ToEsp::NetworkGetRegisteredDetails => {
+                self.respond(FromEsp::NetworkRegisteredDetails, &[])
+            }
+            ToEsp::NetworkRegister | ToEsp::NetworkUnregister | ToEsp::NetworkSetActive => (),
+            ToEsp::FileOpen => {
+                self.open_file = FileName::parse(data).map(|(name, _)| OpenFile { name, cursor: 0 })
+            }
+            ToEsp::FileClose => self.open_file = None,
+            ToEsp::FileStatus => {
+                let mut status = Vec::new();
+                if let Some(file) = self.open_file.as_ref() {
+                    status.push(1);
+                    file.name.encode(&mut status);
+                } else {
+                    status.push(0);
+                }
+                self.respond(FromEsp::FileStatus, &status);
+            }
+            ToEsp::FileExists => {
+                let exists = FileName::parse(data)
+                    .and_then(|(name, _)| self.resolve(&name))
+                    .map(|path| path.is_file())
+                    .unwrap_or(false);
+                self.respond(FromEsp::FileExists, &[exists as u8]);
+            }
+            ToEsp::FileDelete => {
+                // 0 = success, 1 = error, 2 = file not found
+                let result = match FileName::parse(data).and_then(|(name, _)| self.resolve(&name)) {
+                    Some(path) if path.is_file() => match std::fs::remove_file(path) {
+                        Ok(_) => 0,
+                        Err(_) => 1,
+                    },
+                    Some(_) => 2,
+                    None => 1,
+                };
+                self.respond(FromEsp::FileDelete, &[result]);
+            }
+            ToEsp::FileSetCur => {
+                if let Some(file) = self.open_file.as_mut() {
+                    let mut cursor = [0; 4];
+                    for (c, &d) in cursor.iter_mut().zip(data) {
+                        *c = d;
+                    }
+                    file.cursor = u32::from_le_bytes(cursor) as u64;
+                }
+            }
+            ToEsp::FileRead => {
+                let len = data.first().copied().unwrap_or(0) as usize;
+                let read = self.file_read(len.min(MAX_MESSAGE_DATA - 1));
+                let mut data = Vec::with_capacity(read.len() + 1);
+                data.push(read.len() as u8);
+                data.extend(read);
+                self.respond(FromEsp::FileData, &data);
+            }
+            ToEsp::FileWrite => self.file_write(data, false),
+            ToEsp::FileAppend => self.file_write(data, true),
+            ToEsp::FileCount => {
+                let count = self.list_files(data).len() as u8;
+                self.respond(FromEsp::FileCount, &[count]);
+            }
+            ToEsp::FileGetList => {
+                let files = self.list_files(data);
+                let mut data = Vec::with_capacity(files.len() + 1);
+                data.push(files.len() as u8);
+                data.extend(files);
+                self.respond(FromEsp::FileList, &data);
+            }
+            ToEsp::FileGetFreeId => {
+                let files = self.list_files(data);
+                if self.fs_root().is_some()
+                    && let Some(id) = (0..MAX_FILE_ID).find(|id| !files.contains(id))
+                {
+                    self.respond(FromEsp::FileId, &[id]);
+                } else {
+                    self.respond(FromEsp::FileId, &[]);
+                }
+            }
+            ToEsp::FileGetFsInfo => self.respond(FromEsp::FileFsInfo, &[0; 24]),
+            ToEsp::FileGetInfo => {
+                let contents = FileName::parse(data)
+                    .and_then(|(name, _)| self.resolve(&name))
+                    .and_then(|path| std::fs::read(path).ok());
+                if let Some(contents) = contents {
+                    let mut info = Vec::with_capacity(8);
+                    info.extend_from_slice(&crc32(&contents).to_be_bytes());
+                    info.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+                    self.respond(FromEsp::FileInfo, &info);
+                } else {
+                    self.respond(FromEsp::FileInfo, &[]);
+                }
+            }
+            ToEsp::FileDownload => {
+                tracing::warn!("rainbow esp file download unsupported");
+                // 1 = invalid url
+                self.respond(FromEsp::FileDownload, &[1]);
+            }
+            ToEsp::FileFormat => {
+                self.open_file = None;
+                if let Some(root) = self.fs_root() {
+                    for path in FILE_PATHS {
+                        let _ = std::fs::remove_dir_all(root.join(path));
+                    }
+                }
+            }
+        }
+    }
+
+    fn respond_server_settings(&mut self, saved: bool) {
+        let settings = self
+            .server_settings
+            .as_ref()
+            .filter(|_| !saved)
+            .map(|s| (s.port, s.host.clone()))
+            .or_else(|| {
+                self.config
+                    .server
+                    .map(|addr| (addr.port(), addr.ip().to_string().into_bytes()))
+            });
+
+        if let Some((port, host)) = settings {
+            let mut data = Vec::with_capacity(host.len() + 2);
+            data.extend_from_slice(&port.to_be_bytes());
+            data.extend(host);
+            self.respond(FromEsp::ServerSettings, &data);
+        } else {
+            self.respond(FromEsp::ServerSettings, &[]);
+        }
+    }
+
+    fn connect(&mut self) {
+        let Some(addr) = self.config.server else {
+            tracing::warn!("rainbow esp server connect requested without a configured endpoint");
+            return;
+        };
+
+        match Socket::connect(self.protocol, addr) {
+            Ok(socket) => {
+                tracing::debug!("rainbow esp connecting to {addr} ({:?})", self.protocol);
+                self.socket = Some(socket);
+            }
+            Err(err) => {
+                tracing::warn!("rainbow esp unable to connect to {addr}: {err}");
+                self.socket = None;
+            }
+        }
+    }
+
+    /// Only an established stream counts, a connection still in progress or one that failed does not
+    fn server_connected(&mut self) -> bool {
+        let Some(socket) = self.socket.as_mut() else {
+            return false;
+        };
+
+        match socket.is_connected() {
+            Ok(connected) => connected,
+            Err(err) => {
+                tracing::warn!("rainbow esp unable to connect: {err}");
+                self.socket = None;
+                false
+            }
+        }
+    }
+
+    fn poll_server(&mut self) {
+        let mut buf = [0; MAX_MESSAGE_DATA];
+        while self.responses.len() < MAX_QUEUED_MESSAGES {
+            let Some(socket) = self.socket.as_mut() else {
+                return;
+            };
+
+            match socket.recv(&mut buf) {
+                Ok(None) => return,
+                Ok(Some(0)) if matches!(socket, Socket::Tcp(_)) => {
+                    tracing::debug!("rainbow esp server closed connection");
+                    self.socket = None;
+                }
+                Ok(Some(n)) => self.respond(FromEsp::MessageFromServer, &buf[..n]),
+                Err(err) => {
+                    tracing::warn!("rainbow esp receive failed: {err}");
+                    self.socket = None;
+                }
+            }
+        }
+    }
+
+    fn fs_root(&self) -> Option<&Path> {
+        self.config.fs_root.as_deref()
+    }
+
+    fn resolve(&self, name: &FileName) -> Option<PathBuf> {
+        name.resolve(self.fs_root()?)
+    }
+
+    fn list_files(&self, data: &[u8]) -> Vec<u8> {
+        let path = match data {
+            [config, path, ..] if config & 1 == 0 => *path as usize,
+            _ => return Vec::new(),
+        };
+
+        let Some(dir) = self
+            .fs_root()
+            .zip(FILE_PATHS.get(path))
+            .map(|(root, path)| root.join(path))
+        else {
+            return Vec::new();
+        };
+
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+
+        let mut files: Vec<u8> = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file())
+            .filter_map(|e| e.file_name().to_str()?.parse::<u8>().ok())
+            .filter(|&id| id < MAX_FILE_ID)
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn file_read(&mut self, len: usize) -> Vec<u8> {
+        let Some(path) = self
+            .open_file
+            .as_ref()
+            .and_then(|file| self.resolve(&file.name))
+        else {
+            return Vec::new();
+        };
+        let Some(open_file) = self.open_file.as_mut() else {
+            return Vec::new();
+        };
+
+        let mut buf = vec![0; len];
+        let read = std::fs::File::open(path).and_then(|mut file| {
+            file.seek(SeekFrom::Start(open_file.cursor))?;
+            let mut total = 0;
+            while total < len {
+                match file.read(&mut buf[total..])? {
+                    0 => break,
+                    n => total += n,
+                }
+            }
+            Ok(total)
+        });
+
+        let read = read.unwrap_or(0);
+        open_file.cursor += read as u64;
+        buf.truncate(read);
+        buf
+    }
+
+    fn file_write(&mut self, data: &[u8], append: bool) {
+        let Some(path) = self
+            .open_file
+            .as_ref()
+            .and_then(|file| self.resolve(&file.name))
+        else {
+            return;
+        };
+        let Some(open_file) = self.open_file.as_mut() else {
+            return;
+        };
+
+        let res = path
+            .parent()
+            .map(std::fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| {
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+            })
+            .and_then(|mut file| {
+                if append {
+                    file.seek(SeekFrom::End(0))?;
+                } else {
+                    file.seek(SeekFrom::Start(open_file.cursor))?;
+                }
+                file.write_all(data)
+            });
+
+        match res {
+            Ok(_) if !append => open_file.cursor += data.len() as u64,
+            Ok(_) => (),
+            Err(err) => tracing::warn!("rainbow esp file write failed: {err}"),
+        }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x
+    }
+
+    fn random_range(&mut self, min: u32, max: u32) -> u32 {
+        let (min, max) = if min <= max { (min, max) } else { (max, min) };
+        min + self.next_random() % (max - min + 1)
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    const TX_PAGE: u8 = 0;
+    const RX_PAGE: u8 = 1;
+
+    struct Harness {
+        esp: Esp,
+        fpga_ram: FixedMemoryBlock<8>,
+    }
+
+    impl Harness {
+        fn new(config: EspConfig) -> Self {
+            let mut esp = Esp::new(config);
+            let fpga_ram = FixedMemoryBlock::new();
+            esp.write(0x4190, 0x01, &fpga_ram);
+            esp.write(0x4193, RX_PAGE, &fpga_ram);
+            esp.write(0x4194, TX_PAGE, &fpga_ram);
+
+            Self { esp, fpga_ram }
+        }
+
+        fn send(&mut self, cmd: ToEsp, data: &[u8]) {
+            let base = (TX_PAGE as usize) << 8;
+            self.fpga_ram.write(base, data.len() as u8 + 1);
+            self.fpga_ram.write(base + 1, cmd as u8);
+            for (i, &v) in data.iter().enumerate() {
+                self.fpga_ram.write(base + 2 + i, v);
+            }
+            self.esp.write(0x4192, 0, &self.fpga_ram);
+        }
+
+        // Runs the ESP until it has a message for the rom, then acknowledges it
+        fn recv(&mut self) -> (u8, Vec<u8>) {
+            for i in 0..1_000_000 {
+                // The server is only polled every so often, give it time to answer
+                if i & 0x3ff == 0 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                self.esp.tick(&mut self.fpga_ram);
+                if self.esp.peek(0x4191) & 0x80 != 0 {
+                    let base = (RX_PAGE as usize) << 8;
+                    let len = self.fpga_ram.read(base) as usize;
+                    let message: Vec<u8> =
+                        (1..=len).map(|i| self.fpga_ram.read(base + i)).collect();
+                    self.esp.write(0x4191, 0, &self.fpga_ram);
+                    return (message[0], message[1..].to_vec());
+                }
+            }
+
+            panic!("no message from esp");
+        }
+    }
+
+    #[test]
+    fn messages_reach_loopback_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut harness = Harness::new(EspConfig {
+            fs_root: None,
+            server: Some(listener.local_addr().unwrap()),
+        });
+
+        harness.send(ToEsp::EspGetStatus, &[]);
+        assert_eq!(harness.recv(), (FromEsp::Ready as u8, vec![]));
+
+        harness.send(ToEsp::ServerConnect, &[]);
+        harness.send(ToEsp::ServerSendMsg, b"ping");
+
+        let (mut server, _) = listener.accept().unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+
+        // Held messages are sent once the connection is polled
+        let mut buf = [0; 4];
+        let mut read = 0;
+        for _ in 0..1000 {
+            for _ in 0..0x400 {
+                harness.esp.tick(&mut harness.fpga_ram);
+            }
+            if let Ok(n) = server.read(&mut buf[read..]) {
+                read += n;
+            }
+            if read == buf.len() {
+                break;
+            }
+        }
+        assert_eq!(&buf, b"ping");
+
+        server.write_all(b"pong").unwrap();
+        assert_eq!(
+            harness.recv(),
+            (FromEsp::MessageFromServer as u8, b"pong".to_vec())
+        );
+
+        harness.send(ToEsp::ServerGetStatus, &[]);
+        assert_eq!(harness.recv(), (FromEsp::ServerStatus as u8, vec![1]));
+    }
+
+    #[test]
+    fn status_is_disconnected_without_a_listener() {
+        // Claim a free port then release it so nothing is listening there
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut harness = Harness::new(EspConfig {
+            fs_root: None,
+            server: Some(addr),
+        });
+
+        harness.send(ToEsp::ServerConnect, &[]);
+        for _ in 0..100 {
+            harness.send(ToEsp::ServerGetStatus, &[]);
+            assert_eq!(harness.recv(), (FromEsp::ServerStatus as u8, vec![0]));
+            if harness.esp.socket.is_none() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        panic!("refused connection was never dropped");
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::cartridge::INes;
+use crate::debug::Debug;
+
+use super::RcMapper;
+
+type MapperConstructor = Rc<dyn Fn(INes, Rc<Debug>) -> RcMapper>;
+
+/// Maps cartridge boards to user provided mapper constructors, registered boards take
+/// priority over the built in mappers
+///
+/// Mappers are created with [`MapperState::rc`](super::MapperState::rc), and need to
+/// implement `BinarySaveState` (re-exported by this crate) when the `save-states` feature
+/// is enabled. A save state can only be restored by a [`Machine`](crate::Machine) built
+/// with the same registry.
+#[derive(Clone, Default)]
+pub struct MapperRegistry {
+    ines: HashMap<(u32, Option<u32>), MapperConstructor>,
+    unif: HashMap<String, MapperConstructor>,
+}
+
+impl MapperRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a constructor for an iNES mapper number, a `submapper` of `None` matches
+    /// any submapper that does not have its own registration
+    pub fn register_ines<F>(&mut self, mapper: u32, submapper: Option<u32>, constructor: F)
+    where
+        F: Fn(INes, Rc<Debug>) -> RcMapper + 'static,
+    {
+        self.ines.insert((mapper, submapper), Rc::new(constructor));
+    }
+
+    /// Register a constructor for a UNIF board name, the board name is matched without its
+    /// `NES-`, `UNL-`, `HVC-`, `BTL-` or `BMC-` prefix
+    pub fn register_unif<S, F>(&mut self, board: S, constructor: F)
+    where
+        S: AsRef<str>,
+        F: Fn(INes, Rc<Debug>) -> RcMapper + 'static,
+    {
+        let board = unif_board_name(board.as_ref()).to_string();
+        self.unif.insert(board, Rc::new(constructor));
+    }
+
+    pub(crate) fn build(&self, cart: INes, debug: Rc<Debug>) -> RcMapper {
+        let constructor = cart
+            .board
+            .as_deref()
+            .and_then(|board| self.unif.get(unif_board_name(board)))
+            .or_else(|| {
+                cart.submapper
+                    .and_then(|sub| self.ines.get(&(cart.mapper, Some(sub))))
+            })
+            .or_else(|| self.ines.get(&(cart.mapper, None)));
+
+        match constructor {
+            Some(constructor) => constructor(cart, debug),
+            None => super::ines(cart, debug),
+        }
+    }
+}
+
+pub(crate) fn unif_board_name(board: &str) -> &str {
+    ["NES-", "UNL-", "HVC-", "BTL-", "BMC-"]
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board)
+}
@@ -27,8 +27,8 @@ impl Uxrom {
 
         let (mirroring, nt_ram) = if cartridge.alternative_mirroring {
             match cartridge.mirroring {
-                CartMirroring::Horizontal => (Mirroring::Single(Nametable::InternalA), None),
                 CartMirroring::Vertical => (Mirroring::FourScreen, Some(FixedMemoryBlock::new())),
+                _ => (Mirroring::Single(Nametable::InternalA), None),
             }
         } else {
             (cartridge.mirroring.into(), None)
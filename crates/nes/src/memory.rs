@@ -111,6 +111,12 @@ impl<const KB: usize> FixedMemoryBlock<KB> {
     }
 }
 
+impl<const KB: usize> Default for FixedMemoryBlock<KB> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<const KB: usize> Memory for FixedMemoryBlock<KB> {
     fn len(&self) -> usize {
         Self::BYTES
@@ -1133,6 +1133,15 @@ impl Ppu {
         self.screen.as_ref()
     }
 
+    /// Current scanline and dot of the beam
+    pub fn position(&self) -> (u32, u32) {
+        (self.step.scanline, self.step.dot)
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
     fn is_nmi_enabled(&self) -> bool {
         self.regs[0] & 0x80 != 0
     }
@@ -0,0 +1,53 @@
+use nes::{Cartridge, EPSM_EXPANSION_DEVICE, Machine, Region};
+
+fn nes_2_rom(expansion_device: u8) -> Vec<u8> {
+    let mut rom = vec![0; 16 + 0x4000 + 0x2000];
+    rom[0..4].copy_from_slice(b"NES\x1a");
+    rom[4] = 1;
+    rom[5] = 1;
+    rom[7] = 0x08;
+    rom[15] = expansion_device;
+    rom
+}
+
+fn load(expansion_device: u8) -> Machine {
+    let rom = nes_2_rom(expansion_device);
+    let cart = Cartridge::load(&mut rom.as_slice(), None, None, "test.nes").unwrap();
+    Machine::new(Region::Ntsc, cart)
+}
+
+#[test]
+fn epsm_attached_from_header() {
+    assert!(load(EPSM_EXPANSION_DEVICE).epsm());
+}
+
+#[test]
+fn epsm_not_attached_without_header() {
+    assert!(!load(0x01).epsm());
+}
+
+fn nsf_2(features: u8) -> Machine {
+    let mut nsf = vec![0; 128];
+    nsf[0..5].copy_from_slice(b"NESM\x1a");
+    nsf[5] = 2;
+    nsf[6] = 1;
+    nsf[7] = 1;
+    nsf[8..14].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+    nsf[110..112].copy_from_slice(&16639u16.to_le_bytes());
+    nsf[124] = features;
+    nsf.extend([0x60; 0x100]);
+
+    let cart = Cartridge::load(&mut nsf.as_slice(), None, None, "test.nsf").unwrap();
+    Machine::new(Region::Ntsc, cart)
+}
+
+#[test]
+fn epsm_refused_by_nsf_irq() {
+    let mut machine = nsf_2(0x80);
+    machine.set_epsm(true);
+    assert!(!machine.epsm());
+
+    let mut machine = nsf_2(0x00);
+    machine.set_epsm(true);
+    assert!(machine.epsm());
+}
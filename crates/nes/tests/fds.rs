@@ -0,0 +1,132 @@
+use nes::Cartridge;
+
+const FDS_SIDE_LEN: usize = 65500;
+const QD_SIDE_LEN: usize = 0x10000;
+
+// Reflected CCITT checksum over the 0x80 start mark and the block
+fn crc(block: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in [0x80].iter().chain(block) {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn disk_blocks(side: u8) -> Vec<Vec<u8>> {
+    let mut disk_info = vec![0; 56];
+    disk_info[0] = 1;
+    disk_info[1..15].copy_from_slice(b"*NINTENDO-HVC*");
+    disk_info[21] = side;
+
+    let data = [side; 5];
+    let mut file_header = vec![0; 16];
+    file_header[0] = 3;
+    file_header[3..11].copy_from_slice(b"FILENAME");
+    file_header[11..13].copy_from_slice(&0x6000u16.to_le_bytes());
+    file_header[13..15].copy_from_slice(&(data.len() as u16).to_le_bytes());
+
+    let mut file_data = vec![4];
+    file_data.extend_from_slice(&data);
+
+    vec![disk_info, vec![2, 1], file_header, file_data]
+}
+
+fn fds_image(sides: u8) -> Vec<u8> {
+    let mut image = b"FDS\x1a".to_vec();
+    image.push(sides);
+    image.resize(16, 0);
+    for side in 0..sides {
+        let start = image.len();
+        image.extend(disk_blocks(side).concat());
+        image.resize(start + FDS_SIDE_LEN, 0);
+    }
+    image
+}
+
+fn quick_disk_image() -> Vec<u8> {
+    let mut image = Vec::new();
+    for block in disk_blocks(0) {
+        image.extend_from_slice(&block);
+        image.extend_from_slice(&crc(&block).to_le_bytes());
+    }
+    image.resize(QD_SIDE_LEN, 0);
+    image
+}
+
+fn load(image: &[u8], file_name: &str) -> Option<nes::Cartridge> {
+    let bios = [0; 0x2000];
+    Cartridge::load(&mut &image[..], None, Some(&mut &bios[..]), file_name).ok()
+}
+
+fn disk_sides(image: &[u8], file_name: &str) -> Vec<Vec<u8>> {
+    match load(image, file_name) {
+        Some(Cartridge::Fds(fds)) => fds.disk_sides,
+        _ => panic!("disk image did not load"),
+    }
+}
+
+#[test]
+fn quick_disk_checksums() {
+    let image = quick_disk_image();
+    let side = &disk_sides(&image, "test.qd")[0];
+
+    let disk_info = &disk_blocks(0)[0];
+    let start = side.iter().position(|&b| b == 0x80).unwrap() + 1;
+    assert_eq!(&side[start..start + 56], disk_info.as_slice());
+    assert_eq!(
+        side[start + 56..start + 58],
+        crc(disk_info).to_le_bytes(),
+        "stored checksum kept after the block"
+    );
+
+    let mut corrupt = image.clone();
+    corrupt[56] ^= 0xff;
+    assert!(load(&corrupt, "test.qd").is_none());
+}
+
+#[test]
+fn fds_checksums_generated() {
+    let side = &disk_sides(&fds_image(1), "test.fds")[0];
+
+    let disk_info = &disk_blocks(0)[0];
+    let start = side.iter().position(|&b| b == 0x80).unwrap() + 1;
+    assert_eq!(side[start + 56..start + 58], crc(disk_info).to_le_bytes());
+}
+
+#[test]
+fn headerless_image_detected() {
+    let image = fds_image(1);
+    let headerless = &image[16..];
+    assert!(headerless.starts_with(b"\x01*NI"));
+
+    assert!(matches!(
+        load(headerless, "game.bin"),
+        Some(Cartridge::Fds(_))
+    ));
+    assert_eq!(
+        disk_sides(headerless, "game.bin"),
+        disk_sides(&image, "game.fds")
+    );
+}
+
+#[test]
+fn export_round_trip() {
+    let image = fds_image(2);
+    let Some(Cartridge::Fds(fds)) = load(&image, "test.fds") else {
+        panic!("disk image did not load");
+    };
+    assert_eq!(fds.disk_sides.len(), 2);
+
+    let exported = fds.export(true);
+    assert_eq!(exported, image);
+    assert_eq!(fds.export(false), image[16..]);
+
+    assert_eq!(disk_sides(&exported, "test.fds"), fds.disk_sides);
+}
@@ -0,0 +1,133 @@
+use nes::{
+    AddressBus, AndAndMask, BusKind, Cartridge, DeviceKind, INes, Machine, MapperRegistry,
+    MapperState, Memory, Nametable, PpuFetchKind, Region, SaveState, SimpleInput,
+};
+
+// UxROM-like board defined outside of the crate, the last byte written to $8000-$FFFF
+// selects the 16k bank at $8000 and the last bank is fixed at $C000
+struct ExternalMapper {
+    cartridge: INes,
+    bank: u8,
+}
+
+impl ExternalMapper {
+    fn new(cartridge: INes) -> Self {
+        Self { cartridge, bank: 0 }
+    }
+}
+
+impl SaveState for ExternalMapper {
+    type Data = u8;
+
+    fn save_state(&self) -> u8 {
+        self.bank
+    }
+
+    fn restore_state(&mut self, state: &u8) {
+        self.bank = *state;
+    }
+}
+
+impl nes::Mapper for ExternalMapper {
+    fn register(&self, cpu: &mut AddressBus) {
+        cpu.register_read(DeviceKind::Mapper, AndAndMask(0x8000, 0xffff));
+        cpu.register_write(DeviceKind::Mapper, AndAndMask(0x8000, 0xffff));
+    }
+
+    fn peek(&self, bus: BusKind, addr: u16) -> u8 {
+        let prg = &self.cartridge.prg_rom;
+        match bus {
+            BusKind::Cpu if addr & 0x4000 == 0 => {
+                prg.read_mapped(self.bank as usize, 16 * 1024, addr)
+            }
+            BusKind::Cpu => prg.read_mapped(prg.len() / 0x4000 - 1, 16 * 1024, addr),
+            BusKind::Ppu => self.cartridge.chr_rom.read_mapped(0, 8 * 1024, addr),
+        }
+    }
+
+    fn write(&mut self, bus: BusKind, _addr: u16, value: u8) {
+        if let BusKind::Cpu = bus {
+            self.bank = value & 0x03;
+        }
+    }
+
+    fn peek_ppu_fetch(&self, address: u16, _kind: PpuFetchKind) -> Nametable {
+        self.cartridge.mirroring.ppu_fetch(address)
+    }
+}
+
+// Four 16k banks each starting with their bank number, the fixed bank selects bank 2 at reset
+fn prg_rom() -> Vec<u8> {
+    let mut prg = vec![0xea; 0x10000];
+    for bank in 0..4 {
+        prg[bank * 0x4000] = bank as u8;
+    }
+    let reset = [
+        0xa9, 0x02, 0x8d, 0x00, 0x80, // LDA #$02, STA $8000
+        0x4c, 0x05, 0xc0, // JMP $C005
+    ];
+    prg[0xc000..0xc000 + reset.len()].copy_from_slice(&reset);
+    prg[0xfffa..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0, 0x00, 0xc0]);
+    prg
+}
+
+fn ines_rom() -> Vec<u8> {
+    let mut rom = vec![
+        b'N', b'E', b'S', 0x1a, 4, 1, 0xf0, 0xf0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    rom.extend(prg_rom());
+    rom.extend(vec![0; 0x2000]);
+    rom
+}
+
+fn unif_rom() -> Vec<u8> {
+    let chunk = |id: &[u8; 4], data: &[u8]| {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    };
+
+    let mut rom = vec![0; 32];
+    rom[0..4].copy_from_slice(b"UNIF");
+    rom.extend(chunk(b"MAPR", b"UNL-EXTERNAL\0"));
+    rom.extend(chunk(b"PRG0", &prg_rom()));
+    rom.extend(chunk(b"CHR0", &[0; 0x2000]));
+    rom
+}
+
+fn machine(rom: &[u8], file_name: &str, registry: &MapperRegistry) -> Machine {
+    let cart = Cartridge::load(&mut &rom[..], None, None, file_name).unwrap();
+    Machine::with_mappers(Region::Ntsc, cart, registry)
+}
+
+fn round_trip(rom: &[u8], file_name: &str, registry: &MapperRegistry) {
+    let mut original = machine(rom, file_name, registry);
+    assert_eq!(original.peek(0x8000), 0);
+    assert_eq!(original.peek(0xc000), 0xa9);
+
+    original.run(&mut SimpleInput::new());
+    assert_eq!(original.peek(0x8000), 2);
+    let state = original.save_state();
+
+    let mut restored = machine(rom, file_name, registry);
+    assert_eq!(restored.peek(0x8000), 0);
+    restored.restore_state(&state);
+    assert_eq!(restored.peek(0x8000), 2);
+}
+
+#[test]
+fn registered_ines_mapper() {
+    let mut registry = MapperRegistry::new();
+    registry.register_ines(255, None, |cart, _debug| ExternalMapper::new(cart).rc());
+
+    round_trip(&ines_rom(), "test.nes", &registry);
+}
+
+#[test]
+fn registered_unif_board() {
+    let mut registry = MapperRegistry::new();
+    registry.register_unif("EXTERNAL", |cart, _debug| ExternalMapper::new(cart).rc());
+
+    round_trip(&unif_rom(), "test.unf", &registry);
+}
@@ -0,0 +1,73 @@
+use nes::Cartridge;
+
+fn chunk(ident: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+    chunk.extend_from_slice(ident);
+    chunk.extend_from_slice(data);
+    chunk
+}
+
+// Three song NSF2 with 0x100 bytes of program data followed by `metadata`
+fn nsf_2(metadata: &[u8]) -> Vec<u8> {
+    let mut nsf = vec![0; 128];
+    nsf[0..5].copy_from_slice(b"NESM\x1a");
+    nsf[5] = 2;
+    nsf[6] = 3;
+    nsf[7] = 1;
+    nsf[8..14].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+    nsf[14..17].copy_from_slice(b"Old");
+    nsf[110..112].copy_from_slice(&16639u16.to_le_bytes());
+    nsf[125..128].copy_from_slice(&[0x00, 0x01, 0x00]);
+    nsf.extend([0x60; 0x100]);
+    nsf.extend_from_slice(metadata);
+    nsf
+}
+
+fn load(file: &[u8]) -> Option<nes::NsfFile> {
+    match Cartridge::load(&mut &file[..], None, None, "test.nsf") {
+        Ok(Cartridge::Nsf(nsf)) => Some(nsf),
+        _ => None,
+    }
+}
+
+#[test]
+fn nsf_2_metadata() {
+    let metadata = [
+        chunk(b"auth", b"Title\0Artist\0Copyright\0Ripper\0"),
+        chunk(b"tlbl", b"One\0Two\0Three\0"),
+        chunk(b"time", &[1000i32, -1, 3000].map(i32::to_le_bytes).concat()),
+        chunk(b"plst", &[2, 0, 5]),
+        chunk(b"xtra", &[0; 12]),
+        chunk(b"NEND", &[]),
+    ]
+    .concat();
+
+    let nsf = load(&nsf_2(&metadata)).unwrap();
+    assert_eq!(nsf.song_name.as_deref(), Some("Title"));
+    assert_eq!(nsf.artist_name.as_deref(), Some("Artist"));
+    assert_eq!(nsf.copyright_name.as_deref(), Some("Copyright"));
+    assert_eq!(nsf.metadata.track_title(1), Some("Two"));
+    assert_eq!(nsf.metadata.track_time(0), Some(1000));
+    assert_eq!(nsf.metadata.track_time(1), None);
+    assert_eq!(nsf.metadata.playlist.as_deref(), Some(&[2, 0][..]));
+
+    let nsf = load(&nsf_2(&[])).unwrap();
+    assert_eq!(nsf.song_name.as_deref(), Some("Old"));
+    assert_eq!(nsf.metadata.track_title(0), None);
+}
+
+#[test]
+fn nsf_2_truncated_metadata() {
+    let mut metadata = chunk(b"auth", b"Title\0Artist\0");
+    metadata.truncate(metadata.len() - 4);
+    assert!(load(&nsf_2(&metadata)).is_none());
+
+    let mut metadata = chunk(b"xtra", &[0; 12]);
+    metadata.truncate(metadata.len() - 4);
+    assert!(load(&nsf_2(&metadata)).is_none());
+
+    // A length far past the end of the file must not be allocated up front
+    let mut metadata = chunk(b"tlbl", b"One\0");
+    metadata[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(load(&nsf_2(&metadata)).is_none());
+}
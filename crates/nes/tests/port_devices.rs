@@ -0,0 +1,366 @@
+use nes::{
+    Cartridge, Controller, Machine, Mouse, Port, PortDeviceKind, PowerPad, Region, SimpleInput,
+    UserInput, Vaus, Zapper,
+};
+
+const READS: usize = 40;
+
+// NROM cart that sets the backdrop to white, then in its NMI handler strobes the ports,
+// making `strobe_reads` reads of $4016 while OUT0 is high, and stores READS reads of $4016 at
+// $0200 and of $4017 at $0300
+fn strobing_machine(strobe_reads: usize) -> Machine {
+    let mut rom = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let mut prg = vec![0xea; 0x4000];
+    let reset = [
+        0x78, // SEI
+        0xa2, 0xff, 0x9a, // LDX #$FF, TXS
+        0x2c, 0x02, 0x20, 0x10, 0xfb, // BIT $2002, BPL -5
+        0x2c, 0x02, 0x20, 0x10, 0xfb, // BIT $2002, BPL -5
+        0xa9, 0x3f, 0x8d, 0x06, 0x20, // LDA #$3F, STA $2006
+        0xa9, 0x00, 0x8d, 0x06, 0x20, // LDA #$00, STA $2006
+        0xa9, 0x30, 0x8d, 0x07, 0x20, // LDA #$30, STA $2007
+        0xa9, 0x00, 0x8d, 0x06, 0x20, 0x8d, 0x06, 0x20, // LDA #$00, STA $2006, STA $2006
+        0xa9, 0x80, 0x8d, 0x00, 0x20, // LDA #$80, STA $2000
+        0x4c, 0x2a, 0x80, // JMP $802A
+    ];
+    let mut nmi = vec![0xa9, 0x01, 0x8d, 0x16, 0x40]; // LDA #$01, STA $4016
+    for _ in 0..strobe_reads {
+        nmi.extend([0xad, 0x16, 0x40]); // LDA $4016
+    }
+    nmi.extend([0xa9, 0x00, 0x8d, 0x16, 0x40]); // LDA #$00, STA $4016
+    nmi.extend([0xa2, 0x00]); // LDX #$00
+    nmi.extend([0xad, 0x16, 0x40, 0x9d, 0x00, 0x02]); // LDA $4016, STA $0200,X
+    nmi.extend([0xad, 0x17, 0x40, 0x9d, 0x00, 0x03]); // LDA $4017, STA $0300,X
+    nmi.extend([0xe8, 0xe0, READS as u8, 0xd0, 0xef]); // INX, CPX #READS, BNE -17
+    nmi.push(0x40); // RTI
+    prg[..reset.len()].copy_from_slice(&reset);
+    prg[0x100..0x100 + nmi.len()].copy_from_slice(&nmi);
+    prg[0x3ffa..].copy_from_slice(&[0x00, 0x81, 0x00, 0x80, 0x00, 0x81]);
+    rom.extend(prg);
+    rom.extend(vec![0; 0x2000]);
+
+    let cart = Cartridge::load(&mut &rom[..], None, None, "ports.nes").unwrap();
+    Machine::new(Region::Ntsc, cart)
+}
+
+fn machine() -> Machine {
+    strobing_machine(0)
+}
+
+// Runs a few frames with `input` held and returns the reads of $4016 and $4017 made by the
+// last NMI, with the open bus bits masked off
+fn reads(machine: &mut Machine, input: &mut SimpleInput) -> ([u8; READS], [u8; READS]) {
+    for _ in 0..5 {
+        machine.run(input);
+    }
+    next_reads(machine, input)
+}
+
+// Runs a single frame and returns the reads made by its NMI
+fn next_reads(machine: &mut Machine, input: &mut SimpleInput) -> ([u8; READS], [u8; READS]) {
+    machine.run(input);
+
+    let read = |base: u16| std::array::from_fn(|i| machine.peek(base + i as u16) & 0x1f);
+    (read(0x0200), read(0x0300))
+}
+
+// Serial value shifted out on one data line, first read in the lowest bit
+fn serial(reads: &[u8], line: u8) -> u32 {
+    reads.iter().enumerate().fold(0, |value, (i, read)| {
+        value | ((read >> line) as u32 & 1) << i
+    })
+}
+
+// Serial value shifted out on one data line, first read in the highest bit
+fn serial_msb(reads: &[u8], line: u8) -> u32 {
+    reads
+        .iter()
+        .fold(0, |value, read| (value << 1) | ((read >> line) as u32 & 1))
+}
+
+fn controller(byte: u8) -> Controller {
+    Controller {
+        a: byte & 0x01 != 0,
+        b: byte & 0x02 != 0,
+        select: byte & 0x04 != 0,
+        start: byte & 0x08 != 0,
+        up: byte & 0x10 != 0,
+        down: byte & 0x20 != 0,
+        left: byte & 0x40 != 0,
+        right: byte & 0x80 != 0,
+    }
+}
+
+fn four_players() -> SimpleInput {
+    let mut input = SimpleInput::new();
+    for (player, byte) in [0x81, 0x42, 0x24, 0x18].into_iter().enumerate() {
+        input.handle_input(UserInput::Player(player, controller(byte)));
+    }
+    input
+}
+
+#[test]
+fn four_score_report() {
+    let mut machine = machine();
+    machine.set_port_device(Port::One, PortDeviceKind::FourScore);
+    machine.set_port_device(Port::Two, PortDeviceKind::FourScore);
+
+    let (port_one, port_two) = reads(&mut machine, &mut four_players());
+
+    // Players 1 and 3 then the 0x08 signature, players 2 and 4 then 0x04
+    assert_eq!(serial(&port_one[..24], 0), 0x08_24_81);
+    assert_eq!(serial(&port_two[..24], 0), 0x04_18_42);
+    assert!(port_one[24..].iter().all(|&r| r & 1 == 1));
+    assert!(port_two[24..].iter().all(|&r| r & 1 == 1));
+}
+
+#[test]
+fn hori_adapter_report() {
+    let mut machine = machine();
+    machine.set_port_device(Port::Expansion, PortDeviceKind::Hori);
+
+    let (port_one, port_two) = reads(&mut machine, &mut four_players());
+
+    // The built in controllers stay on D0, players 3 and 4 are on D1
+    assert_eq!(serial(&port_one[..8], 0), 0x81);
+    assert_eq!(serial(&port_two[..8], 0), 0x42);
+    assert_eq!(serial(&port_one[..16], 1), 0x04_24);
+    assert_eq!(serial(&port_two[..16], 1), 0x08_18);
+    assert!(port_one[16..].iter().all(|&r| r & 2 == 2));
+    assert!(port_two[16..].iter().all(|&r| r & 2 == 2));
+}
+
+#[test]
+fn port_devices_restored_from_save_state() {
+    let mut input = SimpleInput::new();
+    input.handle_input(UserInput::Vaus(Vaus {
+        position: 0x0a5,
+        button: true,
+    }));
+
+    let mut original = machine();
+    original.set_port_device(Port::Two, PortDeviceKind::Vaus);
+    original.set_port_device(Port::Expansion, PortDeviceKind::FamilyTrainer);
+    let before = reads(&mut original, &mut input);
+    let state = original.save_state();
+
+    let mut restored = machine();
+    assert_eq!(restored.port_device(Port::Two), PortDeviceKind::Controller);
+    restored.restore_state(&state);
+    assert_eq!(restored.port_device(Port::One), PortDeviceKind::Controller);
+    assert_eq!(restored.port_device(Port::Two), PortDeviceKind::Vaus);
+    assert_eq!(
+        restored.port_device(Port::Expansion),
+        PortDeviceKind::FamilyTrainer
+    );
+
+    // The plugged in devices respond to the restored machine
+    assert_eq!(next_reads(&mut restored, &mut input), before);
+}
+
+#[test]
+fn zapper_light_and_trigger() {
+    let mut machine = machine();
+    machine.set_port_device(Port::Two, PortDeviceKind::Zapper);
+    let mut input = SimpleInput::new();
+
+    // Pointed away from the screen nothing is sensed, D3 is high while there's no light
+    input.handle_input(UserInput::Zapper(Zapper {
+        aim: None,
+        trigger: true,
+    }));
+    let (_, port_two) = reads(&mut machine, &mut input);
+    assert!(port_two.iter().all(|&r| r & 0x18 == 0x18));
+
+    // The white backdrop drawn a few lines before the NMI is still lit
+    input.handle_input(UserInput::Zapper(Zapper {
+        aim: Some((128, 235)),
+        trigger: false,
+    }));
+    let (port_one, port_two) = next_reads(&mut machine, &mut input);
+    assert!(port_two.iter().all(|&r| r & 0x18 == 0x00));
+    assert!(port_one.iter().all(|&r| r & 0x18 == 0x00));
+
+    // Lines drawn long before the read have faded
+    input.handle_input(UserInput::Zapper(Zapper {
+        aim: Some((128, 100)),
+        trigger: false,
+    }));
+    let (_, port_two) = next_reads(&mut machine, &mut input);
+    assert!(port_two.iter().all(|&r| r & 0x18 == 0x08));
+}
+
+fn vaus_input(position: u16, button: bool) -> SimpleInput {
+    let mut input = SimpleInput::new();
+    input.handle_input(UserInput::Vaus(Vaus { position, button }));
+    input
+}
+
+#[test]
+fn nes_vaus_report() {
+    let mut machine = machine();
+    machine.set_port_device(Port::Two, PortDeviceKind::Vaus);
+
+    // The knob position is shifted out inverted MSB first on D4, the button is held on D3
+    let (_, port_two) = reads(&mut machine, &mut vaus_input(0x0a5, true));
+    assert_eq!(serial_msb(&port_two[..9], 4), !0x0a5 & 0x1ff);
+    assert!(port_two[9..].iter().all(|&r| r & 0x10 == 0));
+    assert!(port_two.iter().all(|&r| r & 0x08 == 0x08));
+
+    let (_, port_two) = next_reads(&mut machine, &mut vaus_input(0x1ff, false));
+    assert!(port_two.iter().all(|&r| r & 0x18 == 0));
+}
+
+#[test]
+fn famicom_vaus_report() {
+    let mut machine = machine();
+    machine.set_port_device(Port::Expansion, PortDeviceKind::Vaus);
+
+    // The Famicom version uses D1, the button on $4016 and the knob on $4017
+    let (port_one, port_two) = reads(&mut machine, &mut vaus_input(0x13c, true));
+    assert_eq!(serial_msb(&port_two[..9], 1), !0x13c & 0x1ff);
+    assert!(port_one.iter().all(|&r| r & 0x02 == 0x02));
+
+    let (port_one, _) = next_reads(&mut machine, &mut vaus_input(0x13c, false));
+    assert!(port_one.iter().all(|&r| r & 0x02 == 0));
+}
+
+fn power_pad_input(buttons: &[u8]) -> SimpleInput {
+    let mut power_pad = PowerPad::new();
+    for &button in buttons {
+        power_pad.set(button, true);
+    }
+    let mut input = SimpleInput::new();
+    input.handle_input(UserInput::PowerPad(power_pad));
+    input
+}
+
+#[test]
+fn power_pad_report() {
+    let mut machine = machine();
+    machine.set_port_device(Port::Two, PortDeviceKind::PowerPad);
+
+    // D3 shifts out buttons 2, 1, 5, 9, 6, 10, 11, 7 and D4 buttons 4, 3, 12, 8, then 1s
+    let (_, port_two) = reads(&mut machine, &mut power_pad_input(&[1, 3, 9, 12]));
+    assert_eq!(serial(&port_two[..16], 3), 0xff0a);
+    assert_eq!(serial(&port_two[..16], 4), 0xfff6);
+    assert!(port_two[16..].iter().all(|&r| r & 0x18 == 0x18));
+}
+
+#[test]
+fn family_trainer_report() {
+    let mut machine = machine();
+    machine.set_port_device(Port::Expansion, PortDeviceKind::FamilyTrainer);
+
+    // With every row selected the pressed columns of all rows read low on D1-D4
+    let (_, port_two) = reads(&mut machine, &mut power_pad_input(&[2, 7]));
+    assert!(port_two.iter().all(|&r| r & 0x1e == 0x12));
+
+    let (_, port_two) = next_reads(&mut machine, &mut power_pad_input(&[1, 12]));
+    assert!(port_two.iter().all(|&r| r & 0x1e == 0x0c));
+}
+
+#[test]
+fn microphone_report() {
+    let mut machine = machine();
+    let mut input = SimpleInput::new();
+    input.handle_input(UserInput::PlayerTwo(controller(0x81)));
+    input.handle_input(UserInput::Microphone(true));
+
+    // The player two microphone is heard on $4016 D2, the controller is still read on $4017
+    let (port_one, port_two) = reads(&mut machine, &mut input);
+    assert!(port_one.iter().all(|&r| r & 0x04 == 0x04));
+    assert!(port_two.iter().all(|&r| r & 0x04 == 0));
+    assert_eq!(serial(&port_two[..8], 0), 0x81);
+
+    input.handle_input(UserInput::Microphone(false));
+    let (port_one, _) = next_reads(&mut machine, &mut input);
+    assert!(port_one.iter().all(|&r| r & 0x04 == 0));
+
+    // Only the Famicom player two controller has a microphone
+    machine.set_port_device(Port::Two, PortDeviceKind::Unplugged);
+    input.handle_input(UserInput::Microphone(true));
+    let (port_one, _) = next_reads(&mut machine, &mut input);
+    assert!(port_one.iter().all(|&r| r & 0x04 == 0));
+}
+
+fn mouse_input(x: i32, y: i32, left: bool, right: bool) -> SimpleInput {
+    let mut input = SimpleInput::new();
+    input.handle_input(UserInput::Mouse(Mouse { x, y, left, right }));
+    input
+}
+
+#[test]
+fn snes_mouse_report() {
+    let mut machine = machine();
+    machine.set_port_device(Port::One, PortDeviceKind::SnesMouse);
+    reads(&mut machine, &mut mouse_input(100, 100, false, false));
+
+    // Buttons, sensitivity and the 0001 signature, then Y and X as sign and magnitude
+    let (port_one, _) = next_reads(&mut machine, &mut mouse_input(105, 97, true, false));
+    assert_eq!(serial_msb(&port_one[..32], 0), 0x0041_8305);
+    assert!(port_one[32..].iter().all(|&r| r & 1 == 1));
+
+    // Motion is reported once
+    let (port_one, _) = next_reads(&mut machine, &mut mouse_input(105, 97, false, true));
+    assert_eq!(serial_msb(&port_one[..32], 0), 0x0081_0000);
+}
+
+#[test]
+fn snes_mouse_sensitivity() {
+    let mut machine = strobing_machine(1);
+    machine.set_port_device(Port::One, PortDeviceKind::SnesMouse);
+    let mut input = mouse_input(0, 0, false, false);
+
+    // Each read made while OUT0 is high selects the next of the three sensitivities
+    let (port_one, _) = reads(&mut machine, &mut input);
+    let mut sensitivity = serial_msb(&port_one[..32], 0) >> 20 & 3;
+    for _ in 0..4 {
+        let (port_one, _) = next_reads(&mut machine, &mut input);
+        let report = serial_msb(&port_one[..32], 0);
+        assert_eq!(report & 0xffcf_ffff, 0x0001_0000);
+        assert_eq!(report >> 20 & 3, (sensitivity + 1) % 3);
+        sensitivity = report >> 20 & 3;
+    }
+}
+
+#[test]
+fn subor_mouse_report() {
+    let mut machine = machine();
+    machine.set_port_device(Port::One, PortDeviceKind::SuborMouse);
+    reads(&mut machine, &mut mouse_input(0, 0, false, false));
+
+    // Each latch shifts out one byte of the packet MSB first, followed by 1s. Moving more
+    // than a step sends the header with bit 0 set then the X and Y motion
+    let mut input = mouse_input(5, -3, false, true);
+    for byte in [0x5d, 0x05, 0x23, 0x40] {
+        let (port_one, _) = next_reads(&mut machine, &mut input);
+        assert_eq!(serial_msb(&port_one[..8], 0), byte);
+        assert!(port_one[8..].iter().all(|&r| r & 1 == 1));
+    }
+}
+
+#[test]
+fn lag_frames() {
+    let mut machine = machine();
+    let mut input = SimpleInput::new();
+
+    // The frames spent waiting for the PPU to warm up never read the ports
+    reads(&mut machine, &mut input);
+    let lag_count = machine.lag_count();
+    assert!(lag_count > 0);
+    for _ in 0..3 {
+        machine.run(&mut input);
+        assert!(!machine.lag_frame());
+    }
+    assert_eq!(machine.lag_count(), lag_count);
+
+    // After a reset the NMI is disabled until the PPU has warmed up again
+    input.handle_input(UserInput::Reset);
+    machine.run(&mut input);
+    assert!(machine.lag_frame());
+    assert_eq!(machine.lag_count(), lag_count + 1);
+
+    machine.reset_lag_count();
+    assert_eq!(machine.lag_count(), 0);
+}
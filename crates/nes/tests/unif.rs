@@ -0,0 +1,66 @@
+use nes::{CartMirroring, Cartridge};
+
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    chunk
+}
+
+fn unif_rom(chunks: &[Vec<u8>]) -> Vec<u8> {
+    unif_board_rom(b"NES-NROM-128\0", chunks)
+}
+
+fn unif_board_rom(board: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut rom = vec![0; 32];
+    rom[0..4].copy_from_slice(b"UNIF");
+    rom.extend(chunk(b"MAPR", board));
+    rom.extend(chunk(b"PRG0", &[0; 0x4000]));
+    rom.extend(chunk(b"CHR0", &[0; 0x2000]));
+    for chunk in chunks {
+        rom.extend_from_slice(chunk);
+    }
+    rom
+}
+
+fn mirroring(mirr: u8) -> CartMirroring {
+    let rom = unif_rom(&[chunk(b"MIRR", &[mirr])]);
+    match Cartridge::load(&mut rom.as_slice(), None, None, "test.unf") {
+        Ok(Cartridge::INes(cart)) => cart.mirroring,
+        _ => panic!("unif rom did not load"),
+    }
+}
+
+#[test]
+fn unif_mirroring() {
+    assert_eq!(mirroring(0), CartMirroring::Horizontal);
+    assert_eq!(mirroring(1), CartMirroring::Vertical);
+    assert_eq!(mirroring(2), CartMirroring::SingleA);
+    assert_eq!(mirroring(3), CartMirroring::SingleB);
+    assert_eq!(mirroring(5), CartMirroring::Horizontal);
+}
+
+#[test]
+fn unif_chunk_longer_than_file() {
+    let mut rom = unif_rom(&[]);
+    rom.extend_from_slice(b"PRG1");
+    rom.extend_from_slice(&u32::MAX.to_le_bytes());
+    rom.extend_from_slice(&[0; 16]);
+
+    assert!(Cartridge::load(&mut rom.as_slice(), None, None, "test.unf").is_err());
+}
+
+fn board_mapper(board: &[u8]) -> (u32, Option<u32>) {
+    let rom = unif_board_rom(board, &[]);
+    match Cartridge::load(&mut rom.as_slice(), None, None, "test.unf") {
+        Ok(Cartridge::INes(cart)) => (cart.mapper, cart.submapper),
+        _ => panic!("unif rom did not load"),
+    }
+}
+
+#[test]
+fn unif_board_mappers() {
+    assert_eq!(board_mapper(b"NES-TLROM\0"), (4, None));
+    assert_eq!(board_mapper(b"NES-HKROM\0"), (4, Some(1)));
+    assert_eq!(board_mapper(b"NES-TQROM\0"), (0, None));
+}
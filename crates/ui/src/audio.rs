@@ -8,7 +8,7 @@ use direct_ring_buffer::{Consumer, Producer, create_ring_buffer};
 #[cfg(not(target_arch = "wasm32"))]
 mod cpal;
 #[cfg(not(target_arch = "wasm32"))]
-pub use cpal::CpalAudio;
+pub use cpal::{CpalAudio, CpalMicrophone};
 #[cfg(all(not(target_arch = "wasm32"), feature = "jack"))]
 mod jack;
 #[cfg(all(not(target_arch = "wasm32"), feature = "jack"))]
@@ -1,5 +1,5 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{FromSample, OutputCallbackInfo, Sample};
+use cpal::{FromSample, InputCallbackInfo, OutputCallbackInfo, Sample, SizedSample};
 use std::sync::{
     Arc,
     atomic::{AtomicU32, Ordering},
@@ -17,7 +17,10 @@ const DEVICE_BUFFER_DEPTH: usize = 2;
 pub enum Error {
     NoDefaultOutputDevice,
     NoMatchingOutputConfig,
+    NoDefaultInputDevice,
+    NoMatchingInputConfig,
     SupportedStreamConfigsError(Box<cpal::SupportedStreamConfigsError>),
+    DefaultStreamConfigError(Box<cpal::DefaultStreamConfigError>),
     BuildStreamError(Box<cpal::BuildStreamError>),
     PlayStreamError(Box<cpal::PlayStreamError>),
 }
@@ -29,7 +32,10 @@ impl std::fmt::Display for Error {
         match self {
             Error::NoDefaultOutputDevice => write!(f, "no default audio output device"),
             Error::NoMatchingOutputConfig => write!(f, "no valid output device format"),
+            Error::NoDefaultInputDevice => write!(f, "no default audio input device"),
+            Error::NoMatchingInputConfig => write!(f, "no valid input device format"),
             Error::SupportedStreamConfigsError(e) => e.fmt(f),
+            Error::DefaultStreamConfigError(e) => e.fmt(f),
             Error::BuildStreamError(e) => e.fmt(f),
             Error::PlayStreamError(e) => e.fmt(f),
         }
@@ -42,6 +48,12 @@ impl From<cpal::SupportedStreamConfigsError> for Error {
     }
 }
 
+impl From<cpal::DefaultStreamConfigError> for Error {
+    fn from(value: cpal::DefaultStreamConfigError) -> Self {
+        Self::DefaultStreamConfigError(Box::new(value))
+    }
+}
+
 impl From<cpal::BuildStreamError> for Error {
     fn from(value: cpal::BuildStreamError) -> Self {
         Self::BuildStreamError(Box::new(value))
@@ -273,3 +285,87 @@ impl Audio for CpalAudio {
         self.volume.store(new_volume, Ordering::Relaxed);
     }
 }
+
+/// Listens to the default input device, used as the Famicom controller II microphone
+pub struct CpalMicrophone {
+    _host: cpal::Host,
+    _device: cpal::Device,
+    _stream: cpal::Stream,
+    level: Arc<AtomicU32>,
+}
+
+impl CpalMicrophone {
+    pub fn new() -> Result<CpalMicrophone, Error> {
+        let host = cpal::platform::default_host();
+        let device = host
+            .default_input_device()
+            .ok_or(Error::NoDefaultInputDevice)?;
+        let config = device.default_input_config()?;
+        let format = config.config();
+
+        tracing::debug!(
+            "{:?}: microphone {} channel(s), {} sample rate, {} format",
+            host.id(),
+            format.channels,
+            format.sample_rate.0,
+            config.sample_format(),
+        );
+
+        let host_id = host.id();
+        let level = Arc::new(AtomicU32::new(0.0f32.to_bits()));
+        let err_handler = move |err| tracing::error!("{:?}: {:?}", host_id, err);
+
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::I16 => device.build_input_stream(
+                &format,
+                input_callback::<i16>(level.clone()),
+                err_handler,
+                None,
+            )?,
+            cpal::SampleFormat::U16 => device.build_input_stream(
+                &format,
+                input_callback::<u16>(level.clone()),
+                err_handler,
+                None,
+            )?,
+            cpal::SampleFormat::F32 => device.build_input_stream(
+                &format,
+                input_callback::<f32>(level.clone()),
+                err_handler,
+                None,
+            )?,
+            _ => return Err(Error::NoMatchingInputConfig),
+        };
+        stream.play()?;
+
+        Ok(CpalMicrophone {
+            _host: host,
+            _device: device,
+            _stream: stream,
+            level,
+        })
+    }
+
+    /// Loudest sample heard since the last call, from 0.0 to 1.0
+    pub fn take_level(&self) -> f32 {
+        f32::from_bits(self.level.swap(0.0f32.to_bits(), Ordering::Relaxed))
+    }
+}
+
+fn input_callback<S: SizedSample>(
+    level: Arc<AtomicU32>,
+) -> impl FnMut(&[S], &InputCallbackInfo) + Send + 'static
+where
+    f32: FromSample<S>,
+{
+    move |buffer: &[S], _| {
+        let peak = buffer
+            .iter()
+            .map(|s| s.to_sample::<f32>().abs())
+            .fold(0.0, f32::max);
+
+        let _ = level.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            (peak > f32::from_bits(current)).then_some(peak.to_bits())
+        });
+    }
+}
@@ -122,3 +122,44 @@ pub enum GamepadEvent {
         gamepad_id: gilrs::GamepadId,
     },
 }
+
+impl GamepadEvent {
+    pub fn gamepad_id(&self) -> gilrs::GamepadId {
+        match *self {
+            GamepadEvent::Button { gamepad_id, .. }
+            | GamepadEvent::Axis { gamepad_id, .. }
+            | GamepadEvent::Connected { gamepad_id }
+            | GamepadEvent::Disconnected { gamepad_id } => gamepad_id,
+        }
+    }
+}
+
+/// Assigns gamepads to players 1-4, each gamepad takes the lowest free player when it is
+/// first used and keeps it until it disconnects
+pub struct GamepadPlayers {
+    players: [Option<gilrs::GamepadId>; 4],
+}
+
+impl GamepadPlayers {
+    pub fn new() -> Self {
+        Self { players: [None; 4] }
+    }
+
+    /// The player index controlled by `gamepad_id`, `None` when all four players are taken
+    pub fn player(&mut self, gamepad_id: gilrs::GamepadId) -> Option<usize> {
+        if let Some(player) = self.players.iter().position(|&p| p == Some(gamepad_id)) {
+            return Some(player);
+        }
+
+        let player = self.players.iter().position(|p| p.is_none())?;
+        self.players[player] = Some(gamepad_id);
+        Some(player)
+    }
+
+    /// Frees the player used by `gamepad_id`, returning its index
+    pub fn disconnect(&mut self, gamepad_id: gilrs::GamepadId) -> Option<usize> {
+        let player = self.players.iter().position(|&p| p == Some(gamepad_id))?;
+        self.players[player] = None;
+        Some(player)
+    }
+}
@@ -1,28 +1,324 @@
 use gilrs::{Axis, Button};
+use winit::event::MouseButton;
 use winit::keyboard::KeyCode;
 
-use nes::Controller;
+use nes::{Controller, FamilyKey, FamilyKeyboard, Mouse, PowerPad, PowerPadSide, Vaus};
 
 use std::collections::HashMap;
+use std::sync::Arc;
+
+/// How an analog axis or the mouse moves the Vaus paddle
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PaddleSettings {
+    /// Multiplier applied to the axis or mouse movement away from the center
+    pub sensitivity: f32,
+    /// Position reported at the far left of the axis or screen
+    pub min: u16,
+    /// Position reported at the far right of the axis or screen
+    pub max: u16,
+}
+
+impl Default for PaddleSettings {
+    fn default() -> Self {
+        // Arkanoid reads the top 8 bits, where the paddle travels between $62 and $f2
+        Self {
+            sensitivity: 1.0,
+            min: 0x0c4,
+            max: 0x1e4,
+        }
+    }
+}
+
+/// Host keys standing in for the Power Pad, laid out as the mat is seen by the player
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PowerPadSettings {
+    pub side: PowerPadSide,
+    /// Rows of four keys from the top left of the mat
+    pub keys: [KeyCode; 12],
+}
+
+impl PowerPadSettings {
+    /// Parses a block of keys from the 12 characters they type, such as `"rtyufghjvbnm"`
+    pub fn parse_keys(keys: &str) -> Option<[KeyCode; 12]> {
+        let keys: Vec<_> = keys.chars().map(key_from_char).collect::<Option<_>>()?;
+        keys.try_into().ok()
+    }
+}
+
+impl Default for PowerPadSettings {
+    fn default() -> Self {
+        Self {
+            side: PowerPadSide::B,
+            keys: [
+                KeyCode::KeyR,
+                KeyCode::KeyT,
+                KeyCode::KeyY,
+                KeyCode::KeyU,
+                KeyCode::KeyF,
+                KeyCode::KeyG,
+                KeyCode::KeyH,
+                KeyCode::KeyJ,
+                KeyCode::KeyV,
+                KeyCode::KeyB,
+                KeyCode::KeyN,
+                KeyCode::KeyM,
+            ],
+        }
+    }
+}
+
+/// Keys that hold the A and B buttons down and release them repeatedly
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TurboSettings {
+    /// Number of emulated frames the buttons spend pressed and then released
+    pub rate: u32,
+    /// Unbound by default, as any key could already be in use by the controllers or keyboard
+    pub a: Option<KeyCode>,
+    pub b: Option<KeyCode>,
+}
+
+impl TurboSettings {
+    /// Parses the turbo A and B keys from the 2 characters they type, such as `"as"`
+    pub fn parse_keys(keys: &str) -> Option<[KeyCode; 2]> {
+        let keys: Vec<_> = keys.chars().map(key_from_char).collect::<Option<_>>()?;
+        keys.try_into().ok()
+    }
+}
+
+impl Default for TurboSettings {
+    fn default() -> Self {
+        Self {
+            rate: 2,
+            a: None,
+            b: None,
+        }
+    }
+}
+
+/// A named sequence of controller states played one per emulated frame when its key is pressed
+#[derive(Debug, Clone)]
+pub struct InputMacro {
+    pub name: String,
+    pub key: KeyCode,
+    pub frames: Arc<[Controller]>,
+}
+
+impl InputMacro {
+    pub fn new(name: impl Into<String>, key: KeyCode, frames: Vec<Controller>) -> Self {
+        Self {
+            name: name.into(),
+            key,
+            frames: frames.into(),
+        }
+    }
+
+    /// Parses a macro from `name=key:sequence`, see [`InputMacro::parse_sequence`]
+    pub fn parse(value: &str) -> Option<Self> {
+        let (name, binding) = value.split_once('=')?;
+        let (key, sequence) = binding.split_once(':')?;
+        let mut chars = key.trim().chars();
+        let key = match (chars.next(), chars.next()) {
+            (Some(c), None) => key_from_char(c)?,
+            _ => return None,
+        };
+
+        Some(Self::new(name.trim(), key, Self::parse_sequence(sequence)?))
+    }
+
+    /// Parses comma separated steps of buttons joined by `+`, each held for one frame or the
+    /// count after a `*`. `-` is a step with nothing pressed, `down+b*3, -*2, a` presses down
+    /// and B for 3 frames, waits 2 frames then presses A
+    pub fn parse_sequence(sequence: &str) -> Option<Vec<Controller>> {
+        let mut frames = Vec::new();
+        for step in sequence.split(',') {
+            let (buttons, count) = match step.split_once('*') {
+                Some((buttons, count)) => (buttons, count.trim().parse().ok()?),
+                None => (step, 1),
+            };
+
+            let mut controller = Controller::new();
+            for button in buttons.split('+').map(str::trim) {
+                match button.to_ascii_lowercase().as_str() {
+                    "a" => controller.a = true,
+                    "b" => controller.b = true,
+                    "select" => controller.select = true,
+                    "start" => controller.start = true,
+                    "up" => controller.up = true,
+                    "down" => controller.down = true,
+                    "left" => controller.left = true,
+                    "right" => controller.right = true,
+                    "-" => (),
+                    _ => return None,
+                }
+            }
+
+            frames.extend(std::iter::repeat_n(controller, count));
+        }
+
+        (!frames.is_empty()).then_some(frames)
+    }
+}
+
+/// A player's controller before turbo and macros are applied, sent to the emulator and
+/// resolved by a [`PadResolver`] so the result only depends on the emulated frame
+#[derive(Debug, Clone, Default)]
+pub struct PadInput {
+    pub held: Controller,
+    pub turbo: Controller,
+    pub turbo_rate: u32,
+    /// Each macro with a count that increases every time its key is pressed
+    pub macros: Vec<(Arc<[Controller]>, u32)>,
+}
+
+#[derive(Default)]
+struct PadMacros {
+    presses: Vec<u32>,
+    running: Vec<(Arc<[Controller]>, u32)>,
+}
+
+/// Applies the turbo buttons and macros of every player to an input source, runners call it
+/// with the emulated frame counter before each step
+#[derive(Default)]
+pub struct PadResolver {
+    pads: [PadInput; 4],
+    macros: [PadMacros; 4],
+}
+
+impl PadResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_pad(&mut self, player: usize, pad: PadInput) {
+        if let Some(slot) = self.pads.get_mut(player) {
+            *slot = pad;
+        }
+    }
+
+    pub fn apply(&mut self, frame: u32, input: &mut nes::SimpleInput) {
+        for (player, (pad, macros)) in self.pads.iter().zip(self.macros.iter_mut()).enumerate() {
+            let controller = Self::resolve(pad, macros, frame);
+            input.handle_input(nes::UserInput::Player(player, controller));
+        }
+    }
+
+    fn resolve(pad: &PadInput, macros: &mut PadMacros, frame: u32) -> Controller {
+        let mut controller = pad.held;
+        let rate = pad.turbo_rate.max(1);
+        if (frame / rate) % 2 == 0 {
+            controller = merge_controllers(controller, pad.turbo);
+        }
+
+        // Macros start on the first frame their press is seen and play through to the end
+        macros.presses.resize(pad.macros.len(), 0);
+        for ((frames, presses), seen) in pad.macros.iter().zip(macros.presses.iter_mut()) {
+            if *presses > *seen {
+                *seen = *presses;
+                macros.running.push((frames.clone(), frame));
+            }
+        }
+
+        macros.running.retain(|(frames, start)| {
+            let step = frame.wrapping_sub(*start) as usize;
+            if let Some(&step) = frames.get(step) {
+                controller = merge_controllers(controller, step);
+                true
+            } else {
+                false
+            }
+        });
+
+        controller
+    }
+}
+
+fn merge_controllers(a: Controller, b: Controller) -> Controller {
+    Controller {
+        a: a.a || b.a,
+        b: a.b || b.b,
+        select: a.select || b.select,
+        start: a.start || b.start,
+        up: a.up || b.up,
+        down: a.down || b.down,
+        left: a.left || b.left,
+        right: a.right || b.right,
+    }
+}
 
 pub struct InputMap {
     map: HashMap<InputType, bool>,
+    paddle: f32,
+    paddle_settings: PaddleSettings,
+    power_pad_settings: PowerPadSettings,
+    microphone_level: f32,
+    microphone_threshold: f32,
+    mouse: (f64, f64),
+    turbo_settings: TurboSettings,
+    macros: Vec<(InputMacro, u32)>,
+    macro_presses: u32,
 }
 
 impl InputMap {
     pub fn new() -> Self {
         Self {
             map: HashMap::new(),
+            paddle: 0.5,
+            paddle_settings: PaddleSettings::default(),
+            power_pad_settings: PowerPadSettings::default(),
+            microphone_level: 0.0,
+            microphone_threshold: 0.1,
+            mouse: (0.0, 0.0),
+            turbo_settings: TurboSettings::default(),
+            macros: Vec::new(),
+            macro_presses: 0,
         }
     }
 
+    pub fn set_paddle_settings(&mut self, settings: PaddleSettings) {
+        self.paddle_settings = settings;
+    }
+
+    pub fn set_power_pad_settings(&mut self, settings: PowerPadSettings) {
+        self.power_pad_settings = settings;
+    }
+
+    /// Level from 0.0 to 1.0 the microphone must reach to be heard by the game
+    pub fn set_microphone_threshold(&mut self, threshold: f32) {
+        self.microphone_threshold = threshold;
+    }
+
+    /// Feeds the loudest level heard by the host's audio input since the last frame
+    pub fn microphone_level(&mut self, level: f32) {
+        self.microphone_level = level;
+    }
+
     pub fn is_pressed(&self, key: impl Into<InputType>) -> bool {
         self.map.get(&key.into()).cloned().unwrap_or(false)
     }
 
+    pub fn set_turbo_settings(&mut self, settings: TurboSettings) {
+        self.turbo_settings = settings;
+    }
+
+    pub fn set_macros(&mut self, macros: Vec<InputMacro>) {
+        self.macros = macros.into_iter().map(|m| (m, 0)).collect();
+    }
+
     pub fn press(&mut self, key: impl Into<InputType>) {
+        let key = key.into();
+        if !self.is_pressed(key) {
+            for (_, presses) in self
+                .macros
+                .iter_mut()
+                .filter(|(m, _)| InputType::from(m.key) == key)
+            {
+                self.macro_presses += 1;
+                *presses = self.macro_presses;
+            }
+        }
+
         self.map
-            .entry(key.into())
+            .entry(key)
             .and_modify(|e| *e = true)
             .or_insert(true);
     }
@@ -63,10 +359,47 @@ impl InputMap {
                 self.release(Button::DPadUp);
                 self.release(Button::DPadDown);
             }
+            Axis::RightStickX => {
+                let sensitivity = self.paddle_settings.sensitivity;
+                self.paddle = ((value * sensitivity + 1.0) / 2.0).clamp(0.0, 1.0);
+            }
             _ => (),
         };
     }
 
+    /// Moves the paddle to the mouse, `x` is the position across the screen from 0.0 to 1.0
+    pub fn mouse_position(&mut self, x: f32) {
+        let sensitivity = self.paddle_settings.sensitivity;
+        self.paddle = (0.5 + (x - 0.5) * sensitivity).clamp(0.0, 1.0);
+    }
+
+    /// Adds relative mouse motion, in the units reported by the host's raw mouse events
+    pub fn mouse_motion(&mut self, dx: f64, dy: f64) {
+        self.mouse.0 += dx;
+        self.mouse.1 += dy;
+    }
+
+    pub fn mouse(&self) -> Mouse {
+        Mouse {
+            x: self.mouse.0 as i64 as i32,
+            y: self.mouse.1 as i64 as i32,
+            left: self.is_pressed(MouseButton::Left),
+            right: self.is_pressed(MouseButton::Right),
+        }
+    }
+
+    pub fn vaus(&self) -> Vaus {
+        let PaddleSettings { min, max, .. } = self.paddle_settings;
+        let position = min as f32 + (max as f32 - min as f32) * self.paddle;
+
+        Vaus {
+            position: position.round().clamp(0.0, 511.0) as u16,
+            button: self.is_pressed(MouseButton::Left)
+                || self.is_pressed(KeyCode::KeyZ)
+                || self.is_pressed(Button::South),
+        }
+    }
+
     pub fn controller(&self) -> Controller {
         Controller {
             a: self.is_pressed(KeyCode::KeyZ) || self.is_pressed(Button::South),
@@ -81,6 +414,135 @@ impl InputMap {
         }
     }
 
+    pub fn pad(&self) -> PadInput {
+        let TurboSettings { rate, a, b } = self.turbo_settings;
+
+        PadInput {
+            held: self.controller(),
+            turbo: Controller {
+                a: a.is_some_and(|a| self.is_pressed(a)),
+                b: b.is_some_and(|b| self.is_pressed(b)),
+                ..Controller::new()
+            },
+            turbo_rate: rate,
+            macros: self
+                .macros
+                .iter()
+                .map(|(m, presses)| (m.frames.clone(), *presses))
+                .collect(),
+        }
+    }
+
+    pub fn power_pad(&self) -> PowerPad {
+        let PowerPadSettings { side, keys } = self.power_pad_settings;
+        let mut power_pad = PowerPad::new();
+        for (idx, &key) in keys.iter().enumerate() {
+            if let Some(button) = side.button(idx / 4, idx % 4) {
+                if self.is_pressed(key) {
+                    power_pad.set(button, true);
+                }
+            }
+        }
+
+        power_pad
+    }
+
+    pub fn microphone(&self) -> bool {
+        self.is_pressed(KeyCode::KeyC)
+            || self.is_pressed(Button::North)
+            || self.microphone_level > self.microphone_threshold
+    }
+
+    pub fn family_keyboard(&self) -> FamilyKeyboard {
+        const MAP: &[(KeyCode, FamilyKey)] = &[
+            (KeyCode::F1, FamilyKey::F1),
+            (KeyCode::F2, FamilyKey::F2),
+            (KeyCode::F3, FamilyKey::F3),
+            (KeyCode::F4, FamilyKey::F4),
+            (KeyCode::F5, FamilyKey::F5),
+            (KeyCode::F6, FamilyKey::F6),
+            (KeyCode::F7, FamilyKey::F7),
+            (KeyCode::F8, FamilyKey::F8),
+            (KeyCode::Digit1, FamilyKey::Num1),
+            (KeyCode::Digit2, FamilyKey::Num2),
+            (KeyCode::Digit3, FamilyKey::Num3),
+            (KeyCode::Digit4, FamilyKey::Num4),
+            (KeyCode::Digit5, FamilyKey::Num5),
+            (KeyCode::Digit6, FamilyKey::Num6),
+            (KeyCode::Digit7, FamilyKey::Num7),
+            (KeyCode::Digit8, FamilyKey::Num8),
+            (KeyCode::Digit9, FamilyKey::Num9),
+            (KeyCode::Digit0, FamilyKey::Num0),
+            (KeyCode::Minus, FamilyKey::Minus),
+            (KeyCode::Equal, FamilyKey::Caret),
+            (KeyCode::IntlYen, FamilyKey::Yen),
+            (KeyCode::Backslash, FamilyKey::Yen),
+            (KeyCode::Pause, FamilyKey::Stop),
+            (KeyCode::End, FamilyKey::Stop),
+            (KeyCode::Escape, FamilyKey::Escape),
+            (KeyCode::KeyQ, FamilyKey::Q),
+            (KeyCode::KeyW, FamilyKey::W),
+            (KeyCode::KeyE, FamilyKey::E),
+            (KeyCode::KeyR, FamilyKey::R),
+            (KeyCode::KeyT, FamilyKey::T),
+            (KeyCode::KeyY, FamilyKey::Y),
+            (KeyCode::KeyU, FamilyKey::U),
+            (KeyCode::KeyI, FamilyKey::I),
+            (KeyCode::KeyO, FamilyKey::O),
+            (KeyCode::KeyP, FamilyKey::P),
+            (KeyCode::Backquote, FamilyKey::At),
+            (KeyCode::BracketLeft, FamilyKey::LeftBracket),
+            (KeyCode::Enter, FamilyKey::Return),
+            (KeyCode::ControlLeft, FamilyKey::Ctr),
+            (KeyCode::ControlRight, FamilyKey::Ctr),
+            (KeyCode::KeyA, FamilyKey::A),
+            (KeyCode::KeyS, FamilyKey::S),
+            (KeyCode::KeyD, FamilyKey::D),
+            (KeyCode::KeyF, FamilyKey::F),
+            (KeyCode::KeyG, FamilyKey::G),
+            (KeyCode::KeyH, FamilyKey::H),
+            (KeyCode::KeyJ, FamilyKey::J),
+            (KeyCode::KeyK, FamilyKey::K),
+            (KeyCode::KeyL, FamilyKey::L),
+            (KeyCode::Semicolon, FamilyKey::Semicolon),
+            (KeyCode::Quote, FamilyKey::Colon),
+            (KeyCode::BracketRight, FamilyKey::RightBracket),
+            (KeyCode::AltRight, FamilyKey::Kana),
+            (KeyCode::ShiftLeft, FamilyKey::LeftShift),
+            (KeyCode::KeyZ, FamilyKey::Z),
+            (KeyCode::KeyX, FamilyKey::X),
+            (KeyCode::KeyC, FamilyKey::C),
+            (KeyCode::KeyV, FamilyKey::V),
+            (KeyCode::KeyB, FamilyKey::B),
+            (KeyCode::KeyN, FamilyKey::N),
+            (KeyCode::KeyM, FamilyKey::M),
+            (KeyCode::Comma, FamilyKey::Comma),
+            (KeyCode::Period, FamilyKey::Period),
+            (KeyCode::Slash, FamilyKey::Slash),
+            (KeyCode::IntlRo, FamilyKey::Underscore),
+            (KeyCode::ShiftRight, FamilyKey::RightShift),
+            (KeyCode::AltLeft, FamilyKey::Grph),
+            (KeyCode::Space, FamilyKey::Space),
+            (KeyCode::Home, FamilyKey::ClrHome),
+            (KeyCode::Insert, FamilyKey::Insert),
+            (KeyCode::Delete, FamilyKey::Delete),
+            (KeyCode::Backspace, FamilyKey::Delete),
+            (KeyCode::ArrowUp, FamilyKey::Up),
+            (KeyCode::ArrowDown, FamilyKey::Down),
+            (KeyCode::ArrowLeft, FamilyKey::Left),
+            (KeyCode::ArrowRight, FamilyKey::Right),
+        ];
+
+        let mut keyboard = FamilyKeyboard::new();
+        for &(key, family_key) in MAP.iter() {
+            if self.is_pressed(key) {
+                keyboard.set(family_key, true);
+            }
+        }
+
+        keyboard
+    }
+
     pub fn power(&self) -> bool {
         self.is_pressed(KeyCode::Delete)
     }
@@ -162,6 +624,7 @@ impl InputMap {
 pub enum InputType {
     Key(KeyCode),
     Button(Button),
+    Mouse(MouseButton),
 }
 
 impl From<KeyCode> for InputType {
@@ -176,6 +639,27 @@ impl From<Button> for InputType {
     }
 }
 
+impl From<MouseButton> for InputType {
+    fn from(value: MouseButton) -> Self {
+        InputType::Mouse(value)
+    }
+}
+
+#[cfg(feature = "egui")]
+impl From<egui::PointerButton> for InputType {
+    fn from(value: egui::PointerButton) -> Self {
+        use egui::PointerButton;
+        let b = match value {
+            PointerButton::Primary => MouseButton::Left,
+            PointerButton::Secondary => MouseButton::Right,
+            PointerButton::Middle => MouseButton::Middle,
+            PointerButton::Extra1 => MouseButton::Back,
+            PointerButton::Extra2 => MouseButton::Forward,
+        };
+        InputType::Mouse(b)
+    }
+}
+
 #[cfg(feature = "egui")]
 impl From<egui::Key> for InputType {
     fn from(value: egui::Key) -> Self {
@@ -285,3 +769,125 @@ impl From<egui::Key> for InputType {
         InputType::Key(k)
     }
 }
+
+fn key_from_char(c: char) -> Option<KeyCode> {
+    let key = match c.to_ascii_lowercase() {
+        'a' => KeyCode::KeyA,
+        'b' => KeyCode::KeyB,
+        'c' => KeyCode::KeyC,
+        'd' => KeyCode::KeyD,
+        'e' => KeyCode::KeyE,
+        'f' => KeyCode::KeyF,
+        'g' => KeyCode::KeyG,
+        'h' => KeyCode::KeyH,
+        'i' => KeyCode::KeyI,
+        'j' => KeyCode::KeyJ,
+        'k' => KeyCode::KeyK,
+        'l' => KeyCode::KeyL,
+        'm' => KeyCode::KeyM,
+        'n' => KeyCode::KeyN,
+        'o' => KeyCode::KeyO,
+        'p' => KeyCode::KeyP,
+        'q' => KeyCode::KeyQ,
+        'r' => KeyCode::KeyR,
+        's' => KeyCode::KeyS,
+        't' => KeyCode::KeyT,
+        'u' => KeyCode::KeyU,
+        'v' => KeyCode::KeyV,
+        'w' => KeyCode::KeyW,
+        'x' => KeyCode::KeyX,
+        'y' => KeyCode::KeyY,
+        'z' => KeyCode::KeyZ,
+        '0' => KeyCode::Digit0,
+        '1' => KeyCode::Digit1,
+        '2' => KeyCode::Digit2,
+        '3' => KeyCode::Digit3,
+        '4' => KeyCode::Digit4,
+        '5' => KeyCode::Digit5,
+        '6' => KeyCode::Digit6,
+        '7' => KeyCode::Digit7,
+        '8' => KeyCode::Digit8,
+        '9' => KeyCode::Digit9,
+        '-' => KeyCode::Minus,
+        '=' => KeyCode::Equal,
+        '[' => KeyCode::BracketLeft,
+        ']' => KeyCode::BracketRight,
+        ';' => KeyCode::Semicolon,
+        '\'' => KeyCode::Quote,
+        ',' => KeyCode::Comma,
+        '.' => KeyCode::Period,
+        '/' => KeyCode::Slash,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_turbo_and_macros() {
+        let right = Controller {
+            right: true,
+            ..Controller::new()
+        };
+        let left = Controller {
+            left: true,
+            ..Controller::new()
+        };
+        let mut pad = PadInput {
+            held: Controller {
+                b: true,
+                ..Controller::new()
+            },
+            turbo: Controller {
+                a: true,
+                ..Controller::new()
+            },
+            turbo_rate: 2,
+            macros: vec![(Arc::from([right, left, Controller::new()]), 0)],
+        };
+        let mut macros = PadMacros::default();
+
+        // Turbo spends `turbo_rate` frames pressed and then as many released
+        let turbo: Vec<_> = (0..6)
+            .map(|frame| PadResolver::resolve(&pad, &mut macros, frame).a)
+            .collect();
+        assert_eq!(turbo, [true, true, false, false, true, true]);
+        assert!(PadResolver::resolve(&pad, &mut macros, 6).b);
+
+        // The macro starts on the frame its press is first seen, not the frame counter
+        pad.macros[0].1 = 1;
+        let steps: Vec<_> = (10..14)
+            .map(|frame| {
+                let controller = PadResolver::resolve(&pad, &mut macros, frame);
+                (controller.right, controller.left)
+            })
+            .collect();
+        assert_eq!(
+            steps,
+            [(true, false), (false, true), (false, false), (false, false)]
+        );
+
+        // Pressing again restarts it
+        pad.macros[0].1 = 2;
+        assert!(PadResolver::resolve(&pad, &mut macros, 20).right);
+    }
+
+    #[test]
+    fn turbo_keys_unbound_by_default() {
+        let mut input = InputMap::new();
+        input.map.insert(KeyCode::KeyA.into(), true);
+        input.map.insert(KeyCode::KeyS.into(), true);
+        let pad = input.pad();
+        assert!(!pad.turbo.a && !pad.turbo.b);
+
+        input.set_turbo_settings(TurboSettings {
+            a: Some(KeyCode::KeyA),
+            ..TurboSettings::default()
+        });
+        assert!(input.pad().turbo.a);
+    }
+}
@@ -3,5 +3,11 @@ pub mod filters;
 pub mod gamepad;
 pub mod input;
 pub mod movie;
+#[cfg(feature = "save-states")]
+pub mod netplay;
+pub mod overlay;
+#[cfg(feature = "save-states")]
+pub mod run_ahead;
+pub mod wav_reader;
 pub mod wav_writer;
 pub mod wram;
@@ -1,6 +1,9 @@
-use nes::{Controller, UserInput};
+use nes::{Controller, FdsInput, InputSource, InputState, MapperInput, Region, UserInput};
+use sha2::{Digest, Sha256};
 use std::collections::VecDeque;
-use std::io::{BufRead, BufReader, Read, Result as IoResult, Seek};
+use std::io::{BufRead, BufReader, Read, Result as IoResult, Seek, Write};
+
+use crate::wram::CartridgeId;
 
 #[derive(Debug, Clone)]
 pub enum MovieInput {
@@ -19,10 +22,10 @@ pub enum SubframeMode {
 pub struct MovieFile {
     subframe: bool,
     inputs: VecDeque<MovieInput>,
-    player_one: Controller,
-    player_two: Controller,
+    state: InputState,
     reset: bool,
     power: bool,
+    mapper: Option<MapperInput>,
 }
 
 impl MovieFile {
@@ -30,10 +33,10 @@ impl MovieFile {
         Self {
             subframe,
             inputs,
-            player_one: Controller::default(),
-            player_two: Controller::default(),
+            state: InputState::default(),
             reset: false,
             power: false,
+            mapper: None,
         }
     }
 
@@ -70,17 +73,32 @@ impl MovieFile {
         Ok(MovieFile::new(r08.inputs, subframe))
     }
 
+    fn apply(&mut self, input: UserInput) {
+        match input {
+            UserInput::PlayerOne(controller) => self.state.controllers[0] = controller,
+            UserInput::PlayerTwo(controller) => self.state.controllers[1] = controller,
+            UserInput::Player(player, controller) => {
+                if let Some(slot) = self.state.controllers.get_mut(player) {
+                    *slot = controller;
+                }
+            }
+            UserInput::Zapper(zapper) => self.state.zapper = zapper,
+            UserInput::Vaus(vaus) => self.state.vaus = vaus,
+            UserInput::Keyboard(keyboard) => self.state.keyboard = keyboard,
+            UserInput::PowerPad(power_pad) => self.state.power_pad = power_pad,
+            UserInput::Microphone(microphone) => self.state.microphone = microphone,
+            UserInput::Mouse(mouse) => self.state.mouse = mouse,
+            UserInput::Mapper(input) => self.mapper = Some(input),
+            UserInput::Power => self.power = true,
+            UserInput::Reset => self.reset = true,
+        }
+    }
+
     pub fn prepare_frame(&mut self) {
         if !self.subframe {
             while let Some(input) = self.inputs.pop_front() {
                 match input {
-                    MovieInput::Input(input) => match input {
-                        UserInput::PlayerOne(controller) => self.player_one = controller,
-                        UserInput::PlayerTwo(controller) => self.player_two = controller,
-                        UserInput::Mapper(_) => (),
-                        UserInput::Power => self.power = true,
-                        UserInput::Reset => self.reset = true,
-                    },
+                    MovieInput::Input(input) => self.apply(input),
                     MovieInput::Frame => break,
                 }
             }
@@ -93,26 +111,20 @@ impl MovieFile {
 }
 
 impl nes::InputSource for MovieFile {
-    fn strobe(&mut self) -> (Controller, Controller) {
+    fn strobe(&mut self) -> InputState {
         if self.subframe {
             while let Some(input) = self.inputs.pop_front() {
                 match input {
-                    MovieInput::Input(input) => match input {
-                        UserInput::PlayerOne(controller) => self.player_one = controller,
-                        UserInput::PlayerTwo(controller) => self.player_two = controller,
-                        UserInput::Mapper(_) => (),
-                        UserInput::Power => self.power = true,
-                        UserInput::Reset => self.reset = true,
-                    },
+                    MovieInput::Input(input) => self.apply(input),
                     MovieInput::Frame => break,
                 }
             }
         }
-        (self.player_one, self.player_two)
+        self.state
     }
 
-    fn peek(&self) -> (Controller, Controller) {
-        (self.player_one, self.player_two)
+    fn peek(&self) -> InputState {
+        self.state
     }
 
     fn power(&mut self) -> bool {
@@ -128,7 +140,7 @@ impl nes::InputSource for MovieFile {
     }
 
     fn mapper(&mut self) -> Option<nes::MapperInput> {
-        None
+        self.mapper.take()
     }
 }
 
@@ -153,8 +165,15 @@ impl Fm2Input {
             offset -= 1;
         }
 
+        let mut four_score = false;
+
         for line in buf_reader.split(b'\n') {
             let line = line?;
+            if let Some(value) = line.strip_prefix(b"fourscore ") {
+                four_score = value.first() == Some(&b'1');
+                continue;
+            }
+
             if line.get(0).copied() != Some(b'|') {
                 continue;
             }
@@ -173,12 +192,10 @@ impl Fm2Input {
             else {
                 continue;
             };
-            let Some(port0) = splits.next() else {
-                continue;
-            };
-            let Some(port1) = splits.next() else {
-                continue;
-            };
+
+            // Four score movies log all four gamepads ahead of the expansion port
+            let players = if four_score { 4 } else { 2 };
+            let ports: Vec<_> = splits.by_ref().take(players).collect();
             let Some(_exp) = splits.next() else {
                 continue;
             };
@@ -192,22 +209,10 @@ impl Fm2Input {
 
             let is_pressed = |c| c != b'.' && c != b' ';
 
-            if port0.len() == 8 {
-                let port = port0;
-                let controller = Controller {
-                    right: is_pressed(port[0]),
-                    left: is_pressed(port[1]),
-                    down: is_pressed(port[2]),
-                    up: is_pressed(port[3]),
-                    start: is_pressed(port[4]),
-                    select: is_pressed(port[5]),
-                    b: is_pressed(port[6]),
-                    a: is_pressed(port[7]),
-                };
-                inputs.push_back(MovieInput::Input(UserInput::PlayerOne(controller)));
-            }
-            if port1.len() == 8 {
-                let port = port1;
+            for (player, port) in ports.into_iter().enumerate() {
+                if port.len() != 8 {
+                    continue;
+                }
                 let controller = Controller {
                     right: is_pressed(port[0]),
                     left: is_pressed(port[1]),
@@ -218,7 +223,7 @@ impl Fm2Input {
                     b: is_pressed(port[6]),
                     a: is_pressed(port[7]),
                 };
-                inputs.push_back(MovieInput::Input(UserInput::PlayerTwo(controller)));
+                inputs.push_back(MovieInput::Input(UserInput::Player(player, controller)));
             }
 
             inputs.push_back(MovieInput::Frame);
@@ -233,6 +238,34 @@ pub struct Bk2Input {
     inputs: VecDeque<MovieInput>,
 }
 
+// Column order of the NesHawk input log when the movie doesn't include a LogKey
+const BK2_DEFAULT_LOG_KEY: &str = "#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|\
+    P1 Select|P1 B|P1 A|#P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|";
+
+const BK2_BUTTONS: [&str; 8] = ["Up", "Down", "Left", "Right", "Start", "Select", "B", "A"];
+
+fn bk2_log_key(key: &str) -> Vec<String> {
+    key.split('|')
+        .map(|k| k.trim().trim_start_matches('#').to_string())
+        .filter(|k| !k.is_empty())
+        .collect()
+}
+
+fn bk2_button(controller: &mut Controller, button: &str) {
+    let pressed = match button {
+        "Up" => &mut controller.up,
+        "Down" => &mut controller.down,
+        "Left" => &mut controller.left,
+        "Right" => &mut controller.right,
+        "Start" => &mut controller.start,
+        "Select" => &mut controller.select,
+        "B" => &mut controller.b,
+        "A" => &mut controller.a,
+        _ => return,
+    };
+    *pressed = true;
+}
+
 impl Bk2Input {
     pub fn read<R: Read + Seek>(reader: R, mut offset: i32) -> IoResult<Self> {
         let mut zip = zip::ZipArchive::new(reader)?;
@@ -252,10 +285,16 @@ impl Bk2Input {
             offset -= 1;
         }
 
+        let mut log_key = bk2_log_key(BK2_DEFAULT_LOG_KEY);
         let mut input = Vec::new();
 
         for line in buf_reader.split(b'\n') {
             let line = line?;
+            if let Some(key) = line.strip_prefix(b"LogKey:") {
+                log_key = bk2_log_key(&String::from_utf8_lossy(key));
+                continue;
+            }
+
             if line.get(0).copied() != Some(b'|') {
                 continue;
             }
@@ -268,50 +307,55 @@ impl Bk2Input {
             input.clear();
 
             for c in line {
-                if c != b'|' {
+                if c != b'|' && c != b'\r' {
                     input.push(c);
                 }
             }
 
-            let is_pressed = |idx| {
-                if let Some(&c) = input.get(idx) {
-                    c != b'.' && c != b' '
-                } else {
-                    false
-                }
-            };
+            let mut controllers = [None; 4];
 
-            if is_pressed(0) {
-                inputs.push_back(MovieInput::Input(UserInput::Power));
-            }
-            if is_pressed(1) {
-                inputs.push_back(MovieInput::Input(UserInput::Power));
-            }
+            for (key, &c) in log_key.iter().zip(input.iter()) {
+                let pressed = c != b'.' && c != b' ';
 
-            let controller = Controller {
-                up: is_pressed(2),
-                down: is_pressed(3),
-                left: is_pressed(4),
-                right: is_pressed(5),
-                start: is_pressed(6),
-                select: is_pressed(7),
-                b: is_pressed(8),
-                a: is_pressed(9),
-            };
-            inputs.push_back(MovieInput::Input(UserInput::PlayerOne(controller)));
+                if let Some((player, button)) = key
+                    .strip_prefix('P')
+                    .and_then(|k| k.split_once(' '))
+                    .and_then(|(p, b)| Some((p.parse::<usize>().ok()?.checked_sub(1)?, b)))
+                {
+                    if let Some(controller) = controllers.get_mut(player) {
+                        let controller = controller.get_or_insert_with(Controller::default);
+                        if pressed {
+                            bk2_button(controller, button);
+                        }
+                    }
+                    continue;
+                }
 
-            if input.len() > 10 {
-                let controller = Controller {
-                    up: is_pressed(10),
-                    down: is_pressed(11),
-                    left: is_pressed(12),
-                    right: is_pressed(13),
-                    start: is_pressed(14),
-                    select: is_pressed(15),
-                    b: is_pressed(16),
-                    a: is_pressed(17),
+                if !pressed {
+                    continue;
+                }
+
+                let input = match key.as_str() {
+                    "Reset" => UserInput::Reset,
+                    "Power" => UserInput::Power,
+                    "FDS Eject" => UserInput::Mapper(MapperInput::Fds(FdsInput::SetDisk(None))),
+                    key => {
+                        let Some(side) = key
+                            .strip_prefix("FDS Insert ")
+                            .and_then(|s| s.parse::<usize>().ok())
+                        else {
+                            continue;
+                        };
+                        UserInput::Mapper(MapperInput::Fds(FdsInput::SetDisk(Some(side))))
+                    }
                 };
-                inputs.push_back(MovieInput::Input(UserInput::PlayerTwo(controller)));
+                inputs.push_back(MovieInput::Input(input));
+            }
+
+            for (player, controller) in controllers.into_iter().enumerate() {
+                if let Some(controller) = controller {
+                    inputs.push_back(MovieInput::Input(UserInput::Player(player, controller)));
+                }
             }
 
             inputs.push_back(MovieInput::Frame);
@@ -383,3 +427,759 @@ impl R08Input {
         Ok(R08Input { inputs })
     }
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MovieFormat {
+    Fm2,
+    Bk2,
+}
+
+impl MovieFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MovieFormat::Fm2 => "fm2",
+            MovieFormat::Bk2 => "bk2",
+        }
+    }
+}
+
+const FM2_INSERT_DISK: u8 = 4;
+const FM2_SELECT_DISK: u8 = 8;
+
+// Buttons in the same order as BK2_BUTTONS
+fn controller_buttons(controller: &Controller) -> [bool; 8] {
+    [
+        controller.up,
+        controller.down,
+        controller.left,
+        controller.right,
+        controller.start,
+        controller.select,
+        controller.b,
+        controller.a,
+    ]
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct RecordedFrame {
+    controllers: [Controller; 4],
+    reset: bool,
+    power: bool,
+    disk: Option<Option<usize>>,
+    lag: bool,
+}
+
+impl RecordedFrame {
+    fn same_input(&self, other: &RecordedFrame) -> bool {
+        self.reset == other.reset
+            && self.power == other.power
+            && self.disk == other.disk
+            && self
+                .controllers
+                .iter()
+                .zip(other.controllers.iter())
+                .all(|(a, b)| controller_buttons(a) == controller_buttons(b))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MovieMode {
+    /// Frames are played back from the input log, user input is ignored
+    ReadOnly,
+    /// User input is recorded, replacing anything in the input log past the current frame
+    ReadWrite,
+}
+
+/// Input log of a [`MovieRecorder`] up to the frame a save state was made on
+#[derive(Debug, Clone)]
+pub struct MovieCheckpoint {
+    frames: Vec<RecordedFrame>,
+}
+
+/// Records the controllers and console commands the machine takes from its input source each
+/// frame, to be written out as an FM2 or BK2 movie. Movies start from power on, both formats
+/// imply the frame the console is powered on so it is not recorded
+#[derive(Debug, Clone)]
+pub struct MovieRecorder {
+    cart_id: CartridgeId,
+    rom_name: String,
+    region: Region,
+    disk_sides: usize,
+    mode: MovieMode,
+    rerecords: u32,
+    frames: Vec<RecordedFrame>,
+    position: usize,
+    current: Option<RecordedFrame>,
+    replay: Option<RecordedFrame>,
+    power_on: bool,
+}
+
+impl MovieRecorder {
+    pub fn new<S: Into<String>>(cart_id: CartridgeId, rom_name: S, region: Region) -> Self {
+        Self {
+            cart_id,
+            rom_name: rom_name.into(),
+            region,
+            disk_sides: 0,
+            mode: MovieMode::ReadWrite,
+            rerecords: 0,
+            frames: Vec::new(),
+            position: 0,
+            current: None,
+            replay: None,
+            power_on: true,
+        }
+    }
+
+    /// FM2 changes disks by cycling through the sides, so it needs to know how many there are
+    pub fn with_disk_sides(mut self, disk_sides: usize) -> Self {
+        self.disk_sides = disk_sides;
+        self
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Index of the next frame to be recorded or played back
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn rerecords(&self) -> u32 {
+        self.rerecords
+    }
+
+    /// Number of lag frames recorded up to the current frame
+    pub fn lag_count(&self) -> usize {
+        self.frames[..self.position]
+            .iter()
+            .filter(|f| f.lag)
+            .count()
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: MovieMode) {
+        self.mode = mode;
+    }
+
+    /// Input log up to the current frame, to be stored alongside a save state
+    pub fn checkpoint(&self) -> MovieCheckpoint {
+        MovieCheckpoint {
+            frames: self.frames[..self.position].to_vec(),
+        }
+    }
+
+    /// Called after loading a save state made at `checkpoint`. Read-write movies branch from
+    /// the checkpoint, dropping everything recorded after it and counting a rerecord. Read-only
+    /// movies keep their input log and continue playing it back from the checkpoint's frame
+    pub fn restore(&mut self, checkpoint: &MovieCheckpoint) {
+        self.current = None;
+        self.replay = None;
+        self.power_on = false;
+
+        match self.mode {
+            MovieMode::ReadOnly => {
+                let diverged = checkpoint.frames.len() > self.frames.len()
+                    || checkpoint
+                        .frames
+                        .iter()
+                        .zip(self.frames.iter())
+                        .any(|(a, b)| !a.same_input(b));
+                if diverged {
+                    tracing::warn!("save state is from a different movie timeline");
+                }
+                self.position = checkpoint.frames.len().min(self.frames.len());
+            }
+            MovieMode::ReadWrite => {
+                self.frames = checkpoint.frames.clone();
+                self.position = self.frames.len();
+                self.rerecords += 1;
+            }
+        }
+    }
+
+    /// Move back `frames` frames after the machine rewinds, read-write movies overwrite the
+    /// input log from there as frames are recorded
+    pub fn rewind(&mut self, frames: usize) {
+        self.current = None;
+        self.replay = None;
+        self.position = self.position.saturating_sub(frames);
+    }
+
+    /// Wrap `input` so everything the machine takes from it goes into the current frame
+    pub fn record<'a, I: InputSource>(&'a mut self, input: &'a mut I) -> RecordInput<'a, I> {
+        RecordInput {
+            recorder: self,
+            input,
+        }
+    }
+
+    /// Called at the end of every frame with [`nes::Machine::lag_frame`], frames where the
+    /// machine didn't touch its input source keep holding the previous frame's controllers
+    pub fn end_frame(&mut self, lag: bool) {
+        let frame = self.current.take();
+        self.replay = None;
+
+        if self.mode == MovieMode::ReadOnly {
+            // Playback stops advancing at the end of the movie, leaving input to the user
+            self.position = (self.position + 1).min(self.frames.len());
+            return;
+        }
+
+        let mut frame = frame.unwrap_or_else(|| RecordedFrame {
+            controllers: self
+                .position
+                .checked_sub(1)
+                .and_then(|p| self.frames.get(p))
+                .map(|f| f.controllers)
+                .unwrap_or_default(),
+            ..Default::default()
+        });
+
+        if std::mem::take(&mut self.power_on) && frame.power {
+            return;
+        }
+
+        frame.lag = lag;
+        self.frames.truncate(self.position);
+        self.frames.push(frame);
+        self.position += 1;
+    }
+
+    fn replay_controllers(&self) -> Option<[Controller; 4]> {
+        if self.mode != MovieMode::ReadOnly {
+            return None;
+        }
+
+        self.frames.get(self.position).map(|f| f.controllers)
+    }
+
+    // The logged frame being played back, console commands are cleared as the machine takes
+    // them so each only happens once
+    fn replay(&mut self) -> Option<&mut RecordedFrame> {
+        if self.mode != MovieMode::ReadOnly {
+            return None;
+        }
+
+        if self.replay.is_none() {
+            self.replay = self.frames.get(self.position).copied();
+        }
+        self.replay.as_mut()
+    }
+
+    pub fn write<W: Write + Seek>(&self, format: MovieFormat, writer: W) -> IoResult<()> {
+        match format {
+            MovieFormat::Fm2 => self.write_fm2(writer),
+            MovieFormat::Bk2 => self.write_bk2(writer),
+        }
+    }
+
+    pub fn write_fm2<W: Write>(&self, writer: W) -> IoResult<()> {
+        let mut writer = std::io::BufWriter::new(writer);
+        let four_score = self.four_score();
+
+        writeln!(writer, "version 3")?;
+        writeln!(writer, "emuVersion 0")?;
+        writeln!(writer, "rerecordCount {}", self.rerecords)?;
+        writeln!(writer, "palFlag {}", (self.region == Region::Pal) as u8)?;
+        writeln!(writer, "romFilename {}", self.rom_name)?;
+        writeln!(writer, "romChecksum sha256:{}", self.cart_id)?;
+        writeln!(writer, "guid {}", self.guid())?;
+        writeln!(writer, "fourscore {}", four_score as u8)?;
+        writeln!(writer, "microphone 0")?;
+        writeln!(writer, "port0 1")?;
+        writeln!(writer, "port1 1")?;
+        writeln!(writer, "port2 0")?;
+        writeln!(writer, "FDS {}", (self.disk_sides > 0) as u8)?;
+        writeln!(writer, "NewPPU 0")?;
+        writeln!(
+            writer,
+            "comment emulator mass-nes {}",
+            env!("CARGO_PKG_VERSION")
+        )?;
+
+        let players = if four_score { 4 } else { 2 };
+        let sides = self.disk_sides.max(1);
+        let mut disk = Some(0);
+        let mut selected = 0;
+        let mut disk_commands = VecDeque::new();
+
+        for frame in self.frames.iter() {
+            // FM2 can only insert/eject or select the next side once per frame, so disk
+            // changes are spread over the following frames
+            match frame.disk {
+                Some(None) if disk.is_some() => {
+                    disk_commands.push_back(FM2_INSERT_DISK);
+                    disk = None;
+                }
+                Some(Some(side)) if disk != Some(side) => {
+                    if disk.is_some() {
+                        disk_commands.push_back(FM2_INSERT_DISK);
+                    }
+                    for _ in 0..(side + sides - selected) % sides {
+                        disk_commands.push_back(FM2_SELECT_DISK);
+                    }
+                    disk_commands.push_back(FM2_INSERT_DISK);
+                    disk = Some(side);
+                    selected = side;
+                }
+                _ => (),
+            }
+
+            let mut command = disk_commands.pop_front().unwrap_or(0);
+            if frame.reset {
+                command |= 1;
+            }
+            if frame.power {
+                command |= 2;
+            }
+
+            write!(writer, "|{command}|")?;
+            for controller in frame.controllers.iter().take(players) {
+                let buttons = controller_buttons(controller);
+                for (idx, c) in [3, 2, 1, 0, 4, 5, 6, 7].into_iter().zip(b"RLDUTSBA") {
+                    let c = if buttons[idx] { *c } else { b'.' };
+                    writer.write_all(&[c])?;
+                }
+                write!(writer, "|")?;
+            }
+            writeln!(writer, "|")?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn write_bk2<W: Write + Seek>(&self, writer: W) -> IoResult<()> {
+        let mut zip = zip::ZipWriter::new(writer);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+
+        zip.start_file("Header.txt", options)?;
+        writeln!(zip, "MovieVersion BizHawk v2.0.0")?;
+        writeln!(zip, "Author ")?;
+        writeln!(zip, "emuVersion mass-nes {}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(zip, "Platform NES")?;
+        writeln!(zip, "GameName {}", self.rom_name)?;
+        writeln!(zip, "SHA256 {}", self.cart_id)?;
+        writeln!(zip, "Core NesHawk")?;
+        writeln!(zip, "rerecordCount {}", self.rerecords)?;
+        if self.region == Region::Pal {
+            writeln!(zip, "PAL True")?;
+        }
+
+        zip.start_file("Input Log.txt", options)?;
+        let players = if self.four_score() { 4 } else { 2 };
+
+        writeln!(zip, "[Input]")?;
+        write!(zip, "LogKey:#Reset|Power|")?;
+        for side in 0..self.disk_sides {
+            if side == 0 {
+                write!(zip, "FDS Eject|")?;
+            }
+            write!(zip, "FDS Insert {side}|")?;
+        }
+        for player in 1..=players {
+            write!(zip, "#")?;
+            for button in BK2_BUTTONS {
+                write!(zip, "P{player} {button}|")?;
+            }
+        }
+        writeln!(zip)?;
+
+        let mark = |pressed: bool, c: u8| if pressed { c } else { b'.' };
+        let mut line = Vec::new();
+        for frame in self.frames.iter() {
+            line.clear();
+            line.push(b'|');
+            line.push(mark(frame.reset, b'r'));
+            line.push(mark(frame.power, b'P'));
+            for side in 0..self.disk_sides {
+                if side == 0 {
+                    line.push(mark(frame.disk == Some(None), b'E'));
+                }
+                line.push(mark(
+                    frame.disk == Some(Some(side)),
+                    b'0' + (side % 10) as u8,
+                ));
+            }
+            line.push(b'|');
+            for controller in frame.controllers.iter().take(players) {
+                let buttons = controller_buttons(controller);
+                for (&pressed, c) in buttons.iter().zip(b"UDLRSsBA") {
+                    line.push(mark(pressed, *c));
+                }
+                line.push(b'|');
+            }
+            line.push(b'\n');
+            zip.write_all(&line)?;
+        }
+        writeln!(zip, "[/Input]")?;
+
+        zip.finish()?;
+        Ok(())
+    }
+
+    fn four_score(&self) -> bool {
+        self.frames.iter().any(|f| {
+            f.controllers[2..]
+                .iter()
+                .any(|c| controller_buttons(c).contains(&true))
+        })
+    }
+
+    fn guid(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.cart_id.to_string());
+        for frame in self.frames.iter() {
+            for controller in frame.controllers.iter() {
+                for pressed in controller_buttons(controller) {
+                    hasher.update([pressed as u8]);
+                }
+            }
+        }
+        let hash = hasher.finalize();
+
+        let hex: String = hash[..16].iter().map(|b| format!("{b:02X}")).collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+
+/// Input source handed to the machine while recording, see [`MovieRecorder::record`]
+pub struct RecordInput<'a, I> {
+    recorder: &'a mut MovieRecorder,
+    input: &'a mut I,
+}
+
+impl<I: InputSource> RecordInput<'_, I> {
+    fn frame(&mut self) -> &mut RecordedFrame {
+        let controllers = self.input.peek().controllers;
+        self.recorder.current.get_or_insert_with(|| RecordedFrame {
+            controllers,
+            ..Default::default()
+        })
+    }
+}
+
+impl<I: InputSource> InputSource for RecordInput<'_, I> {
+    fn strobe(&mut self) -> InputState {
+        if let Some(controllers) = self.recorder.replay_controllers() {
+            return InputState {
+                controllers,
+                ..self.input.strobe()
+            };
+        }
+
+        self.frame();
+        self.input.strobe()
+    }
+
+    fn peek(&self) -> InputState {
+        if let Some(controllers) = self.recorder.replay_controllers() {
+            return InputState {
+                controllers,
+                ..self.input.peek()
+            };
+        }
+
+        self.input.peek()
+    }
+
+    fn power(&mut self) -> bool {
+        let power = self.input.power();
+        if let Some(replay) = self.recorder.replay() {
+            return std::mem::take(&mut replay.power);
+        }
+
+        self.frame().power |= power;
+        power
+    }
+
+    fn reset(&mut self) -> bool {
+        let reset = self.input.reset();
+        if let Some(replay) = self.recorder.replay() {
+            return std::mem::take(&mut replay.reset);
+        }
+
+        self.frame().reset |= reset;
+        reset
+    }
+
+    fn mapper(&mut self) -> Option<MapperInput> {
+        let mapper = self.input.mapper();
+        if let Some(replay) = self.recorder.replay() {
+            return replay
+                .disk
+                .take()
+                .map(|disk| MapperInput::Fds(FdsInput::SetDisk(disk)));
+        }
+
+        match mapper {
+            Some(MapperInput::Fds(FdsInput::SetDisk(disk))) => self.frame().disk = Some(disk),
+            None => {
+                self.frame();
+            }
+        }
+        mapper
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::SimpleInput;
+
+    fn pad(a: bool, b: bool, right: bool) -> Controller {
+        Controller {
+            a,
+            b,
+            right,
+            ..Default::default()
+        }
+    }
+
+    // Takes the inputs the way the machine does once per frame
+    fn run_frame(recorder: &mut MovieRecorder, input: &mut SimpleInput) -> InputState {
+        let mut record = recorder.record(input);
+        record.power();
+        record.reset();
+        record.mapper();
+        let state = record.strobe();
+        recorder.end_frame(false);
+        state
+    }
+
+    fn recorder() -> MovieRecorder {
+        MovieRecorder::new(CartridgeId::new(b"rom"), "test.nes", Region::Ntsc)
+    }
+
+    // Frame i holds player one pad(i & 1, i & 2, i & 4), player two with A on odd frames, and a
+    // reset on frame 3
+    fn record_frames(recorder: &mut MovieRecorder, frames: usize) {
+        let mut input = SimpleInput::new();
+        for i in 0..frames {
+            input.handle_input(UserInput::PlayerOne(pad(
+                i & 1 != 0,
+                i & 2 != 0,
+                i & 4 != 0,
+            )));
+            input.handle_input(UserInput::PlayerTwo(pad(i & 1 != 0, false, false)));
+            if i == 3 {
+                input.handle_input(UserInput::Reset);
+            }
+            run_frame(recorder, &mut input);
+        }
+    }
+
+    fn check_playback(mut movie: MovieFile, frames: usize) {
+        // Both formats imply the power on frame
+        movie.prepare_frame();
+        assert!(movie.power());
+
+        for i in 0..frames {
+            movie.prepare_frame();
+            let state = movie.strobe();
+            let expected = pad(i & 1 != 0, i & 2 != 0, i & 4 != 0);
+            assert_eq!(
+                controller_buttons(&state.controllers[0]),
+                controller_buttons(&expected),
+                "player one on frame {i}"
+            );
+            assert_eq!(
+                state.controllers[1].a,
+                i & 1 != 0,
+                "player two on frame {i}"
+            );
+            assert_eq!(movie.reset(), i == 3, "reset on frame {i}");
+        }
+        assert!(movie.done());
+    }
+
+    #[test]
+    fn fm2_round_trip() {
+        let mut recorder = recorder();
+        record_frames(&mut recorder, 8);
+
+        let mut fm2 = Vec::new();
+        recorder.write_fm2(&mut fm2).unwrap();
+
+        let movie = MovieFile::fm2(fm2.as_slice(), 0, SubframeMode::Off).unwrap();
+        check_playback(movie, 8);
+    }
+
+    #[test]
+    fn bk2_round_trip() {
+        let mut recorder = recorder();
+        record_frames(&mut recorder, 8);
+
+        let mut bk2 = std::io::Cursor::new(Vec::new());
+        recorder.write_bk2(&mut bk2).unwrap();
+        bk2.set_position(0);
+
+        let movie = MovieFile::bk2(bk2, 0, SubframeMode::Off).unwrap();
+        check_playback(movie, 8);
+    }
+
+    #[test]
+    fn bk2_disk_changes() {
+        let mut recorder = recorder().with_disk_sides(2);
+        let mut input = SimpleInput::new();
+        for disk in [None, Some(Some(1)), None, Some(None)] {
+            if let Some(disk) = disk {
+                input.handle_input(UserInput::Mapper(MapperInput::Fds(FdsInput::SetDisk(disk))));
+            }
+            run_frame(&mut recorder, &mut input);
+        }
+
+        let mut bk2 = std::io::Cursor::new(Vec::new());
+        recorder.write_bk2(&mut bk2).unwrap();
+        bk2.set_position(0);
+
+        let mut movie = MovieFile::bk2(bk2, 0, SubframeMode::Off).unwrap();
+        movie.prepare_frame();
+        let disks: Vec<_> = (0..4)
+            .map(|_| {
+                movie.prepare_frame();
+                movie
+                    .mapper()
+                    .map(|MapperInput::Fds(FdsInput::SetDisk(disk))| disk)
+            })
+            .collect();
+        assert_eq!(disks, [None, Some(Some(1)), None, Some(None)]);
+    }
+
+    #[test]
+    fn fm2_spreads_disk_commands() {
+        let mut recorder = recorder().with_disk_sides(2);
+        let mut input = SimpleInput::new();
+        input.handle_input(UserInput::Mapper(MapperInput::Fds(FdsInput::SetDisk(
+            Some(1),
+        ))));
+        run_frame(&mut recorder, &mut input);
+        input.handle_input(UserInput::Reset);
+        for _ in 0..4 {
+            run_frame(&mut recorder, &mut input);
+        }
+
+        let mut fm2 = Vec::new();
+        recorder.write_fm2(&mut fm2).unwrap();
+        let fm2 = String::from_utf8(fm2).unwrap();
+        assert!(fm2.contains("\nFDS 1\n"));
+
+        // Eject, select the next side and insert it again, one command per frame
+        let commands: Vec<u32> = fm2
+            .lines()
+            .filter_map(|line| line.strip_prefix('|'))
+            .map(|line| line.split('|').next().unwrap().parse().unwrap())
+            .collect();
+        let (insert, select) = (FM2_INSERT_DISK as u32, FM2_SELECT_DISK as u32);
+        assert_eq!(commands, [insert, select | 1, insert, 0, 0]);
+    }
+
+    #[test]
+    fn restore_read_write_truncates() {
+        let mut recorder = recorder();
+        record_frames(&mut recorder, 3);
+        let checkpoint = recorder.checkpoint();
+        record_frames(&mut recorder, 3);
+        assert_eq!(recorder.frames(), 6);
+
+        recorder.restore(&checkpoint);
+        assert_eq!(recorder.frames(), 3);
+        assert_eq!(recorder.position(), 3);
+        assert_eq!(recorder.rerecords(), 1);
+
+        record_frames(&mut recorder, 1);
+        assert_eq!(recorder.frames(), 4);
+
+        recorder.restore(&checkpoint);
+        assert_eq!(recorder.frames(), 3);
+        assert_eq!(recorder.rerecords(), 2);
+    }
+
+    #[test]
+    fn restore_read_only_plays_back() {
+        let mut recorder = recorder();
+        record_frames(&mut recorder, 3);
+        let checkpoint = recorder.checkpoint();
+        record_frames(&mut recorder, 3);
+
+        recorder.set_mode(MovieMode::ReadOnly);
+        recorder.restore(&checkpoint);
+        assert_eq!(recorder.frames(), 6);
+        assert_eq!(recorder.position(), 3);
+        assert_eq!(recorder.rerecords(), 0);
+
+        // The logged frames win over the user's input until the movie ends
+        let mut input = SimpleInput::new();
+        input.handle_input(UserInput::PlayerOne(pad(false, false, true)));
+        for i in 0..3 {
+            let state = run_frame(&mut recorder, &mut input);
+            assert_eq!(state.controllers[0].a, i & 1 != 0);
+            assert!(!state.controllers[0].right);
+        }
+        assert_eq!(recorder.position(), 6);
+
+        let state = run_frame(&mut recorder, &mut input);
+        assert!(state.controllers[0].right);
+        assert_eq!(recorder.frames(), 6);
+    }
+
+    // NROM cart whose NMI handler reads $4016 on every other frame
+    fn alternate_polling_machine() -> nes::Machine {
+        let mut rom = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xea; 0x4000];
+        let reset = [
+            0x78, // SEI
+            0xa2, 0xff, 0x9a, // LDX #$FF, TXS
+            0x2c, 0x02, 0x20, 0x10, 0xfb, // BIT $2002, BPL -5
+            0x2c, 0x02, 0x20, 0x10, 0xfb, // BIT $2002, BPL -5
+            0xa9, 0x80, 0x8d, 0x00, 0x20, // LDA #$80, STA $2000
+            0x4c, 0x13, 0x80, // JMP $8013
+        ];
+        let nmi = [
+            0xe6, 0x00, 0xa5, 0x00, 0x29, 0x01, // INC $00, LDA $00, AND #$01
+            0xf0, 0x03, 0xad, 0x16, 0x40, // BEQ +3, LDA $4016
+            0x40, // RTI
+        ];
+        prg[..reset.len()].copy_from_slice(&reset);
+        prg[0x100..0x100 + nmi.len()].copy_from_slice(&nmi);
+        prg[0x3ffa..].copy_from_slice(&[0x00, 0x81, 0x00, 0x80, 0x00, 0x81]);
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+
+        let cart = nes::Cartridge::load(&mut &rom[..], None, None, "lag.nes").unwrap();
+        nes::Machine::new(Region::Ntsc, cart)
+    }
+
+    #[test]
+    fn lag_count_follows_machine() {
+        let mut machine = alternate_polling_machine();
+        let mut recorder = recorder();
+        let mut input = SimpleInput::new();
+
+        let mut lag_frames = Vec::new();
+        for _ in 0..10 {
+            machine.run(&mut recorder.record(&mut input));
+            recorder.end_frame(machine.lag_frame());
+            lag_frames.push(machine.lag_frame());
+        }
+        assert_eq!(recorder.lag_count(), machine.lag_count() as usize);
+
+        // Once the NMI is running polling and lag frames alternate
+        assert!(lag_frames[4..].windows(2).all(|w| w[0] != w[1]));
+
+        recorder.rewind(2);
+        assert_eq!(recorder.lag_count(), machine.lag_count() as usize - 1);
+    }
+}
@@ -0,0 +1,559 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use nes::{Controller, FrameEnd, Machine, SaveData, SimpleInput, UserInput, run_until};
+
+mod transport;
+
+pub use transport::{LoopbackTransport, Transport, UdpTransport};
+
+const INPUT_PACKET: u8 = 0;
+const CHECKSUM_PACKET: u8 = 1;
+// Local inputs sent per packet, resent until the peer acknowledges them
+const MAX_PACKET_INPUTS: usize = 255;
+// Checksums kept while waiting for the matching one from the peer
+const MAX_CHECKSUMS: usize = 16;
+
+/// How the local player takes part in a session, both players must use the same input delay
+/// and checksum interval
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NetplaySettings {
+    /// Controller port of the local player, 0 or 1
+    pub local_player: usize,
+    /// Frames local input is held back, trading latency for fewer rollbacks
+    pub input_delay: u32,
+    /// Frames emulated past the last confirmed remote input before waiting on the peer
+    pub max_prediction: u32,
+    /// Frames between comparing save state checksums with the peer
+    pub checksum_interval: u32,
+}
+
+impl Default for NetplaySettings {
+    fn default() -> Self {
+        Self {
+            local_player: 0,
+            input_delay: 2,
+            max_prediction: 8,
+            checksum_interval: 60,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetplayEvent {
+    /// Remote input differed from its prediction and `frames` frames were emulated again
+    Rollback { frames: u32 },
+    /// Save state of `frame` hashed differently on each machine
+    Desync { frame: u32, local: u64, remote: u64 },
+}
+
+/// Two player rollback session, both machines must start from the same state with the same
+/// cartridge. Remote input is predicted to repeat until it arrives, and frames emulated with a
+/// wrong prediction are emulated again from a save state
+pub struct Session<T> {
+    transport: T,
+    settings: NetplaySettings,
+    frame: u32,
+    local_inputs: BTreeMap<u32, u8>,
+    remote_inputs: BTreeMap<u32, u8>,
+    predictions: BTreeMap<u32, u8>,
+    // Every remote input before this frame has arrived
+    confirmed: u32,
+    last_remote: u8,
+    // The peer has every local input before this frame
+    remote_ack: u32,
+    rollback: Option<u32>,
+    states: VecDeque<(u32, SaveData)>,
+    next_checksum: u32,
+    checksums: BTreeMap<u32, u64>,
+    remote_checksums: BTreeMap<u32, u64>,
+    events: Vec<NetplayEvent>,
+}
+
+impl<T: Transport> Session<T> {
+    pub fn new(transport: T, settings: NetplaySettings) -> Self {
+        // Nothing is pressed during the frames covered by the input delay
+        let local_inputs = (0..settings.input_delay).map(|f| (f, 0)).collect();
+
+        Self {
+            transport,
+            settings,
+            frame: 0,
+            local_inputs,
+            remote_inputs: BTreeMap::new(),
+            predictions: BTreeMap::new(),
+            confirmed: 0,
+            last_remote: 0,
+            remote_ack: 0,
+            rollback: None,
+            states: VecDeque::new(),
+            next_checksum: 0,
+            checksums: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn settings(&self) -> NetplaySettings {
+        self.settings
+    }
+
+    /// Frames emulated by the session
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Frames with input confirmed by the peer
+    pub fn confirmed_frame(&self) -> u32 {
+        self.confirmed
+    }
+
+    pub fn events(&mut self) -> impl Iterator<Item = NetplayEvent> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Emulates the next frame with `local` as the local player's controller, returns `false`
+    /// without emulating when too far ahead of the peer. Samples of frames emulated again
+    /// after a rollback are dropped, only the new frame is left to be heard
+    pub fn advance(
+        &mut self,
+        machine: &mut Machine,
+        input: &mut SimpleInput,
+        local: Controller,
+    ) -> io::Result<bool> {
+        self.poll()?;
+
+        if self.frame >= self.confirmed + self.settings.max_prediction {
+            self.send_inputs()?;
+            return Ok(false);
+        }
+
+        if let Some(frame) = self.rollback.take() {
+            self.roll_back(machine, input, frame);
+        }
+
+        self.local_inputs
+            .insert(self.frame + self.settings.input_delay, encode(local));
+        self.run_frame(machine, input);
+
+        self.check_states()?;
+        self.send_inputs()?;
+
+        Ok(true)
+    }
+
+    fn poll(&mut self) -> io::Result<()> {
+        let mut buf = [0; 512];
+        while let Some(len) = self.transport.recv(&mut buf)? {
+            match Packet::decode(&buf[..len]) {
+                Some(Packet::Input { ack, start, inputs }) => self.remote_input(ack, start, inputs),
+                Some(Packet::Checksum { frame, checksum }) => {
+                    self.remote_checksums.insert(frame, checksum);
+                    self.compare_checksums();
+                }
+                None => tracing::warn!("invalid netplay packet"),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remote_input(&mut self, ack: u32, start: u32, inputs: &[u8]) {
+        // The peer can not be further ahead than its own prediction limit, inputs past it are
+        // resent once the earlier ones are confirmed
+        let limit = self
+            .confirmed
+            .saturating_add(self.settings.max_prediction)
+            .saturating_add(self.settings.input_delay);
+        let end = start.checked_add(inputs.len() as u32);
+        let sent = self.frame.saturating_add(self.settings.input_delay);
+        let Some(end) = end.filter(|_| start <= limit && ack <= sent) else {
+            tracing::warn!("netplay input packet out of range");
+            return;
+        };
+
+        self.remote_ack = self.remote_ack.max(ack);
+
+        for (frame, &value) in (start..end.min(limit)).zip(inputs) {
+            if frame < self.confirmed || self.remote_inputs.contains_key(&frame) {
+                continue;
+            }
+
+            self.remote_inputs.insert(frame, value);
+            if self.predictions.get(&frame).is_some_and(|&p| p != value) {
+                self.rollback = Some(self.rollback.map_or(frame, |f| f.min(frame)));
+            }
+        }
+
+        while let Some(&value) = self.remote_inputs.get(&self.confirmed) {
+            self.last_remote = value;
+            self.predictions.remove(&self.confirmed);
+            self.confirmed += 1;
+        }
+    }
+
+    fn roll_back(&mut self, machine: &mut Machine, input: &mut SimpleInput, frame: u32) {
+        let Some(idx) = self.states.iter().position(|(f, _)| *f == frame) else {
+            tracing::error!("netplay state for frame {frame} missing");
+            return;
+        };
+
+        let (_, state) = &self.states[idx];
+        machine.restore_state(state);
+        self.states.truncate(idx);
+
+        let end = self.frame;
+        self.frame = frame;
+        while self.frame < end {
+            self.run_frame(machine, input);
+            let _ = machine.take_samples();
+        }
+
+        self.events.push(NetplayEvent::Rollback {
+            frames: end - frame,
+        });
+    }
+
+    fn run_frame(&mut self, machine: &mut Machine, input: &mut SimpleInput) {
+        let frame = self.frame;
+        self.states.push_back((frame, machine.save_state()));
+
+        let local = self.local_inputs.get(&frame).copied().unwrap_or(0);
+        let remote = match self.remote_inputs.get(&frame) {
+            Some(&value) => {
+                self.predictions.remove(&frame);
+                value
+            }
+            None => {
+                self.predictions.insert(frame, self.last_remote);
+                self.last_remote
+            }
+        };
+
+        let local_player = self.settings.local_player.min(1);
+        input.handle_input(UserInput::Player(local_player, decode(local)));
+        input.handle_input(UserInput::Player(1 - local_player, decode(remote)));
+
+        machine.run_with_breakpoints(FrameEnd::SetVblank, run_until::Frames(1), (), input);
+        self.frame += 1;
+    }
+
+    // A state is final once every input before it is confirmed, final states are hashed and
+    // the ones no rollback can return to are dropped
+    fn check_states(&mut self) -> io::Result<()> {
+        let interval = self.settings.checksum_interval.max(1);
+        let mut hashed = Vec::new();
+        for (frame, state) in self.states.iter() {
+            if *frame > self.confirmed {
+                break;
+            }
+            if *frame >= self.next_checksum && frame % interval == 0 {
+                hashed.push((*frame, state.checksum()));
+                self.next_checksum = frame + 1;
+            }
+        }
+
+        for (frame, checksum) in hashed {
+            self.transport
+                .send(&Packet::Checksum { frame, checksum }.encode())?;
+            self.checksums.insert(frame, checksum);
+            self.compare_checksums();
+        }
+
+        while self
+            .states
+            .front()
+            .is_some_and(|(f, _)| *f < self.confirmed)
+        {
+            self.states.pop_front();
+        }
+
+        let confirmed = self.confirmed;
+        self.remote_inputs.retain(|&f, _| f >= confirmed);
+        self.predictions.retain(|&f, _| f >= confirmed);
+        let acked = self.remote_ack.min(confirmed);
+        self.local_inputs.retain(|&f, _| f >= acked);
+
+        Ok(())
+    }
+
+    fn compare_checksums(&mut self) {
+        let matched: Vec<_> = self
+            .checksums
+            .keys()
+            .filter(|f| self.remote_checksums.contains_key(f))
+            .copied()
+            .collect();
+
+        for frame in matched {
+            let local = self.checksums.remove(&frame).unwrap_or_default();
+            let remote = self.remote_checksums.remove(&frame).unwrap_or_default();
+            if local != remote {
+                tracing::warn!("netplay desync at frame {frame}");
+                self.events.push(NetplayEvent::Desync {
+                    frame,
+                    local,
+                    remote,
+                });
+            }
+        }
+
+        while self.checksums.len() > MAX_CHECKSUMS {
+            self.checksums.pop_first();
+        }
+        while self.remote_checksums.len() > MAX_CHECKSUMS {
+            self.remote_checksums.pop_first();
+        }
+    }
+
+    fn send_inputs(&mut self) -> io::Result<()> {
+        let Some(start) = self
+            .local_inputs
+            .range(self.remote_ack..)
+            .next()
+            .map(|(&f, _)| f)
+        else {
+            return Ok(());
+        };
+
+        let inputs: Vec<_> = (start..)
+            .map_while(|f| self.local_inputs.get(&f).copied())
+            .take(MAX_PACKET_INPUTS)
+            .collect();
+
+        let packet = Packet::Input {
+            ack: self.confirmed,
+            start,
+            inputs: &inputs,
+        };
+
+        self.transport.send(&packet.encode())
+    }
+}
+
+enum Packet<'a> {
+    /// Local inputs from `start`, with every remote input before `ack` received
+    Input {
+        ack: u32,
+        start: u32,
+        inputs: &'a [u8],
+    },
+    Checksum {
+        frame: u32,
+        checksum: u64,
+    },
+}
+
+impl<'a> Packet<'a> {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Packet::Input { ack, start, inputs } => {
+                let _ = buf.write_u8(INPUT_PACKET);
+                let _ = buf.write_u32::<LE>(*ack);
+                let _ = buf.write_u32::<LE>(*start);
+                let _ = buf.write_u8(inputs.len() as u8);
+                buf.extend_from_slice(inputs);
+            }
+            Packet::Checksum { frame, checksum } => {
+                let _ = buf.write_u8(CHECKSUM_PACKET);
+                let _ = buf.write_u32::<LE>(*frame);
+                let _ = buf.write_u64::<LE>(*checksum);
+            }
+        }
+
+        buf
+    }
+
+    fn decode(mut buf: &'a [u8]) -> Option<Self> {
+        match buf.read_u8().ok()? {
+            INPUT_PACKET => {
+                let ack = buf.read_u32::<LE>().ok()?;
+                let start = buf.read_u32::<LE>().ok()?;
+                let len = buf.read_u8().ok()? as usize;
+                let inputs = buf.get(..len)?;
+                Some(Packet::Input { ack, start, inputs })
+            }
+            CHECKSUM_PACKET => {
+                let frame = buf.read_u32::<LE>().ok()?;
+                let checksum = buf.read_u64::<LE>().ok()?;
+                Some(Packet::Checksum { frame, checksum })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn encode(controller: Controller) -> u8 {
+    [
+        controller.a,
+        controller.b,
+        controller.select,
+        controller.start,
+        controller.up,
+        controller.down,
+        controller.left,
+        controller.right,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |byte, (bit, &pressed)| byte | ((pressed as u8) << bit))
+}
+
+fn decode(byte: u8) -> Controller {
+    let pressed = |bit: u8| byte & (1 << bit) != 0;
+    Controller {
+        a: pressed(0),
+        b: pressed(1),
+        select: pressed(2),
+        start: pressed(3),
+        up: pressed(4),
+        down: pressed(5),
+        left: pressed(6),
+        right: pressed(7),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NROM cart that strobes both controllers each loop and sums their reads into $00 and $01
+    fn machine(region: nes::Region) -> Machine {
+        let mut rom = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xea; 0x4000];
+        let code = [
+            0xa9, 0x01, 0x8d, 0x16, 0x40, // LDA #$01, STA $4016
+            0xa9, 0x00, 0x8d, 0x16, 0x40, // LDA #$00, STA $4016
+            0xad, 0x16, 0x40, // LDA $4016
+            0x65, 0x00, 0x85, 0x00, // ADC $00, STA $00
+            0xad, 0x17, 0x40, // LDA $4017
+            0x65, 0x01, 0x85, 0x01, // ADC $01, STA $01
+            0x4c, 0x00, 0x80, // JMP $8000
+        ];
+        prg[..code.len()].copy_from_slice(&code);
+        for vector in prg[0x3ffa..].chunks_mut(2) {
+            vector.copy_from_slice(&[0x00, 0x80]);
+        }
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+
+        let cart = nes::Cartridge::load(&mut &rom[..], None, None, "netplay.nes").unwrap();
+        Machine::new(region, cart)
+    }
+
+    fn controller(frame: u32, player: u32) -> Controller {
+        decode(((frame / (7 + player * 3)) as u8).wrapping_mul(37))
+    }
+
+    #[test]
+    fn loopback_sessions_stay_in_sync() {
+        let (a, b) = LoopbackTransport::pair();
+        let settings = NetplaySettings {
+            checksum_interval: 10,
+            ..Default::default()
+        };
+        let mut players = [
+            (
+                Session::new(a, settings),
+                machine(nes::Region::Ntsc),
+                SimpleInput::new(),
+            ),
+            (
+                Session::new(
+                    b,
+                    NetplaySettings {
+                        local_player: 1,
+                        ..settings
+                    },
+                ),
+                machine(nes::Region::Ntsc),
+                SimpleInput::new(),
+            ),
+        ];
+
+        let mut rollbacks = [0; 2];
+        let mut step = 0;
+        loop {
+            // Each player falls behind now and then, forcing the other to predict, until both
+            // hold their controllers still and have every input of the frames they emulated
+            let settling = step >= 600;
+            let idle = players.iter().all(|(s, _, _)| {
+                s.frame() == players[0].0.frame() && s.confirmed_frame() >= s.frame()
+            });
+            if settling && idle {
+                break;
+            }
+            assert!(step < 1000, "sessions never settled");
+
+            for (player, (session, machine, input)) in players.iter_mut().enumerate() {
+                if !settling && step % (5 + player * 2) == 4 {
+                    continue;
+                }
+                let local = if settling {
+                    Controller::default()
+                } else {
+                    controller(session.frame(), player as u32)
+                };
+                session.advance(machine, input, local).unwrap();
+
+                for event in session.events() {
+                    match event {
+                        NetplayEvent::Rollback { .. } => rollbacks[player] += 1,
+                        NetplayEvent::Desync { frame, .. } => panic!("desync at frame {frame}"),
+                    }
+                }
+            }
+            step += 1;
+        }
+
+        assert!(rollbacks.iter().all(|&r| r > 0));
+
+        let [(_, a_machine, _), (_, b_machine, _)] = &mut players;
+        assert_eq!(
+            a_machine.save_state().checksum(),
+            b_machine.save_state().checksum()
+        );
+    }
+
+    #[test]
+    fn diverged_machines_report_desync() {
+        let (a, b) = LoopbackTransport::pair();
+        let settings = NetplaySettings {
+            checksum_interval: 1,
+            ..Default::default()
+        };
+        let mut a = (
+            Session::new(a, settings),
+            machine(nes::Region::Ntsc),
+            SimpleInput::new(),
+        );
+        // The two machines no longer run the same frames
+        let mut b = (
+            Session::new(
+                b,
+                NetplaySettings {
+                    local_player: 1,
+                    ..settings
+                },
+            ),
+            machine(nes::Region::Pal),
+            SimpleInput::new(),
+        );
+
+        let mut desync = false;
+        for _ in 0..60 {
+            for (session, machine, input) in [&mut a, &mut b] {
+                session
+                    .advance(machine, input, Controller::default())
+                    .unwrap();
+                desync |= session
+                    .events()
+                    .any(|e| matches!(e, NetplayEvent::Desync { .. }));
+            }
+        }
+
+        assert!(desync);
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::VecDeque;
+
+use nes::{Controller, InputState, Machine};
+
+use crate::filters::{FilterContext, FilterUniforms, TextureFilter, TextureFormat, TextureParams};
+
+pub const OVERLAY_WIDTH: usize = 256;
+pub const OVERLAY_HEIGHT: usize = 240;
+
+// Seconds a message stays on screen
+const MESSAGE_SECONDS: f64 = 3.0;
+const MAX_MESSAGES: usize = 4;
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const PAD_WIDTH: usize = 26;
+const PAD_HEIGHT: usize = 8;
+
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+const RED: [u8; 4] = [255, 64, 64, 255];
+const SHADOW: [u8; 4] = [0, 0, 0, 255];
+const BACKGROUND: [u8; 4] = [0, 0, 0, 160];
+const BUTTON_UP: [u8; 4] = [96, 96, 96, 255];
+
+/// What the overlay shows
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OverlaySettings {
+    /// Controllers of players one and two
+    pub inputs: bool,
+    /// Frame and lag counters
+    pub counters: bool,
+    pub fps: bool,
+    /// Transient messages like "State 3 saved"
+    pub messages: bool,
+    /// Bake the overlay into captured frames as well as the screen
+    pub capture: bool,
+}
+
+impl Default for OverlaySettings {
+    fn default() -> Self {
+        Self {
+            inputs: false,
+            counters: false,
+            fps: false,
+            messages: true,
+            capture: false,
+        }
+    }
+}
+
+/// State of the emulator shown by the overlay, taken once per frame
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FrameInfo {
+    pub controllers: [Controller; 2],
+    pub frame: u32,
+    pub lag_frame: bool,
+    pub lag_count: u32,
+}
+
+impl FrameInfo {
+    pub fn new(machine: &Machine, input: &InputState) -> Self {
+        Self {
+            controllers: [input.controllers[0], input.controllers[1]],
+            frame: machine.frame(),
+            lag_frame: machine.lag_frame(),
+            lag_count: machine.lag_count(),
+        }
+    }
+}
+
+/// Frames per second measured over one second windows, `now` is in seconds from any fixed
+/// point in time
+#[derive(Debug, Clone, Default)]
+pub struct FpsCounter {
+    start: Option<f64>,
+    frames: u32,
+    fps: Option<f64>,
+}
+
+impl FpsCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frame(&mut self, now: f64) {
+        let start = *self.start.get_or_insert(now);
+        self.frames += 1;
+
+        let elapsed = now - start;
+        if elapsed >= 1.0 {
+            self.fps = Some(self.frames as f64 / elapsed);
+            self.frames = 0;
+            self.start = Some(now);
+        }
+    }
+
+    pub fn fps(&self) -> Option<f64> {
+        self.fps
+    }
+}
+
+/// Software rendered RGBA layer covering the 256x240 screen, composited over the output of
+/// any filter with [`crate::filters::OVERLAY_SHADER`]
+pub struct Overlay {
+    settings: OverlaySettings,
+    info: FrameInfo,
+    fps: Option<f64>,
+    // Time each message expires, set when it is first presented
+    messages: VecDeque<(String, Option<f64>)>,
+    pixels: Vec<u8>,
+    empty: bool,
+    dirty: bool,
+}
+
+impl Overlay {
+    pub fn new(settings: OverlaySettings) -> Self {
+        Self {
+            settings,
+            info: FrameInfo::default(),
+            fps: None,
+            messages: VecDeque::new(),
+            pixels: vec![0; OVERLAY_WIDTH * OVERLAY_HEIGHT * 4],
+            empty: true,
+            dirty: true,
+        }
+    }
+
+    pub fn settings(&self) -> OverlaySettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: OverlaySettings) {
+        self.settings = settings;
+        self.dirty = true;
+    }
+
+    pub fn message<S: Into<String>>(&mut self, message: S) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((message.into(), None));
+        self.dirty = true;
+    }
+
+    /// Called once per emulated frame
+    pub fn frame(&mut self, info: FrameInfo) {
+        self.info = info;
+        self.dirty = true;
+    }
+
+    /// Called whenever the overlay is presented so messages expire a few seconds after they
+    /// are first shown, even while emulation is paused. `now` is in seconds from any fixed
+    /// point in time
+    pub fn update(&mut self, now: f64) {
+        let len = self.messages.len();
+        self.messages
+            .retain_mut(|(_, expires)| now < *expires.get_or_insert(now + MESSAGE_SECONDS));
+        if self.messages.len() != len {
+            self.dirty = true;
+        }
+    }
+
+    /// Whether messages are on screen, the overlay needs presenting until they expire
+    pub fn showing_messages(&self) -> bool {
+        self.settings.messages && !self.messages.is_empty()
+    }
+
+    pub fn set_fps(&mut self, fps: Option<f64>) {
+        if self.fps != fps {
+            self.fps = fps;
+            self.dirty = self.dirty || self.settings.fps;
+        }
+    }
+
+    /// RGBA pixels of the overlay, `None` when nothing is shown
+    pub fn render(&mut self) -> Option<&[u8]> {
+        if self.dirty {
+            self.redraw();
+            self.dirty = false;
+        }
+
+        (!self.empty).then_some(&self.pixels[..])
+    }
+
+    /// Uniforms for drawing the overlay with [`crate::filters::OVERLAY_SHADER`], `None` when
+    /// nothing is shown
+    pub fn uniforms<C: FilterContext>(&mut self, ctx: &C) -> Option<C::Uniforms> {
+        let pixels = self.render()?;
+        let texture = ctx.create_texture(TextureParams {
+            width: OVERLAY_WIDTH,
+            height: OVERLAY_HEIGHT,
+            format: TextureFormat::RGBA,
+            pixels,
+            filter: TextureFilter::Nearest,
+        });
+
+        let mut uniforms = ctx.create_uniforms();
+        uniforms.add_texture("overlay", texture);
+        Some(uniforms)
+    }
+
+    /// Blend the last rendered overlay over the `rgb` color of screen pixel `(x, y)`, for
+    /// baking the overlay into captured video
+    pub fn blend(&self, x: usize, y: usize, rgb: [u8; 3]) -> [u8; 3] {
+        if self.empty || x >= OVERLAY_WIDTH || y >= OVERLAY_HEIGHT {
+            return rgb;
+        }
+
+        let idx = (y * OVERLAY_WIDTH + x) * 4;
+        let alpha = self.pixels[idx + 3] as u32;
+        let mix = |o: u8, c: u8| ((o as u32 * alpha + c as u32 * (255 - alpha)) / 255) as u8;
+
+        [
+            mix(self.pixels[idx], rgb[0]),
+            mix(self.pixels[idx + 1], rgb[1]),
+            mix(self.pixels[idx + 2], rgb[2]),
+        ]
+    }
+
+    fn redraw(&mut self) {
+        let pixels = &mut self.pixels;
+        pixels.fill(0);
+        self.empty = true;
+
+        if self.settings.counters {
+            let info = self.info;
+            let x = draw_text(pixels, 2, 2, &format!("F {}", info.frame), WHITE);
+            let x = draw_text(pixels, x + 4, 2, &format!("L {}", info.lag_count), WHITE);
+            if info.lag_frame {
+                draw_text(pixels, x + 4, 2, "LAG", RED);
+            }
+            self.empty = false;
+        }
+
+        if let Some(fps) = self.fps.filter(|_| self.settings.fps) {
+            let text = format!("{fps:.1} FPS");
+            let x = OVERLAY_WIDTH.saturating_sub(text_width(&text) + 2);
+            draw_text(pixels, x, 2, &text, WHITE);
+            self.empty = false;
+        }
+
+        if self.settings.inputs {
+            let x = OVERLAY_WIDTH - PAD_WIDTH - 2;
+            for (player, controller) in self.info.controllers.iter().enumerate() {
+                let y = OVERLAY_HEIGHT - (2 - player) * (PAD_HEIGHT + 2);
+                draw_text(pixels, x - 5, y + 2, &(player + 1).to_string(), WHITE);
+                draw_pad(pixels, x, y, controller);
+            }
+            self.empty = false;
+        }
+
+        if self.settings.messages && !self.messages.is_empty() {
+            let mut y = OVERLAY_HEIGHT - GLYPH_HEIGHT - 3;
+            for (message, _) in self.messages.iter().rev() {
+                draw_text(pixels, 2, y, message, WHITE);
+                y -= GLYPH_HEIGHT + 2;
+            }
+            self.empty = false;
+        }
+    }
+}
+
+fn fill_rect(pixels: &mut [u8], x: usize, y: usize, width: usize, height: usize, color: [u8; 4]) {
+    for row in y..(y + height).min(OVERLAY_HEIGHT) {
+        for col in x..(x + width).min(OVERLAY_WIDTH) {
+            let idx = (row * OVERLAY_WIDTH + col) * 4;
+            pixels[idx..idx + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+fn text_width(text: &str) -> usize {
+    (text.chars().count() * (GLYPH_WIDTH + 1)).saturating_sub(1)
+}
+
+// Draws `text` with a drop shadow, returning the x position just past the last glyph
+fn draw_text(pixels: &mut [u8], x: usize, y: usize, text: &str, color: [u8; 4]) -> usize {
+    for (offset, color) in [(1, SHADOW), (0, color)] {
+        for (i, c) in text.chars().enumerate() {
+            let glyph = glyph(c);
+            let glyph_x = x + i * (GLYPH_WIDTH + 1) + offset;
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> col) != 0 {
+                        fill_rect(pixels, glyph_x + col, y + row + offset, 1, 1, color);
+                    }
+                }
+            }
+        }
+    }
+
+    x + text_width(text)
+}
+
+fn draw_pad(pixels: &mut [u8], x: usize, y: usize, controller: &Controller) {
+    fill_rect(pixels, x, y, PAD_WIDTH, PAD_HEIGHT, BACKGROUND);
+
+    let buttons = [
+        (3, 1, 2, 2, controller.up),
+        (3, 5, 2, 2, controller.down),
+        (1, 3, 2, 2, controller.left),
+        (5, 3, 2, 2, controller.right),
+        (9, 4, 3, 1, controller.select),
+        (13, 4, 3, 1, controller.start),
+        (18, 3, 3, 3, controller.b),
+        (22, 3, 3, 3, controller.a),
+    ];
+
+    for (button_x, button_y, width, height, pressed) in buttons {
+        let color = if pressed { WHITE } else { BUTTON_UP };
+        fill_rect(pixels, x + button_x, y + button_y, width, height, color);
+    }
+}
+
+// 3x5 glyphs, one row per byte with the leftmost pixel in bit 2
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_expire_on_wall_clock() {
+        let mut overlay = Overlay::new(OverlaySettings::default());
+        overlay.message("State 1 saved");
+        assert!(overlay.render().is_some());
+
+        // Emulated frames alone never expire a message
+        for _ in 0..1000 {
+            overlay.frame(FrameInfo::default());
+        }
+        overlay.update(10.0);
+        assert!(overlay.showing_messages());
+
+        overlay.message("State 2 saved");
+        overlay.update(11.0);
+        overlay.update(12.9);
+        assert!(overlay.showing_messages());
+        overlay.update(13.0);
+        assert!(overlay.showing_messages());
+        overlay.update(14.0);
+        assert!(!overlay.showing_messages());
+        assert!(overlay.render().is_none());
+    }
+}
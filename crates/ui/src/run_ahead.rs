@@ -0,0 +1,91 @@
+use nes::{FrameEnd, InputSource, Machine, run_until};
+
+/// Where the hidden frames of run-ahead are emulated
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RunAheadMode {
+    /// Run ahead on the primary machine and restore it afterwards
+    #[default]
+    SingleInstance,
+    /// Run ahead on a second machine loaded with the same cartridge, the primary machine is
+    /// never restored so its audio can not be disturbed
+    SecondInstance,
+}
+
+/// Hides input latency by presenting the screen from a few frames in the future, emulated
+/// from a snapshot of the machine using the current input
+pub struct RunAhead {
+    frames: u32,
+    second_instance: Option<Machine>,
+}
+
+impl RunAhead {
+    pub fn new(frames: u32) -> Self {
+        Self {
+            frames,
+            second_instance: None,
+        }
+    }
+
+    /// Run ahead on `machine` rather than the primary machine, it must have the same cartridge
+    /// and peripherals
+    pub fn with_second_instance(frames: u32, machine: Machine) -> Self {
+        Self {
+            frames,
+            second_instance: Some(machine),
+        }
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn set_frames(&mut self, frames: u32) {
+        self.frames = frames;
+    }
+
+    pub fn mode(&self) -> RunAheadMode {
+        if self.second_instance.is_some() {
+            RunAheadMode::SecondInstance
+        } else {
+            RunAheadMode::SingleInstance
+        }
+    }
+
+    /// Called at the end of each frame of `machine`, after its samples have been taken, and
+    /// copies the screen to present into `screen`. Single instance run-ahead is suspended
+    /// while the Data Recorder is recording, as the tape can not be restored
+    pub fn present<I: InputSource>(
+        &mut self,
+        machine: &mut Machine,
+        input: &mut I,
+        screen: &mut [u16],
+    ) {
+        let suspended = self.second_instance.is_none() && machine.tape_recording();
+        if self.frames == 0 || suspended {
+            screen.copy_from_slice(machine.get_screen());
+            return;
+        }
+
+        let state = machine.save_state();
+        let restore = self.second_instance.is_none();
+        let ahead = match self.second_instance.as_mut() {
+            Some(second) => {
+                second.restore_state(&state);
+                second
+            }
+            None => &mut *machine,
+        };
+
+        for _ in 0..self.frames {
+            ahead.run_with_breakpoints(FrameEnd::SetVblank, run_until::Frames(1), (), input);
+            // Hidden frames are never heard
+            let _ = ahead.take_samples();
+        }
+
+        screen.copy_from_slice(ahead.get_screen());
+
+        if restore {
+            machine.restore_state(&state);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{Error, ErrorKind, Read};
+
+use byteorder::{LE, ReadBytesExt};
+
+/// Decoded contents of a .wav file, multi-channel files are reduced to their first channel
+pub struct WavReader {
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+impl WavReader {
+    pub fn new<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut ident = [0; 4];
+        reader.read_exact(&mut ident)?;
+        if &ident != b"RIFF" {
+            return Err(invalid("missing RIFF header"));
+        }
+        let _size = reader.read_u32::<LE>()?;
+        reader.read_exact(&mut ident)?;
+        if &ident != b"WAVE" {
+            return Err(invalid("not a WAVE file"));
+        }
+
+        let mut format = None;
+
+        loop {
+            reader.read_exact(&mut ident)?;
+            let len = reader.read_u32::<LE>()? as usize;
+            let mut chunk = vec![0; len + (len & 1)];
+            reader.read_exact(&mut chunk)?;
+            chunk.truncate(len);
+
+            match &ident {
+                b"fmt " => {
+                    let mut chunk = chunk.as_slice();
+                    let audio_format = chunk.read_u16::<LE>()?;
+                    let channels = chunk.read_u16::<LE>()?;
+                    let sample_rate = chunk.read_u32::<LE>()?;
+                    let _byte_rate = chunk.read_u32::<LE>()?;
+                    let _block_align = chunk.read_u16::<LE>()?;
+                    let bits = chunk.read_u16::<LE>()?;
+                    format = Some((audio_format, channels.max(1) as usize, sample_rate, bits));
+                }
+                b"data" => {
+                    let Some((audio_format, channels, sample_rate, bits)) = format else {
+                        return Err(invalid("data chunk before fmt chunk"));
+                    };
+                    let samples = decode(&chunk, audio_format, channels, bits)?;
+                    return Ok(Self {
+                        sample_rate,
+                        samples,
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn decode(data: &[u8], audio_format: u16, channels: usize, bits: u16) -> std::io::Result<Vec<i16>> {
+    let frame_size = channels * (bits as usize / 8);
+    if frame_size == 0 {
+        return Err(invalid("unsupported sample size"));
+    }
+
+    let frames = data.chunks_exact(frame_size);
+    let samples = match (audio_format, bits) {
+        (1, 8) => frames.map(|f| (f[0] as i16 - 128) << 8).collect(),
+        (1, 16) => frames.map(|f| i16::from_le_bytes([f[0], f[1]])).collect(),
+        (1, 24) => frames.map(|f| i16::from_le_bytes([f[1], f[2]])).collect(),
+        (1, 32) => frames.map(|f| i16::from_le_bytes([f[2], f[3]])).collect(),
+        (3, 32) => frames
+            .map(|f| {
+                let v = f32::from_le_bytes([f[0], f[1], f[2], f[3]]);
+                (v.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+            })
+            .collect(),
+        _ => return Err(invalid("unsupported sample format")),
+    };
+
+    Ok(samples)
+}
+
+fn invalid(msg: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
@@ -4,7 +4,7 @@ use futures::StreamExt;
 use web_sys::HtmlCanvasElement;
 use web_sys::wasm_bindgen::JsError;
 use winit::application::ApplicationHandler;
-use winit::event::WindowEvent;
+use winit::event::{DeviceEvent, DeviceId, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy};
 use winit::keyboard::PhysicalKey;
 #[cfg(target_arch = "wasm32")]
@@ -16,13 +16,16 @@ use crate::offscreen_gfx::GfxWorker;
 
 use nes::UserInput;
 use ui::audio::Audio;
-use ui::gamepad::{GamepadEvent, GilrsInput};
+use ui::gamepad::{GamepadEvent, GamepadPlayers, GilrsInput, gilrs::GamepadId};
 use ui::input::InputMap;
 
 #[derive(Debug)]
 pub enum EmulatorInput {
     UserInput(UserInput),
     Load(Vec<u8>),
+    PortDevice(nes::Port, nes::PortDeviceKind),
+    RunAhead(u32, ui::run_ahead::RunAheadMode),
+    FdsConfig(nes::FdsConfig),
 }
 
 impl From<UserInput> for EmulatorInput {
@@ -34,6 +37,10 @@ impl From<UserInput> for EmulatorInput {
 pub enum UserEvent {
     Gamepad(GamepadEvent),
     Load(Vec<u8>),
+    PortDevice(nes::Port, nes::PortDeviceKind),
+    Overlay(ui::overlay::OverlaySettings),
+    RunAhead(u32, ui::run_ahead::RunAheadMode),
+    FdsConfig(nes::FdsConfig),
 }
 
 impl From<GamepadEvent> for UserEvent {
@@ -49,6 +56,8 @@ pub struct App<A> {
     window: Option<Window>,
     event_loop: Option<EventLoop<UserEvent>>,
     input: InputMap,
+    pads: [InputMap; 3],
+    gamepad_players: GamepadPlayers,
     input_tx: Option<Sender<EmulatorInput>>,
     gfx_worker: GfxWorker,
     pause: bool,
@@ -73,6 +82,8 @@ impl<A: Audio + 'static> App<A> {
             window: None,
             event_loop: Some(event_loop),
             input: InputMap::new(),
+            pads: std::array::from_fn(|_| InputMap::new()),
+            gamepad_players: GamepadPlayers::new(),
             input_tx: None,
             gamepad: Some(gamepad),
             gfx_worker,
@@ -133,6 +144,14 @@ impl<A> App<A> {
         }
     }
 
+    // Keyboard and the first gamepad control player one, later gamepads players 2-4
+    fn gamepad_input(&mut self, gamepad_id: GamepadId) -> Option<&mut InputMap> {
+        match self.gamepad_players.player(gamepad_id)? {
+            0 => Some(&mut self.input),
+            player => self.pads.get_mut(player - 1),
+        }
+    }
+
     fn send_inputs(&self) {
         if let Some(tx) = self.input_tx.as_ref() {
             if self.input.reset() {
@@ -145,6 +164,10 @@ impl<A> App<A> {
 
             let p1 = self.input.controller();
             let _ = tx.send(UserInput::PlayerOne(p1).into());
+            for (idx, pad) in self.pads.iter().enumerate() {
+                let _ = tx.send(UserInput::Player(idx + 1, pad.controller()).into());
+            }
+            let _ = tx.send(UserInput::Mouse(self.input.mouse()).into());
         }
     }
 }
@@ -188,15 +211,29 @@ impl<A: Audio> ApplicationHandler<UserEvent> for App<A> {
 
                     if self.input.pause() {
                         self.pause = !self.pause;
-                        if self.pause {
+                        let message = if self.pause {
                             self.audio.pause();
+                            "Paused"
                         } else {
                             self.audio.play();
-                        }
+                            "Resumed"
+                        };
+                        let _ = self
+                            .gfx_worker
+                            .tx
+                            .try_send(GfxRequest::Message(message.into()));
                     }
                     self.send_inputs();
                 }
             }
+            WindowEvent::MouseInput { state, button, .. } => {
+                if state.is_pressed() {
+                    self.input.press(button);
+                } else {
+                    self.input.release(button);
+                }
+                self.send_inputs();
+            }
             WindowEvent::ScaleFactorChanged {
                 scale_factor: _,
                 inner_size_writer: _,
@@ -210,24 +247,77 @@ impl<A: Audio> ApplicationHandler<UserEvent> for App<A> {
         }
     }
 
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            self.input.mouse_motion(dx, dy);
+            if let Some(tx) = self.input_tx.as_ref() {
+                let _ = tx.send(UserInput::Mouse(self.input.mouse()).into());
+            }
+        }
+    }
+
     fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: UserEvent) {
         match event {
             UserEvent::Gamepad(ev) => {
                 match ev {
-                    GamepadEvent::Button { state, button, .. } => {
-                        if state.is_pressed() {
-                            self.input.press(button);
-                        } else {
-                            self.input.release(button);
+                    GamepadEvent::Button {
+                        gamepad_id,
+                        state,
+                        button,
+                    } => {
+                        if let Some(input) = self.gamepad_input(gamepad_id) {
+                            if state.is_pressed() {
+                                input.press(button);
+                            } else {
+                                input.release(button);
+                            }
                         }
                     }
-                    GamepadEvent::Axis { axis, value, .. } => {
-                        self.input.axis(axis, value);
+                    GamepadEvent::Axis {
+                        gamepad_id,
+                        axis,
+                        value,
+                    } => {
+                        if let Some(input) = self.gamepad_input(gamepad_id) {
+                            input.axis(axis, value);
+                        }
+                    }
+                    GamepadEvent::Connected { gamepad_id } => {
+                        self.gamepad_players.player(gamepad_id);
+                    }
+                    GamepadEvent::Disconnected { gamepad_id } => {
+                        if let Some(player) = self.gamepad_players.disconnect(gamepad_id) {
+                            if player > 0 {
+                                self.pads[player - 1] = InputMap::new();
+                            }
+                        }
                     }
-                    _ => (),
                 }
                 self.send_inputs();
             }
+            UserEvent::PortDevice(port, device) => {
+                if let Some(tx) = self.input_tx.as_mut() {
+                    let _ = tx.send(EmulatorInput::PortDevice(port, device));
+                }
+            }
+            UserEvent::RunAhead(frames, mode) => {
+                if let Some(tx) = self.input_tx.as_mut() {
+                    let _ = tx.send(EmulatorInput::RunAhead(frames, mode));
+                }
+            }
+            UserEvent::FdsConfig(config) => {
+                if let Some(tx) = self.input_tx.as_mut() {
+                    let _ = tx.send(EmulatorInput::FdsConfig(config));
+                }
+            }
+            UserEvent::Overlay(settings) => {
+                let _ = self.gfx_worker.tx.try_send(GfxRequest::Overlay(settings));
+            }
             UserEvent::Load(rom) => {
                 if let Some(tx) = self.input_tx.as_mut() {
                     let _ = tx.send(EmulatorInput::Load(rom));
@@ -7,6 +7,7 @@ use web_sys::OffscreenCanvas;
 use std::sync::{Arc, Mutex};
 
 use ui::filters::Filter;
+use ui::overlay::{FpsCounter, FrameInfo, Overlay, OverlaySettings};
 
 use crate::gl;
 use crate::offscreen_gfx::OffscreenGfxSpawner;
@@ -16,6 +17,8 @@ pub enum GfxRequest {
     Frame,
     Redraw,
     Resize(u32, u32),
+    Overlay(OverlaySettings),
+    Message(String),
 }
 
 pub struct Gfx<T> {
@@ -25,11 +28,14 @@ pub struct Gfx<T> {
     gl: gl::GlContext,
     screen: gl::GlModel<Vertex>,
     program: gl::GlProgram,
+    overlay_program: gl::GlProgram,
     size: (f64, f64),
     render_size: (u32, u32),
     resize_count: usize,
     frame: Vec<u16>,
     back_buffer: GfxBackBuffer,
+    overlay: Overlay,
+    fps: FpsCounter,
 }
 
 impl<T: Filter<gl::GlContext>> Gfx<T> {
@@ -72,6 +78,12 @@ impl<T: Filter<gl::GlContext>> Gfx<T> {
         let screen = gl::GlModel::new(&gl, shape);
         let program = gl::GlProgram::new(&gl, filter.vertex_shader(), filter.fragment_shader());
 
+        let overlay_shaders = ui::filters::Preprocessor::new(ui::filters::OVERLAY_SHADER)
+            .process()
+            .unwrap();
+        let overlay_program =
+            gl::GlProgram::new(&gl, overlay_shaders.vertex, overlay_shaders.fragment);
+
         let frame = vec![15; 256 * 240];
         Self {
             filter,
@@ -80,11 +92,14 @@ impl<T: Filter<gl::GlContext>> Gfx<T> {
             gl,
             screen,
             program,
+            overlay_program,
             size,
             render_size,
             resize_count: 0,
             frame,
             back_buffer,
+            overlay: Overlay::new(OverlaySettings::default()),
+            fps: FpsCounter::new(),
         }
     }
 
@@ -102,8 +117,19 @@ impl<T: Filter<gl::GlContext>> Gfx<T> {
         match request {
             GfxRequest::Redraw => self.render(),
             GfxRequest::Resize(width, height) => self.resize((width, height)),
+            GfxRequest::Overlay(settings) => {
+                self.overlay.set_settings(settings);
+                self.render();
+            }
+            GfxRequest::Message(message) => {
+                self.overlay.message(message);
+                self.render();
+            }
             GfxRequest::Frame => {
-                self.back_buffer.swap(&mut self.frame);
+                let info = self.back_buffer.swap(&mut self.frame);
+                self.overlay.frame(info);
+                self.fps.frame(web_sys::js_sys::Date::now() / 1000.0);
+                self.overlay.set_fps(self.fps.fps());
                 self.render();
             }
         }
@@ -151,6 +177,7 @@ impl<T: Filter<gl::GlContext>> Gfx<T> {
     }
 
     pub fn render(&mut self) {
+        self.overlay.update(web_sys::js_sys::Date::now() / 1000.0);
         let (render_width, render_height) = self.render_size;
 
         let uniforms = self.filter.process(
@@ -162,6 +189,16 @@ impl<T: Filter<gl::GlContext>> Gfx<T> {
         let (width, height) = (render_width as i32, render_height as i32);
         self.gl.viewport(0, 0, width, height);
         self.program.draw(&self.screen, &uniforms, None);
+
+        if let Some(overlay_uniforms) = self.overlay.uniforms(&self.gl) {
+            self.gl.enable(gl::GL::BLEND);
+            self.gl
+                .blend_func(gl::GL::SRC_ALPHA, gl::GL::ONE_MINUS_SRC_ALPHA);
+            self.overlay_program
+                .draw(&self.screen, &overlay_uniforms, None);
+            self.gl.disable(gl::GL::BLEND);
+        }
+
         self.gl.flush();
     }
 }
@@ -222,13 +259,20 @@ impl ui::filters::FilterUniforms<gl::GlContext> for gl::GlUniformCollection {
 #[derive(Clone)]
 pub struct GfxBackBuffer {
     frame: Arc<Mutex<Vec<u16>>>,
+    info: Arc<Mutex<FrameInfo>>,
     tx: Sender<GfxRequest>,
 }
 
 impl GfxBackBuffer {
     pub fn new(tx: Sender<GfxRequest>) -> Self {
         let frame = Arc::new(Mutex::new(vec![0; 256 * 240]));
-        Self { frame, tx }
+        let info = Arc::new(Mutex::new(FrameInfo::default()));
+        Self { frame, info, tx }
+    }
+
+    /// Counters and controllers shown by the overlay with the next frame
+    pub fn set_info(&self, info: FrameInfo) {
+        *self.info.lock().unwrap() = info;
     }
 
     pub fn update<F: FnOnce(&mut [u16])>(&mut self, func: F) {
@@ -239,9 +283,13 @@ impl GfxBackBuffer {
         let _ = self.tx.try_send(GfxRequest::Frame);
     }
 
-    pub fn swap(&self, other: &mut Vec<u16>) {
-        let mut frame = self.frame.lock().unwrap();
-        std::mem::swap(&mut *frame, other);
+    pub fn swap(&self, other: &mut Vec<u16>) -> FrameInfo {
+        {
+            let mut frame = self.frame.lock().unwrap();
+            std::mem::swap(&mut *frame, other);
+        }
+
+        *self.info.lock().unwrap()
     }
 }
 
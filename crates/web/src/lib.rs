@@ -62,6 +62,62 @@ impl Emulator {
         let _ = self.proxy.send_event(UserEvent::Load(bytes));
     }
 
+    /// Plugs a device into controller port two, one of `controller`, `snes-mouse` or
+    /// `subor-mouse`
+    #[wasm_bindgen]
+    pub fn set_port_two(&self, device: String) -> Result<(), JsError> {
+        let device = match device.to_lowercase().as_str() {
+            "controller" => nes::PortDeviceKind::Controller,
+            "snes-mouse" => nes::PortDeviceKind::SnesMouse,
+            "subor-mouse" => nes::PortDeviceKind::SuborMouse,
+            _ => return Err(JsError::new("unknown port device")),
+        };
+        let _ = self
+            .proxy
+            .send_event(UserEvent::PortDevice(nes::Port::Two, device));
+
+        Ok(())
+    }
+
+    /// Chooses what is drawn over the screen: the controllers of players one and two, the
+    /// frame and lag counters, frames per second and messages such as pausing
+    #[wasm_bindgen]
+    pub fn set_overlay(&self, inputs: bool, counters: bool, fps: bool, messages: bool) {
+        let settings = ui::overlay::OverlaySettings {
+            inputs,
+            counters,
+            fps,
+            messages,
+            capture: false,
+        };
+        let _ = self.proxy.send_event(UserEvent::Overlay(settings));
+    }
+
+    /// Presents the screen from `frames` frames ahead of the emulator to hide input latency,
+    /// with `second_instance` running ahead on a second copy of the console to keep audio free
+    /// of glitches
+    #[wasm_bindgen]
+    pub fn set_run_ahead(&self, frames: u32, second_instance: bool) {
+        let mode = if second_instance {
+            ui::run_ahead::RunAheadMode::SecondInstance
+        } else {
+            ui::run_ahead::RunAheadMode::SingleInstance
+        };
+        let _ = self.proxy.send_event(UserEvent::RunAhead(frames, mode));
+    }
+
+    /// Lets Famicom Disk System games load without manual disk swaps by inserting the side
+    /// they ask for, with `fast_load` also skipping the drive delays. Applies from the next
+    /// rom loaded
+    #[wasm_bindgen]
+    pub fn set_fds(&self, auto_swap: bool, fast_load: bool) {
+        let config = nes::FdsConfig {
+            auto_swap,
+            fast_load,
+        };
+        let _ = self.proxy.send_event(UserEvent::FdsConfig(config));
+    }
+
     #[wasm_bindgen]
     pub fn load_rom_array_buffer(&self, buffer: js_sys::ArrayBuffer) {
         let buffer_u8 = js_sys::Uint8Array::new(&buffer);
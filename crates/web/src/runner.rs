@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use nes::{FrameEnd, SimpleInput, run_until::RunUntil};
+use nes::{FrameEnd, InputSource, SimpleInput, run_until::RunUntil};
 use web_sys::{
     js_sys::Array,
     wasm_bindgen::{self, prelude::*},
@@ -8,6 +8,8 @@ use web_sys::{
 use web_worker::WorkerSpawn;
 
 use ui::audio::SamplesSender;
+use ui::overlay::FrameInfo;
+use ui::run_ahead::{RunAhead, RunAheadMode};
 
 use crate::{
     app::{EmulatorInput, NesInputs},
@@ -64,6 +66,11 @@ struct MachineRunner {
     nes_inputs: Option<NesInputs>,
     last_frame: Option<u32>,
     input: SimpleInput,
+    ports: Vec<(nes::Port, nes::PortDeviceKind)>,
+    fds_config: nes::FdsConfig,
+    rom: Option<Vec<u8>>,
+    run_ahead: RunAhead,
+    run_ahead_mode: RunAheadMode,
 }
 
 impl MachineRunner {
@@ -89,6 +96,11 @@ impl MachineRunner {
             samples_tx,
             last_frame: None,
             input: SimpleInput::new(),
+            ports: Vec::new(),
+            fds_config: nes::FdsConfig::default(),
+            rom: None,
+            run_ahead: RunAhead::new(0),
+            run_ahead_mode: RunAheadMode::default(),
         }
     }
 
@@ -121,20 +133,58 @@ impl MachineRunner {
     fn handle_input(&mut self, input: EmulatorInput) {
         match input {
             EmulatorInput::Load(rom) => {
-                let mut rom = std::io::Cursor::new(rom);
-                let Ok(cart) = nes::Cartridge::load(&mut rom, None, None, "rom.nes") else {
-                    tracing::error!("failed to load rom");
+                self.rom = Some(rom);
+                let Some(machine) = self.load_machine() else {
                     return;
                 };
-                let machine = nes::Machine::new(self.region, cart);
                 self.machine = Some(machine);
                 self.last_frame = None;
                 self.input = SimpleInput::new();
+                self.set_run_ahead(self.run_ahead.frames(), self.run_ahead_mode);
             }
             EmulatorInput::UserInput(input) => {
                 self.input.handle_input(input);
             }
+            EmulatorInput::PortDevice(port, device) => {
+                self.ports.retain(|&(p, _)| p != port);
+                self.ports.push((port, device));
+                if let Some(machine) = self.machine.as_mut() {
+                    machine.set_port_device(port, device);
+                }
+                if self.run_ahead_mode == RunAheadMode::SecondInstance {
+                    self.set_run_ahead(self.run_ahead.frames(), self.run_ahead_mode);
+                }
+            }
+            EmulatorInput::RunAhead(frames, mode) => self.set_run_ahead(frames, mode),
+            EmulatorInput::FdsConfig(config) => self.fds_config = config,
+        }
+    }
+
+    fn load_machine(&self) -> Option<nes::Machine> {
+        let mut rom = std::io::Cursor::new(self.rom.as_ref()?);
+        let Ok(cart) = nes::Cartridge::load(&mut rom, None, None, "rom.nes") else {
+            tracing::error!("failed to load rom");
+            return None;
+        };
+        let cart = cart.with_fds_config(self.fds_config);
+        let mut machine = nes::Machine::new(self.region, cart);
+        for &(port, device) in self.ports.iter() {
+            machine.set_port_device(port, device);
         }
+
+        Some(machine)
+    }
+
+    fn set_run_ahead(&mut self, frames: u32, mode: RunAheadMode) {
+        self.run_ahead_mode = mode;
+        let second_instance = match mode {
+            RunAheadMode::SecondInstance if frames > 0 => self.load_machine(),
+            _ => None,
+        };
+        self.run_ahead = match second_instance {
+            Some(machine) => RunAhead::with_second_instance(frames, machine),
+            None => RunAhead::new(frames),
+        };
     }
 
     fn step(&mut self, samples: u32) {
@@ -143,13 +193,6 @@ impl MachineRunner {
             machine.run_with_breakpoints(FrameEnd::SetVblank, until, (), &mut self.input);
             let frame = Some(machine.frame());
 
-            if frame != self.last_frame {
-                self.back_buffer.update(|frame| {
-                    frame.copy_from_slice(machine.get_screen());
-                });
-                self.last_frame = frame;
-            }
-
             let mut count = 0;
 
             for (i, v) in machine.get_samples().enumerate() {
@@ -160,6 +203,15 @@ impl MachineRunner {
             self.blip.end_frame(count as u32);
 
             self.samples_tx.add_samples_from_blip(&mut self.blip);
+
+            if frame != self.last_frame {
+                self.back_buffer
+                    .set_info(FrameInfo::new(machine, &self.input.peek()));
+                self.back_buffer.update(|frame| {
+                    self.run_ahead.present(machine, &mut self.input, frame);
+                });
+                self.last_frame = frame;
+            }
         }
     }
 }